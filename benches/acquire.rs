@@ -0,0 +1,34 @@
+//! Acquire/release hot path with many threads sharing a client.
+//!
+//! Run with `cargo bench --bench acquire`, add `--features parking_lot` to
+//! compare the locks.
+
+use std::{thread, time::Instant};
+
+use jobslot::Client;
+
+const ITERATIONS: usize = 100_000;
+
+fn main() {
+    for threads in [1, 4, 16] {
+        let client = Client::new(threads).unwrap();
+
+        let start = Instant::now();
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    for _ in 0..ITERATIONS {
+                        drop(client.acquire().unwrap());
+                    }
+                });
+            }
+        });
+        let elapsed = start.elapsed();
+
+        println!(
+            "{:>2} threads: {:>6.0} ns per acquire/release",
+            threads,
+            elapsed.as_nanos() as f64 / (threads * ITERATIONS) as f64
+        );
+    }
+}
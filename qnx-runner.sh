@@ -0,0 +1,21 @@
+#!/bin/bash
+
+set -euo pipefail
+# Cargo runner for the QNX targets, e.g. set as
+# `CARGO_TARGET_X86_64_PC_NTO_QNX710_RUNNER`: copies the binary built by
+# `cargo test` to the QNX system `QNX_HOST`, reachable over ssh, and runs
+# it there with the same arguments.
+
+HOST="${QNX_HOST?}"
+BIN="$1"
+shift
+
+DEST="/tmp/jobslot-ci/$(basename "$BIN")"
+ssh "$HOST" mkdir -p /tmp/jobslot-ci
+scp -q "$BIN" "$HOST:$DEST"
+
+ARGS=""
+for arg in "$@"; do
+    ARGS="$ARGS $(printf '%q' "$arg")"
+done
+exec ssh "$HOST" "cd /tmp/jobslot-ci && $DEST$ARGS"
@@ -0,0 +1,42 @@
+use std::{fmt, io, sync::Arc};
+
+/// What to do when a wait on the jobserver semaphore returns
+/// `WAIT_ABANDONED`, set with
+/// [`Client::set_abandoned_policy`](crate::Client::set_abandoned_policy).
+///
+/// Semaphores cannot be abandoned, only mutexes can, so this only happens
+/// if a third-party tool on the machine misuses the name of the jobserver,
+/// e.g. for a mutex of its own. This only has an effect on windows.
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub enum AbandonedPolicy {
+    /// Treat it as a successful acquire, like a mutex is owned by the
+    /// waiter after its previous owner exits without releasing it.
+    Acquire,
+
+    /// Fail the acquire with an error, the default.
+    #[default]
+    Error,
+
+    /// Call the hook, e.g. to log it or repair the jobserver, treating it
+    /// as a successful acquire if it returns `Ok`, or failing the acquire
+    /// with the error it returns otherwise.
+    Recover(Arc<dyn Fn() -> io::Result<()> + Send + Sync>),
+}
+
+impl fmt::Debug for AbandonedPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Acquire => f.write_str("Acquire"),
+            Self::Error => f.write_str("Error"),
+            Self::Recover(_) => f.write_str("Recover(..)"),
+        }
+    }
+}
+
+impl AbandonedPolicy {
+    /// Create an [`AbandonedPolicy::Recover`] calling `hook`.
+    pub fn recover(hook: impl Fn() -> io::Result<()> + Send + Sync + 'static) -> Self {
+        Self::Recover(Arc::new(hook))
+    }
+}
@@ -0,0 +1,80 @@
+use std::{collections::BTreeSet, io, iter::FromIterator, mem};
+
+/// A set of CPUs a job can be pinned to, associated with tokens by
+/// [`Client::new_with_cpusets`](crate::Client::new_with_cpusets).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CpuSet(BTreeSet<usize>);
+
+impl CpuSet {
+    /// Create an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `cpu` to the set.
+    pub fn insert(&mut self, cpu: usize) -> &mut Self {
+        self.0.insert(cpu);
+        self
+    }
+
+    /// Return true if `cpu` is in the set.
+    pub fn contains(&self, cpu: usize) -> bool {
+        self.0.contains(&cpu)
+    }
+
+    /// Iterate over the CPUs in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// Pin the thread `pid` to this set with `sched_setaffinity`, where
+    /// `0` means the calling thread.
+    ///
+    /// To pin a spawned job, pass its process id, or call this with `0`
+    /// in [`CommandExt::pre_exec`](std::os::unix::process::CommandExt::pre_exec).
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if any of
+    /// the CPUs is not below `CPU_SETSIZE`.
+    pub fn set_affinity(&self, pid: u32) -> io::Result<()> {
+        // Safety: cpu_set_t is a plain bit array.
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+
+        for cpu in self.iter() {
+            if cpu >= libc::CPU_SETSIZE as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("cpu {} is out of range", cpu),
+                ));
+            }
+            // Safety: cpu is checked to be in range above.
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+
+        let ret =
+            unsafe { libc::sched_setaffinity(pid as libc::pid_t, mem::size_of_val(&set), &set) };
+        if ret == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl FromIterator<usize> for CpuSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Extend<usize> for CpuSet {
+    fn extend<I: IntoIterator<Item = usize>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+/// Bytes identifying tokens associated with a [`CpuSet`], skipping the
+/// ones written by make and this crate for tokens without any identity,
+/// and the one reserved for shutdown.
+pub(crate) fn token_bytes() -> impl Iterator<Item = u8> {
+    (0..=u8::MAX).filter(|byte| ![b'+', b'|', crate::SHUTDOWN_TOKEN].contains(byte))
+}
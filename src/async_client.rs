@@ -1,62 +1,205 @@
 use std::{
-    fmt,
+    borrow::Borrow,
+    collections::VecDeque,
     future::Future,
     io, ops,
     pin::Pin,
-    task::{Context, Poll},
+    sync::Arc,
+    task::{Context, Poll, Waker},
 };
 
-#[cfg(unix)]
+#[cfg(not(unix))]
+use std::time::Instant;
+
+#[cfg(all(unix, not(any(target_os = "illumos", target_os = "solaris"))))]
 use tokio::io::{unix::AsyncFd, Interest};
 
-use crate::{Acquired, TryAcquireClient};
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+use crate::event_port::EventPortFd;
+
+use crate::{
+    sync::{Mutex, MutexGuard},
+    Acquired, TryAcquireClient,
+};
 
-#[cfg(unix)]
-type AsyncAcquireClientInner = AsyncFd<TryAcquireClient>;
+#[cfg(all(unix, not(any(target_os = "illumos", target_os = "solaris"))))]
+type Registration = AsyncFd<TryAcquireClient>;
+
+/// Native event ports are used on illumos and Solaris, see
+/// [`EventPortFd`].
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+type Registration = EventPortFd<TryAcquireClient>;
 
 #[cfg(not(unix))]
-type AsyncAcquireClientInner = TryAcquireClient;
+type Registration = TryAcquireClient;
 
-/// Extension of [`Client`] that supports async acquire.
 #[derive(Debug)]
-pub struct AsyncAcquireClient(AsyncAcquireClientInner);
+struct AsyncAcquireClientInner {
+    registration: Registration,
+    waiters: Mutex<Waiters>,
+}
+
+impl AsyncAcquireClientInner {
+    fn waiters(&self) -> MutexGuard<'_, Waiters> {
+        self.waiters.lock()
+    }
+}
+
+/// FIFO queue of tasks waiting in [`AsyncAcquireClient::acquire`], only
+/// the one in the front polls the jobserver.
+#[derive(Debug, Default)]
+struct Waiters {
+    next_id: u64,
+    queue: VecDeque<(u64, Option<Waker>)>,
+}
+
+impl AsyncAcquireClientInner {
+    /// Queue a new waiter, returning its id.
+    fn push_waiter(&self) -> u64 {
+        let mut waiters = self.waiters();
+        let id = waiters.next_id;
+        waiters.next_id += 1;
+        waiters.queue.push_back((id, None));
+        id
+    }
+
+    /// Ready once the waiter `id` is in the front of the queue.
+    fn poll_turn(&self, id: u64, cx: &mut Context<'_>) -> Poll<()> {
+        let mut waiters = self.waiters();
+
+        if waiters.queue.front().map(|(front, _)| *front) == Some(id) {
+            return Poll::Ready(());
+        }
+
+        if let Some((_, waker)) = waiters.queue.iter_mut().find(|(i, _)| *i == id) {
+            match waker {
+                Some(waker) if waker.will_wake(cx.waker()) => (),
+                _ => *waker = Some(cx.waker().clone()),
+            }
+        }
+
+        Poll::Pending
+    }
+
+    /// Remove the waiter `id`, letting the next one in if it was in the
+    /// front.
+    fn remove_waiter(&self, id: u64) {
+        let mut waiters = self.waiters();
+        let was_front = waiters.queue.front().map(|(front, _)| *front) == Some(id);
+
+        waiters.queue.retain(|(i, _)| *i != id);
+
+        if was_front {
+            if let Some(waker) = waiters
+                .queue
+                .front_mut()
+                .and_then(|(_, waker)| waker.take())
+            {
+                drop(waiters);
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Extension of [`Client`] that supports async acquire.
+///
+/// `AsyncAcquireClient` is cheap to clone: all clones share the same
+/// registration with the async runtime, so it can be handed to multiple
+/// tasks which then call [`AsyncAcquireClient::acquire`] concurrently.
+///
+/// # Fairness
+///
+/// Tasks waiting in [`AsyncAcquireClient::acquire`] (across all clones)
+/// are served in FIFO order: only the task that started waiting first
+/// polls the jobserver, the rest are woken up one by one as the ones in
+/// front of them obtain a token or are dropped.
+///
+/// [`AsyncAcquireClient::poll_acquire`] does not take part in the queue,
+/// so mixing it with [`AsyncAcquireClient::acquire`] gives no ordering
+/// guarantees.
+///
+/// # Cancel safety
+///
+/// [`AsyncAcquireClient::acquire`] is cancel safe: a token is only taken
+/// from the jobserver when the future is about to return it, so dropping
+/// the future never loses a token and lets the next waiter in.
+///
+/// [`Client`]: crate::Client
+#[derive(Clone, Debug)]
+pub struct AsyncAcquireClient(Arc<AsyncAcquireClientInner>);
 
 impl ops::Deref for AsyncAcquireClient {
     type Target = TryAcquireClient;
 
     fn deref(&self) -> &Self::Target {
         #[cfg(unix)]
-        return self.0.get_ref();
+        return self.0.registration.get_ref();
 
         #[cfg(not(unix))]
-        return &self.0;
+        return &self.0.registration;
     }
 }
 
 impl AsyncAcquireClient {
     /// Create async acquire client
     pub fn new(try_acquire_client: TryAcquireClient) -> io::Result<Self> {
-        #[cfg(unix)]
-        return AsyncFd::with_interest(try_acquire_client, Interest::READABLE).map(Self);
+        #[cfg(all(unix, not(any(target_os = "illumos", target_os = "solaris"))))]
+        let registration = AsyncFd::with_interest(try_acquire_client, Interest::READABLE)?;
+
+        #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+        let registration = EventPortFd::new(try_acquire_client)?;
 
         #[cfg(not(unix))]
-        return Ok(Self(try_acquire_client));
+        let registration = try_acquire_client;
+
+        Ok(Self(Arc::new(AsyncAcquireClientInner {
+            registration,
+            waiters: Mutex::default(),
+        })))
     }
 
-    /// Deregisters and returns [`TryAcquireClient`]
+    /// Deregisters and returns [`TryAcquireClient`].
+    ///
+    /// If there are other clones of this `AsyncAcquireClient` still alive,
+    /// they stay registered and another instance of the [`TryAcquireClient`]
+    /// is returned instead, see [`AsyncAcquireClient::try_into_inner`].
+    #[deprecated(note = "use `AsyncAcquireClient::try_into_inner`, other clones might be alive")]
     pub fn into_inner(self) -> TryAcquireClient {
+        self.try_into_inner()
+            .unwrap_or_else(|this| TryAcquireClient::share(&this))
+    }
+
+    /// Deregisters and returns [`TryAcquireClient`], or returns `Err(self)`
+    /// if there are other clones of this `AsyncAcquireClient` still alive.
+    pub fn try_into_inner(self) -> Result<TryAcquireClient, Self> {
+        let registration = Arc::try_unwrap(self.0).map_err(Self)?.registration;
+
         #[cfg(unix)]
-        return self.0.into_inner();
+        return Ok(registration.into_inner());
 
         #[cfg(not(unix))]
-        return self.0;
+        return Ok(registration);
     }
 
     /// Async poll version of [`crate::Client::acquire`]
+    ///
+    /// Note that on Unix, only the [`Waker`](std::task::Waker) passed to the
+    /// most recent call of this function (across all clones) is scheduled
+    /// to receive a wakeup. Use [`AsyncAcquireClient::acquire`] if multiple
+    /// tasks wait on the same client concurrently, see
+    /// [fairness](AsyncAcquireClient#fairness).
     pub fn poll_acquire(&self, cx: &mut Context<'_>) -> Poll<io::Result<Acquired>> {
+        // Try acquiring first, since a helper thread might only start
+        // reading from the jobserver once it is requested.
+        #[cfg(unix)]
+        if let Some(acquired) = self.try_acquire()? {
+            return Poll::Ready(Ok(acquired));
+        }
+
         #[cfg(unix)]
         return loop {
-            let mut ready_guard = match self.0.poll_read_ready(cx) {
+            let mut ready_guard = match self.0.registration.poll_read_ready(cx) {
                 Poll::Pending => break Poll::Pending,
                 Poll::Ready(res) => res?,
             };
@@ -68,59 +211,155 @@ impl AsyncAcquireClient {
             }
         };
 
+        // Without a key, the waker is deduplicated against the registered
+        // ones instead.
         #[cfg(not(unix))]
-        return self
-            .0
-             .0
-             .0
-            .inner
-            .poll_acquire(cx)
-            .map_ok(|data| Acquired::new(&self.0, data));
+        return poll_token(self, cx, &mut None, Instant::now());
     }
 
     /// Async version of [`crate::Client::acquire`]
+    ///
+    /// It is fine to call this concurrently from multiple tasks, see
+    /// [fairness](AsyncAcquireClient#fairness) and
+    /// [cancel safety](AsyncAcquireClient#cancel-safety).
     pub fn acquire(&self) -> impl Future<Output = io::Result<Acquired>> + Send + Sync + Unpin + '_ {
-        poll_fn(move |cx| self.poll_acquire(cx))
+        Acquire::new(self)
+    }
+
+    /// Async version of [`Client::with_token`](crate::Client::with_token),
+    /// which acquires a token, awaits `fut` while holding it and releases it
+    /// once `fut` completes, returning its output.
+    ///
+    /// The token is also released if the returned future is dropped, e.g.
+    /// when cancelled by `select!` or a timeout, or if `fut` panics. Before a
+    /// token is acquired, dropping it is the same as dropping
+    /// [`AsyncAcquireClient::acquire`].
+    pub async fn with_token<F: Future>(&self, fut: F) -> io::Result<F::Output> {
+        let _token = self.acquire().await?;
+        Ok(fut.await)
+    }
+
+    /// Async version of [`Acquired::yield_token`], which releases `token`,
+    /// awaits `fut` and acquires a token back from this client before
+    /// returning.
+    ///
+    /// `token` should be acquired from the same jobserver as this client.
+    pub async fn yield_token<F: Future>(
+        &self,
+        token: &mut Acquired,
+        fut: F,
+    ) -> io::Result<F::Output> {
+        let lent = token.lend()?;
+        let ret = fut.await;
+        if lent.is_some() {
+            token.reclaim(self.acquire().await)?;
+        }
+        Ok(ret)
     }
 
     /// Async owned version of [`crate::Client::acquire`]
     pub fn acquire_owned(
         self,
     ) -> impl Future<Output = io::Result<Acquired>> + Send + Sync + Unpin + 'static {
-        poll_fn(move |cx| self.poll_acquire(cx))
+        Acquire::new(self)
     }
 }
 
-// Code below is copied from https://doc.rust-lang.org/nightly/src/core/future/poll_fn.rs.html#143-153
+/// Future of [`AsyncAcquireClient::acquire`] and
+/// [`AsyncAcquireClient::acquire_owned`], which waits for its turn in
+/// [`Waiters`] and then for a token.
+///
+/// Dropping it removes it from the queue, letting the next one in.
+struct Acquire<C: Borrow<AsyncAcquireClient>> {
+    client: C,
+    /// Position in the queue, set on the first poll.
+    id: Option<u64>,
+    /// Key of the waker registered with the jobserver, so that it is
+    /// updated in place and removed once this is dropped.
+    #[cfg(not(unix))]
+    key: Option<usize>,
+    #[cfg(not(unix))]
+    started: Instant,
+}
+
+impl<C: Borrow<AsyncAcquireClient>> Acquire<C> {
+    fn new(client: C) -> Self {
+        Self {
+            client,
+            id: None,
+            #[cfg(not(unix))]
+            key: None,
+            #[cfg(not(unix))]
+            started: Instant::now(),
+        }
+    }
+
+    fn poll_token(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Acquired>> {
+        #[cfg(unix)]
+        return self.client.borrow().poll_acquire(cx);
 
-fn poll_fn<T, F>(f: F) -> PollFn<F>
-where
-    F: FnMut(&mut Context<'_>) -> Poll<T>,
-{
-    PollFn { f }
+        #[cfg(not(unix))]
+        return poll_token(self.client.borrow(), cx, &mut self.key, self.started);
+    }
 }
 
-#[must_use = "futures do nothing unless you `.await` or poll them"]
-struct PollFn<F> {
-    f: F,
+/// Poll the jobserver for a token, with the waker registered under `key`,
+/// recording the wait since `started`.
+#[cfg(not(unix))]
+fn poll_token(
+    client: &crate::Client,
+    cx: &mut Context<'_>,
+    key: &mut Option<usize>,
+    started: Instant,
+) -> Poll<io::Result<Acquired>> {
+    let obtained =
+        client
+            .0
+            .acquire_with(started, None, |inner| match inner.poll_acquire(cx, key) {
+                Poll::Ready(res) => res.map(Some),
+                Poll::Pending => Ok(None),
+            });
+
+    match obtained {
+        Ok(Some(obtained)) => Poll::Ready(Ok(Acquired::new(client, obtained.into_data()))),
+        Ok(None) => Poll::Pending,
+        Err(err) => Poll::Ready(Err(err)),
+    }
 }
 
-impl<F: Unpin> Unpin for PollFn<F> {}
+impl<C: Borrow<AsyncAcquireClient> + Unpin> Future for Acquire<C> {
+    type Output = io::Result<Acquired>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = &this.client.borrow().0;
+        let id = *this.id.get_or_insert_with(|| inner.push_waiter());
 
-impl<F> fmt::Debug for PollFn<F> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("PollFn").finish()
+        if inner.poll_turn(id, cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let res = match this.poll_token(cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.client.borrow().0.remove_waiter(id);
+        this.id = None;
+        Poll::Ready(res)
     }
 }
 
-impl<T, F> Future for PollFn<F>
-where
-    F: FnMut(&mut Context<'_>) -> Poll<T>,
-{
-    type Output = T;
+impl<C: Borrow<AsyncAcquireClient>> Drop for Acquire<C> {
+    fn drop(&mut self) {
+        #[cfg(not(unix))]
+        if let Some(key) = self.key.take() {
+            let client: &AsyncAcquireClient = self.client.borrow();
+            let client: &crate::Client = client;
+            client.0.inner.cancel_poll_acquire(key);
+        }
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
-        // SAFETY: We are not moving out of the pinned field.
-        (unsafe { &mut self.get_unchecked_mut().f })(cx)
+        if let Some(id) = self.id.take() {
+            self.client.borrow().0.remove_waiter(id);
+        }
     }
 }
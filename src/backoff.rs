@@ -0,0 +1,73 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// How long to wait before retrying after losing the race for a token,
+/// set with [`Client::set_backoff`](crate::Client::set_backoff).
+///
+/// When many processes wait on the same jobserver, all of them are woken
+/// up once a token is released but only one of them gets it. Without a
+/// backoff, the others retry in lockstep, hammering the jobserver. It can
+/// also be used by callers building their own polling on top of
+/// [`TryAcquireClient::try_acquire`](crate::TryAcquireClient::try_acquire),
+/// see [`Backoff::delay`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Backoff {
+    /// Retry right away, the default.
+    #[default]
+    Immediate,
+
+    /// Wait the same duration before every retry.
+    Fixed(Duration),
+
+    /// Wait `initial` before the first retry, doubling the wait on every
+    /// retry up to `max`.
+    ///
+    /// With `jitter` set, a duration picked uniformly at random between
+    /// zero and that is waited instead, so that the retries of different
+    /// processes drift apart.
+    Exponential {
+        /// Wait before the first retry.
+        initial: Duration,
+        /// Upper bound of the wait.
+        max: Duration,
+        /// Randomize the wait.
+        jitter: bool,
+    },
+}
+
+impl Backoff {
+    /// Return how long to wait before retry number `attempt`, starting from
+    /// zero.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        match *self {
+            Self::Immediate => Duration::ZERO,
+            Self::Fixed(delay) => delay,
+            Self::Exponential {
+                initial,
+                max,
+                jitter,
+            } => {
+                let delay = initial
+                    .checked_mul(1 << attempt.min(31))
+                    .map_or(max, |delay| delay.min(max));
+
+                if jitter {
+                    delay.mul_f64(random_fraction())
+                } else {
+                    delay
+                }
+            }
+        }
+    }
+}
+
+/// Return a random number in `[0, 1]`, using the random keys of
+/// [`RandomState`] as a cheap source of randomness.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,247 @@
+//! Helpers for build scripts (`build.rs`) running parallel jobs, e.g.
+//! compiling C sources like `cc` does.
+//!
+//! Cargo runs build scripts with a jobserver in `CARGO_MAKEFLAGS`, and
+//! each build script already holds one implicit token for itself, which
+//! is not in the jobserver. [`Jobs`] hands out that implicit token first
+//! and acquires the others from the jobserver, so that a build script
+//! neither runs one job too few nor deadlocks waiting for a token it
+//! already has.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::process::Command;
+//!
+//! // Safety: called at the start of `main`, before any fd is opened.
+//! let jobs = unsafe { jobslot::build_script::Jobs::from_env() }.unwrap();
+//!
+//! let sources = ["a.c", "b.c", "c.c"];
+//! let statuses = jobs
+//!     .run(sources, |source| {
+//!         Command::new("cc").args(["-c", source]).status()
+//!     })
+//!     .unwrap();
+//! ```
+
+use std::{
+    env, io,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread,
+};
+
+use crate::{Acquired, CancellationFlag, Client};
+
+/// Cache of [`client`].
+static CLIENT: Mutex<Option<Option<Client>>> = Mutex::new(None);
+
+/// Returns the jobserver passed to the build script by cargo, connecting
+/// to it with [`Client::from_env`] on the first call only, so that all
+/// callers in the process share the same client.
+///
+/// # Safety
+///
+/// Same as [`Client::from_env`].
+pub unsafe fn client() -> Option<Client> {
+    CLIENT
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_with(|| Client::from_env())
+        .clone()
+}
+
+/// Job slots of a build script: the implicit token of the process plus
+/// the tokens of a jobserver.
+///
+/// Cloning it returns another handle to the same slots.
+#[derive(Clone, Debug)]
+pub struct Jobs(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    client: Option<Client>,
+    state: Mutex<State>,
+    cvar: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    implicit_free: bool,
+    /// Flags of the threads blocked on the jobserver, to wake one of them
+    /// up once the implicit token is free.
+    waiters: Vec<Arc<CancellationFlag>>,
+}
+
+impl Jobs {
+    /// Create job slots from the jobserver passed by cargo, see
+    /// [`client`].
+    ///
+    /// Without one, e.g. when the build script is run by hand, a jobserver
+    /// is created with `NUM_JOBS - 1` tokens, where `NUM_JOBS` is also set
+    /// by cargo, or none if it is not set, in which case only the implicit
+    /// token is available.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Client::from_env`].
+    pub unsafe fn from_env() -> io::Result<Self> {
+        let client = match client() {
+            Some(client) => Some(client),
+            None => match env::var("NUM_JOBS")
+                .ok()
+                .and_then(|n| n.parse::<usize>().ok())
+            {
+                Some(n) if n > 1 => Some(Client::new(n - 1)?),
+                _ => None,
+            },
+        };
+        Ok(Self::new(client))
+    }
+
+    /// Create job slots from `client`, or only the implicit token if it is
+    /// `None`.
+    pub fn new(client: Option<Client>) -> Self {
+        Self(Arc::new(Inner {
+            client,
+            state: Mutex::new(State {
+                implicit_free: true,
+                waiters: Vec::new(),
+            }),
+            cvar: Condvar::new(),
+        }))
+    }
+
+    /// Return the jobserver of these slots, if any.
+    pub fn client(&self) -> Option<&Client> {
+        self.0.client.as_ref()
+    }
+
+    /// Block until a job slot is available, taking the implicit token if
+    /// it is free, or a token from the jobserver otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error returned by [`Client::acquire_with_cancel`].
+    pub fn acquire(&self) -> io::Result<JobToken> {
+        loop {
+            let mut state = self.0.state();
+            if state.implicit_free {
+                state.implicit_free = false;
+                return Ok(JobToken {
+                    jobs: self.0.clone(),
+                    acquired: None,
+                });
+            }
+
+            let client = match &self.0.client {
+                Some(client) => client,
+                None => {
+                    drop(
+                        self.0
+                            .cvar
+                            .wait(state)
+                            .unwrap_or_else(PoisonError::into_inner),
+                    );
+                    continue;
+                }
+            };
+
+            let flag = Arc::new(CancellationFlag::new()?);
+            state.waiters.push(flag.clone());
+            drop(state);
+
+            let res = client.acquire_with_cancel(&flag);
+            self.0
+                .state()
+                .waiters
+                .retain(|waiter| !Arc::ptr_eq(waiter, &flag));
+
+            match res {
+                Ok(acquired) => {
+                    return Ok(JobToken {
+                        jobs: self.0.clone(),
+                        acquired: Some(acquired),
+                    })
+                }
+                // Woken up to take the implicit token.
+                Err(_) if flag.is_cancelled() => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Run `f` on every item of `items` in parallel, each holding a job
+    /// slot, and return the results in order.
+    ///
+    /// The first job runs in a new thread too, so that the calling thread
+    /// keeps acquiring slots for the next ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a slot cannot be acquired, after waiting for
+    /// the jobs already started.
+    pub fn run<I, F, R>(&self, items: I, f: F) -> io::Result<Vec<R>>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) -> R + Sync,
+        R: Send,
+    {
+        let f = &f;
+        thread::scope(|scope| {
+            let mut handles = Vec::new();
+            for item in items {
+                let token = self.acquire()?;
+                handles.push(scope.spawn(move || {
+                    let ret = f(item);
+                    drop(token);
+                    ret
+                }));
+            }
+
+            Ok(handles
+                .into_iter()
+                .map(|handle| match handle.join() {
+                    Ok(ret) => ret,
+                    Err(payload) => std::panic::resume_unwind(payload),
+                })
+                .collect())
+        })
+    }
+}
+
+impl Inner {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A job slot returned by [`Jobs::acquire`], released once dropped.
+#[derive(Debug)]
+pub struct JobToken {
+    jobs: Arc<Inner>,
+    /// `None` for the implicit token.
+    acquired: Option<Acquired>,
+}
+
+impl JobToken {
+    /// Return true if this is the implicit token of the process.
+    pub fn is_implicit(&self) -> bool {
+        self.acquired.is_none()
+    }
+}
+
+impl Drop for JobToken {
+    fn drop(&mut self) {
+        if self.acquired.is_some() {
+            return;
+        }
+
+        let mut state = self.jobs.state();
+        state.implicit_free = true;
+        if let Some(waiter) = state.waiters.first() {
+            drop(waiter.cancel());
+        }
+        self.jobs.cvar.notify_one();
+    }
+}
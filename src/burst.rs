@@ -0,0 +1,53 @@
+use std::{
+    io,
+    sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::ClientInner;
+
+/// How long the retiring thread waits for a token before checking whether
+/// the jobserver is gone.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Spawn a thread retiring `n` tokens of the jobserver of `client` once
+/// `duration` has passed, by acquiring them as they come back and never
+/// releasing them.
+///
+/// Tokens are acquired directly from the jobserver, so that the ones
+/// returned by child processes are retired too. The thread only keeps
+/// `client` alive while polling for a token, so that it is still dropped
+/// with its last handle.
+pub(crate) fn spawn_retire(
+    client: &Arc<ClientInner>,
+    n: usize,
+    duration: Duration,
+) -> io::Result<()> {
+    let client = Arc::downgrade(client);
+    thread::Builder::new()
+        .name("jobslot-burst".into())
+        .spawn(move || {
+            thread::sleep(duration);
+            retire(&client, n);
+        })
+        .map(drop)
+}
+
+fn retire(client: &Weak<ClientInner>, mut n: usize) {
+    while n > 0 {
+        // Nothing is left to retire once the jobserver is gone.
+        let client = match client.upgrade() {
+            Some(client) => client,
+            None => return,
+        };
+
+        // Fails once the jobserver is shut down, which forgets the tokens
+        // anyway.
+        match client.inner.acquire_until(Instant::now() + POLL_INTERVAL) {
+            Ok(Some(_)) => n -= 1,
+            Ok(None) => {}
+            Err(_) => return,
+        }
+    }
+}
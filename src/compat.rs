@@ -0,0 +1,190 @@
+//! API compatible with the [`jobserver`] crate.
+//!
+//! This module mirrors part of the API of `jobserver`: creating a client
+//! or connecting to one with `from_env`, blocking acquires, `configure`,
+//! `string_arg` and helper threads. Code using only these can switch to
+//! jobslot by replacing `use jobserver::...` with
+//! `use jobslot::compat::...`, or by adding `use jobslot::compat as
+//! jobserver;` at the crate root.
+//!
+//! Other parts, e.g. `Client::try_acquire` and `Client::from_env_ext` with
+//! its `FromEnv` and `FromEnvError`, are not mirrored. Convert the client
+//! into [`crate::Client`] with [`From`] to use the equivalents of this
+//! crate instead, e.g. [`crate::Client::into_try_acquire_client`] and
+//! [`crate::Client::from_env_with_config`].
+//!
+//! [`jobserver`]: https://docs.rs/jobserver
+
+use std::{
+    io, process,
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+pub use crate::Acquired;
+
+/// A jobserver client with the same API as `jobserver::Client`.
+///
+/// It is a thin wrapper of [`crate::Client`], use [`From`] to convert
+/// between them.
+#[derive(Clone, Debug)]
+pub struct Client(crate::Client);
+
+impl Client {
+    /// Creates a new jobserver initialized with the given parallelism limit.
+    ///
+    /// Check [`crate::Client::new`] for more information.
+    pub fn new(limit: usize) -> io::Result<Client> {
+        crate::Client::new(limit).map(Client)
+    }
+
+    /// Attempts to connect to the jobserver specified in this process's
+    /// environment.
+    ///
+    /// Check [`crate::Client::from_env`] for more information.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`crate::Client::from_env`].
+    pub unsafe fn from_env() -> Option<Client> {
+        crate::Client::from_env().map(Client)
+    }
+
+    /// Acquires a token from this jobserver client.
+    ///
+    /// Check [`crate::Client::acquire`] for more information.
+    pub fn acquire(&self) -> io::Result<Acquired> {
+        self.0.acquire()
+    }
+
+    /// Returns amount of tokens in the read-side pipe.
+    ///
+    /// Check [`crate::Client::available`] for more information.
+    pub fn available(&self) -> io::Result<usize> {
+        self.0.available()
+    }
+
+    /// Configures a child process to have access to this client's jobserver
+    /// as well.
+    ///
+    /// This sets up `CARGO_MAKEFLAGS` for `cmd`, unlike
+    /// [`crate::Client::configure_and_run`] the environment variable is not
+    /// removed after `cmd` is spawned. The jobserver is kept open by `cmd`
+    /// until it is dropped, even if this client is dropped first.
+    ///
+    /// If the jobserver cannot be passed to other processes, in which case
+    /// [`crate::Client::configure_and_run`] returns a
+    /// [`crate::ConfigureError`], `cmd` is left unchanged.
+    pub fn configure(&self, cmd: &mut process::Command) {
+        self.configure_inner(cmd, &["CARGO_MAKEFLAGS"])
+    }
+
+    /// Same as [`Client::configure`] except that it sets up environment
+    /// variables `CARGO_MAKEFLAGS`, `MAKEFLAGS` and `MFLAGS`.
+    pub fn configure_make(&self, cmd: &mut process::Command) {
+        self.configure_inner(cmd, &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"])
+    }
+
+    fn configure_inner(&self, cmd: &mut process::Command, envs: &[&str]) {
+        if crate::check_configurable()
+            .and_then(|()| self.0.check_string_arg())
+            .is_err()
+        {
+            return;
+        }
+
+        let client = &self.0 .0;
+        #[cfg(unix)]
+        client.inner.pre_run_keeping(cmd, client.clone());
+        #[cfg(not(unix))]
+        client.inner.pre_run(cmd);
+        client.mark_exported();
+
+        let value = format!(
+            "-j --jobserver-fds={0} --jobserver-auth={0}",
+            self.string_arg()
+        );
+        for env in envs {
+            cmd.env(env, &value);
+        }
+    }
+
+    /// Returns a string that can be passed to `--jobserver-auth` to refer
+    /// to this jobserver.
+    ///
+    /// # Panics
+    ///
+    /// Panics on platforms without cross process jobserver support, like
+    /// `jobserver` does.
+    pub fn string_arg(&self) -> String {
+        self.0.string_arg().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Converts this client into a helper thread to deal with a blocking
+    /// `acquire` function a little more easily.
+    ///
+    /// `f` is called on the helper thread with the result of `acquire`
+    /// once for each [`HelperThread::request_token`].
+    pub fn into_helper_thread<F>(self, mut f: F) -> io::Result<HelperThread>
+    where
+        F: FnMut(io::Result<Acquired>) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel::<()>();
+
+        thread::Builder::new()
+            .name("jobslot-helper-thread".into())
+            .spawn(move || {
+                for () in rx {
+                    f(self.acquire());
+                }
+            })?;
+
+        Ok(HelperThread { tx })
+    }
+
+    /// Blocks the current thread until a token is acquired.
+    ///
+    /// Check [`crate::Client::acquire_raw`] for more information.
+    pub fn acquire_raw(&self) -> io::Result<()> {
+        self.0.acquire_raw()
+    }
+
+    /// Releases a jobserver token back to the original jobserver.
+    ///
+    /// Check [`crate::Client::release_raw`] for more information.
+    pub fn release_raw(&self) -> io::Result<()> {
+        self.0.release_raw()
+    }
+}
+
+impl From<crate::Client> for Client {
+    fn from(client: crate::Client) -> Self {
+        Client(client)
+    }
+}
+
+impl From<Client> for crate::Client {
+    fn from(client: Client) -> Self {
+        client.0
+    }
+}
+
+/// A helper thread created by [`Client::into_helper_thread`].
+///
+/// Dropping it stops the helper thread once the pending requests are
+/// served. Unlike `jobserver`, it does not wait for the helper thread to
+/// exit, since a blocking `acquire` cannot be interrupted.
+#[derive(Debug)]
+pub struct HelperThread {
+    tx: Sender<()>,
+}
+
+impl HelperThread {
+    /// Requests that the helper thread acquires a token, eventually calling
+    /// the closure passed to [`Client::into_helper_thread`] with the result.
+    pub fn request_token(&self) {
+        // The helper thread only exits after `tx` is dropped, or if the
+        // closure panics, in which case the request is ignored.
+        self.tx.send(()).ok();
+    }
+}
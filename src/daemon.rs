@@ -0,0 +1,315 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+    },
+    path::PathBuf,
+    ptr,
+};
+
+use crate::{check_jobserver_name, imp, Client};
+
+/// A named jobserver (see [`Client::new_named`]) kept alive by a daemon
+/// process, so that it outlives the process creating it, e.g. a
+/// short-lived CLI command, and keeps serving later invocations.
+///
+/// The daemon only holds the fifo open, so that the tokens in it are not
+/// lost once all clients are closed, and exits once torn down with
+/// [`Daemon::teardown`].
+///
+/// The daemon also holds a lock on a state file next to the fifo, which
+/// is released once it exits, so that a process reusing its pid is never
+/// mistaken for it.
+#[derive(Debug)]
+pub struct Daemon {
+    name: Box<str>,
+    pid: libc::pid_t,
+}
+
+impl Daemon {
+    /// Create a jobserver named `name` with `limit` tokens and spawn a
+    /// daemon owning it.
+    ///
+    /// If the daemon of an existing jobserver named `name` has exited,
+    /// e.g. killed or lost on reboot, its leftovers are removed first.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Client::new_named`].
+    pub fn spawn(name: &str, limit: usize) -> io::Result<Self> {
+        let client = match Client::new_named(name, limit) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && remove_stale(name)? => {
+                Client::new_named(name, limit)?
+            }
+            res => res?,
+        };
+        // The fifo is removed by `Daemon::teardown` instead.
+        client.persist_fifo();
+
+        let pid = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(state_path(name))
+            .and_then(|state| fork_daemon(client.0.inner.get_read_fd(), state.as_raw_fd()));
+
+        match pid {
+            Ok(pid) => Ok(Self {
+                name: name.into(),
+                pid,
+            }),
+            Err(err) => {
+                for path in [imp::named_fifo_path(name), state_path(name)] {
+                    let _ = fs::remove_file(path);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Attach to the daemon of the jobserver named `name` spawned by
+    /// [`Daemon::spawn`], usually in another process.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if there is no
+    /// such daemon, in which case the leftovers of a daemon which has
+    /// exited are removed, and an error of kind
+    /// [`io::ErrorKind::InvalidInput`] if `name` is invalid, see
+    /// [`Client::new_named`].
+    pub fn attach(name: &str) -> io::Result<Self> {
+        check_jobserver_name(name)?;
+
+        match lock_holder(name) {
+            Ok(Some(pid)) if imp::named_fifo_path(name).exists() => Ok(Self {
+                name: name.into(),
+                pid,
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Err(err),
+            res => {
+                res?;
+                // Nothing to signal, since the daemon has exited.
+                remove_leftovers(name)?;
+                Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("daemon of jobserver {:?} has exited", name),
+                ))
+            }
+        }
+    }
+
+    /// Return the name of the jobserver.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return the process id of the daemon.
+    pub fn pid(&self) -> u32 {
+        self.pid as u32
+    }
+
+    /// Return true if the daemon is still running, i.e. it still holds the
+    /// lock on its state file.
+    pub fn is_alive(&self) -> bool {
+        matches!(lock_holder(&self.name), Ok(Some(pid)) if pid == self.pid)
+    }
+
+    /// Connect to the jobserver, see [`Client::connect_named`].
+    pub fn client(&self) -> io::Result<Client> {
+        Client::connect_named(&self.name)
+    }
+
+    /// Stop the daemon and remove the jobserver.
+    ///
+    /// Clients connected to the jobserver keep working, but new ones can
+    /// no longer connect to it.
+    pub fn teardown(self) -> io::Result<()> {
+        self.teardown_inner()
+    }
+
+    fn teardown_inner(&self) -> io::Result<()> {
+        // Only signal the daemon while it holds the lock, its pid might
+        // have been reused by an unrelated process otherwise.
+        if self.is_alive() && unsafe { libc::kill(self.pid, libc::SIGTERM) } == -1 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                return Err(err);
+            }
+        }
+
+        remove_leftovers(&self.name)
+    }
+}
+
+/// Path of the state file the daemon of `name` holds a lock on.
+fn state_path(name: &str) -> PathBuf {
+    let mut path = imp::named_fifo_path(name).into_os_string();
+    path.push(".daemon");
+    path.into()
+}
+
+/// Return the pid of the daemon holding the lock on the state file of
+/// `name`, or `None` if it has exited.
+///
+/// Returns an error of kind [`io::ErrorKind::NotFound`] if there is no
+/// state file.
+fn lock_holder(name: &str) -> io::Result<Option<libc::pid_t>> {
+    let state = File::open(state_path(name))?;
+
+    let mut lock = write_lock();
+    if unsafe { libc::fcntl(state.as_raw_fd(), libc::F_GETLK, &mut lock) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(if lock.l_type == libc::F_UNLCK as _ {
+        None
+    } else {
+        Some(lock.l_pid)
+    })
+}
+
+/// A lock on the whole file for writing.
+fn write_lock() -> libc::flock {
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as _;
+    lock.l_whence = libc::SEEK_SET as _;
+    lock
+}
+
+fn remove_leftovers(name: &str) -> io::Result<()> {
+    for path in [imp::named_fifo_path(name), state_path(name)] {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the leftovers of the daemon of `name` if it has exited.
+///
+/// Return true if they are removed.
+fn remove_stale(name: &str) -> io::Result<bool> {
+    match Daemon::attach(name) {
+        Ok(_) => Ok(false),
+        // `attach` removes the leftovers if the daemon has exited, but a
+        // jobserver without a daemon is not touched.
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Ok(!imp::named_fifo_path(name).exists())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Double fork a daemon holding `keep` open and a lock on `state`, and
+/// return its pid once it holds the lock.
+fn fork_daemon(keep: RawFd, state: RawFd) -> io::Result<libc::pid_t> {
+    // Record locks are not inherited across fork, so the daemon takes it.
+    let lock = write_lock();
+
+    // Prepare everything needing allocation before forking, since only
+    // async-signal-safe functions may be called in the child.
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    let null = null.as_raw_fd();
+
+    let mut pipe = [0; 2];
+    if unsafe { libc::pipe(pipe.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let (read, write) = unsafe { (File::from_raw_fd(pipe[0]), File::from_raw_fd(pipe[1])) };
+    for fd in pipe {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+
+    let max_fd = unsafe {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) == 0
+            && limit.rlim_cur != libc::RLIM_INFINITY
+        {
+            limit.rlim_cur.min(65536) as RawFd
+        } else {
+            65536
+        }
+    };
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => unsafe {
+            // Detach from the session of the parent, then fork again so
+            // that the daemon is reparented and never becomes a session
+            // leader acquiring a controlling terminal.
+            libc::setsid();
+            match libc::fork() {
+                -1 => libc::_exit(1),
+                0 => daemon_main(keep, state, &lock, null, write.as_raw_fd(), max_fd),
+                _ => libc::_exit(0),
+            }
+        },
+        child => {
+            drop(write);
+
+            let mut status = 0;
+            while unsafe { libc::waitpid(child, &mut status, 0) } == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+
+            let mut pid = [0; std::mem::size_of::<libc::pid_t>()];
+            (&read).read_exact(&mut pid).map_err(|_| {
+                io::Error::new(io::ErrorKind::Other, "failed to spawn jobserver daemon")
+            })?;
+
+            Ok(libc::pid_t::from_ne_bytes(pid))
+        }
+    }
+}
+
+/// Only async-signal-safe functions are called here.
+unsafe fn daemon_main(
+    keep: RawFd,
+    state: RawFd,
+    lock: &libc::flock,
+    null: RawFd,
+    write: RawFd,
+    max_fd: RawFd,
+) -> ! {
+    for fd in 0..=2 {
+        libc::dup2(null, fd);
+    }
+    for fd in 3..max_fd {
+        if fd != keep && fd != state && fd != write {
+            libc::close(fd);
+        }
+    }
+
+    // The parent fails to spawn the daemon if the pid is not written.
+    if libc::fcntl(state, libc::F_SETLK, lock) == -1 {
+        libc::_exit(1);
+    }
+    libc::chdir(b"/\0".as_ptr().cast());
+
+    let mut set = std::mem::MaybeUninit::uninit();
+    libc::sigemptyset(set.as_mut_ptr());
+    libc::pthread_sigmask(libc::SIG_SETMASK, set.as_ptr(), ptr::null_mut());
+    libc::signal(libc::SIGTERM, libc::SIG_DFL);
+
+    let pid = libc::getpid().to_ne_bytes();
+    libc::write(write, pid.as_ptr().cast(), pid.len());
+    libc::close(write);
+
+    loop {
+        libc::pause();
+    }
+}
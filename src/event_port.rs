@@ -0,0 +1,176 @@
+use std::{
+    fs::File,
+    io,
+    mem::MaybeUninit,
+    os::unix::prelude::*,
+    ptr,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use crate::{imp::cvt, sync::Mutex};
+
+/// Read readiness of a fd watched with an event port, used by the async
+/// client on illumos and Solaris instead of tokio's `AsyncFd`.
+///
+/// Associations of fds with an event port are oneshot: once an event is
+/// retrieved, the fd is dissociated and has to be associated again to
+/// report the next one. The fd is only associated while a task waits on
+/// it, and a thread blocked in `port_get` wakes the task up. Associating
+/// a fd which is already readable reports it right away, so no wakeup is
+/// lost in between.
+#[derive(Debug)]
+pub(crate) struct EventPortFd<T> {
+    port: Port,
+    inner: T,
+}
+
+#[derive(Debug)]
+struct Port {
+    file: Arc<File>,
+    state: Arc<Mutex<State>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Whether the fd is associated with the port.
+    associated: bool,
+    ready: bool,
+    /// Set once the thread exits on an error of `port_get`.
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl<T: AsRawFd> EventPortFd<T> {
+    pub(crate) fn new(inner: T) -> io::Result<Self> {
+        let fd = cvt(unsafe { libc::port_create() })?;
+        let file = Arc::new(unsafe { File::from_raw_fd(fd) });
+        cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let thread = thread::Builder::new()
+            .name("jobslot-event-port".into())
+            .spawn({
+                let file = file.clone();
+                let state = state.clone();
+                move || wait_events(&file, &state)
+            })?;
+
+        Ok(Self {
+            port: Port {
+                file,
+                state,
+                thread: Some(thread),
+            },
+            inner,
+        })
+    }
+
+    pub(crate) fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Ready once the fd is readable, until [`ReadyGuard::clear_ready`] is
+    /// called.
+    pub(crate) fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<ReadyGuard<'_>>> {
+        let mut state = self.port.state.lock();
+        if state.ready {
+            return Poll::Ready(Ok(ReadyGuard {
+                state: &self.port.state,
+            }));
+        }
+        if state.closed {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Other,
+                "event port of the jobserver is closed",
+            )));
+        }
+
+        match &state.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => (),
+            _ => state.waker = Some(cx.waker().clone()),
+        }
+
+        if !state.associated {
+            cvt(unsafe {
+                libc::port_associate(
+                    self.port.file.as_raw_fd(),
+                    libc::PORT_SOURCE_FD,
+                    self.inner.as_raw_fd() as libc::uintptr_t,
+                    i32::from(libc::POLLIN),
+                    ptr::null_mut(),
+                )
+            })?;
+            state.associated = true;
+        }
+
+        Poll::Pending
+    }
+}
+
+/// Returned by [`EventPortFd::poll_read_ready`].
+pub(crate) struct ReadyGuard<'a> {
+    state: &'a Mutex<State>,
+}
+
+impl ReadyGuard<'_> {
+    /// Wait for the fd to be readable again, e.g. since another process
+    /// took the token first.
+    pub(crate) fn clear_ready(&mut self) {
+        self.state.lock().ready = false;
+    }
+}
+
+fn wait_events(port: &File, state: &Mutex<State>) {
+    loop {
+        let mut event = MaybeUninit::<libc::port_event>::uninit();
+        if unsafe { libc::port_get(port.as_raw_fd(), event.as_mut_ptr(), ptr::null_mut()) } == -1 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            let waker = {
+                let mut state = state.lock();
+                state.closed = true;
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+            break;
+        }
+
+        // Sent by `Port::drop`.
+        let event = unsafe { event.assume_init() };
+        if i32::from(event.portev_source) == libc::PORT_SOURCE_USER {
+            break;
+        }
+
+        let waker = {
+            let mut state = state.lock();
+            state.associated = false;
+            state.ready = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Port {
+    fn drop(&mut self) {
+        unsafe {
+            libc::port_send(self.file.as_raw_fd(), 0, ptr::null_mut());
+        }
+        if let Some(thread) = self.thread.take() {
+            drop(thread.join());
+        }
+    }
+}
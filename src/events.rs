@@ -0,0 +1,110 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    process,
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::trace::escape;
+
+/// A sink of machine-readable events describing the token lifecycle and
+/// configure calls of clients, set with
+/// [`Client::set_event_sink`](crate::Client::set_event_sink), for build
+/// analytics pipelines.
+///
+/// Events are written as newline-delimited JSON objects, each with an
+/// `event` name, the `ts_us` in microseconds since the unix epoch, the
+/// `pid` and the `thread` it happened in:
+///
+/// - `acquire`: a token is acquired from the jobserver after waiting
+///   `wait_us` microseconds.
+/// - `release`: a token is written back to the jobserver.
+/// - `configure`: the jobserver is passed to a child via the environment
+///   variables `envs`, with `fifo` set if it is passed by path.
+///
+/// Only tokens going through the jobserver are reported, not the ones
+/// reused by [`Client::set_linger`](crate::Client::set_linger). Errors
+/// writing events are ignored. Clones write to the same writer.
+#[derive(Clone)]
+pub struct EventSink(Arc<Mutex<Box<dyn Write + Send>>>);
+
+impl fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSink").finish_non_exhaustive()
+    }
+}
+
+impl EventSink {
+    /// Write events to `writer`, e.g. a [`File`](std::fs::File) created
+    /// from an fd passed by the build system.
+    ///
+    /// Each event is written with a single `write_all` followed by a
+    /// `flush`, so `writer` needs no buffering of its own.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self(Arc::new(Mutex::new(Box::new(writer))))
+    }
+
+    /// Append events to the file at `path`, creating it if it does not
+    /// exist.
+    pub fn append_to(path: impl AsRef<Path>) -> io::Result<Self> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map(Self::new)
+    }
+
+    pub(crate) fn acquire(&self, wait: Duration) {
+        self.emit("acquire", &format!(r#","wait_us":{}"#, wait.as_micros()));
+    }
+
+    pub(crate) fn release(&self) {
+        self.emit("release", "");
+    }
+
+    pub(crate) fn configure(&self, envs: &[&str], fifo: bool) {
+        let envs: Vec<_> = envs
+            .iter()
+            .map(|env| format!(r#""{}""#, escape(env)))
+            .collect();
+        self.emit(
+            "configure",
+            &format!(r#","envs":[{}],"fifo":{}"#, envs.join(","), fifo),
+        );
+    }
+
+    /// Write an event named `event`, with `fields` appended to the common
+    /// ones.
+    fn emit(&self, event: &str, fields: &str) {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        let thread = thread::current();
+        let thread = match thread.name() {
+            Some(name) => escape(name),
+            None => format!("{:?}", thread.id()),
+        };
+
+        let line = format!(
+            "{{\"event\":\"{}\",\"ts_us\":{},\"pid\":{},\"thread\":\"{}\"{}}}\n",
+            event,
+            ts,
+            process::id(),
+            thread,
+            fields
+        );
+
+        let mut writer = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        // The events are for analytics only, so do not fail on them.
+        drop(
+            writer
+                .write_all(line.as_bytes())
+                .and_then(|()| writer.flush()),
+        );
+    }
+}
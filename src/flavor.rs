@@ -0,0 +1,80 @@
+use std::ffi::OsStr;
+#[cfg(windows)]
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+/// Flavor of a make to run, deciding how the jobserver is passed to it by
+/// [`Client::configure_make_and_run_for`](crate::Client::configure_make_and_run_for).
+///
+/// On windows, only make built as a native program, e.g. `mingw32-make`,
+/// connects to the jobserver semaphore. The make of MSYS2 or Cygwin is a
+/// unix program, which expects fds or a fifo of its emulation layer
+/// instead.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum MakeFlavor {
+    /// Make connecting to the jobserver of the platform, the default.
+    #[default]
+    Native,
+
+    /// Make of MSYS2 or Cygwin on windows, which cannot connect to the
+    /// jobserver, so that only `-jN` is passed to it, with `N` picked like
+    /// [`Client::configure_and_run_with_jobs_arg`](crate::Client::configure_and_run_with_jobs_arg)
+    /// does. It then runs a jobserver of its own with that many tokens.
+    Msys,
+}
+
+impl MakeFlavor {
+    /// Guess the flavor of the make `program`, either a path or a name
+    /// looked up in `PATH` like `std::process::Command` does.
+    ///
+    /// On windows, a program is deemed to be of MSYS2 or Cygwin if
+    /// `msys-2.0.dll` or `cygwin1.dll`, which all their programs link to,
+    /// is in the same directory. Elsewhere, and if the program is not
+    /// found, this returns [`MakeFlavor::Native`].
+    pub fn detect(program: impl AsRef<OsStr>) -> Self {
+        #[cfg(windows)]
+        return match find_program(program.as_ref()) {
+            Some(path) if is_msys_program(&path) => Self::Msys,
+            _ => Self::Native,
+        };
+
+        #[cfg(not(windows))]
+        {
+            let _ = program;
+            Self::Native
+        }
+    }
+}
+
+/// Resolve `program` to the path of its executable.
+#[cfg(windows)]
+fn find_program(program: &OsStr) -> Option<PathBuf> {
+    let candidates = |path: PathBuf| {
+        let exe = match path.extension() {
+            Some(_) => None,
+            None => Some(path.with_extension("exe")),
+        };
+        exe.into_iter().chain(Some(path))
+    };
+
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return candidates(path.into()).find(|path| path.is_file());
+    }
+
+    env::split_paths(&env::var_os("PATH")?)
+        .flat_map(|dir| candidates(dir.join(program)))
+        .find(|path| path.is_file())
+}
+
+#[cfg(windows)]
+fn is_msys_program(path: &Path) -> bool {
+    path.parent().map_or(false, |dir| {
+        ["msys-2.0.dll", "cygwin1.dll"]
+            .iter()
+            .any(|dll| dir.join(dll).is_file())
+    })
+}
@@ -0,0 +1,36 @@
+use std::ops;
+
+/// Runs `dropfn` on `value` once dropped, including on unwinding, while
+/// giving access to `value` in the meantime.
+pub(crate) struct Guard<T, F: FnOnce(T)> {
+    /// Only `None` while being dropped.
+    inner: Option<(T, F)>,
+}
+
+pub(crate) fn guard<T, F: FnOnce(T)>(value: T, dropfn: F) -> Guard<T, F> {
+    Guard {
+        inner: Some((value, dropfn)),
+    }
+}
+
+impl<T, F: FnOnce(T)> ops::Deref for Guard<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner.as_ref().unwrap().0
+    }
+}
+
+impl<T, F: FnOnce(T)> ops::DerefMut for Guard<T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner.as_mut().unwrap().0
+    }
+}
+
+impl<T, F: FnOnce(T)> Drop for Guard<T, F> {
+    fn drop(&mut self) {
+        if let Some((value, dropfn)) = self.inner.take() {
+            dropfn(value);
+        }
+    }
+}
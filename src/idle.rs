@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{imp, Client, ClientInner};
+
+/// Releases tokens held idle by [`Acquired`](crate::Acquired) guards, e.g.
+/// kept in caches, set with
+/// [`Client::set_idle_reclaim`](crate::Client::set_idle_reclaim).
+///
+/// A held token counts as idle once it has not been used, i.e. acquired or
+/// [touched](crate::Acquired::touch), for longer than the threshold. It is
+/// then released to the jobserver by a background thread, and the hook is
+/// called with the client, e.g. to let the owner know that it has to
+/// acquire a token again before its next use.
+///
+/// The hook is called on the background thread, so it should not block,
+/// e.g. on acquiring a token.
+#[derive(Clone)]
+pub struct IdleReclaim {
+    threshold: Duration,
+    hook: Option<Hook>,
+}
+
+type Hook = Arc<dyn Fn(&Client) + Send + Sync>;
+
+impl fmt::Debug for IdleReclaim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdleReclaim")
+            .field("threshold", &self.threshold)
+            .field("hook", &self.hook.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl IdleReclaim {
+    /// Reclaim tokens idle for longer than `threshold`.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            hook: None,
+        }
+    }
+
+    /// Call `hook` with the client whenever a token is reclaimed.
+    pub fn on_reclaim(mut self, hook: impl Fn(&Client) + Send + Sync + 'static) -> Self {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+}
+
+/// Bookkeeping of the held tokens of a [`Client`] tracked for
+/// [`IdleReclaim`].
+#[derive(Debug, Default)]
+pub(crate) struct IdleTokens {
+    /// Whether a policy is set, checked without locking on every acquire.
+    enabled: AtomicBool,
+    state: Mutex<IdleState>,
+    cvar: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct IdleState {
+    policy: Option<IdleReclaim>,
+    next_id: u64,
+    /// Tokens held and not reclaimed yet, along with the policy they are
+    /// tracked with and their last use.
+    held: HashMap<u64, Held>,
+    /// Whether a thread reclaiming idle tokens is running.
+    ///
+    /// It holds a strong reference to the client and exits once there
+    /// is no token held.
+    thread_running: bool,
+}
+
+#[derive(Debug)]
+struct Held {
+    policy: IdleReclaim,
+    last_use: Instant,
+    data: imp::Acquired,
+}
+
+impl Held {
+    fn deadline(&self) -> Instant {
+        self.last_use + self.policy.threshold
+    }
+}
+
+impl IdleTokens {
+    fn state(&self) -> MutexGuard<'_, IdleState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn set_policy(&self, policy: Option<IdleReclaim>) {
+        let mut state = self.state();
+        self.enabled.store(policy.is_some(), Ordering::Relaxed);
+        state.policy = policy;
+    }
+
+    /// Track the token `data` just acquired from `client`, returning its id
+    /// if a policy is set.
+    pub(crate) fn register(client: &Arc<ClientInner>, data: &imp::Acquired) -> Option<u64> {
+        let idle = &client.idle;
+        if !idle.enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let mut state = idle.state();
+        let policy = state.policy.clone()?;
+
+        if !state.thread_running {
+            let client = client.clone();
+            // Without the thread, the token is simply not tracked.
+            thread::Builder::new()
+                .name("jobslot-idle".into())
+                .spawn(move || reclaim_idle(&client))
+                .ok()?;
+            state.thread_running = true;
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.held.insert(
+            id,
+            Held {
+                policy,
+                last_use: Instant::now(),
+                data: data.clone(),
+            },
+        );
+        idle.cvar.notify_one();
+
+        Some(id)
+    }
+
+    /// Mark the token `id` as used now, returning false if it has been
+    /// reclaimed.
+    pub(crate) fn touch(&self, id: u64) -> bool {
+        match self.state().held.get_mut(&id) {
+            Some(held) => {
+                held.last_use = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn is_reclaimed(&self, id: u64) -> bool {
+        !self.state().held.contains_key(&id)
+    }
+
+    /// Stop tracking the token `id`, returning false if it has been
+    /// reclaimed, in which case it must not be released again.
+    pub(crate) fn unregister(&self, id: u64) -> bool {
+        self.state().held.remove(&id).is_some()
+    }
+}
+
+fn reclaim_idle(client: &Arc<ClientInner>) {
+    let idle = &client.idle;
+    let mut state = idle.state();
+
+    loop {
+        let now = Instant::now();
+
+        let (id, deadline) = match state
+            .held
+            .iter()
+            .map(|(id, held)| (*id, held.deadline()))
+            .min_by_key(|(_, deadline)| *deadline)
+        {
+            Some(next) => next,
+            None => break,
+        };
+
+        if deadline > now {
+            state = idle
+                .cvar
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        } else {
+            // Release it while holding the lock, so that the token is
+            // observed as reclaimed only once it is released.
+            let held = state.held.remove(&id).unwrap();
+            if let Err(err) = client.release_or_cover(Some(&held.data)) {
+                client.release_failed(err);
+            }
+
+            if let Some(hook) = held.policy.hook {
+                drop(state);
+                hook(&Client(client.clone()));
+                state = idle.state();
+            }
+        }
+    }
+
+    state.thread_running = false;
+}
@@ -0,0 +1,88 @@
+use std::{
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+/// Check through `/proc` that the process `pid` has access to the
+/// jobserver configured with `auth`, i.e. `R,W`: the auth is set in
+/// `CARGO_MAKEFLAGS` or `MAKEFLAGS` of its environment, and both fds refer
+/// to the same pipe as in this process.
+///
+/// Returns `Ok(Err(_))` if it did not inherit the jobserver, and `Err(_)`
+/// if that cannot be verified, e.g. since it changed its uid and `/proc`
+/// denies access to its environment. If it has exited already, there is
+/// nothing left to check, so this succeeds.
+pub(crate) fn verify(pid: u32, auth: &str) -> io::Result<io::Result<()>> {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+
+    let res = check(&proc_dir, pid, auth);
+    // A zombie has neither an environment nor fds left.
+    if !matches!(res, Ok(Ok(()))) && has_exited(&proc_dir)? {
+        return Ok(Ok(()));
+    }
+    res
+}
+
+fn check(proc_dir: &Path, pid: u32, auth: &str) -> io::Result<io::Result<()>> {
+    let not_inherited = |reason: String| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            format!("process {} did not inherit the jobserver: {}", pid, reason),
+        )
+    };
+
+    let environ = fs::read(proc_dir.join("environ"))?;
+    let flag = format!("--jobserver-auth={}", auth);
+    let configured = environ.split(|b| *b == 0).any(|var| {
+        let value = match var.iter().position(|b| *b == b'=') {
+            Some(i) if [&b"CARGO_MAKEFLAGS"[..], b"MAKEFLAGS"].contains(&&var[..i]) => {
+                &var[i + 1..]
+            }
+            _ => return false,
+        };
+        value
+            .split(|b| *b == b' ')
+            .any(|arg| arg == flag.as_bytes())
+    });
+    if !configured {
+        return Ok(Err(not_inherited(format!(
+            "`{}` is not in its environment",
+            flag
+        ))));
+    }
+
+    for fd in auth.split(',') {
+        let theirs = match fs::metadata(proc_dir.join("fd").join(fd)) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Err(not_inherited(format!("fd {} is not open", fd))))
+            }
+            Err(err) => return Err(err),
+        };
+        let ours = fs::metadata(Path::new("/proc/self/fd").join(fd))?;
+        if (theirs.dev(), theirs.ino()) != (ours.dev(), ours.ino()) {
+            return Ok(Err(not_inherited(format!(
+                "fd {} does not refer to the jobserver",
+                fd
+            ))));
+        }
+    }
+
+    Ok(Ok(()))
+}
+
+fn has_exited(proc_dir: &Path) -> io::Result<bool> {
+    let stat = match fs::read(proc_dir.join("stat")) {
+        Ok(stat) => stat,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(err) => return Err(err),
+    };
+    // The state follows the command name, which is in parentheses and
+    // might contain any character.
+    let state = stat
+        .iter()
+        .rposition(|b| *b == b')')
+        .and_then(|i| stat.get(i + 2));
+    Ok(matches!(state, Some(b'Z') | Some(b'X')))
+}
@@ -0,0 +1,111 @@
+use std::{
+    io,
+    sync::{Arc, Weak},
+    thread,
+    time::Duration,
+};
+
+use crate::{imp, sync::Mutex, ClientInner};
+
+/// Number of tokens written at once by [`Client::new_lazy`](crate::Client::new_lazy).
+pub(crate) const BATCH: usize = 128;
+
+/// How often the top-up thread checks the jobserver after writing tokens.
+const MIN_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How often the top-up thread checks the jobserver at most, doubling the
+/// interval from [`MIN_INTERVAL`] every time nothing needs to be written.
+const MAX_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Tokens of a jobserver created by [`Client::new_lazy`](crate::Client::new_lazy)
+/// which are not written to it yet.
+#[derive(Debug)]
+pub(crate) struct LazyTokens {
+    /// Number of tokens not issued when the jobserver is created, restored
+    /// by [`Client::reset`](crate::Client::reset).
+    total: usize,
+    unissued: Mutex<usize>,
+}
+
+impl LazyTokens {
+    pub(crate) fn new(total: usize) -> Self {
+        Self {
+            total,
+            unissued: Mutex::new(total),
+        }
+    }
+
+    pub(crate) fn total(&self) -> usize {
+        self.total
+    }
+
+    pub(crate) fn unissued(&self) -> usize {
+        *self.unissued.lock()
+    }
+
+    /// Stop issuing tokens, e.g. on shutdown.
+    pub(crate) fn clear(&self) {
+        *self.unissued.lock() = 0;
+    }
+
+    pub(crate) fn reset(&self) {
+        *self.unissued.lock() = self.total;
+    }
+
+    /// Write up to [`BATCH`] more tokens if less than half of that is
+    /// `available`, returning the number of tokens written.
+    pub(crate) fn top_up(&self, inner: &imp::Client, available: usize) -> io::Result<usize> {
+        if available >= BATCH / 2 {
+            return Ok(0);
+        }
+
+        let mut unissued = self.unissued.lock();
+        let n = (*unissued).min(BATCH - available);
+        if n > 0 {
+            inner.init(n)?;
+            *unissued -= n;
+        }
+        Ok(n)
+    }
+}
+
+/// Spawn a thread topping up the jobserver of `client` until the client
+/// is dropped.
+///
+/// Polling is needed since tokens might be taken by child processes
+/// without going through the client, while its own acquires top it up
+/// themselves, so the interval backs off while the jobserver has enough
+/// tokens, e.g. once all of them are written.
+pub(crate) fn spawn_top_up(client: &Arc<ClientInner>) -> io::Result<()> {
+    let client = Arc::downgrade(client);
+    thread::Builder::new()
+        .name("jobslot-lazy".into())
+        .spawn(move || top_up_loop(&client))
+        .map(drop)
+}
+
+fn top_up_loop(client: &Weak<ClientInner>) {
+    let mut interval = MIN_INTERVAL;
+    loop {
+        thread::sleep(interval);
+
+        let client = match client.upgrade() {
+            Some(client) => client,
+            None => break,
+        };
+        let lazy = match &client.lazy {
+            Some(lazy) => lazy,
+            None => break,
+        };
+
+        let written = client
+            .inner
+            .available()
+            .and_then(|available| lazy.top_up(&client.inner, available));
+        interval = match written {
+            Ok(0) => (interval * 2).min(MAX_INTERVAL),
+            Ok(_) => MIN_INTERVAL,
+            Err(_) => break,
+        };
+    }
+}
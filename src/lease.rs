@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{imp, ClientInner};
+
+/// Bookkeeping of the alive [`Lease`]s of a [`Client`](crate::Client).
+#[derive(Debug, Default)]
+pub(crate) struct Leases {
+    state: Mutex<LeasesState>,
+    cvar: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct LeasesState {
+    next_id: u64,
+    /// Deadlines of leases which are neither released nor expired.
+    deadlines: HashMap<u64, Instant>,
+    /// Whether a thread re-issuing tokens of expired leases is running.
+    ///
+    /// It holds a strong reference to the client and exits once there
+    /// is no lease left.
+    thread_running: bool,
+}
+
+impl Leases {
+    fn state(&self) -> MutexGuard<'_, LeasesState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A token acquired by [`Client::acquire_lease`](crate::Client::acquire_lease),
+/// which is re-issued to the jobserver if it is neither renewed nor
+/// released before its TTL runs out.
+///
+/// Once expired, dropping it no longer releases the token, since it has
+/// already been re-issued.
+pub struct Lease {
+    client: Arc<ClientInner>,
+    id: u64,
+    ttl: Duration,
+    data: imp::Acquired,
+}
+
+impl Lease {
+    pub(crate) fn new(
+        client: &Arc<ClientInner>,
+        data: imp::Acquired,
+        ttl: Duration,
+    ) -> std::io::Result<Self> {
+        let leases = &client.leases;
+        let mut state = leases.state();
+
+        if !state.thread_running {
+            let client = client.clone();
+            thread::Builder::new()
+                .name("jobslot-lease".into())
+                .spawn(move || reissue_expired(&client))?;
+            state.thread_running = true;
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.deadlines.insert(id, Instant::now() + ttl);
+        leases.cvar.notify_one();
+
+        Ok(Self {
+            client: client.clone(),
+            id,
+            ttl,
+            data,
+        })
+    }
+
+    /// Extend the lease by its TTL from now.
+    ///
+    /// Returns false if it has already expired, in which case the token
+    /// has been re-issued and should be considered lost.
+    pub fn renew(&self) -> bool {
+        let leases = &self.client.leases;
+        let mut state = leases.state();
+
+        match state.deadlines.get_mut(&self.id) {
+            Some(deadline) => {
+                *deadline = Instant::now() + self.ttl;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return true if the lease has expired and its token re-issued.
+    pub fn is_expired(&self) -> bool {
+        !self.client.leases.state().deadlines.contains_key(&self.id)
+    }
+}
+
+impl fmt::Debug for Lease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lease")
+            .field("id", &self.id)
+            .field("ttl", &self.ttl)
+            .field("expired", &self.is_expired())
+            .finish()
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let released = self
+            .client
+            .leases
+            .state()
+            .deadlines
+            .remove(&self.id)
+            .is_some();
+
+        if released {
+            drop(self.client.release_or_cover(Some(&self.data)));
+        }
+    }
+}
+
+fn reissue_expired(client: &ClientInner) {
+    let leases = &client.leases;
+    let mut state = leases.state();
+
+    loop {
+        let now = Instant::now();
+
+        let (id, deadline) = match state
+            .deadlines
+            .iter()
+            .min_by_key(|(_, deadline)| **deadline)
+        {
+            Some((id, deadline)) => (*id, *deadline),
+            None => break,
+        };
+
+        if deadline > now {
+            state = leases
+                .cvar
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        } else {
+            // Release it while holding the lock, so that the lease is
+            // observed as expired only once the token is re-issued.
+            state.deadlines.remove(&id);
+            drop(client.release_token(None));
+        }
+    }
+
+    state.thread_running = false;
+}
@@ -0,0 +1,194 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[cfg(target_os = "linux")]
+use crate::shm::Region;
+
+/// A file-backed record of how many tokens each process holds, set with
+/// [`Client::set_ledger`](crate::Client::set_ledger).
+///
+/// Each process has a file named after its pid in the ledger directory,
+/// which survives `exec`, so that the new image (or reclamation and
+/// auditing tools) can still attribute the tokens held by the process.
+///
+/// On Linux, a ledger can also live in a shared memory region created by
+/// [`Ledger::new_shared`], which avoids touching the filesystem on every
+/// acquire and release.
+///
+/// The pid is the only key, so use one directory per jobserver.
+#[derive(Clone, Debug)]
+pub struct Ledger {
+    dir: Arc<Path>,
+    #[cfg(target_os = "linux")]
+    shared: Option<Arc<Region>>,
+}
+
+impl Ledger {
+    /// Open the ledger in `dir`, creating the directory if it does not
+    /// exist.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            dir: dir.into(),
+            #[cfg(target_os = "linux")]
+            shared: None,
+        })
+    }
+
+    /// Create a ledger in a new sealed memfd, with room for `slots`
+    /// processes holding tokens at the same time.
+    ///
+    /// Other processes, e.g. the ones using the same jobserver or a
+    /// `status` tool, map it with [`Ledger::open_shared`] on the path
+    /// returned by [`Ledger::dir`], which stays valid as long as this
+    /// process is alive. The memfd has `CLOEXEC` set, so the region lives
+    /// only as long as one of the processes mapping it keeps it open.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the memfd cannot be created, e.g. on kernels
+    /// older than 3.17.
+    #[cfg(target_os = "linux")]
+    pub fn new_shared(slots: usize) -> io::Result<Self> {
+        let region = Region::create(slots)?;
+        Ok(Self {
+            dir: PathBuf::from(format!("/proc/{}/fd/{}", std::process::id(), region.fd())).into(),
+            shared: Some(Arc::new(region)),
+        })
+    }
+
+    /// Map the shared ledger at `path`, as returned by [`Ledger::dir`] of a
+    /// ledger created by [`Ledger::new_shared`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidData`] if `path` is
+    /// not a shared ledger.
+    #[cfg(target_os = "linux")]
+    pub fn open_shared(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        Ok(Self {
+            dir: path.into(),
+            shared: Some(Arc::new(Region::open(path)?)),
+        })
+    }
+
+    /// Return the directory of the ledger, or the path to open a shared
+    /// ledger from other processes.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, pid: u32) -> PathBuf {
+        self.dir.join(pid.to_string())
+    }
+
+    /// Return how many tokens the process `pid` holds according to the
+    /// ledger.
+    pub fn held(&self, pid: u32) -> io::Result<usize> {
+        #[cfg(target_os = "linux")]
+        if let Some(shared) = &self.shared {
+            return Ok(shared.held(pid));
+        }
+
+        match fs::read_to_string(self.path(pid)) {
+            Ok(held) => held
+                .trim()
+                .parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Return the pids of all processes holding tokens according to the
+    /// ledger along with how many tokens they hold.
+    ///
+    /// Entries of processes which have exited are kept until removed with
+    /// [`Ledger::remove`], e.g. after reclaiming their tokens.
+    pub fn entries(&self) -> io::Result<Vec<(u32, usize)>> {
+        #[cfg(target_os = "linux")]
+        if let Some(shared) = &self.shared {
+            let mut entries = shared.entries();
+            entries.sort_unstable();
+            return Ok(entries);
+        }
+
+        let mut entries = Vec::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            // Skip temporary files written by `Ledger::record`.
+            let pid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            };
+
+            match self.held(pid)? {
+                0 => (),
+                held => entries.push((pid, held)),
+            }
+        }
+
+        entries.sort_unstable();
+
+        Ok(entries)
+    }
+
+    /// Return the entries of processes which have exited, e.g. crashed,
+    /// without returning their tokens, so that they can be reclaimed by
+    /// releasing them and removing the entries with [`Ledger::remove`].
+    ///
+    /// Note that the pid of an exited process might be reused by another
+    /// one, whose entry is then not returned.
+    #[cfg(unix)]
+    pub fn stale_entries(&self) -> io::Result<Vec<(u32, usize)>> {
+        use std::convert::TryInto;
+
+        let mut entries = self.entries()?;
+        entries.retain(|(pid, _)| match (*pid).try_into() {
+            Ok(pid) => {
+                let res = unsafe { libc::kill(pid, 0) };
+                res == -1 && io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
+            }
+            Err(_) => false,
+        });
+        Ok(entries)
+    }
+
+    /// Remove the entry of the process `pid`.
+    pub fn remove(&self, pid: u32) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(shared) = &self.shared {
+            shared.remove(pid);
+            return Ok(());
+        }
+
+        match fs::remove_file(self.path(pid)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn record(&self, pid: u32, held: usize) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(shared) = &self.shared {
+            return shared.record(pid, held);
+        }
+
+        if held == 0 {
+            return self.remove(pid);
+        }
+
+        // Write to a temporary file and rename it, so that readers never
+        // see a partially written entry.
+        let tmp = self.dir.join(format!("{}.tmp", pid));
+        fs::write(&tmp, held.to_string())?;
+        fs::rename(tmp, self.path(pid))
+    }
+}
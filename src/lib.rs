@@ -112,6 +112,46 @@
 //! compatible with `make` on Windows. It is, however, compatible with
 //! `mingw32-make`.
 //!
+//! To run either, use [`MakeFlavor::detect`] and
+//! [`Client::configure_make_and_run_for`], which only passes `-jN` to the
+//! `make` of MSYS2 or Cygwin.
+//!
+//! ## WASI caveats
+//!
+//! On WASI, [`Client::new`] creates a jobserver that only works within the
+//! current process, since spawning processes is not supported.
+//!
+//! [`Client::from_env`] can however connect to a jobserver shared by the
+//! host runtime, specified as `--jobserver-auth=fifo:PATH` with `PATH` in a
+//! preopened directory or as `--jobserver-auth=R,W` with descriptors given
+//! to the module. Waiting for its tokens uses `poll`, which is implemented
+//! with pollables on WASI preview 2, and tokens are read with `O_NONBLOCK`
+//! set, only while reading for descriptors given to the module, so
+//! [`TryAcquireClient::try_acquire`] never blocks. Async acquires are woken
+//! up by a helper thread polling the jobserver. Where threads cannot be
+//! spawned, e.g. on `wasm32-wasip1`, every poll waits up to 10ms for a
+//! token instead, before the task is woken up to poll it again.
+//! [`Client::available`] returns an error of kind
+//! [`io::ErrorKind::Unsupported`] for such a jobserver.
+//!
+//! ## Web workers
+//!
+//! On `wasm32-unknown-unknown`, the `web-workers` feature adds
+//! `Client::new_with_shared_array_buffer`, a jobserver whose tokens are
+//! counted in a `SharedArrayBuffer`. Posting it to other web workers, which
+//! connect with `Client::from_shared_array_buffer`, limits parallelism
+//! across all of them, e.g. for build tools running in a playground.
+//! Blocking acquires use `Atomics.wait`, so they fail on the main thread of
+//! the page, where `AsyncAcquireClient` waits with `Atomics.waitAsync`
+//! instead.
+//!
+//! ## QNX caveats
+//!
+//! QNX Neutrino uses the unix implementation, with its `poll` quirks taken
+//! into account. CI builds it for every change, and runs the tests on a QNX
+//! system through a self-hosted runner, see `qnx-runner.sh`, since there is
+//! no hosted QNX runner.
+//!
 //! [docs]: http://make.mad-scientist.net/papers/jobserver-implementation/
 
 #![deny(missing_docs, missing_debug_implementations)]
@@ -122,12 +162,22 @@
 use std::{
     env,
     error::Error as StdError,
-    ffi, fmt, io, ops, process,
-    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    ffi, fmt,
+    future::Future,
+    io, mem, ops,
+    pin::Pin,
+    process, ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
+use std::collections::{HashMap, VecDeque};
+
 use cfg_if::cfg_if;
-use scopeguard::{guard, ScopeGuard};
 
 cfg_if! {
     if #[cfg(unix)] {
@@ -142,8 +192,125 @@ cfg_if! {
     }
 }
 
+#[cfg(feature = "jobserver-compat")]
+pub mod compat;
+
+pub mod build_script;
+
+pub mod protocol;
+
+mod linger;
+use linger::Linger;
+
+mod ledger;
+pub use ledger::Ledger;
+#[cfg(target_os = "linux")]
+mod shm;
+
+mod lease;
+pub use lease::Lease;
+use lease::Leases;
+
+#[cfg(unix)]
+mod preempt;
+
+mod pools;
+pub use pools::{PoolTokens, Pools};
+
+mod limit;
+pub use limit::{limit_from_env, limit_from_vars};
+
+mod trace;
+pub use trace::TraceRecorder;
+
+mod events;
+pub use events::EventSink;
+
+#[cfg(feature = "prometheus")]
+mod metrics;
+
+mod watch;
+pub use watch::AvailabilityWatch;
+
+mod stats;
+pub use stats::Stats;
+
+mod observer;
+pub use observer::Observer;
+
+mod report;
+pub use report::{AcquireBackend, AcquirePath, AcquireReport};
+
+mod worker;
+pub use worker::WorkerTokens;
+
+mod backoff;
+pub use backoff::Backoff;
+
+mod release;
+pub use release::{ReleaseError, ReleasePolicy};
+
+mod abandoned;
+pub use abandoned::AbandonedPolicy;
+
+#[cfg(target_os = "linux")]
+mod inherit;
+
+mod idle;
+pub use idle::IdleReclaim;
+use idle::IdleTokens;
+
+mod flavor;
+pub use flavor::MakeFlavor;
+
+#[cfg(any(unix, windows))]
+mod wsl;
+#[cfg(any(unix, windows))]
+pub use wsl::WslBridge;
+
+pub mod sync;
+
+mod semaphore;
+
+mod guard;
+use guard::{guard, Guard};
+#[cfg(not(windows))]
+mod burst;
+#[cfg(unix)]
+mod lazy;
+use stats::StatsRecorder;
+
+#[cfg(unix)]
+mod daemon;
+#[cfg(unix)]
+pub use daemon::Daemon;
+
+#[cfg(unix)]
+mod spawn;
+#[cfg(unix)]
+pub use spawn::SpawnConfig;
+
+#[cfg(target_os = "linux")]
+mod affinity;
+#[cfg(target_os = "linux")]
+pub use affinity::CpuSet;
+
+#[cfg(target_os = "linux")]
+mod pressure;
+#[cfg(target_os = "linux")]
+pub use pressure::PressureGovernor;
+
 #[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
 mod async_client;
+#[cfg(all(feature = "tokio", any(target_os = "illumos", target_os = "solaris")))]
+mod event_port;
+#[cfg(all(
+    feature = "web-workers",
+    target_family = "wasm",
+    target_os = "unknown",
+    not(target_feature = "atomics")
+))]
+mod web_workers;
 #[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
 pub use async_client::AsyncAcquireClient;
 
@@ -158,6 +325,16 @@ pub trait Command {
     /// Removes an environment variable mapping.
     fn env_remove<K: AsRef<ffi::OsStr>>(&mut self, key: K) -> &mut Self;
 
+    /// Returns true if the environment variable `key` is explicitly set or
+    /// removed for the command, so that it is left alone, e.g. `MAKELEVEL`
+    /// with [`Client::set_increment_makelevel`].
+    ///
+    /// The default implementation returns false.
+    fn has_env(&self, key: &ffi::OsStr) -> bool {
+        let _ = key;
+        false
+    }
+
     /// Schedules a closure to be run just before the exec function is invoked.
     ///
     /// Check [`std::os::unix::process::CommandExt::pre_exec`]
@@ -184,6 +361,10 @@ impl Command for process::Command {
         process::Command::env_remove(self, key.as_ref())
     }
 
+    fn has_env(&self, key: &ffi::OsStr) -> bool {
+        self.get_envs().any(|(k, _)| k == key)
+    }
+
     #[cfg(unix)]
     unsafe fn pre_exec<F>(&mut self, f: F) -> &mut Self
     where
@@ -207,6 +388,10 @@ impl Command for tokio::process::Command {
         tokio::process::Command::env_remove(self, key.as_ref())
     }
 
+    fn has_env(&self, key: &ffi::OsStr) -> bool {
+        self.as_std().has_env(key)
+    }
+
     #[cfg(unix)]
     unsafe fn pre_exec<F>(&mut self, f: F) -> &mut Self
     where
@@ -230,6 +415,10 @@ impl<T: Command> Command for &mut T {
         self
     }
 
+    fn has_env(&self, key: &ffi::OsStr) -> bool {
+        (**self).has_env(key)
+    }
+
     #[cfg(unix)]
     unsafe fn pre_exec<F>(&mut self, f: F) -> &mut Self
     where
@@ -240,12 +429,240 @@ impl<T: Command> Command for &mut T {
     }
 }
 
-/// Returns RAII to ensure env_remove is called on unwinding
+/// Command that accepts arguments, for
+/// [`Client::configure_and_run_with_jobs_arg`].
+pub trait ArgCommand: Command {
+    /// Adds an argument to pass to the program.
+    fn arg<S: AsRef<ffi::OsStr>>(&mut self, arg: S) -> &mut Self;
+}
+
+impl ArgCommand for process::Command {
+    fn arg<S: AsRef<ffi::OsStr>>(&mut self, arg: S) -> &mut Self {
+        process::Command::arg(self, arg)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl ArgCommand for tokio::process::Command {
+    fn arg<S: AsRef<ffi::OsStr>>(&mut self, arg: S) -> &mut Self {
+        tokio::process::Command::arg(self, arg)
+    }
+}
+
+impl<T: ArgCommand> ArgCommand for &mut T {
+    fn arg<S: AsRef<ffi::OsStr>>(&mut self, arg: S) -> &mut Self {
+        (*self).arg(arg);
+        self
+    }
+}
+
+/// Command that can be spawned by [`Client::configure_and_spawn`] and
+/// [`Client::configure_and_output`].
+pub trait SpawnCommand: Command {
+    /// The spawned child process.
+    type Child;
+
+    /// The output of the child process.
+    type Output;
+
+    /// Spawns the command as a child process.
+    fn spawn_child(&mut self) -> io::Result<Self::Child>;
+
+    /// Spawns the command as a child process and collects its output.
+    ///
+    /// The child process must be spawned before this returns, since the
+    /// environment set up by [`Client`] is removed afterwards.
+    fn spawn_output(&mut self) -> io::Result<Self::Output>;
+
+    /// Returns the process id of `child`, if it is still known, for
+    /// [`Client::set_verify_inheritance`].
+    fn child_id(child: &Self::Child) -> Option<u32> {
+        let _ = child;
+        None
+    }
+
+    /// Kills `child`, which turned out not to have access to the jobserver
+    /// with [`Client::set_verify_inheritance`], and reaps it if possible.
+    fn kill_child(child: Self::Child) {
+        let _ = child;
+    }
+}
+
+impl SpawnCommand for process::Command {
+    type Child = process::Child;
+    type Output = process::Output;
+
+    fn spawn_child(&mut self) -> io::Result<process::Child> {
+        self.spawn()
+    }
+
+    fn spawn_output(&mut self) -> io::Result<process::Output> {
+        self.output()
+    }
+
+    fn child_id(child: &process::Child) -> Option<u32> {
+        Some(child.id())
+    }
+
+    fn kill_child(mut child: process::Child) {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// The output is a future of the child process spawned eagerly, with
+/// stdout and stderr piped just like [`tokio::process::Command::output`].
+///
+/// Like tokio's own `output`, this replaces any stdout or stderr set on
+/// the command, since neither tokio nor std exposes what has been set.
+/// Use [`Client::configure_and_spawn`] and
+/// [`tokio::process::Child::wait_with_output`] to keep them.
+#[cfg(feature = "tokio")]
+impl SpawnCommand for tokio::process::Command {
+    type Child = tokio::process::Child;
+    type Output =
+        std::pin::Pin<Box<dyn std::future::Future<Output = io::Result<process::Output>> + Send>>;
+
+    fn spawn_child(&mut self) -> io::Result<tokio::process::Child> {
+        self.spawn()
+    }
+
+    fn spawn_output(&mut self) -> io::Result<Self::Output> {
+        let child = self
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()?;
+        Ok(Box::pin(child.wait_with_output()))
+    }
+
+    fn child_id(child: &tokio::process::Child) -> Option<u32> {
+        child.id()
+    }
+
+    /// The child is reaped in the background by tokio once dropped.
+    fn kill_child(mut child: tokio::process::Child) {
+        let _ = child.start_kill();
+    }
+}
+
+/// A target for the configuration of a child process, for spawning
+/// frameworks which do not build on [`Command`], see
+/// [`Client::configure_into`].
+pub trait EnvConsumer {
+    /// Set the environment variable `key` to `value`.
+    fn set(&mut self, key: &ffi::OsStr, value: &ffi::OsStr);
+
+    /// Remove the environment variable `key`.
+    fn unset(&mut self, key: &ffi::OsStr);
+
+    /// The child process needs `fd` to be inherited.
+    ///
+    /// The default implementation does nothing, in which case the caller
+    /// is responsible for making the fds inheritable, e.g. using
+    /// [`Client::set_inheritable`].
+    #[cfg(unix)]
+    fn needs_fd(&mut self, fd: std::os::unix::io::RawFd) {
+        let _ = fd;
+    }
+}
+
+impl EnvConsumer for HashMap<ffi::OsString, ffi::OsString> {
+    fn set(&mut self, key: &ffi::OsStr, value: &ffi::OsStr) {
+        self.insert(key.to_owned(), value.to_owned());
+    }
+
+    fn unset(&mut self, key: &ffi::OsStr) {
+        self.remove(key);
+    }
+}
+
+impl EnvConsumer for Vec<(ffi::OsString, ffi::OsString)> {
+    fn set(&mut self, key: &ffi::OsStr, value: &ffi::OsStr) {
+        match self.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_owned(),
+            None => self.push((key.to_owned(), value.to_owned())),
+        }
+    }
+
+    fn unset(&mut self, key: &ffi::OsStr) {
+        self.retain(|(k, _)| k != key);
+    }
+}
+
+impl<T: EnvConsumer + ?Sized> EnvConsumer for &mut T {
+    fn set(&mut self, key: &ffi::OsStr, value: &ffi::OsStr) {
+        (**self).set(key, value)
+    }
+
+    fn unset(&mut self, key: &ffi::OsStr) {
+        (**self).unset(key)
+    }
+
+    #[cfg(unix)]
+    fn needs_fd(&mut self, fd: std::os::unix::io::RawFd) {
+        (**self).needs_fd(fd)
+    }
+}
+
+/// Parse the last `-jN`, `--jobs=N` or `--jobs N` in `makeflags`, ignoring
+/// a bare `-j` or `--jobs`.
+fn parse_jobs<'a>(makeflags: impl Iterator<Item = &'a [u8]>) -> Option<usize> {
+    let parse = |jobs: &[u8]| -> Option<usize> {
+        std::str::from_utf8(jobs)
+            .ok()?
+            .parse()
+            .ok()
+            .filter(|jobs| *jobs > 0)
+    };
+
+    let mut makeflags = makeflags.filter(|flag| !flag.is_empty()).peekable();
+    let mut last = None;
+    while let Some(flag) = makeflags.next() {
+        let jobs = if flag == b"--jobs" {
+            // Only take the next word if it is the number of jobs.
+            makeflags.next_if(|next| parse(next).is_some())
+        } else {
+            flag.strip_prefix(b"--jobs=")
+                .or_else(|| flag.strip_prefix(b"-j"))
+        };
+
+        if let Some(jobs) = jobs.and_then(parse) {
+            last = Some(jobs);
+        }
+    }
+    last
+}
+
+/// Names of named jobservers end up in paths and names of kernel objects,
+/// so only allow characters which are safe in both.
+#[cfg(any(unix, windows))]
+fn check_jobserver_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid jobserver name {:?}", name),
+        ))
+    }
+}
+
+/// Set `envs` to `value` and, if given, `MAKELEVEL` to `makelevel` in
+/// `cmd`, until the returned guard is dropped. `MAKELEVEL` is left alone
+/// if the caller has set it for `cmd`.
 fn setup_envs<'a, Cmd>(
     mut cmd: Cmd,
     envs: &'a [&'a str],
     value: &ffi::OsStr,
-) -> ScopeGuard<Cmd, impl FnOnce(Cmd) + 'a>
+    makelevel: Option<ffi::OsString>,
+) -> Guard<Cmd, impl FnOnce(Cmd) + 'a>
 where
     Cmd: Command,
 {
@@ -253,12 +670,20 @@ where
     for env in envs {
         cmd.env(env, value);
     }
+    let makelevel = makelevel.filter(|_| !cmd.has_env("MAKELEVEL".as_ref()));
+    let set_makelevel = makelevel.is_some();
+    if let Some(makelevel) = makelevel {
+        cmd.env("MAKELEVEL", makelevel);
+    }
 
     // Use RAII to ensure env_remove is called on unwinding
     guard(cmd, move |mut cmd| {
         for env in envs {
             cmd.env_remove(env);
         }
+        if set_makelevel {
+            cmd.env_remove("MAKELEVEL");
+        }
     })
 }
 
@@ -266,257 +691,2360 @@ where
 struct ClientInner {
     inner: imp::Client,
     #[cfg(unix)]
-    acitve_try_acquire_client_count: Mutex<usize>,
+    acitve_try_acquire_client_count: sync::Mutex<usize>,
+    borrowed: sync::Mutex<BorrowedSlots>,
+    linger: Linger,
+    leases: Leases,
+    /// Held tokens tracked for [`Client::set_idle_reclaim`].
+    idle: IdleTokens,
+    /// Tokens the jobserver is created with if this client created it, for
+    /// [`Client::reset`].
+    initial_tokens: Option<Box<[u8]>>,
+    /// The `N` of `-jN` exported to children, see [`Client::jobs`].
+    jobs: Option<usize>,
+    /// Tokens not written yet, set by [`Client::new_lazy`].
+    #[cfg(unix)]
+    lazy: Option<lazy::LazyTokens>,
+    /// Recent snapshots of [`Client::available`], for smoothing
+    /// [`Client::recommended_jobs`].
+    availability: sync::Mutex<VecDeque<(Instant, usize)>>,
+    /// Set by [`Client::set_increment_makelevel`].
+    increment_makelevel: AtomicBool,
+    /// Set by [`Client::set_verify_inheritance`].
+    #[cfg(target_os = "linux")]
+    verify_inheritance: AtomicBool,
+    /// Set once the jobserver is passed to other programs, see
+    /// [`Client::shutdown`].
+    #[cfg(unix)]
+    exported: AtomicBool,
+    /// Tokens acquired by [`Client::acquire_raw`] and not yet released,
+    /// tracked only in the strict mode set by [`Client::set_strict`].
+    raw_acquired: sync::Mutex<Option<Vec<imp::Acquired>>>,
+    /// Set by [`Client::set_ledger`], along with the number of tokens held
+    /// by this process.
+    ledger: sync::Mutex<Option<(Ledger, usize)>>,
+    /// Set by [`Client::set_trace_recorder`].
+    trace: sync::Mutex<Option<TraceRecorder>>,
+    /// Set by [`Client::set_event_sink`].
+    events: sync::Mutex<Option<EventSink>>,
+    #[cfg(feature = "prometheus")]
+    metrics: metrics::Metrics,
+    stats: StatsRecorder,
+    /// Set by [`Client::set_pressure_governor`].
+    #[cfg(target_os = "linux")]
+    pressure: sync::Mutex<Option<PressureGovernor>>,
+    /// Set by [`Client::set_release_policy`].
+    release_policy: sync::Mutex<ReleasePolicy>,
+    /// Error kept by [`ReleasePolicy::Propagate`].
+    release_error: sync::Mutex<Option<ReleaseError>>,
+    /// Set by [`Client::new_with_cpusets`], keyed by token byte.
+    #[cfg(target_os = "linux")]
+    cpusets: HashMap<u8, CpuSet>,
+    /// Assigned by the first call of [`Client::id`], or 0.
+    id: AtomicUsize,
 }
 
-impl ClientInner {
-    #[cfg(unix)]
-    fn acitve_try_acquire_client_count(&self) -> MutexGuard<'_, usize> {
-        self.acitve_try_acquire_client_count
-            .lock()
-            .unwrap_or_else(PoisonError::into_inner)
+/// Slots handed out by [`Client::acquire_or_proceed`] without a token.
+#[derive(Debug, Default)]
+struct BorrowedSlots {
+    /// Number of alive [`Borrowed`] not backed by any token.
+    uncovered: usize,
+    /// Number of tokens kept on behalf of alive [`Borrowed`], instead of
+    /// being released.
+    covered: usize,
+}
+
+/// How a blocking acquire waits, so that the delays added by
+/// [`ClientInner::acquire_with`] end as early as the acquire itself.
+#[derive(Clone, Copy, Debug, Default)]
+struct Wait<'a> {
+    /// Give up at this point, see [`Client::acquire_or_proceed`].
+    deadline: Option<Instant>,
+    /// Give up once cancelled, see [`Client::acquire_with_cancel`].
+    cancel: Option<&'a CancellationFlag>,
+    /// Give up on signals, see [`Client::acquire_interruptible`].
+    interruptible: bool,
+}
+
+/// A token obtained by [`ClientInner::acquire_with`].
+enum Obtained {
+    /// The token kept by [`Client::set_linger`].
+    Lingered(imp::Acquired),
+    /// A token acquired from the jobserver.
+    Acquired(imp::Acquired),
+}
+
+impl Obtained {
+    fn into_data(self) -> imp::Acquired {
+        match self {
+            Self::Lingered(data) | Self::Acquired(data) => data,
+        }
     }
 }
 
-/// A client of a jobserver
-///
-/// This structure is the main type exposed by this library, and is where
-/// interaction to a jobserver is configured through. Clients are either created
-/// from scratch in which case the internal semphore is initialied on the spot,
-/// or a client is created from the environment to connect to a jobserver
-/// already created.
-///
-/// Some usage examples can be found in the crate documentation for using a
-/// client.
-///
-/// Note that a `Client` implements the `Clone` trait, and all instances of a
-/// `Client` refer to the same jobserver instance.
-#[derive(Clone, Debug)]
-pub struct Client(Arc<ClientInner>);
+impl ClientInner {
+    fn new(inner: imp::Client) -> Self {
+        Self {
+            inner,
+            #[cfg(unix)]
+            acitve_try_acquire_client_count: sync::Mutex::default(),
+            borrowed: sync::Mutex::default(),
+            linger: Linger::default(),
+            leases: Leases::default(),
+            idle: IdleTokens::default(),
+            initial_tokens: None,
+            jobs: None,
+            #[cfg(unix)]
+            lazy: None,
+            availability: sync::Mutex::default(),
+            increment_makelevel: AtomicBool::new(false),
+            #[cfg(target_os = "linux")]
+            verify_inheritance: AtomicBool::new(false),
+            #[cfg(unix)]
+            exported: AtomicBool::new(false),
+            raw_acquired: sync::Mutex::default(),
+            ledger: sync::Mutex::default(),
+            trace: sync::Mutex::default(),
+            events: sync::Mutex::default(),
+            #[cfg(feature = "prometheus")]
+            metrics: metrics::Metrics::default(),
+            stats: StatsRecorder::default(),
+            #[cfg(target_os = "linux")]
+            pressure: sync::Mutex::default(),
+            release_policy: sync::Mutex::default(),
+            release_error: sync::Mutex::default(),
+            #[cfg(target_os = "linux")]
+            cpusets: HashMap::new(),
+            id: AtomicUsize::new(0),
+        }
+    }
 
-impl Client {
-    /// Creates a new jobserver initialized with the given parallelism limit.
-    ///
-    /// A client to the jobserver created will be returned. This client will
-    /// allow at most `limit` tokens to be acquired from it in parallel. More
-    /// calls to `acquire` will cause the calling thread to block.
-    ///
-    /// Note that the created `Client` is not automatically inherited into
-    /// spawned child processes from this program. Manual usage of the
-    /// `configure` function is required for a child process to have access to a
-    /// job server.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use jobslot::Client;
-    ///
-    /// let client = Client::new(4).expect("failed to create jobserver");
-    /// ```
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if any I/O error happens when attempting to create the
-    /// jobserver client.
-    pub fn new(limit: usize) -> io::Result<Self> {
-        imp::Client::new(limit).map(Self::new_inner)
+    /// See [`Client::id`].
+    fn id(&self) -> usize {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+        /// Ids of the jobservers seen so far, keyed by
+        /// `imp::Client::jobserver_key`.
+        static IDS: sync::Mutex<Option<HashMap<String, usize>>> = sync::Mutex::new(None);
+
+        let id = self.id.load(Ordering::Acquire);
+        if id != 0 {
+            return id;
+        }
+
+        let new_id = || NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let id = match self.inner.jobserver_key() {
+            Some(key) => *IDS
+                .lock()
+                .get_or_insert_with(HashMap::new)
+                .entry(key)
+                .or_insert_with(new_id),
+            None => new_id(),
+        };
+
+        // Clones racing to assign it must agree on one.
+        match self
+            .id
+            .compare_exchange(0, id, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => id,
+            Err(assigned) => assigned,
+        }
     }
 
-    /// Same as [`Client::new`] except that it will create a named fifo on
-    /// unix so that you can use [`Client::configure_and_run_with_fifo`] or
-    /// [`Client::configure_make_and_run_with_fifo`] to pass the fifo
-    /// instead of fds.
-    pub fn new_with_fifo(limit: usize) -> io::Result<Self> {
+    /// Return the tokens available in the jobserver, plus the ones not
+    /// written yet by [`Client::new_lazy`] after topping it up.
+    fn available(&self) -> io::Result<usize> {
+        let available = self.inner.available()?;
+
         #[cfg(unix)]
-        {
-            imp::Client::new_fifo(limit).map(Self::new_inner)
+        if let Some(lazy) = &self.lazy {
+            let issued = lazy.top_up(&self.inner, available)?;
+            return Ok(available + issued + lazy.unissued());
         }
-        #[cfg(not(unix))]
+
+        Ok(available)
+    }
+
+    /// Same as `available`, but without writing any of the tokens of
+    /// [`Client::new_lazy`], nor taking a token for a moment on windows,
+    /// for [`Observer::available`].
+    fn peek_available(&self) -> io::Result<usize> {
+        #[cfg(windows)]
+        let available = self.inner.peek_available()?;
+        #[cfg(not(windows))]
+        let available = self.inner.available()?;
+
+        #[cfg(unix)]
+        if let Some(lazy) = &self.lazy {
+            return Ok(available + lazy.unissued());
+        }
+
+        Ok(available)
+    }
+
+    /// Obtain a token for any kind of acquire: the one kept by
+    /// [`Client::set_linger`] if any, otherwise one acquired from the
+    /// jobserver with `acquire`, which returns `None` if it gives up, e.g.
+    /// at a deadline or if no token is available right now.
+    ///
+    /// Blocking acquires pass `wait`, to be delayed under pressure first.
+    fn acquire_with(
+        &self,
+        started: Instant,
+        wait: Option<Wait<'_>>,
+        acquire: impl FnOnce(&imp::Client) -> io::Result<Option<imp::Acquired>>,
+    ) -> io::Result<Option<Obtained>> {
+        if let Some(data) = self.linger.take() {
+            return Ok(Some(Obtained::Lingered(data)));
+        }
+
+        self.take_release_error()?;
+
+        // Top the jobserver of `Client::new_lazy` up right away instead of
+        // waiting for its helper thread, whose polling backs off. An error
+        // only means that tokens cannot be counted, which stops the helper
+        // thread too.
+        #[cfg(unix)]
+        if let Some(lazy) = &self.lazy {
+            if lazy.unissued() > 0 {
+                let _ = self.available();
+            }
+        }
+
+        let res = match wait {
+            Some(wait) => self.throttle(wait).and_then(|()| acquire(&self.inner)),
+            None => acquire(&self.inner),
+        };
+
+        match res {
+            Ok(Some(data)) => {
+                self.record_acquire(started);
+                Ok(Some(Obtained::Acquired(data)))
+            }
+            Ok(None) => Ok(None),
+            Err(err) => Err(self.record_failure(err)),
+        }
+    }
+
+    /// Same as [`ClientInner::acquire_with`], for blocking acquires which
+    /// never give up.
+    fn acquire_blocking(
+        &self,
+        wait: Wait<'_>,
+        acquire: impl FnOnce(&imp::Client) -> io::Result<imp::Acquired>,
+    ) -> io::Result<imp::Acquired> {
+        let obtained =
+            self.acquire_with(Instant::now(), Some(wait), |inner| acquire(inner).map(Some))?;
+        Ok(obtained
+            .expect("blocking acquires never give up")
+            .into_data())
+    }
+
+    /// Delay an acquire while under pressure, see
+    /// [`Client::set_pressure_governor`], for no longer than the acquire
+    /// would wait.
+    fn throttle(&self, wait: Wait<'_>) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
         {
-            Self::new(limit)
+            let governor = self.pressure.lock().clone();
+            if let Some(governor) = governor {
+                return governor.throttle(wait.deadline, |delay| {
+                    imp::sleep(delay, wait.cancel.map(|flag| &flag.0), wait.interruptible)?;
+                    match wait.cancel {
+                        Some(flag) if flag.is_cancelled() => Err(cancelled()),
+                        _ => Ok(()),
+                    }
+                });
+            }
         }
+
+        #[cfg(not(target_os = "linux"))]
+        let _ = (wait.deadline, wait.cancel, wait.interruptible);
+
+        Ok(())
     }
 
-    fn new_inner(inner: imp::Client) -> Self {
-        Self(Arc::new(ClientInner {
-            inner,
-            #[cfg(unix)]
-            acitve_try_acquire_client_count: Mutex::default(),
-        }))
+    /// Handle an error releasing a token on drop, see
+    /// [`Client::set_release_policy`].
+    fn release_failed(&self, err: io::Error) {
+        let policy = self.release_policy.lock().clone();
+        let err = ReleaseError::new(err);
+        match policy {
+            ReleasePolicy::Ignore => (),
+            ReleasePolicy::Hook(hook) => hook(&err),
+            ReleasePolicy::Propagate => {
+                self.release_error.lock().get_or_insert(err);
+            }
+        }
     }
 
-    /// Attempts to connect to the jobserver specified in this process's
-    /// environment.
+    /// Return the error kept by [`ReleasePolicy::Propagate`], if any.
+    fn take_release_error(&self) -> io::Result<()> {
+        match self.release_error.lock().take() {
+            Some(err) => Err(err.into()),
+            None => Ok(()),
+        }
+    }
+
+    fn borrowed(&self) -> sync::MutexGuard<'_, BorrowedSlots> {
+        self.borrowed.lock()
+    }
+
+    fn raw_acquired(&self) -> sync::MutexGuard<'_, Option<Vec<imp::Acquired>>> {
+        self.raw_acquired.lock()
+    }
+
+    fn ledger(&self) -> sync::MutexGuard<'_, Option<(Ledger, usize)>> {
+        self.ledger.lock()
+    }
+
+    fn update_ledger(&self, f: impl FnOnce(usize) -> usize) {
+        if let Some((ledger, held)) = &mut *self.ledger() {
+            *held = f(*held);
+            // The ledger is for auditing only, so do not fail on it.
+            drop(ledger.record(process::id(), *held));
+        }
+    }
+
+    fn trace(&self) -> sync::MutexGuard<'_, Option<TraceRecorder>> {
+        self.trace.lock()
+    }
+
+    fn events(&self) -> sync::MutexGuard<'_, Option<EventSink>> {
+        self.events.lock()
+    }
+
+    /// Record a token acquired from the jobserver after waiting since
+    /// `started` in the ledger, the trace and the event sink.
+    fn record_acquire(&self, started: Instant) {
+        self.update_ledger(|held| held + 1);
+        if let Some(trace) = &*self.trace() {
+            trace.record_acquire(started);
+        }
+        if let Some(events) = &*self.events() {
+            events.acquire(started.elapsed());
+        }
+        self.stats.acquired(started.elapsed());
+        #[cfg(feature = "prometheus")]
+        self.metrics.acquired(started.elapsed());
+    }
+
+    /// Record a failed acquire in the metrics, returning `err`.
+    fn record_failure(&self, err: io::Error) -> io::Error {
+        #[cfg(feature = "prometheus")]
+        self.metrics.failed();
+        err
+    }
+
+    /// Write a token back to the jobserver and record it in the ledger, the
+    /// trace and the event sink.
+    fn release_token(&self, data: Option<&imp::Acquired>) -> io::Result<()> {
+        self.inner.release(data)?;
+        self.update_ledger(|held| held.saturating_sub(1));
+        if let Some(trace) = &*self.trace() {
+            trace.record_release();
+        }
+        if let Some(events) = &*self.events() {
+            events.release();
+        }
+        self.stats.released();
+        #[cfg(feature = "prometheus")]
+        self.metrics.released();
+        Ok(())
+    }
+
+    /// Report passing the jobserver to a child via `envs` to the event
+    /// sink.
+    fn record_configure(&self, envs: &[&str], fifo: bool) {
+        self.mark_exported();
+
+        if let Some(events) = &*self.events() {
+            events.configure(envs, fifo);
+        }
+    }
+
+    /// Record that the jobserver is handed out to other programs, see
+    /// [`Client::shutdown`].
+    fn mark_exported(&self) {
+        #[cfg(unix)]
+        self.exported.store(true, Ordering::Relaxed);
+    }
+
+    /// Record a token acquired by [`Client::acquire_raw`] in strict mode.
+    fn acquired_raw(&self, data: imp::Acquired) {
+        if let Some(raw_acquired) = &mut *self.raw_acquired() {
+            raw_acquired.push(data);
+        }
+    }
+
+    /// Release a token acquired by [`Client::acquire_raw`], rejecting it in
+    /// strict mode if there is none.
+    fn release_raw(self: &Arc<Self>) -> io::Result<()> {
+        let data = match &mut *self.raw_acquired() {
+            Some(raw_acquired) => Some(raw_acquired.pop().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "release_raw without a preceding acquire_raw",
+                )
+            })?),
+            None => None,
+        };
+
+        self.release_or_cover(data.as_ref())
+    }
+
+    /// Release a token, unless it is kept to cover a [`Borrowed`] or kept
+    /// for reuse by [`Client::set_linger`].
+    fn release_or_cover(self: &Arc<Self>, data: Option<&imp::Acquired>) -> io::Result<()> {
+        {
+            let mut borrowed = self.borrowed();
+            if borrowed.uncovered > 0 {
+                borrowed.uncovered -= 1;
+                borrowed.covered += 1;
+                return Ok(());
+            }
+        }
+
+        match data {
+            Some(data) if Linger::keep(self, data) => Ok(()),
+            _ => self.release_token(data),
+        }
+    }
+
+    #[cfg(unix)]
+    fn acitve_try_acquire_client_count(&self) -> sync::MutexGuard<'_, usize> {
+        self.acitve_try_acquire_client_count.lock()
+    }
+}
+
+/// Configuration for [`Client::from_env_with_config`].
+///
+/// The default configuration is what [`Client::from_env`] uses.
+///
+/// All options currently only have effect on Unix, they are ignored on
+/// other platforms, except for `allow_stdio_fds` which also applies to
+/// descriptors given to a module on WASI, and `allow_acquire_only` which
+/// only applies to windows.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct FromEnvConfig {
+    /// Duplicate the inherited fds instead of taking ownership of them.
     ///
-    /// When the a `make` executable calls a child process it will configure the
-    /// environment of the child to ensure that it has handles to the jobserver
-    /// it's passing down. This function will attempt to look for these details
-    /// and connect to the jobserver.
+    /// If set to `false`, the `Client` uses the exact fds specified in the
+    /// environment and closes them on drop.
+    ///
+    /// Defaults to `true`.
+    pub dup_fds: bool,
+
+    /// Set `CLOEXEC` on the fds used by the `Client`, so that they are not
+    /// inherited by child processes unless [`Client::configure_and_run`]
+    /// or similar is used.
+    ///
+    /// If set to `false`, the fds are left inheritable as if
+    /// [`Client::set_inheritable`] was called with `true`. Combined with
+    /// `dup_fds` being `false`, this makes sure that the flags of the
+    /// inherited fds are not touched at all.
+    ///
+    /// Defaults to `true`.
+    pub set_cloexec: bool,
+
+    /// On Linux, try to reopen the inherited pipe via `/dev/fd/` to obtain
+    /// a new file description, which makes [`Client::into_try_acquire_client`]
+    /// safe to use.
+    ///
+    /// This takes precedence over `dup_fds` if it succeeds, so set it to
+    /// `false` if you need the `Client` to use the exact inherited fds.
+    ///
+    /// Defaults to `true`.
+    pub prefer_fifo_reopen: bool,
+
+    /// Accept stdin, stdout and stderr (fds `0`, `1` and `2`) as the fds
+    /// of the jobserver.
+    ///
+    /// A malformed `MAKEFLAGS` naming them would otherwise make the `Client`
+    /// read from and write to the standard streams of the process, so they
+    /// are rejected with [`FromEnvError::StdioFd`] by default.
+    ///
+    /// Defaults to `false`.
+    pub allow_stdio_fds: bool,
+
+    /// On windows, if the creator of the semaphore restricts its access,
+    /// e.g. with a DACL only granting `SYNCHRONIZE`, open it anyway with
+    /// the rights needed to acquire tokens only.
+    ///
+    /// Tokens acquired from such a client can never be released, so every
+    /// acquire permanently shrinks the pool of the jobserver until it is
+    /// recreated, see [`Client::can_release`]. Without this, opening it
+    /// fails with an error of kind [`io::ErrorKind::PermissionDenied`].
+    ///
+    /// Defaults to `false`.
+    pub allow_acquire_only: bool,
+}
+
+impl Default for FromEnvConfig {
+    fn default() -> Self {
+        Self {
+            dup_fds: true,
+            set_cloexec: true,
+            prefer_fifo_reopen: true,
+            allow_stdio_fds: false,
+            allow_acquire_only: false,
+        }
+    }
+}
+
+/// A client of a jobserver
+///
+/// This structure is the main type exposed by this library, and is where
+/// interaction to a jobserver is configured through. Clients are either created
+/// from scratch in which case the internal semphore is initialied on the spot,
+/// or a client is created from the environment to connect to a jobserver
+/// already created.
+///
+/// Some usage examples can be found in the crate documentation for using a
+/// client.
+///
+/// Note that a `Client` implements the `Clone` trait, and all instances of a
+/// `Client` refer to the same jobserver instance.
+#[derive(Clone, Debug)]
+pub struct Client(Arc<ClientInner>);
+
+impl Client {
+    /// Creates a new jobserver initialized with the given parallelism limit.
+    ///
+    /// A client to the jobserver created will be returned. This client will
+    /// allow at most `limit` tokens to be acquired from it in parallel. More
+    /// calls to `acquire` will cause the calling thread to block.
     ///
     /// Note that the created `Client` is not automatically inherited into
     /// spawned child processes from this program. Manual usage of the
-    /// [`Client::configure_and_run`] or [`Client::configure_make_and_run`]
-    /// function is required for a child process to have access to a job server.
+    /// `configure` function is required for a child process to have access to a
+    /// job server.
     ///
-    /// # Return value
+    /// # Examples
     ///
-    /// If a jobserver was found in the environment and it looks correct then
-    /// `Some` of the connected client will be returned. If no jobserver was
-    /// found then `None` will be returned.
+    /// ```
+    /// use jobslot::Client;
     ///
-    /// Note that on Unix  this function will configure the file descriptors
-    /// with `CLOEXEC` so they're not automatically inherited by spawned
-    /// children.
+    /// let client = Client::new(4).expect("failed to create jobserver");
+    /// ```
     ///
-    /// Jobservers on Unix are implemented with `pipe` file descriptors,
-    /// and they're inherited from parent processes.
+    /// # Errors
     ///
-    /// # Safety
+    /// Returns an error if any I/O error happens when attempting to create the
+    /// jobserver client.
+    pub fn new(limit: usize) -> io::Result<Self> {
+        imp::Client::new(limit).map(|inner| Self::new_owned(inner, vec![b'|'; limit]))
+    }
+
+    /// Same as [`Client::new`] except that only a small batch of tokens is
+    /// written to the jobserver at first, for very large limits.
     ///
-    /// This function is `unsafe` to call on Unix specifically as it
-    /// transitively requires usage of the `from_raw_fd` function, which is
-    /// itself unsafe in some circumstances.
+    /// Writing all `limit` tokens upfront would take a while and even fail
+    /// once they do not fit in the pipe, whose capacity is typically 64KiB.
+    /// Instead, the client keeps track of the tokens not written yet and
+    /// tops the jobserver up whenever few tokens are available, until all of
+    /// them are written. It does so on every acquire and in
+    /// [`Client::available`], which counts them, and in a helper thread for
+    /// tokens taken by child processes. The helper thread checks the
+    /// jobserver every 5ms at first, backing off to every 500ms while
+    /// nothing needs to be written.
     ///
-    /// It's recommended to call this function very early in the lifetime of a
-    /// program before any other file descriptors are opened. That way you can
-    /// make sure to take ownership properly of the file descriptors passed
-    /// down, if any.
+    /// On platforms other than unix, this is the same as [`Client::new`].
     ///
-    /// Note, though, that on Windows and Unix it should be safe to
-    /// call this function any number of times.
-    pub unsafe fn from_env() -> Option<Self> {
-        let var = env::var_os("CARGO_MAKEFLAGS")
-            .or_else(|| env::var_os("MAKEFLAGS"))
-            .or_else(|| env::var_os("MFLAGS"))?;
+    /// # Errors
+    ///
+    /// Returns an error if any I/O error happens when attempting to create the
+    /// jobserver client or the helper thread.
+    pub fn new_lazy(limit: usize) -> io::Result<Self> {
+        #[cfg(unix)]
+        {
+            let issued = limit.min(lazy::BATCH);
+            let inner = imp::Client::new(issued)?;
+            let mut client = Self::new_owned(inner, vec![b'|'; issued]);
+
+            let inner = Arc::get_mut(&mut client.0).unwrap();
+            inner.jobs = Some(limit).filter(|jobs| *jobs > 0);
+            if limit > issued {
+                inner.lazy = Some(lazy::LazyTokens::new(limit - issued));
+                lazy::spawn_top_up(&client.0)?;
+            }
+            Ok(client)
+        }
+        #[cfg(not(unix))]
+        {
+            Self::new(limit)
+        }
+    }
+
+    /// Same as [`Client::new`] except that on Linux, the pipe is created in
+    /// packet mode (`O_DIRECT`), so that every token is a discrete packet.
+    ///
+    /// This is experimental. A read of several bytes then returns one
+    /// token at most, so that a reader asking for more cannot take tokens
+    /// it does not expect and releases cannot interleave with each other.
+    /// Since every packet is a single byte, it stays compatible with
+    /// readers such as make, which read one byte at a time.
+    ///
+    /// Tokens written by this crate, e.g. by [`Client::reset`], are written
+    /// one packet each in this mode, see [`Client::is_packet_mode`].
+    ///
+    /// Every packet takes a whole page of the pipe buffer, so the pipe is
+    /// grown with `F_SETPIPE_SZ` to hold `limit` packets. This is bounded
+    /// by `/proc/sys/fs/pipe-max-size` for unprivileged processes, which
+    /// is 256 packets by default with 4KiB pages.
+    ///
+    /// On other platforms, this is the same as [`Client::new`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any I/O error happens when attempting to create the
+    /// jobserver client, e.g. of kind [`io::ErrorKind::InvalidInput`] on
+    /// kernels older than 3.4 which do not support packet mode, or if the
+    /// pipe cannot be grown to hold `limit` packets.
+    pub fn new_packet(limit: usize) -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        return imp::Client::new_packet(limit)
+            .map(|inner| Self::new_owned(inner, vec![b'|'; limit]));
+
+        #[cfg(not(target_os = "linux"))]
+        return Self::new(limit);
+    }
+
+    /// Returns true if the write end of the jobserver is in packet mode, see
+    /// [`Client::new_packet`].
+    ///
+    /// Packet mode is a flag of the file description, so this is false for
+    /// clients which reopened the pipe, e.g. [`Client::from_env`] with
+    /// [`FromEnvConfig::prefer_fifo_reopen`] set. This is always false on
+    /// platforms other than Linux.
+    pub fn is_packet_mode(&self) -> bool {
+        #[cfg(unix)]
+        return self.0.inner.is_packet_mode();
+
+        #[cfg(not(unix))]
+        return false;
+    }
+
+    /// Same as [`Client::new`] except that it will create a named fifo on
+    /// unix so that you can use [`Client::configure_and_run_with_fifo`] or
+    /// [`Client::configure_make_and_run_with_fifo`] to pass the fifo
+    /// instead of fds.
+    ///
+    /// The fifo is created in `/tmp`, except on Android, where there is no
+    /// such directory writable by apps, in `$TMPDIR` or else the cache
+    /// directory of the app. Use [`Client::new_with_fifo_in`] to pick the
+    /// directory.
+    ///
+    /// # Errors
+    ///
+    /// On Android, returns an error of kind [`io::ErrorKind::NotFound`] if
+    /// neither directory is writable.
+    pub fn new_with_fifo(limit: usize) -> io::Result<Self> {
+        #[cfg(unix)]
+        {
+            imp::Client::new_fifo(limit).map(|inner| Self::new_owned(inner, vec![b'|'; limit]))
+        }
+        #[cfg(not(unix))]
+        {
+            Self::new(limit)
+        }
+    }
+
+    /// Same as [`Client::new_with_fifo`], except that the fifo is created
+    /// in the directory `dir` instead of the default one.
+    #[cfg(unix)]
+    pub fn new_with_fifo_in(dir: impl AsRef<std::path::Path>, limit: usize) -> io::Result<Self> {
+        imp::Client::new_fifo_in(dir.as_ref(), limit)
+            .map(|inner| Self::new_owned(inner, vec![b'|'; limit]))
+    }
+
+    /// Same as [`Client::new_with_fifo`], except that the fifo is created
+    /// in the directory `dir` with `mkfifoat` and `openat` instead of in
+    /// `/tmp`, for sandboxes handing out directory fds, e.g. with capsicum
+    /// or landlock.
+    ///
+    /// The path of the fifo, passed to children by
+    /// [`Client::configure_and_run_with_fifo`] and removed once the client
+    /// is dropped, is found from `dir` via `/proc/self/fd` on Linux and
+    /// `F_GETPATH` on macOS. Where it cannot be found, the fifo is unlinked
+    /// right after it is opened, and children are passed fds as with
+    /// [`Client::new`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] on Android,
+    /// Solaris and illumos, which lack `mkfifoat`.
+    #[cfg(unix)]
+    pub fn new_with_fifo_at(dir: impl std::os::unix::io::AsFd, limit: usize) -> io::Result<Self> {
+        imp::Client::new_fifo_at(dir.as_fd(), limit)
+            .map(|inner| Self::new_owned(inner, vec![b'|'; limit]))
+    }
+
+    /// Creates a new jobserver whose tokens are counted in a
+    /// `SharedArrayBuffer`, for build tools running in web workers, e.g. in
+    /// playgrounds or IDEs in the browser.
+    ///
+    /// Post the buffer returned by [`Client::shared_array_buffer`] to other
+    /// workers, each running its own instance of the module, which connect
+    /// to the jobserver with [`Client::from_shared_array_buffer`] to limit
+    /// parallelism across all of them.
+    ///
+    /// Blocking acquires wait with `Atomics.wait`, which browsers do not
+    /// allow on the main thread, where they return an error. Use
+    /// [`AsyncAcquireClient`], which waits with `Atomics.waitAsync`, or
+    /// [`TryAcquireClient`] there instead.
+    ///
+    /// Only available on `wasm32-unknown-unknown` with the `web-workers`
+    /// feature, built without the `atomics` target feature. With it,
+    /// workers sharing the memory of one instance already share the
+    /// jobserver created by [`Client::new`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] if
+    /// `SharedArrayBuffer` is not available, i.e. if the page is not
+    /// cross-origin isolated, or of kind [`io::ErrorKind::InvalidInput`] if
+    /// `limit` exceeds `i32::MAX`.
+    #[cfg(all(
+        feature = "web-workers",
+        target_family = "wasm",
+        target_os = "unknown",
+        not(target_feature = "atomics")
+    ))]
+    pub fn new_with_shared_array_buffer(limit: usize) -> io::Result<Self> {
+        web_workers::SharedCounter::new(limit)
+            .map(|shared| Self::new_owned(imp::Client::new_shared(shared), vec![b'|'; limit]))
+    }
+
+    /// Connects to a jobserver created by
+    /// [`Client::new_with_shared_array_buffer`] in another web worker, from
+    /// the buffer it is posted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `buffer`
+    /// does not have the size of one returned by
+    /// [`Client::shared_array_buffer`].
+    #[cfg(all(
+        feature = "web-workers",
+        target_family = "wasm",
+        target_os = "unknown",
+        not(target_feature = "atomics")
+    ))]
+    pub fn from_shared_array_buffer(buffer: &js_sys::SharedArrayBuffer) -> io::Result<Self> {
+        web_workers::SharedCounter::from_buffer(buffer)
+            .map(|shared| Self::new_inner(imp::Client::new_shared(shared)))
+    }
+
+    /// Returns the `SharedArrayBuffer` counting the tokens of a jobserver
+    /// created by [`Client::new_with_shared_array_buffer`], to be posted to
+    /// other web workers, or `None` for any other jobserver.
+    #[cfg(all(
+        feature = "web-workers",
+        target_family = "wasm",
+        target_os = "unknown",
+        not(target_feature = "atomics")
+    ))]
+    pub fn shared_array_buffer(&self) -> Option<js_sys::SharedArrayBuffer> {
+        self.0
+            .inner
+            .shared()
+            .map(web_workers::SharedCounter::buffer)
+    }
+
+    /// Same as [`Client::new`], except that the semaphore has no name, so
+    /// that no other process on the machine can open it.
+    ///
+    /// Such a jobserver can only be passed to a child with
+    /// [`Client::duplicate_into_process`], since its [`Client::string_arg`]
+    /// names the handle in this process. [`Client::configure_and_run`] and
+    /// similar return an error wrapping [`ConfigureError::Unnamed`] for it,
+    /// as well as for a client created from such a handle.
+    #[cfg(windows)]
+    pub fn new_unnamed(limit: usize) -> io::Result<Self> {
+        imp::Client::new_unnamed(limit).map(|inner| Self::new_owned(inner, vec![b'|'; limit]))
+    }
+
+    /// Creates a new jobserver like [`Client::new_with_fifo`], at a
+    /// well-known location derived from `name`, so that unrelated processes
+    /// (e.g. editor plugins, language servers or file watchers) can join it
+    /// with [`Client::connect_named`] without inheriting it.
+    ///
+    /// On unix this is a fifo in `$XDG_RUNTIME_DIR`, or in `/tmp` if it is
+    /// not set, scoped to the current user, and on windows a semaphore in
+    /// the current session. The fifo is removed once the last clone of the
+    /// client is dropped, unless [`Client::persist_fifo`] is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `name` is
+    /// empty, longer than 64 bytes, starts with `.` or contains characters
+    /// other than ASCII alphanumerics, `-`, `_` and `.`, and an error of kind
+    /// [`io::ErrorKind::AlreadyExists`] if a jobserver named `name`
+    /// exists.
+    #[cfg(any(unix, windows))]
+    pub fn new_named(name: &str, limit: usize) -> io::Result<Self> {
+        check_jobserver_name(name)?;
+        imp::Client::new_named(name, limit).map(|inner| Self::new_owned(inner, vec![b'|'; limit]))
+    }
+
+    /// Connects to the jobserver created by [`Client::new_named`] with the
+    /// same `name`, usually in another process.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if no jobserver
+    /// named `name` exists, and an error of kind
+    /// [`io::ErrorKind::InvalidInput`] if `name` is invalid, see
+    /// [`Client::new_named`].
+    ///
+    /// On unix, returns an error of kind [`io::ErrorKind::PermissionDenied`]
+    /// if the fifo is not owned by the current user or is accessible to
+    /// other users, e.g. since another user created it first in `/tmp`.
+    #[cfg(any(unix, windows))]
+    pub fn connect_named(name: &str) -> io::Result<Self> {
+        check_jobserver_name(name)?;
+        imp::Client::connect_named(name).map(Self::new_inner)
+    }
+
+    /// Removes fifos left behind by [`Client::new_with_fifo`] in its
+    /// directory, e.g. `/tmp`, by processes which are no longer running,
+    /// e.g. after a crash, which are owned by the current user and not
+    /// modified for `older_than`.
+    ///
+    /// Returns the number of fifos removed.
+    ///
+    /// [`Client::new_with_fifo`] already removes orphaned fifos older than a
+    /// day when their names collide. Note that fifos persisted with
+    /// [`Client::persist_fifo`] might still be used by other processes
+    /// after their creator exits.
+    #[cfg(unix)]
+    pub fn remove_orphaned_fifos(older_than: Duration) -> io::Result<usize> {
+        imp::remove_orphaned_fifos(older_than)
+    }
+
+    /// Creates a new jobserver with one token per byte of `tokens`, which
+    /// is returned by [`Acquired::byte`] when the token is acquired.
+    ///
+    /// Make and this crate write back the byte of a token when releasing
+    /// it, so distinct bytes can serve as cheap slot identifiers, e.g. for
+    /// debugging and attributing work.
+    ///
+    /// On platforms other than unix, tokens do not carry any byte and this
+    /// is the same as [`Client::new`] with `tokens.len()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `tokens`
+    /// contains `0xff`, which is used by [`Client::shutdown`], or any I/O
+    /// error happening when attempting to create the jobserver.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use jobslot::Client;
+    ///
+    /// let client = Client::new_with_tokens(b"abcd").expect("failed to create jobserver");
+    /// ```
+    pub fn new_with_tokens(tokens: &[u8]) -> io::Result<Self> {
+        if tokens.contains(&SHUTDOWN_TOKEN) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "0xff is reserved for shutting down the jobserver",
+            ));
+        }
+
+        #[cfg(unix)]
+        return imp::Client::new_with_tokens(tokens)
+            .map(|inner| Self::new_owned(inner, tokens.to_vec()));
+
+        #[cfg(not(unix))]
+        return Self::new(tokens.len());
+    }
+
+    /// Creates a new jobserver with one token per set in `cpusets`, so that
+    /// [`Acquired::cpuset`] tells which CPUs the job run with a token should
+    /// be pinned to, e.g. to keep parallel jobs on separate NUMA nodes.
+    ///
+    /// The set of a token is only known to this process and is identified
+    /// by the byte of the token, which is preserved by make and this crate
+    /// when it is released.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if there
+    /// are more than 253 sets, or any I/O error happening when attempting
+    /// to create the jobserver.
+    #[cfg(target_os = "linux")]
+    pub fn new_with_cpusets<I>(cpusets: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = CpuSet>,
+    {
+        let cpusets: Vec<CpuSet> = cpusets.into_iter().collect();
+        let bytes: Vec<u8> = affinity::token_bytes().take(cpusets.len()).collect();
+        if bytes.len() < cpusets.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many cpusets for distinct token bytes",
+            ));
+        }
+
+        let mut inner = ClientInner::new(imp::Client::new_with_tokens(&bytes)?);
+        inner.initial_tokens = Some(bytes.clone().into());
+        inner.cpusets = bytes.into_iter().zip(cpusets).collect();
+
+        Ok(Self(Arc::new(inner)))
+    }
+
+    fn new_inner(inner: imp::Client) -> Self {
+        Self(Arc::new(ClientInner::new(inner)))
+    }
+
+    fn new_owned(inner: imp::Client, tokens: Vec<u8>) -> Self {
+        let mut inner = ClientInner::new(inner);
+        inner.jobs = Some(tokens.len()).filter(|jobs| *jobs > 0);
+        inner.initial_tokens = Some(tokens.into());
+        Self(Arc::new(inner))
+    }
+
+    /// Block until all tokens the jobserver is created with are returned
+    /// to it, or until `timeout` expires, so that it can be torn down
+    /// safely, e.g. by removing its fifo.
+    ///
+    /// Returns the number of tokens still not returned when `timeout`
+    /// expires, e.g. leaked by a child process, or 0 if all of them are
+    /// returned. Tokens kept by [`Client::set_linger`] count as returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] if this
+    /// client did not create the jobserver, or any error returned by
+    /// [`Client::available`].
+    pub fn wait_for_all_returned(&self, timeout: Duration) -> io::Result<usize> {
+        let limit = self.limit()?;
+        let deadline = Instant::now() + timeout;
+        let mut interval = Duration::from_millis(1);
+
+        loop {
+            let returned = self.available()? + self.0.linger.len();
+            let missing = limit.saturating_sub(returned);
+
+            let now = Instant::now();
+            if missing == 0 || now >= deadline {
+                break Ok(missing);
+            }
+
+            // There is no way to wait for the jobserver to be filled up, so
+            // poll it with backoff.
+            thread::sleep(interval.min(deadline - now));
+            interval = (interval * 2).min(Duration::from_millis(100));
+        }
+    }
+
+    /// Return the number of tokens the jobserver is created with.
+    fn limit(&self) -> io::Result<usize> {
+        let limit = self
+            .0
+            .initial_tokens
+            .as_deref()
+            .map(<[u8]>::len)
+            .ok_or_else(not_owned)?;
+
+        #[cfg(unix)]
+        if let Some(lazy) = &self.0.lazy {
+            return Ok(limit + lazy.total());
+        }
+
+        Ok(limit)
+    }
+
+    /// Shut down the jobserver, so that all pending and future acquires
+    /// fail with an error of kind [`io::ErrorKind::BrokenPipe`], e.g. to
+    /// stop workers blocked in [`Client::acquire`] when a build is aborted.
+    ///
+    /// This covers waiters in this process and in other processes using
+    /// this crate. On unix, a sentinel byte is written to the jobserver,
+    /// which waiters put back after reading it. Since other programs such
+    /// as make would take it as a regular token, this is refused once the
+    /// jobserver has been passed to other programs, through
+    /// [`Client::configure_and_run`] and its variants,
+    /// [`Client::makeflags_value`], [`Client::string_arg`] or the `compat`
+    /// client. On Windows, an event created along with
+    /// the semaphore is signaled.
+    ///
+    /// Tokens kept by [`Client::set_linger`] are forgotten. Use
+    /// [`Client::reset`] to bring the jobserver back.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] if this
+    /// client did not create the jobserver, or on unix if it has been
+    /// passed to other programs.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.limit()?;
+
+        #[cfg(unix)]
+        if self.0.exported.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the jobserver is passed to other programs, which would take the shutdown sentinel as a token",
+            ));
+        }
+
+        self.0.linger.clear();
+        #[cfg(unix)]
+        if let Some(lazy) = &self.0.lazy {
+            lazy.clear();
+        }
+        self.0.inner.shutdown()
+    }
+
+    /// Restore the jobserver to its initial state, by draining all tokens
+    /// available in it and writing back the ones it was created with.
+    ///
+    /// This is useful between build phases when a previous phase is known
+    /// to have leaked or double-released tokens, and it also undoes
+    /// [`Client::shutdown`]. Tokens kept by
+    /// [`Client::set_linger`] are forgotten, but tokens still held, e.g. by
+    /// alive [`Acquired`] or child processes, are written back when
+    /// released, so call this only once all of them are done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] if this
+    /// client did not create the jobserver, e.g. it is created by
+    /// [`Client::from_env`].
+    pub fn reset(&self) -> io::Result<()> {
+        let tokens = self.0.initial_tokens.as_deref().ok_or_else(not_owned)?;
+
+        self.0.linger.clear();
+        #[cfg(unix)]
+        if let Some(lazy) = &self.0.lazy {
+            lazy.reset();
+        }
+        self.0.inner.reset(tokens)
+    }
+
+    /// Temporarily oversubscribes the jobserver by `n` tokens, e.g. to
+    /// exceed the CPU limit during I/O-heavy phases, while keeping its
+    /// steady-state limit unchanged.
+    ///
+    /// The tokens are released to the jobserver right away. Once
+    /// `duration` has passed, `n` tokens are retired in the background as
+    /// they come back to the jobserver, whoever holds them, so that it
+    /// converges back to its limit. Bursts still not retired when
+    /// [`Client::reset`] is called are retired after it all the same, so
+    /// only reset the jobserver once they are done.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Unsupported`] if this
+    /// client did not create the jobserver, and on windows, where the
+    /// semaphore cannot hold more tokens than its limit.
+    pub fn borrow_burst(&self, n: usize, duration: Duration) -> io::Result<()> {
+        self.limit()?;
+
+        #[cfg(windows)]
+        {
+            let _ = (n, duration);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the jobserver semaphore cannot hold more tokens than its limit",
+            ))
+        }
+
+        #[cfg(not(windows))]
+        {
+            let mut released = 0;
+            let res = (0..n).try_for_each(|_| {
+                self.0.inner.release(None)?;
+                released += 1;
+                Ok(())
+            });
+            // Retire the ones released before an error too.
+            burst::spawn_retire(&self.0, released, duration)?;
+            res
+        }
+    }
+
+    /// Returns an identifier of the jobserver this client refers to, which
+    /// is stable within the process.
+    ///
+    /// All clones of a `Client` (including the ones wrapped in
+    /// [`TryAcquireClient`]) share the same id. So do clients connected to
+    /// the same jobserver separately, e.g. by [`Client::from_env`] in
+    /// several crates: on unix, they are matched by the device and inode of
+    /// the pipe or fifo, on windows by the name of the semaphore.
+    ///
+    /// A jobserver without a name on windows, e.g. one inherited as a
+    /// handle, is only known by its clones, and so are jobservers within
+    /// the module on wasm. Once a jobserver is gone, another one might reuse
+    /// its pipe inode and thus its id.
+    pub fn id(&self) -> usize {
+        self.0.id()
+    }
+
+    /// Returns `true` if `self` and `other` are clones of each other.
+    ///
+    /// Use [`Client::id`] to find out whether clients connected separately
+    /// refer to the same jobserver.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Attempts to connect to the jobserver specified in this process's
+    /// environment.
+    ///
+    /// When the a `make` executable calls a child process it will configure the
+    /// environment of the child to ensure that it has handles to the jobserver
+    /// it's passing down. This function will attempt to look for these details
+    /// and connect to the jobserver.
+    ///
+    /// Note that the created `Client` is not automatically inherited into
+    /// spawned child processes from this program. Manual usage of the
+    /// [`Client::configure_and_run`] or [`Client::configure_make_and_run`]
+    /// function is required for a child process to have access to a job server.
+    ///
+    /// # Return value
+    ///
+    /// If a jobserver was found in the environment and it looks correct then
+    /// `Some` of the connected client will be returned. If no jobserver was
+    /// found then `None` will be returned.
+    ///
+    /// Note that on Unix  this function will configure the file descriptors
+    /// with `CLOEXEC` so they're not automatically inherited by spawned
+    /// children.
+    ///
+    /// Jobservers on Unix are implemented with `pipe` file descriptors,
+    /// and they're inherited from parent processes.
+    ///
+    /// # Safety
+    ///
+    /// This function is `unsafe` to call on Unix specifically as it
+    /// transitively requires usage of the `from_raw_fd` function, which is
+    /// itself unsafe in some circumstances.
+    ///
+    /// It's recommended to call this function very early in the lifetime of a
+    /// program before any other file descriptors are opened. That way you can
+    /// make sure to take ownership properly of the file descriptors passed
+    /// down, if any.
+    ///
+    /// Note, though, that on Windows and Unix it should be safe to
+    /// call this function any number of times.
+    pub unsafe fn from_env() -> Option<Self> {
+        Self::from_env_with_config(&FromEnvConfig::default()).ok()
+    }
+
+    /// Same as [`Client::from_env`] except that it uses `config` to decide
+    /// how the inherited jobserver is opened and returns a [`FromEnvError`]
+    /// describing why no client could be created.
+    ///
+    /// See [`FromEnvConfig`] for the available options.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Client::from_env`]. In addition, when
+    /// [`FromEnvConfig::dup_fds`] is `false` the fds are taken ownership of
+    /// and will be closed when the last clone of the `Client` is dropped, so
+    /// they must not be used or closed elsewhere after this call.
+    pub unsafe fn from_env_with_config(config: &FromEnvConfig) -> Result<Self, FromEnvError> {
+        let var = env::var_os("CARGO_MAKEFLAGS")
+            .or_else(|| env::var_os("MAKEFLAGS"))
+            .or_else(|| env::var_os("MFLAGS"))
+            .ok_or(FromEnvError::NotFound)?;
+
+        let var = {
+            cfg_if! {
+                if #[cfg(unix)] {
+                    std::os::unix::ffi::OsStrExt::as_bytes(var.as_os_str())
+                } else {
+                    var.to_str()
+                        .ok_or_else(|| FromEnvError::Malformed(var.to_string_lossy().into_owned()))?
+                        .as_bytes()
+                }
+            }
+        };
+        Self::from_makeflags(var, |auth| imp::Client::open(auth, config))
+    }
+
+    /// Creates a client to the jobserver of another running process, e.g.
+    /// for a supervisor attaching to a build already in progress.
+    ///
+    /// The jobserver is looked up in the environment the process was
+    /// started with, like [`Client::from_env`] does. Its fds are then
+    /// duplicated out of the process with `pidfd_getfd`, which requires
+    /// the permission to `ptrace` it, e.g. being its parent or having
+    /// `CAP_SYS_PTRACE`, and Linux 5.6 or later. A fifo is opened through
+    /// `/proc/PID/root` instead.
+    ///
+    /// The fds of the new client have `CLOEXEC` set.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FromEnvError::NotFound`] if there is no jobserver in the
+    /// environment of the process, and [`FromEnvError::IoError`] of kind
+    /// [`io::ErrorKind::PermissionDenied`] if its fds cannot be duplicated.
+    #[cfg(target_os = "linux")]
+    pub fn from_process(pid: u32) -> Result<Self, FromEnvError> {
+        let environ = std::fs::read(format!("/proc/{}/environ", pid))?;
+        let var = |name: &[u8]| {
+            environ
+                .split(|b| *b == 0)
+                .find_map(|var| var.strip_prefix(name)?.strip_prefix(b"="))
+        };
+        let var = var(b"CARGO_MAKEFLAGS")
+            .or_else(|| var(b"MAKEFLAGS"))
+            .or_else(|| var(b"MFLAGS"))
+            .ok_or(FromEnvError::NotFound)?;
+
+        Self::from_makeflags(var, |auth| imp::Client::from_process(pid, auth))
+    }
+
+    /// Creates a client from the jobserver in `var`, the value of
+    /// `MAKEFLAGS` or similar, opened by `open` from its auth.
+    fn from_makeflags(
+        var: &[u8],
+        open: impl FnOnce(&[u8]) -> Result<imp::Client, FromEnvError>,
+    ) -> Result<Self, FromEnvError> {
+        let makeflags = var.split(u8::is_ascii_whitespace);
+
+        let jobs = parse_jobs(makeflags.clone());
+
+        let flag = protocol::select_auth(makeflags).ok_or(FromEnvError::NotFound)?;
+
+        if protocol::is_disabled_auth(flag) {
+            return Err(FromEnvError::Disabled);
+        }
+
+        open(flag).map(|inner| {
+            let mut inner = ClientInner::new(inner);
+            inner.jobs = jobs;
+            Self(Arc::new(inner))
+        })
+    }
+
+    /// Creates a client from `auth`, the value of `--jobserver-auth=`,
+    /// e.g. `fifo:PATH`, `R,W` or the name of a semaphore on windows, or
+    /// `handle:N` returned by `Client::duplicate_into_process`.
+    ///
+    /// This can be used to share a jobserver with the `jobserver` crate in
+    /// the same process, by passing the result of
+    /// `jobserver::Client::string_arg` to it. With
+    /// [`FromEnvConfig::dup_fds`] set (the default), the returned client
+    /// owns fds of its own and the `jobserver::Client` can be dropped
+    /// independently.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Client::from_env_with_config`].
+    pub unsafe fn from_auth(auth: &str, config: &FromEnvConfig) -> Result<Self, FromEnvError> {
+        if protocol::is_disabled_auth(auth.as_bytes()) {
+            return Err(FromEnvError::Disabled);
+        }
+
+        imp::Client::open(auth.as_bytes(), config).map(Self::new_inner)
+    }
+
+    /// Duplicates the semaphore of this jobserver into `process` with
+    /// `DuplicateHandle`, returning the value of `--jobserver-auth=` which
+    /// refers to it in that process, i.e. `handle:N`.
+    ///
+    /// This passes the jobserver to a single child without the global name
+    /// of the semaphore, e.g. of one created by [`Client::new_unnamed`].
+    /// Since the environment of a process is fixed once it is created,
+    /// create the child suspended, duplicate the semaphore into it, and pass
+    /// the value to it through other means, e.g. its stdin, before resuming
+    /// it. The child then connects with [`Client::from_auth`].
+    ///
+    /// The handle is owned by the child and is closed once it exits. It
+    /// has the same access rights as the handle of this client.
+    #[cfg(windows)]
+    pub fn duplicate_into_process(
+        &self,
+        process: impl std::os::windows::io::AsHandle,
+    ) -> io::Result<String> {
+        self.0.inner.duplicate_into_process(process.as_handle())
+    }
+
+    /// Returns the value to pass to `--jobserver-auth=` to refer to this
+    /// jobserver, the reverse of [`Client::from_auth`].
+    ///
+    /// `jobserver::Client::from_env` takes ownership of the fds named by it
+    /// on unix, so to hand this jobserver over to the `jobserver` crate in
+    /// the same process, use [`Client::into_raw_parts`], release the fds
+    /// with `into_raw_fd` and set `CARGO_MAKEFLAGS` to
+    /// `--jobserver-auth={auth}` before calling `from_env`.
+    ///
+    /// # Errors
+    ///
+    /// On platforms other than Unix and Windows, returns an error wrapping
+    /// [`ConfigureError::Unsupported`], unless the client is created from
+    /// the environment on WASI.
+    pub fn string_arg(&self) -> io::Result<String> {
+        #[cfg(not(any(unix, windows)))]
+        if !self.0.inner.has_string_arg() {
+            return Err(ConfigureError::Unsupported.into());
+        }
+
+        self.0.mark_exported();
+        Ok(self.0.inner.string_arg().into_owned())
+    }
+
+    /// Acquires a token from this jobserver client.
+    ///
+    /// This function will block the calling thread until a new token can be
+    /// acquired from the jobserver.
+    ///
+    /// # Return value
+    ///
+    /// On successful acquisition of a token an instance of `Acquired` is
+    /// returned. This structure, when dropped, will release the token back to
+    /// the jobserver. It's recommended to avoid leaking this value.
+    ///
+    /// # Errors
+    ///
+    /// If an I/O error happens while acquiring a token then this function will
+    /// return immediately with the error. If an error is returned then a token
+    /// was not acquired.
+    pub fn acquire(&self) -> io::Result<Acquired> {
+        let data = self
+            .0
+            .acquire_blocking(Wait::default(), imp::Client::acquire)?;
+        Ok(Acquired::new(self, data))
+    }
+
+    /// Acquires a token, runs `f` while holding it and releases it once `f`
+    /// returns, or unwinds on panic, returning the result of `f`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Client::acquire`], in which case `f` is not called.
+    pub fn with_token<R>(&self, f: impl FnOnce() -> R) -> io::Result<R> {
+        let _token = self.acquire()?;
+        Ok(f())
+    }
+
+    /// Same as [`Client::acquire`], except that on unix it returns an error
+    /// of kind [`io::ErrorKind::Interrupted`] if it is interrupted by a
+    /// signal while waiting for a token, instead of retrying.
+    ///
+    /// This allows signal handlers that only set a flag, e.g. for ctrl-c,
+    /// to abort the wait. For a signal handler that needs to abort waits
+    /// in other threads, use [`Client::acquire_with_cancel`] instead, since
+    /// [`CancellationFlag::cancel`] is async-signal-safe on unix.
+    ///
+    /// On other platforms, waits are never interrupted by signals, so this
+    /// is the same as [`Client::acquire`].
+    pub fn acquire_interruptible(&self) -> io::Result<Acquired> {
+        let wait = Wait {
+            interruptible: true,
+            ..Wait::default()
+        };
+        let data = self
+            .0
+            .acquire_blocking(wait, imp::Client::acquire_interruptible)?;
+        Ok(Acquired::new(self, data))
+    }
+
+    /// Same as [`Client::acquire`], except that it returns an error once
+    /// `flag` is cancelled, even if it is blocked waiting for a token.
+    ///
+    /// An error caused by cancellation wraps [`AcquireError::Cancelled`],
+    /// so that it can be told apart from failures of the jobserver.
+    ///
+    /// On unix, if a new file description of the jobserver cannot be
+    /// opened (see [`TryAcquireMode::IndependentDescription`]), it might
+    /// still block after `flag` is cancelled when another process takes
+    /// the token first.
+    pub fn acquire_with_cancel(&self, flag: &CancellationFlag) -> io::Result<Acquired> {
+        let wait = Wait {
+            cancel: Some(flag),
+            ..Wait::default()
+        };
+        let data = self
+            .0
+            .acquire_blocking(wait, |inner| inner.acquire_with_cancel(&flag.0))?;
+        Ok(Acquired::new(self, data))
+    }
+
+    /// Same as [`Client::acquire`], also returning an [`AcquireReport`]
+    /// describing how the token is obtained, e.g. how long it waited and
+    /// how often it is woken up, to debug performance differences between
+    /// fifo and fd-style jobservers.
+    pub fn acquire_with_report(&self) -> io::Result<(Acquired, AcquireReport)> {
+        let started = Instant::now();
+        let report = |path, wakeups| AcquireReport {
+            backend: self.backend(),
+            path,
+            wait: started.elapsed(),
+            wakeups,
+        };
+
+        let mut wakeups = 0;
+        let obtained = self
+            .0
+            .acquire_with(started, Some(Wait::default()), |inner| {
+                #[cfg(unix)]
+                return inner.acquire_counting_wakeups(&mut wakeups).map(Some);
+
+                // Waiting is a single call, so try first to tell whether it waits.
+                #[cfg(not(unix))]
+                return match inner.try_acquire() {
+                    Ok(Some(data)) => Ok(Some(data)),
+                    _ => {
+                        wakeups = 1;
+                        inner.acquire().map(Some)
+                    }
+                };
+            })?;
+        let data = match obtained.expect("blocking acquires never give up") {
+            Obtained::Lingered(data) => {
+                return Ok((Acquired::new(self, data), report(AcquirePath::Linger, 0)));
+            }
+            Obtained::Acquired(data) => data,
+        };
+
+        let path = if wakeups == 0 {
+            AcquirePath::Fast
+        } else {
+            AcquirePath::Fallback
+        };
+        Ok((Acquired::new(self, data), report(path, wakeups)))
+    }
+
+    fn backend(&self) -> AcquireBackend {
+        #[cfg(unix)]
+        return match self.fifo_path() {
+            Some(_) => AcquireBackend::Fifo,
+            None => AcquireBackend::Pipe,
+        };
+
+        #[cfg(windows)]
+        return AcquireBackend::Semaphore;
+
+        #[cfg(not(any(unix, windows)))]
+        return AcquireBackend::InProcess;
+    }
+
+    /// Same as [`Client::acquire`], except that once `deadline` is reached
+    /// without acquiring a token, it returns [`AcquireOrProceed::Borrowed`]
+    /// so that the caller can proceed anyway, oversubscribing the
+    /// jobserver instead of stalling.
+    ///
+    /// The borrowed slot is repaid automatically: while it is alive, the
+    /// next token released by this client (or its clones) is kept instead
+    /// of being written back to the jobserver, and is released once the
+    /// [`Borrowed`] is dropped. This keeps the long-term accounting
+    /// balanced.
+    pub fn acquire_or_proceed(&self, deadline: Instant) -> io::Result<AcquireOrProceed> {
+        let wait = Wait {
+            deadline: Some(deadline),
+            ..Wait::default()
+        };
+        if let Some(obtained) = self.0.acquire_with(Instant::now(), Some(wait), |inner| {
+            inner.acquire_until(deadline)
+        })? {
+            let token = Acquired::new(self, obtained.into_data());
+            return Ok(AcquireOrProceed::Acquired(token));
+        }
+
+        self.0.borrowed().uncovered += 1;
+
+        Ok(AcquireOrProceed::Borrowed(Borrowed {
+            client: self.0.clone(),
+        }))
+    }
+
+    /// Same as [`Client::acquire`], except that the token is leased for
+    /// `ttl`: if the [`Lease`] is neither renewed with [`Lease::renew`] nor
+    /// dropped before it runs out, the token is re-issued to the
+    /// jobserver, so that a participant hanging forever while holding it
+    /// does not stall everyone else.
+    ///
+    /// The jobserver protocol has no way to tell which process holds a
+    /// token, so this only covers tokens acquired in this process, e.g.
+    /// by a server handing them out to worker threads.
+    pub fn acquire_lease(&self, ttl: Duration) -> io::Result<Lease> {
+        let data = self
+            .0
+            .acquire_blocking(Wait::default(), imp::Client::acquire)?;
+
+        Lease::new(&self.0, data, ttl).map_err(|err| {
+            // The token is not leased, give it back.
+            drop(self.0.release_token(None));
+            err
+        })
+    }
+
+    /// Ask cooperating participants of the jobserver to release `tokens`
+    /// idle tokens early, e.g. for a high-priority job.
+    ///
+    /// The request is sent through a side channel, a fifo named after the
+    /// jobserver fifo with `.preempt` appended, to participants which have
+    /// registered with [`Client::on_preemption`]. Each requested token is
+    /// delivered to exactly one of them. Requests are dropped if the side
+    /// channel is full of unanswered ones.
+    ///
+    /// It is only supported on unix for a jobserver using a named fifo,
+    /// otherwise an error of kind [`io::ErrorKind::Unsupported`] is
+    /// returned.
+    pub fn request_preemption(&self, tokens: usize) -> io::Result<()> {
+        #[cfg(unix)]
+        return preempt::request(&self.0, tokens);
+
+        #[cfg(not(unix))]
+        return {
+            let _ = tokens;
+            Err(preemption_unsupported())
+        };
+    }
+
+    /// Spawn a thread answering requests of [`Client::request_preemption`].
+    ///
+    /// For each request, tokens kept by [`Client::set_linger`] are released
+    /// first, then `callback` is called with the number of tokens still
+    /// requested, which it should release as soon as possible if it holds
+    /// idle ones.
+    ///
+    /// The thread keeps running until a request arrives after all clones
+    /// of this client are dropped.
+    ///
+    /// See [`Client::request_preemption`] for supported platforms.
+    pub fn on_preemption<F>(&self, callback: F) -> io::Result<()>
+    where
+        F: FnMut(usize) + Send + 'static,
+    {
+        #[cfg(unix)]
+        return preempt::listen(&self.0, callback);
+
+        #[cfg(not(unix))]
+        return {
+            drop(callback);
+            Err(preemption_unsupported())
+        };
+    }
+
+    /// Enable or disable the strict mode of this client (and its clones),
+    /// catching double releases which silently inflate the capacity of the
+    /// jobserver.
+    ///
+    /// In strict mode, tokens acquired by [`Client::acquire_raw`] are
+    /// accounted for in this process, [`Client::release_raw`] writes back
+    /// the same byte as was acquired, and it is rejected if there is no
+    /// such token left, including when relinquishing the implicit token.
+    ///
+    /// It is disabled by default. Tokens acquired by `acquire_raw` before
+    /// enabling it cannot be released with `release_raw` afterwards, and
+    /// disabling it forgets the accounted ones.
+    pub fn set_strict(&self, strict: bool) {
+        let mut raw_acquired = self.0.raw_acquired();
+        if strict != raw_acquired.is_some() {
+            *raw_acquired = if strict { Some(Vec::new()) } else { None };
+        }
+    }
+
+    /// Enable or disable incrementing `MAKELEVEL` for children configured by
+    /// this client (and its clones) with [`Client::configure_make_and_run`]
+    /// and its variants, as a recursive make invocation does.
+    ///
+    /// When enabled, `MAKELEVEL` of the child is set to the one of this
+    /// process plus one, starting from `0` if it is unset or invalid, unless
+    /// it is set or removed explicitly for the command (see
+    /// [`Command::has_env`]). Otherwise, which is the default, it is left
+    /// alone and the child inherits it unchanged. Variants only setting `CARGO_MAKEFLAGS`,
+    /// e.g. [`Client::configure_and_run`], never touch it.
+    pub fn set_increment_makelevel(&self, increment: bool) {
+        self.0
+            .increment_makelevel
+            .store(increment, Ordering::Relaxed);
+    }
+
+    /// Enable or disable the debug mode of this client (and its clones)
+    /// verifying that children spawned by [`Client::configure_and_spawn`]
+    /// actually have access to the jobserver, see
+    /// [`Client::verify_inheritance`].
+    ///
+    /// In this mode, if a child turns out not to have access to it, e.g.
+    /// since a custom [`SpawnCommand`] drops the configuration or closes
+    /// the fds, the child is killed and an error is returned, instead of
+    /// make warning about it much later. If that cannot be verified, e.g.
+    /// since the child changed its uid through `sudo` and its environment
+    /// cannot be read, it is left running. It is disabled by default.
+    ///
+    /// This only has an effect on Linux, and for [`SpawnCommand`]s telling
+    /// the process id of their children.
+    pub fn set_verify_inheritance(&self, verify: bool) {
+        #[cfg(target_os = "linux")]
+        self.0.verify_inheritance.store(verify, Ordering::Relaxed);
+        #[cfg(not(target_os = "linux"))]
+        let _ = verify;
+    }
+
+    /// Verify that the running process `pid`, configured by this client and
+    /// spawned, e.g. by a spawn wrapper in the closure passed to
+    /// [`Client::configure_and_run`], actually has access to the jobserver.
+    ///
+    /// This checks through `/proc` that the jobserver is set in its
+    /// environment and that its fds refer to the same pipe as in this
+    /// process. If the process has exited already, there is nothing left to
+    /// check and this succeeds.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Other`] if it does not have
+    /// access to the jobserver, and the error reading `/proc` if that cannot
+    /// be verified, e.g. one of kind [`io::ErrorKind::PermissionDenied`] if
+    /// it changed its uid. On platforms other than Linux, this returns an
+    /// error of kind [`io::ErrorKind::Unsupported`].
+    pub fn verify_inheritance(&self, pid: u32) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        return inherit::verify(pid, &self.0.inner.string_arg())?;
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = pid;
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "verifying inheritance requires /proc on Linux",
+            ))
+        }
+    }
+
+    /// Returns the `MAKELEVEL` to set for a child configured with `envs`,
+    /// see [`Client::set_increment_makelevel`].
+    fn child_makelevel(&self, envs: &[&str]) -> Option<ffi::OsString> {
+        if !envs.contains(&"MAKEFLAGS") || !self.0.increment_makelevel.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let level = env::var("MAKELEVEL")
+            .ok()
+            .and_then(|level| level.trim().parse::<u32>().ok())
+            .unwrap_or(0);
+
+        Some(level.saturating_add(1).to_string().into())
+    }
+
+    /// Record how many tokens this process holds from this client (and its
+    /// clones) in `ledger`, or stop recording if it is `None`.
+    ///
+    /// The count starts from the one recorded for this process, so that
+    /// tokens acquired before `exec` are still attributed to it and can be
+    /// released by the new image. Tokens acquired before calling this in
+    /// the current image are not counted, so set it before acquiring.
+    ///
+    /// Tokens kept by [`Client::set_linger`] or for a [`Borrowed`] are
+    /// counted as held until written back to the jobserver.
+    pub fn set_ledger(&self, ledger: Option<Ledger>) -> io::Result<()> {
+        let ledger = match ledger {
+            Some(ledger) => {
+                let held = ledger.held(process::id())?;
+                Some((ledger, held))
+            }
+            None => None,
+        };
+
+        *self.0.ledger() = ledger;
+        Ok(())
+    }
+
+    /// Record the token activity of this client (and its clones) in
+    /// `recorder`, or stop recording if it is `None`.
+    pub fn set_trace_recorder(&self, recorder: Option<TraceRecorder>) {
+        *self.0.trace() = recorder;
+    }
+
+    /// Report the token lifecycle and configure calls of this client (and
+    /// its clones) to `sink`, or stop reporting if it is `None`.
+    pub fn set_event_sink(&self, sink: Option<EventSink>) {
+        *self.0.events() = sink;
+    }
+
+    /// Render the metrics of this client (and its clones) in the
+    /// Prometheus text exposition format, to be served to a scraper, e.g.
+    /// by a long-running build daemon.
+    ///
+    /// The metrics are:
+    ///
+    /// - `jobslot_tokens_available`: gauge of [`Client::available`],
+    ///   omitted if it cannot be counted.
+    /// - `jobslot_tokens_held`: gauge of tokens acquired from the jobserver
+    ///   and not yet released.
+    /// - `jobslot_acquire_duration_seconds`: histogram of the time spent
+    ///   acquiring tokens from the jobserver.
+    /// - `jobslot_acquire_failures_total`: counter of acquires returning an
+    ///   error, including cancelled and interrupted ones.
+    ///
+    /// Only tokens going through the jobserver are counted, not the ones
+    /// reused by [`Client::set_linger`], and async acquires are not
+    /// counted as failures.
+    #[cfg(feature = "prometheus")]
+    pub fn gather(&self) -> String {
+        self.0.metrics.gather(self.available().ok())
+    }
+
+    /// Keep tokens released by this client (and its clones) for up to
+    /// `linger` before writing them back to the jobserver, so that they can
+    /// be reused by the next acquire without a round trip through the
+    /// jobserver.
+    ///
+    /// This reduces churn for tools that release and re-acquire tokens
+    /// between many tiny tasks, at the cost of other processes waiting
+    /// up to `linger` longer for a token. It is disabled by default, and
+    /// setting it to [`Duration::ZERO`] writes back the kept tokens.
+    ///
+    /// Kept tokens are released by a helper thread, which keeps the
+    /// jobserver open until they are released even if all clones of this
+    /// client are dropped, so [`Client::into_raw_parts`] might fail in
+    /// the meantime. Kept tokens are not counted by [`Client::available`].
+    pub fn set_linger(&self, linger: Duration) {
+        self.0.linger.set_duration(linger);
+    }
+
+    /// Returns amount of tokens in the read-side pipe.
+    ///
+    /// # Return value
+    ///
+    /// Number of bytes available to be read from the jobserver pipe, plus
+    /// the tokens not written yet if it is created by [`Client::new_lazy`].
+    ///
+    /// # Errors
+    ///
+    /// Underlying errors from the ioctl will be passed up. If the platform
+    /// does not support querying it, e.g. on Redox or Haiku, an error of kind
+    /// [`io::ErrorKind::Unsupported`] is returned.
+    ///
+    /// On windows, if [`Client::can_release`] returns false, an error of
+    /// kind [`io::ErrorKind::PermissionDenied`] is returned.
+    pub fn available(&self) -> io::Result<usize> {
+        self.0.available()
+    }
+
+    /// Sets the [`Backoff`] applied by this client (and its clones) when a
+    /// token it is woken up for is taken by another process first, before
+    /// waiting for the next one. The default is [`Backoff::Immediate`].
+    ///
+    /// This only has an effect on unix, where every process waiting on
+    /// the pipe is woken up by a released token. Elsewhere, waiters are
+    /// woken up one at a time. The wait is still cut short by cancelling
+    /// [`Client::acquire_with_cancel`], and by signals in
+    /// [`Client::acquire_interruptible`].
+    pub fn set_backoff(&self, backoff: Backoff) {
+        #[cfg(unix)]
+        self.0.inner.set_backoff(backoff);
+
+        #[cfg(not(unix))]
+        let _ = backoff;
+    }
+
+    /// Sets the [`AbandonedPolicy`] of this client (and its clones),
+    /// deciding what to do when a wait on the jobserver semaphore returns
+    /// `WAIT_ABANDONED`. The default is [`AbandonedPolicy::Error`].
+    ///
+    /// This only has an effect on windows, where the jobserver is a
+    /// semaphore.
+    pub fn set_abandoned_policy(&self, policy: AbandonedPolicy) {
+        #[cfg(windows)]
+        self.0.inner.set_abandoned_policy(policy);
+
+        #[cfg(not(windows))]
+        let _ = policy;
+    }
+
+    /// Sets the [`ReleasePolicy`] of this client (and its clones), deciding
+    /// what to do with errors releasing a token on drop, e.g. of
+    /// [`Acquired`]. The default is [`ReleasePolicy::Ignore`].
+    pub fn set_release_policy(&self, policy: ReleasePolicy) {
+        *self.0.release_policy.lock() = policy;
+    }
+
+    /// Sets the [`IdleReclaim`] policy of this client (and its clones),
+    /// releasing tokens held idle by [`Acquired`] for too long, or removes
+    /// it with `None`, which is the default.
+    ///
+    /// Only tokens acquired after it is set are tracked, with the policy set
+    /// when they are acquired. Call [`Acquired::touch`] whenever a token is
+    /// used to keep it from being reclaimed.
+    pub fn set_idle_reclaim(&self, policy: Option<IdleReclaim>) {
+        self.0.idle.set_policy(policy);
+    }
+
+    /// Sets the [`PressureGovernor`] delaying blocking acquires of this
+    /// client (and its clones) while the system is under memory or I/O
+    /// pressure, or removes it with `None`, which is the default.
+    ///
+    /// This covers [`Client::acquire`] and its blocking variants, but not
+    /// tokens reused by [`Client::set_linger`], try-acquires or async
+    /// acquires, which must not block. The delay ends as early as the
+    /// acquire itself would, e.g. once the flag passed to
+    /// [`Client::acquire_with_cancel`] is cancelled.
+    #[cfg(target_os = "linux")]
+    pub fn set_pressure_governor(&self, governor: Option<PressureGovernor>) {
+        *self.0.pressure.lock() = governor;
+    }
+
+    /// Returns the utilization statistics of this client (and its clones)
+    /// since it is created or [`Client::reset_stats`] is called.
+    pub fn stats(&self) -> Stats {
+        self.0.stats.get(self.jobs())
+    }
+
+    /// Resets the statistics returned by [`Client::stats`], e.g. between
+    /// the phases of a build. Tokens held are still counted.
+    pub fn reset_stats(&self) {
+        self.0.stats.reset();
+    }
+
+    /// Returns a handle yielding the number of tokens available (see
+    /// [`Observer::available`]) whenever it changes, as observed by sampling
+    /// it every `interval` in a background thread.
+    ///
+    /// Changes shorter than `interval` might be missed, and every sample
+    /// costs a syscall, so pick `interval` according to how quickly the
+    /// changes need to be observed. Sampling has no side effect, see
+    /// [`Observer::available`]. On unix, while no token is available, the
+    /// thread waits for one to be released instead, so that it is observed
+    /// right away.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first sample, e.g. if the available
+    /// tokens cannot be counted, or the error of spawning the thread.
+    pub fn watch_available(&self, interval: Duration) -> io::Result<AvailabilityWatch> {
+        AvailabilityWatch::new(&self.0, interval)
+    }
+
+    /// Returns a read-only [`Observer`] of this client, which can report
+    /// the tokens available and watch the token flow but cannot acquire or
+    /// release tokens, e.g. for dashboards. See [`Observer`] for how
+    /// counting the tokens still touches the jobserver.
+    pub fn observe(&self) -> Observer {
+        Observer::new(self.0.clone())
+    }
+
+    /// Returns false if tokens acquired from this client cannot be
+    /// released.
+    ///
+    /// On windows, when the creator of the semaphore restricts its access,
+    /// e.g. with a DACL only granting `SYNCHRONIZE`, and
+    /// [`FromEnvConfig::allow_acquire_only`] is set,
+    /// [`Client::from_env_with_config`] still opens it so that tokens can be
+    /// acquired, but releasing them fails with an error of kind
+    /// [`io::ErrorKind::PermissionDenied`] and the tokens are lost until the
+    /// jobserver is recreated.
+    ///
+    /// On other platforms this always returns true.
+    pub fn can_release(&self) -> bool {
+        #[cfg(windows)]
+        return self.0.inner.can_release();
+        #[cfg(not(windows))]
+        return true;
+    }
+
+    /// Configures a child process to have access to this client's jobserver as
+    /// well and run the `f` which spawns the process.
+    ///
+    /// NOTE that you have to spawn the process inside `f`, otherwise the jobserver
+    /// would not be inherited.
+    ///
+    /// See [`Client::configure_and_run_with_err`] for closures returning
+    /// other error types.
+    ///
+    /// This function is required to be called to ensure that a jobserver is
+    /// properly inherited to a child process. If this function is *not* called
+    /// then this `Client` will not be accessible in the child process. In other
+    /// words, if not called, then `Client::from_env` will return `None` in the
+    /// child process (or the equivalent of `Child::from_env` that `make` uses).
+    ///
+    /// ## Environment variables
+    ///
+    /// This function only sets up `CARGO_MAKEFLAGS`, which is used by
+    /// `cargo`.
+    ///
+    /// ## Platform-specific behavior
+    ///
+    /// On Unix and Windows this will clobber the `CARGO_MAKEFLAGS` environment
+    /// variables for the child process, and on Unix this will also allow the
+    /// two file descriptors for this client to be inherited to the child.
+    ///
+    /// On platforms other than Unix and Windows this returns an
+    /// [`io::Error`] wrapping [`ConfigureError::Unsupported`] without
+    /// calling `f`, or panics if the `legacy-configure-panic` feature is
+    /// enabled. On Windows, it returns one wrapping
+    /// [`ConfigureError::Unnamed`] if the semaphore has no name, e.g. one
+    /// created by `Client::new_unnamed`.
+    pub fn configure_and_run<Cmd, F, R>(&self, cmd: Cmd, f: F) -> io::Result<R>
+    where
+        Cmd: Command,
+        F: FnOnce(&mut Cmd) -> io::Result<R>,
+    {
+        self.configure_and_run_inner(cmd, f, &["CARGO_MAKEFLAGS"])
+    }
+
+    /// Same as [`Client::configure_and_run`] except that it sets up environment
+    /// variables `CARGO_MAKEFLAGS`, `MAKEFLAGS` and `MFLAGS`, which is used by
+    /// `cargo` and `make`.
+    pub fn configure_make_and_run<Cmd, F, R>(&self, cmd: Cmd, f: F) -> io::Result<R>
+    where
+        Cmd: Command,
+        F: FnOnce(&mut Cmd) -> io::Result<R>,
+    {
+        self.configure_and_run_inner(cmd, f, &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"])
+    }
+
+    /// Same as [`Client::configure_and_run`] except that `f` can return any
+    /// error type convertible from [`io::Error`], e.g. the error type of a
+    /// spawn wrapper, which is returned as is.
+    pub fn configure_and_run_with_err<Cmd, F, R, E>(&self, cmd: Cmd, f: F) -> Result<R, E>
+    where
+        Cmd: Command,
+        F: FnOnce(&mut Cmd) -> Result<R, E>,
+        E: From<io::Error>,
+    {
+        self.configure_and_run_inner(cmd, f, &["CARGO_MAKEFLAGS"])
+    }
+
+    /// Same as [`Client::configure_make_and_run`] except that `f` can return
+    /// any error type convertible from [`io::Error`], see
+    /// [`Client::configure_and_run_with_err`].
+    pub fn configure_make_and_run_with_err<Cmd, F, R, E>(&self, cmd: Cmd, f: F) -> Result<R, E>
+    where
+        Cmd: Command,
+        F: FnOnce(&mut Cmd) -> Result<R, E>,
+        E: From<io::Error>,
+    {
+        self.configure_and_run_inner(cmd, f, &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"])
+    }
+
+    /// Same as [`Client::configure_make_and_run`] except that the
+    /// configuration is adapted to the [`MakeFlavor`] of `cmd`, e.g. from
+    /// [`MakeFlavor::detect`].
+    ///
+    /// For [`MakeFlavor::Msys`], the jobserver is not passed at all, and the
+    /// environment variables are set to `-jN` only.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jobslot::{Client, MakeFlavor};
+    ///
+    /// # fn f() -> std::io::Result<()> {
+    /// let client = Client::new(4)?;
+    /// let mut cmd = std::process::Command::new("make");
+    /// let flavor = MakeFlavor::detect(cmd.get_program());
+    ///
+    /// let status = client.configure_make_and_run_for(flavor, &mut cmd, |cmd| cmd.status())?;
+    /// # drop(status);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn configure_make_and_run_for<Cmd, F, R>(
+        &self,
+        flavor: MakeFlavor,
+        cmd: Cmd,
+        f: F,
+    ) -> io::Result<R>
+    where
+        Cmd: Command,
+        F: FnOnce(&mut Cmd) -> io::Result<R>,
+    {
+        match flavor {
+            MakeFlavor::Native => self.configure_make_and_run(cmd, f),
+            MakeFlavor::Msys => {
+                let envs = &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"];
+                let jobs = match self.jobs() {
+                    Some(jobs) => jobs,
+                    None => self.available()? + 1,
+                };
+                let value = ffi::OsString::from(format!("-j{}", jobs));
+                let mut cmd = setup_envs(cmd, envs, &value, self.child_makelevel(envs));
+
+                f(&mut cmd)
+            }
+        }
+    }
+
+    fn configure_and_run_inner<Cmd, F, R, E>(
+        &self,
+        mut cmd: Cmd,
+        f: F,
+        envs: &[&str],
+    ) -> Result<R, E>
+    where
+        Cmd: Command,
+        F: FnOnce(&mut Cmd) -> Result<R, E>,
+        E: From<io::Error>,
+    {
+        check_configurable()?;
+
+        // Register one-time callback on unix to unset CLO_EXEC
+        // in child process.
+        self.0.inner.pre_run(&mut cmd);
+
+        let value = self.makeflags_value(MakeflagsStyle::Compatible)?;
+        self.0.record_configure(envs, false);
+        let mut cmd = setup_envs(cmd, envs, &value, self.child_makelevel(envs));
+
+        f(&mut cmd)
+    }
+
+    /// Same as [`Client::configure_and_run`] except that it also appends
+    /// `-jN` to the arguments of `cmd`, for tools taking the number of jobs
+    /// on the command line instead of using a jobserver, e.g. `ninja` or
+    /// `cmake --build`.
+    ///
+    /// `N` is [`Client::jobs`] if known, or otherwise the number of tokens
+    /// currently available plus the implicit one.
+    ///
+    /// Note that unlike the environment variables, the argument cannot be
+    /// removed from `cmd` afterwards.
+    pub fn configure_and_run_with_jobs_arg<Cmd, F, R>(&self, mut cmd: Cmd, f: F) -> io::Result<R>
+    where
+        Cmd: ArgCommand,
+        F: FnOnce(&mut Cmd) -> io::Result<R>,
+    {
+        // Fail before `cmd` is modified.
+        check_configurable()?;
+
+        let jobs = match self.jobs() {
+            Some(jobs) => jobs,
+            None => self.available()? + 1,
+        };
+        cmd.arg(format!("-j{}", jobs));
+
+        self.configure_and_run(cmd, f)
+    }
+
+    /// Async version of [`Client::configure_and_run`], for spawn flows that
+    /// need to await before spawning `cmd`, e.g. acquiring a lock or
+    /// waiting on a limiter.
+    ///
+    /// The environment variables stay set up until the future returned by
+    /// `f` completes, and are removed afterwards, or once the returned
+    /// future is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async fn wait_for_turn() {}
+    /// # async fn f() -> std::io::Result<()> {
+    /// let client = jobslot::Client::new(4)?;
+    /// let mut cmd = std::process::Command::new("make");
+    ///
+    /// let child = client
+    ///     .configure_and_run_async(&mut cmd, |cmd| {
+    ///         Box::pin(async move {
+    ///             wait_for_turn().await;
+    ///             cmd.spawn()
+    ///         })
+    ///     })
+    ///     .await?;
+    /// # drop(child);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn configure_and_run_async<Cmd, F, R>(&self, cmd: Cmd, f: F) -> io::Result<R>
+    where
+        Cmd: Command,
+        F: for<'a> FnOnce(&'a mut Cmd) -> Pin<Box<dyn Future<Output = io::Result<R>> + Send + 'a>>,
+    {
+        self.configure_and_run_async_inner(cmd, f, &["CARGO_MAKEFLAGS"])
+            .await
+    }
+
+    /// Same as [`Client::configure_and_run_async`] except that it sets up
+    /// environment variables `CARGO_MAKEFLAGS`, `MAKEFLAGS` and `MFLAGS`,
+    /// which is used by `cargo` and `make`.
+    pub async fn configure_make_and_run_async<Cmd, F, R>(&self, cmd: Cmd, f: F) -> io::Result<R>
+    where
+        Cmd: Command,
+        F: for<'a> FnOnce(&'a mut Cmd) -> Pin<Box<dyn Future<Output = io::Result<R>> + Send + 'a>>,
+    {
+        self.configure_and_run_async_inner(cmd, f, &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"])
+            .await
+    }
+
+    async fn configure_and_run_async_inner<Cmd, F, R, E>(
+        &self,
+        mut cmd: Cmd,
+        f: F,
+        envs: &[&str],
+    ) -> Result<R, E>
+    where
+        Cmd: Command,
+        F: for<'a> FnOnce(&'a mut Cmd) -> Pin<Box<dyn Future<Output = Result<R, E>> + Send + 'a>>,
+        E: From<io::Error>,
+    {
+        check_configurable()?;
+
+        self.0.inner.pre_run(&mut cmd);
+
+        let value = self.makeflags_value(MakeflagsStyle::Compatible)?;
+        self.0.record_configure(envs, false);
+        // The guard is held across the await, so that the environment
+        // variables are removed once the future completes or is dropped.
+        let mut cmd = setup_envs(cmd, envs, &value, self.child_makelevel(envs));
+
+        f(&mut cmd).await
+    }
+
+    /// Same as [`Client::configure_and_run`] with a closure spawning
+    /// `cmd`, for the common case of spawning it right away.
+    ///
+    /// See [`Client::set_verify_inheritance`] for checking the child in
+    /// debug mode.
+    pub fn configure_and_spawn<Cmd: SpawnCommand>(&self, cmd: &mut Cmd) -> io::Result<Cmd::Child> {
+        let child = self.configure_and_run(cmd, |cmd| cmd.spawn_child())?;
 
-        let var = {
-            cfg_if! {
-                if #[cfg(unix)] {
-                    std::os::unix::ffi::OsStrExt::as_bytes(var.as_os_str())
-                } else {
-                    var.to_str()?.as_bytes()
+        #[cfg(target_os = "linux")]
+        if self.0.verify_inheritance.load(Ordering::Relaxed) {
+            if let Some(pid) = Cmd::child_id(&child) {
+                // Only a child known not to have access is killed, not one
+                // that cannot be verified.
+                if let Ok(Err(err)) = inherit::verify(pid, &self.0.inner.string_arg()) {
+                    Cmd::kill_child(child);
+                    return Err(err);
                 }
             }
-        };
-        let makeflags = var.split(u8::is_ascii_whitespace);
-
-        // `--jobserver-auth=` is the only documented makeflags.
-        // `--jobserver-fds=` is actually an internal only makeflags, so we should
-        // always prefer `--jobserver-auth=`.
-        //
-        // Also, according to doc of makeflags, if there are multiple `--jobserver-auth=`
-        // the last one is used
-        if let Some(flag) = makeflags
-            .clone()
-            .filter_map(|s| s.strip_prefix(b"--jobserver-auth="))
-            .last()
-        {
-            imp::Client::open(flag)
-        } else {
-            imp::Client::open(
-                makeflags
-                    .filter_map(|s| s.strip_prefix(b"--jobserver-fds="))
-                    .last()?,
-            )
         }
-        .map(Self::new_inner)
+
+        Ok(child)
     }
 
-    /// Acquires a token from this jobserver client.
-    ///
-    /// This function will block the calling thread until a new token can be
-    /// acquired from the jobserver.
+    /// Same as [`Client::configure_and_run`] with a closure spawning
+    /// `cmd` and collecting its output.
     ///
-    /// # Return value
+    /// For `tokio::process::Command`, this returns a future of the
+    /// output of the child process, which is spawned right away with
+    /// stdout and stderr piped, replacing whatever the caller set.
+    pub fn configure_and_output<Cmd: SpawnCommand>(
+        &self,
+        cmd: &mut Cmd,
+    ) -> io::Result<Cmd::Output> {
+        self.configure_and_run(cmd, |cmd| cmd.spawn_output())
+    }
+
+    /// Same as [`Client::configure_and_run`] except that it writes the
+    /// configuration into `consumer` instead of a [`Command`], so that any
+    /// spawning framework can consume it.
     ///
-    /// On successful acquisition of a token an instance of `Acquired` is
-    /// returned. This structure, when dropped, will release the token back to
-    /// the jobserver. It's recommended to avoid leaking this value.
+    /// On unix, [`EnvConsumer::needs_fd`] is called with both fds of this
+    /// client, which are not made inheritable here.
     ///
     /// # Errors
     ///
-    /// If an I/O error happens while acquiring a token then this function will
-    /// return immediately with the error. If an error is returned then a token
-    /// was not acquired.
-    pub fn acquire(&self) -> io::Result<Acquired> {
-        let data = self.0.inner.acquire()?;
-        Ok(Acquired::new(self, data))
+    /// Same as [`Client::makeflags_value`], in which case `consumer` is left
+    /// untouched.
+    pub fn configure_into<C: EnvConsumer + ?Sized>(&self, consumer: &mut C) -> io::Result<()> {
+        self.configure_into_inner(consumer, &["CARGO_MAKEFLAGS"])
     }
 
-    /// Returns amount of tokens in the read-side pipe.
-    ///
-    /// # Return value
+    /// Same as [`Client::configure_into`] except that it sets up environment
+    /// variables `CARGO_MAKEFLAGS`, `MAKEFLAGS` and `MFLAGS`, which is used by
+    /// `cargo` and `make`.
+    pub fn configure_make_into<C: EnvConsumer + ?Sized>(&self, consumer: &mut C) -> io::Result<()> {
+        self.configure_into_inner(consumer, &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"])
+    }
+
+    fn configure_into_inner<C: EnvConsumer + ?Sized>(
+        &self,
+        consumer: &mut C,
+        envs: &[&str],
+    ) -> io::Result<()> {
+        let value = self.makeflags_value(MakeflagsStyle::Compatible)?;
+
+        #[cfg(unix)]
+        {
+            consumer.needs_fd(self.0.inner.get_read_fd());
+            consumer.needs_fd(self.0.inner.get_write_fd());
+        }
+
+        for env in envs {
+            consumer.set(env.as_ref(), &value);
+        }
+        self.0.record_configure(envs, false);
+
+        Ok(())
+    }
+
+    /// Returns the exact value [`Client::configure_and_run`] (for
+    /// [`MakeflagsStyle::Compatible`]) or
+    /// [`Client::configure_and_run_with_fifo`] (for
+    /// [`MakeflagsStyle::Fifo`]) sets `CARGO_MAKEFLAGS` to, e.g. for
+    /// embedding it in env files, container specs or remote execution
+    /// requests.
     ///
-    /// Number of bytes available to be read from the jobserver pipe
+    /// Note that on unix, unless it refers to a fifo, the value refers to
+    /// fds that are only inherited by processes spawned by
+    /// [`Client::configure_and_run`], or after calling
+    /// [`Client::set_inheritable`].
     ///
     /// # Errors
     ///
-    /// Underlying errors from the ioctl will be passed up.
-    pub fn available(&self) -> io::Result<usize> {
-        self.0.inner.available()
+    /// Returns an [`io::Error`] wrapping [`ConfigureError::Unsupported`] if
+    /// this client cannot be passed to other processes, e.g. on platforms
+    /// other than Unix and Windows unless it is created from the
+    /// environment on WASI, and [`ConfigureError::Unnamed`] on Windows if
+    /// its semaphore has no name.
+    pub fn makeflags_value(&self, style: MakeflagsStyle) -> io::Result<ffi::OsString> {
+        self.0.mark_exported();
+
+        if style == MakeflagsStyle::Fifo {
+            if let Some(value) = self.fifo_makeflags_value() {
+                return Ok(value);
+            }
+        }
+
+        self.check_string_arg()?;
+
+        let arg = self.0.inner.string_arg();
+        // Older implementations of make use `--jobserver-fds` and newer
+        // implementations use `--jobserver-auth`, pass both to try to catch
+        // both implementations.
+        Ok(format!(
+            "{} --jobserver-fds={1} --jobserver-auth={1}",
+            self.jobs_flag(),
+            arg
+        )
+        .into())
     }
 
-    /// Configures a child process to have access to this client's jobserver as
-    /// well and run the `f` which spawns the process.
-    ///
-    /// NOTE that you have to spawn the process inside `f`, otherwise the jobserver
-    /// would not be inherited.
+    /// Returns [`ConfigureError::Unsupported`] if `string_arg` of the
+    /// client panics since it cannot be passed to other processes, and
+    /// [`ConfigureError::Unnamed`] if it names a handle only valid in this
+    /// process.
+    fn check_string_arg(&self) -> io::Result<()> {
+        #[cfg(not(any(unix, windows, feature = "legacy-configure-panic")))]
+        if !self.0.inner.has_string_arg() {
+            return Err(ConfigureError::Unsupported.into());
+        }
+
+        #[cfg(windows)]
+        if !self.0.inner.has_string_arg() {
+            return Err(ConfigureError::Unnamed.into());
+        }
+
+        Ok(())
+    }
+
+    /// Returns `-jN` if the limit is known, or `-j` otherwise.
+    fn jobs_flag(&self) -> String {
+        match self.jobs() {
+            Some(jobs) => format!("-j{}", jobs),
+            None => "-j".into(),
+        }
+    }
+
+    /// Returns a sensible number of workers to pre-commit to, e.g. for a
+    /// thread pool or the number of test harness shards, based on the
+    /// state of the jobserver.
     ///
-    /// This function is required to be called to ensure that a jobserver is
-    /// properly inherited to a child process. If this function is *not* called
-    /// then this `Client` will not be accessible in the child process. In other
-    /// words, if not called, then `Client::from_env` will return `None` in the
-    /// child process (or the equivalent of `Child::from_env` that `make` uses).
+    /// This is the number of tokens available plus the implicit one of
+    /// this process, averaged over the snapshots taken by the calls in the
+    /// last second to smooth out short bursts of other processes, and
+    /// capped by [`Client::jobs`] if known. It is always at least 1.
     ///
-    /// ## Environment variables
+    /// If the available tokens cannot be counted, [`Client::jobs`] or 1 is
+    /// returned.
+    pub fn recommended_jobs(&self) -> usize {
+        const WINDOW: Duration = Duration::from_secs(1);
+        const MAX_SNAPSHOTS: usize = 16;
+
+        let available = match self.available() {
+            Ok(available) => available,
+            Err(_) => return self.jobs().unwrap_or(1),
+        };
+
+        let mut snapshots = self.0.availability.lock();
+
+        let now = Instant::now();
+        while snapshots.len() >= MAX_SNAPSHOTS
+            || snapshots
+                .front()
+                .map_or(false, |(at, _)| now.duration_since(*at) > WINDOW)
+        {
+            snapshots.pop_front();
+        }
+        snapshots.push_back((now, available));
+
+        let total: usize = snapshots.iter().map(|(_, available)| available).sum();
+        // Round to the nearest integer.
+        let average = (total + snapshots.len() / 2) / snapshots.len();
+
+        let jobs = average + 1;
+        self.jobs().map_or(jobs, |limit| jobs.min(limit)).max(1)
+    }
+
+    /// Returns the total parallelism `N` this client exports as `-jN` to
+    /// children, like make does, so that they know it without counting
+    /// tokens.
     ///
-    /// This function only sets up `CARGO_MAKEFLAGS`, which is used by
-    /// `cargo`.
+    /// This is the limit the jobserver is created with if this client
+    /// created it, or the value of the last `-jN` (or `--jobs=N`) in the
+    /// environment for [`Client::from_env`]. Otherwise, e.g. for [`Client::from_auth`],
+    /// this returns `None` and a bare `-j` is exported instead.
+    pub fn jobs(&self) -> Option<usize> {
+        self.0.jobs
+    }
+
+    /// Returns a snippet for `shell` setting `CARGO_MAKEFLAGS`, `MAKEFLAGS`
+    /// and `MFLAGS` to [`Client::makeflags_value`] just like
+    /// [`Client::configure_make_and_run`], for build systems generating
+    /// wrapper scripts instead of spawning processes directly.
     ///
-    /// ## Platform-specific behavior
+    /// The same caveat about inheriting fds on unix applies, see
+    /// [`Client::makeflags_value`].
     ///
-    /// On Unix and Windows this will clobber the `CARGO_MAKEFLAGS` environment
-    /// variables for the child process, and on Unix this will also allow the
-    /// two file descriptors for this client to be inherited to the child.
+    /// # Errors
     ///
-    /// On platforms other than Unix and Windows this panics.
-    pub fn configure_and_run<Cmd, F, R>(&self, cmd: Cmd, f: F) -> io::Result<R>
-    where
-        Cmd: Command,
-        F: FnOnce(&mut Cmd) -> io::Result<R>,
-    {
-        self.configure_and_run_inner(cmd, f, &["CARGO_MAKEFLAGS"])
-    }
+    /// Returns an error of kind [`io::ErrorKind::InvalidData`] if the value
+    /// is not valid UTF-8, e.g. the path to the fifo, and an error of kind
+    /// [`io::ErrorKind::Unsupported`] if this client cannot be passed to
+    /// other processes, see [`Client::makeflags_value`].
+    pub fn to_shell_exports(&self, shell: Shell, style: MakeflagsStyle) -> io::Result<String> {
+        let value = self
+            .makeflags_value(style)?
+            .into_string()
+            .map_err(|value| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:?} is not valid UTF-8", value),
+                )
+            })?;
 
-    /// Same as [`Client::configure_and_run`] except that it sets up environment
-    /// variables `CARGO_MAKEFLAGS`, `MAKEFLAGS` and `MFLAGS`, which is used by
-    /// `cargo` and `make`.
-    pub fn configure_make_and_run<Cmd, F, R>(&self, cmd: Cmd, f: F) -> io::Result<R>
-    where
-        Cmd: Command,
-        F: FnOnce(&mut Cmd) -> io::Result<R>,
-    {
-        self.configure_and_run_inner(cmd, f, &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"])
+        let mut snippet = String::new();
+        for name in ["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"] {
+            shell.write_export(&mut snippet, name, &value);
+        }
+
+        Ok(snippet)
     }
 
-    fn configure_and_run_inner<Cmd, F, R>(&self, mut cmd: Cmd, f: F, envs: &[&str]) -> io::Result<R>
-    where
-        Cmd: Command,
-        F: FnOnce(&mut Cmd) -> io::Result<R>,
-    {
-        // Register one-time callback on unix to unset CLO_EXEC
-        // in child process.
-        self.0.inner.pre_run(&mut cmd);
+    fn fifo_makeflags_value(&self) -> Option<ffi::OsString> {
+        #[cfg(unix)]
+        {
+            // If the fifo has been removed, the child won't be able to
+            // connect to it, so fallback to passing the fds instead.
+            if let Some(path) = self
+                .0
+                .inner
+                .get_fifo()
+                .filter(|_| self.0.inner.verify_fifo().is_ok())
+            {
+                let path = path.as_os_str();
 
-        let arg = self.0.inner.string_arg();
-        // Older implementations of make use `--jobserver-fds` and newer
-        // implementations use `--jobserver-auth`, pass both to try to catch
-        // both implementations.
-        let value = format!("-j --jobserver-fds={0} --jobserver-auth={0}", arg);
+                let prefix = format!("{} --jobserver-auth=fifo:", self.jobs_flag());
 
-        let mut cmd = setup_envs(cmd, envs, ffi::OsStr::new(&value));
+                let mut value = ffi::OsString::with_capacity(prefix.len() + path.len());
+                value.push(prefix);
+                value.push(path);
 
-        f(&mut cmd)
+                return Some(value);
+            }
+        }
+
+        None
     }
 
     /// Same as [`Client::configure_and_run`] except that it tries to pass
@@ -531,6 +3059,10 @@ impl Client {
     /// such API is not supported since spawning processes is not supported
     /// by wasm yet.
     ///
+    /// If the fifo has been removed (e.g. by a tmp cleaner) after the
+    /// client is created, this function also falls back to
+    /// [`Client::configure_and_run`], see [`Client::verify_fifo`].
+    ///
     /// Using this function will break backwards compatibility for
     /// some programs, e.g. make < `4.4`.
     ///
@@ -556,34 +3088,274 @@ impl Client {
         self.configure_and_run_with_fifo_inner(cmd, f, &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"])
     }
 
-    fn configure_and_run_with_fifo_inner<Cmd, F, R>(
+    fn configure_and_run_with_fifo_inner<Cmd, F, R, E>(
         &self,
         cmd: Cmd,
         f: F,
         envs: &[&str],
-    ) -> io::Result<R>
+    ) -> Result<R, E>
     where
         Cmd: Command,
-        F: FnOnce(&mut Cmd) -> io::Result<R>,
+        F: FnOnce(&mut Cmd) -> Result<R, E>,
+        E: From<io::Error>,
     {
+        if let Some(value) = self.fifo_makeflags_value() {
+            self.0.record_configure(envs, true);
+            let mut cmd = setup_envs(cmd, envs, &value, self.child_makelevel(envs));
+
+            return f(&mut cmd);
+        }
+
+        self.configure_and_run_inner(cmd, f, envs)
+    }
+
+    /// Creates a new client to the same jobserver with freshly duplicated
+    /// fds/handles, unlike [`Clone::clone`], so that one component can
+    /// close or reconfigure its copy without affecting other holders in
+    /// the same process.
+    ///
+    /// The new client behaves like one created by [`Client::from_env`]:
+    /// it does not own the jobserver or its fifo, and none of the settings
+    /// of this client (e.g. [`Client::set_linger`]) are carried over.
+    ///
+    /// On unix, the new fds refer to a new file description when possible
+    /// (i.e. for fifos, and for pipes on linux and apple platforms), so that
+    /// e.g. setting `O_NONBLOCK` on them does not affect the fds of this
+    /// client. Otherwise, they are `dup`ed and share file status flags.
+    #[cfg(any(unix, windows))]
+    pub fn duplicate(&self) -> io::Result<Self> {
+        self.0.inner.duplicate().map(Self::new_inner)
+    }
+
+    /// Decomposes this client into its raw parts, transferring ownership of
+    /// the underlying fds/handle to the caller.
+    ///
+    /// Returns `Err(self)` if there are other clones of this client still
+    /// alive.
+    #[cfg(any(unix, windows))]
+    pub fn into_raw_parts(self) -> Result<RawParts, Self> {
+        Arc::try_unwrap(self.0)
+            .map(|inner| {
+                inner.mark_exported();
+                inner.inner.into_raw_parts()
+            })
+            .map_err(Self)
+    }
+
+    /// Closes the jobserver, removing the fifo if this client owns it, and
+    /// returns any error happening in the process, unlike dropping the
+    /// last clone of the client, which ignores them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::Other`] without closing
+    /// anything if other clones of this client are still alive, including
+    /// the ones held by helper threads of [`Client::set_linger`] and
+    /// [`Client::acquire_lease`]. The jobserver is then closed once the
+    /// last of them is dropped.
+    pub fn close(self) -> io::Result<()> {
+        match Arc::try_unwrap(self.0) {
+            Ok(inner) => inner.inner.close(),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "jobserver is still used by other clones of this client",
+            )),
+        }
+    }
+
+    /// Permanently makes the jobserver inheritable by child processes, or
+    /// reverts that if `inheritable` is `false`.
+    ///
+    /// This is intended for programs which re-exec themselves (e.g. after
+    /// self-update) and have to keep the jobserver accessible across `exec`
+    /// without going through [`Client::configure_and_run`].
+    ///
+    /// While this is enabled, [`Client::configure_and_run`] no longer needs
+    /// to register a callback to run in the new process.
+    ///
+    /// ## Platform-specific behavior
+    ///
+    /// On Unix this clears `CLOEXEC` on both fds of this client.
+    ///
+    /// On Windows this is a no-op since the semaphore is opened by name.
+    #[cfg(any(unix, windows))]
+    pub fn set_inheritable(&self, inheritable: bool) -> io::Result<()> {
+        self.0.inner.set_inheritable(inheritable)
+    }
+
+    /// Returns the path to the named fifo this client uses, if any.
+    ///
+    /// This is always `None` on platforms other than Unix.
+    pub fn fifo_path(&self) -> Option<&std::path::Path> {
         #[cfg(unix)]
-        {
-            if let Some(path) = self.0.inner.get_fifo() {
-                let path = path.as_os_str();
+        return self.0.inner.get_fifo();
 
-                let prefix = "-j --jobserver-auth=";
+        #[cfg(not(unix))]
+        return None;
+    }
 
-                let mut value = ffi::OsString::with_capacity(prefix.len() + path.len());
-                value.push(prefix);
-                value.push(path);
+    /// Keeps the named fifo created by [`Client::new_with_fifo`] after the
+    /// last clone of this client is dropped, so that processes started
+    /// later can still connect to the jobserver via [`Client::fifo_path`].
+    ///
+    /// After calling this, removing the fifo is the responsibility of the
+    /// caller, otherwise it is left in the temporary directory. Any process
+    /// that has the path can connect to the jobserver as long as it exists,
+    /// though the tokens are only available while at least one process
+    /// still has it open.
+    ///
+    /// This is a no-op if the client does not own a named fifo, e.g. when
+    /// it is created by [`Client::from_env`], or on platforms other than
+    /// Unix.
+    pub fn persist_fifo(&self) {
+        #[cfg(unix)]
+        self.0.inner.persist_fifo();
+    }
+
+    /// Makes this client responsible for removing its named fifo once the
+    /// last clone of it is dropped, as if it was created by
+    /// [`Client::new_with_fifo`].
+    ///
+    /// This can be used to hand over cleanup duty across processes, e.g.
+    /// a supervisor creates the fifo and calls [`Client::persist_fifo`],
+    /// then the longest-lived worker connects to it via
+    /// [`Client::from_env`] and calls this function.
+    ///
+    /// Returns `false` if the client does not use a named fifo, or on
+    /// platforms other than Unix.
+    pub fn take_fifo_ownership(&self) -> bool {
+        #[cfg(unix)]
+        return self.0.inner.take_fifo_ownership();
+
+        #[cfg(not(unix))]
+        return false;
+    }
 
-                let mut cmd = setup_envs(cmd, envs, &value);
+    /// Moves the responsibility of removing the named fifo from this client
+    /// to `to`, which must use the same fifo, e.g. it is created by
+    /// [`Client::from_env`] connecting to it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if this
+    /// client does not own its fifo, or if `to` does not use the same fifo.
+    /// Ownership is left unchanged in this case.
+    pub fn transfer_fifo_ownership(&self, to: &Client) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            if !self.0.inner.owns_fifo() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the client does not own its fifo",
+                ));
+            }
+            if !self.0.inner.is_same_fifo(&to.0.inner)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the clients do not use the same fifo",
+                ));
+            }
 
-                return f(&mut cmd);
+            if self.0.inner.persist_fifo() {
+                to.0.inner.take_fifo_ownership();
             }
+
+            Ok(())
         }
 
-        self.configure_and_run_inner(cmd, f, envs)
+        #[cfg(not(unix))]
+        {
+            let _ = to;
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the client does not own its fifo",
+            ))
+        }
+    }
+
+    /// Returns `true` if this client is responsible for removing its
+    /// named fifo once the last clone of it is dropped.
+    pub fn owns_fifo(&self) -> bool {
+        #[cfg(unix)]
+        return self.0.inner.owns_fifo();
+
+        #[cfg(not(unix))]
+        return false;
+    }
+
+    /// Checks that the named fifo this client uses still exists.
+    ///
+    /// Removing the fifo, e.g. by a tmp cleaner, does not affect processes
+    /// that have already connected to it, but new processes are no longer
+    /// able to connect via its path.
+    ///
+    /// Returns `Ok(())` if the fifo is intact or if this client does not
+    /// use a named fifo.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::NotFound`] if the fifo
+    /// has been removed or replaced by another file.
+    ///
+    /// Note that a removed fifo cannot be recreated, since a new fifo would
+    /// be a different pipe than the one the existing processes use.
+    /// [`Client::configure_and_run_with_fifo`] falls back to passing fds in
+    /// this case.
+    pub fn verify_fifo(&self) -> io::Result<()> {
+        #[cfg(unix)]
+        return self.0.inner.verify_fifo();
+
+        #[cfg(not(unix))]
+        return Ok(());
+    }
+
+    /// Checks that the jobserver actually works, e.g. right after
+    /// [`Client::from_env`] and before a build starts, instead of failing
+    /// in the middle of it.
+    ///
+    /// This verifies the fifo as [`Client::verify_fifo`] does, then
+    /// acquires a token, waiting at most `timeout` for one, and releases it
+    /// right away. Not getting a token in time is not an error, since all
+    /// tokens may legitimately be in use by other processes.
+    ///
+    /// Clients which cannot release tokens (see [`Client::can_release`])
+    /// skip the round trip, since the token would be lost.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error encountered, e.g. [`io::ErrorKind::NotFound`] for
+    /// a removed fifo, or the error of `read(2)` for fds which are closed
+    /// or not a jobserver, with a message describing the jobserver.
+    pub fn probe(&self, timeout: Duration) -> io::Result<()> {
+        let describe = |err: io::Error| {
+            io::Error::new(
+                err.kind(),
+                format!("jobserver {} is not working: {}", self.describe(), err),
+            )
+        };
+
+        self.verify_fifo().map_err(describe)?;
+
+        if !self.can_release() {
+            return Ok(());
+        }
+
+        let deadline = Instant::now() + timeout;
+        if let Some(data) = self.0.inner.acquire_until(deadline).map_err(describe)? {
+            self.0.inner.release(Some(&data)).map_err(describe)?;
+        }
+
+        Ok(())
+    }
+
+    /// Describe the jobserver in error messages, without panicking on
+    /// platforms without cross process jobserver support.
+    fn describe(&self) -> String {
+        if cfg!(any(unix, windows)) {
+            format!("`{}`", self.0.inner.string_arg())
+        } else {
+            "in this process".into()
+        }
     }
 
     /// Blocks the current thread until a token is acquired.
@@ -592,7 +3364,10 @@ impl Client {
     /// helper. If successful the process will need to guarantee that
     /// `release_raw` is called in the future.
     pub fn acquire_raw(&self) -> io::Result<()> {
-        self.0.inner.acquire()?;
+        let data = self
+            .0
+            .acquire_blocking(Wait::default(), imp::Client::acquire)?;
+        self.0.acquired_raw(data);
         Ok(())
     }
 
@@ -601,22 +3376,113 @@ impl Client {
     /// This is intended to be paired with `acquire_raw` if it was called, but
     /// in some situations it could also be called to relinquish a process's
     /// implicit token temporarily which is then re-acquired later.
+    ///
+    /// # Errors
+    ///
+    /// In the strict mode set by [`Client::set_strict`], returns an error of
+    /// kind [`io::ErrorKind::InvalidInput`] without releasing anything if
+    /// there is no token acquired by `acquire_raw` left to release.
     pub fn release_raw(&self) -> io::Result<()> {
+        self.0.release_raw()
+    }
+
+    /// Releases the implicit token this process runs with, e.g. while it
+    /// is blocked waiting on its children, and returns a guard acquiring
+    /// it back once dropped.
+    ///
+    /// Per make's protocol, every process started by make (or any other
+    /// jobserver) implicitly holds one token which is never read from the
+    /// jobserver, and should lend it out while it is not doing any work
+    /// itself.
+    ///
+    /// The token is not tracked by the strict mode of [`Client::set_strict`]
+    /// or counted by [`Client::set_ledger`], since it is not acquired.
+    pub fn release_implicit(&self) -> io::Result<ImplicitToken> {
         self.0.inner.release(None)?;
-        Ok(())
+
+        Ok(ImplicitToken {
+            client: Some(self.0.clone()),
+        })
+    }
+
+    /// Return true if [`Client::into_try_acquire_client`] is expected to
+    /// succeed without returning
+    /// `IntoTryAcquireClientError::IncompatibleWithOlderMake`.
+    ///
+    /// This does not change the jobserver in any way, nor open or spawn
+    /// anything. On unix, the conversion falls back to a helper thread if
+    /// no new file description of the jobserver can be opened, so this only
+    /// returns false if a [`TryAcquireClient`] of this client is alive and
+    /// has set `O_NONBLOCK` on the jobserver. Conversion might still fail
+    /// if the process runs out of resources.
+    pub fn supports_try_acquire(&self) -> bool {
+        #[cfg(unix)]
+        return self.0.inner.supports_try_acquire();
+
+        #[cfg(not(unix))]
+        return true;
+    }
+
+    /// Similar to [`Client::acquire`], but returns `Ok(None)` instead of
+    /// blocking if there is no token available, without converting this
+    /// client with [`Client::into_try_acquire_client`] first.
+    ///
+    /// This works on platforms where non-blocking acquire needs no setup,
+    /// e.g. windows, and on unix while a [`TryAcquireClient`] of this
+    /// client (or its clones) is alive.
+    ///
+    /// # Errors
+    ///
+    /// Otherwise, returns an error of kind [`io::ErrorKind::Unsupported`]
+    /// wrapping [`TryAcquireError::ConversionRequired`], in which case use
+    /// [`Client::into_try_acquire_client`] instead.
+    pub fn try_acquire(&self) -> io::Result<Option<Acquired>> {
+        // The count is not kept locked while acquiring, so that converting
+        // or dropping a `TryAcquireClient` does not wait for it. If the last
+        // one is dropped in the meantime, the jobserver fails the same way
+        // instead of blocking.
+        #[cfg(unix)]
+        if *self.0.acitve_try_acquire_client_count() == 0 {
+            return Err(TryAcquireError::ConversionRequired.into());
+        }
+
+        self.try_acquire_inner()
+    }
+
+    fn try_acquire_inner(&self) -> io::Result<Option<Acquired>> {
+        let obtained = self
+            .0
+            .acquire_with(Instant::now(), None, imp::Client::try_acquire)?;
+        Ok(obtained.map(|obtained| Acquired::new(self, obtained.into_data())))
     }
 
     /// Get [`TryAcquireClient`], which supports non-blocking acquire.
     ///
-    /// It would return `Err(IntoTryAcquireClientError::IncompatibleWithOlderMake)`
-    /// on unix if an annoymous pipe is used as jobserver.
+    /// On unix, the jobserver might be shared with processes that do not
+    /// expect `O_NONBLOCK` on it, so this function tries the following, in
+    /// order, until the last instance of [`TryAcquireClient`] is dropped:
+    ///
+    ///  - open a new file description of the jobserver with `O_NONBLOCK` set,
+    ///    which works for named fifos, and for annoymous pipes on linux via
+    ///    `/proc/self/fd` and on macOS via `F_GETPATH`.
+    ///  - spawn a helper thread to read from it whenever
+    ///    [`TryAcquireClient::try_acquire`] is called without a token being
+    ///    available. If the jobserver is readable, `try_acquire` waits up to
+    ///    100ms for the helper thread to read a token, and returns `None`
+    ///    otherwise, e.g. if another process takes it first. The helper
+    ///    thread holds
+    ///    at most one token, which is handed out by the next `try_acquire`,
+    ///    or released if none takes it within 100ms or once the last
+    ///    instance of [`TryAcquireClient`] is dropped.
+    ///  - set `O_NONBLOCK` on the jobserver shared with other processes, in
+    ///    which case `Err(IntoTryAcquireClientError::IncompatibleWithOlderMake)`
+    ///    is returned.
     ///
-    /// Once this function is called, on unix, `O_NONBLOCK` will bes et on jobserver
-    /// until the last instance of [`TryAcquireClient`] is dropped.
+    /// Use [`TryAcquireClient::mode`] to find out which one is chosen.
     pub fn into_try_acquire_client(self) -> Result<TryAcquireClient, IntoTryAcquireClientError> {
         #[cfg(unix)]
         return {
-            // Construct `TryAcquireClient` here, in case `set_nonblocking`
+            // Construct `TryAcquireClient` here, in case `start_try_acquire`
             // failed, its dtor would set it back to blocking.
             let client = TryAcquireClient(self);
 
@@ -626,7 +3492,7 @@ impl Client {
                 *active_try_acquire_client_count += 1;
 
                 if *active_try_acquire_client_count == 1 {
-                    client.0 .0.inner.set_nonblocking()?;
+                    client.0 .0.inner.start_try_acquire()?;
                 }
             }
 
@@ -642,6 +3508,165 @@ impl Client {
     }
 }
 
+/// Flag to cancel [`Client::acquire_with_cancel`], which can be
+/// triggered from another thread.
+#[derive(Debug)]
+pub struct CancellationFlag(imp::CancellationFlag);
+
+impl CancellationFlag {
+    /// Create a new flag which is not cancelled.
+    pub fn new() -> io::Result<Self> {
+        imp::CancellationFlag::new().map(Self)
+    }
+
+    /// Cancel all ongoing and future [`Client::acquire_with_cancel`] using
+    /// this flag.
+    ///
+    /// On unix, this is async-signal-safe and can be called from a signal
+    /// handler.
+    pub fn cancel(&self) -> io::Result<()> {
+        self.0.cancel()
+    }
+
+    /// Return true if [`CancellationFlag::cancel`] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+}
+
+/// A group of processes which can be signaled together, e.g. to stop a
+/// whole tree of jobs on abort so that the tokens they hold are reclaimed
+/// coherently.
+///
+/// On unix this is a process group, led by the first process spawned in
+/// it. On Windows this is a job object. Processes spawned by members of
+/// the group belong to it as well, unless they move themselves out, and
+/// they can still reach the jobserver as long as it is passed down to
+/// them, e.g. through `MAKEFLAGS`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::process::Command;
+/// use jobslot::{Client, ProcessGroup};
+///
+/// let client = Client::new(4).expect("failed to create jobserver");
+/// let group = ProcessGroup::new().unwrap();
+/// let mut cmd = Command::new("make");
+/// let child = client
+///     .configure_make_and_run(&mut cmd, |cmd| group.spawn(cmd))
+///     .unwrap();
+///
+/// // On abort
+/// group.kill().unwrap();
+/// ```
+#[cfg(any(unix, windows))]
+#[derive(Debug)]
+pub struct ProcessGroup(imp::ProcessGroup);
+
+#[cfg(any(unix, windows))]
+impl ProcessGroup {
+    /// Create a new group without any process.
+    pub fn new() -> io::Result<Self> {
+        imp::ProcessGroup::new().map(Self)
+    }
+
+    /// Spawn `cmd` in this group, e.g. in the closure passed to
+    /// [`Client::configure_and_run`].
+    ///
+    /// On unix, the group is identified by the pid of the first process
+    /// spawned in it, which might be reused once that process is reaped,
+    /// e.g. by [`process::Child::wait`]. From then on, spawning more fails
+    /// and signals are no longer sent, even if other processes in the
+    /// group are still running, so keep the first child around unreaped
+    /// for as long as the group is used.
+    ///
+    /// On Windows, `cmd` is spawned suspended and resumed once it is
+    /// assigned to the job object, so that it cannot spawn processes
+    /// outside of it, which replaces the creation flags set with
+    /// `CommandExt::creation_flags`.
+    pub fn spawn(&self, cmd: &mut process::Command) -> io::Result<process::Child> {
+        self.0.spawn(cmd)
+    }
+
+    /// Send `signal` to all processes in the group.
+    #[cfg(unix)]
+    pub fn signal(&self, signal: std::os::raw::c_int) -> io::Result<()> {
+        self.0.signal(signal)
+    }
+
+    /// Kill all processes in the group, with `SIGKILL` on unix.
+    pub fn kill(&self) -> io::Result<()> {
+        self.0.kill()
+    }
+}
+
+#[cfg(not(unix))]
+fn preemption_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "preemption requires a jobserver using a named fifo",
+    )
+}
+
+/// Byte written to the jobserver by [`Client::shutdown`] on unix.
+const SHUTDOWN_TOKEN: u8 = 0xff;
+
+fn shut_down() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "jobserver is shut down")
+}
+
+/// Returns [`ConfigureError::Unsupported`] on platforms without cross
+/// process jobserver support, where `pre_run` and `string_arg` of the
+/// client panic.
+fn check_configurable() -> io::Result<()> {
+    if cfg!(any(unix, windows, feature = "legacy-configure-panic")) {
+        Ok(())
+    } else {
+        Err(ConfigureError::Unsupported.into())
+    }
+}
+
+fn not_owned() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "only the client creating the jobserver can do this",
+    )
+}
+
+fn cancelled() -> io::Error {
+    AcquireError::Cancelled.into()
+}
+
+/// Raw parts of a [`Client`], returned by [`Client::into_raw_parts`].
+#[cfg(any(unix, windows))]
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct RawParts {
+    /// Read end of the jobserver.
+    #[cfg(unix)]
+    pub read: std::os::unix::io::OwnedFd,
+
+    /// Write end of the jobserver.
+    #[cfg(unix)]
+    pub write: std::os::unix::io::OwnedFd,
+
+    /// Path to the named fifo, if the jobserver uses one.
+    ///
+    /// If the fifo is created by [`Client::new_with_fifo`], then it is no
+    /// longer removed automatically and it is up to the caller to remove it.
+    #[cfg(unix)]
+    pub fifo: Option<std::path::PathBuf>,
+
+    /// Handle to the semaphore.
+    #[cfg(windows)]
+    pub handle: std::os::windows::io::OwnedHandle,
+
+    /// The value of `--jobserver-auth=` that refers to this jobserver,
+    /// i.e. `R,W` on unix and the name of the semaphore on windows.
+    pub auth: String,
+}
+
 /// An acquired token from a jobserver.
 ///
 /// This token will be released back to the jobserver when it is dropped and
@@ -650,16 +3675,77 @@ impl Client {
 pub struct Acquired {
     client: Option<Arc<ClientInner>>,
     data: imp::Acquired,
+    /// Id of the token tracked for [`Client::set_idle_reclaim`].
+    idle: Option<u64>,
 }
 
 impl Acquired {
     fn new(client: &Client, data: imp::Acquired) -> Self {
         Self {
             client: Some(client.0.clone()),
+            idle: IdleTokens::register(&client.0, &data),
             data,
         }
     }
 
+    /// Take the client to release this token to, or `None` if there is
+    /// nothing to release, e.g. since it is reclaimed as idle.
+    fn take_client(&mut self) -> Option<Arc<ClientInner>> {
+        let client = self.client.take()?;
+        match self.idle.take() {
+            Some(id) if !client.idle.unregister(id) => None,
+            _ => Some(client),
+        }
+    }
+
+    /// Marks this token as used now, so that it is not reclaimed by the
+    /// [`IdleReclaim`] policy of the client until it is idle again.
+    ///
+    /// Returns false if it has already been reclaimed, in which case this
+    /// no longer holds a token and releases nothing when dropped.
+    pub fn touch(&self) -> bool {
+        match (&self.client, self.idle) {
+            (Some(client), Some(id)) => client.idle.touch(id),
+            (client, _) => client.is_some(),
+        }
+    }
+
+    /// Returns true if this token has been reclaimed by the
+    /// [`IdleReclaim`] policy of the client.
+    pub fn is_reclaimed(&self) -> bool {
+        match (&self.client, self.idle) {
+            (Some(client), Some(id)) => client.idle.is_reclaimed(id),
+            _ => false,
+        }
+    }
+
+    /// Returns the byte read from the jobserver for this token, which
+    /// make and this crate preserve when the token is released.
+    ///
+    /// See [`Client::new_with_tokens`] for creating a jobserver with
+    /// chosen bytes.
+    ///
+    /// Returns `None` on windows, where tokens do not carry any byte, and
+    /// on other platforms unless the token is read from a jobserver shared
+    /// with the host on WASI.
+    pub fn byte(&self) -> Option<u8> {
+        #[cfg(unix)]
+        return Some(self.data.byte());
+
+        #[cfg(windows)]
+        return None;
+
+        #[cfg(not(any(unix, windows)))]
+        return self.data.byte();
+    }
+
+    /// Returns the set of CPUs associated with this token by
+    /// [`Client::new_with_cpusets`], if any.
+    #[cfg(target_os = "linux")]
+    pub fn cpuset(&self) -> Option<&CpuSet> {
+        self.client.as_ref()?.cpusets.get(&self.data.byte())
+    }
+
     /// This drops the `Acquired` token without releasing the associated token.
     ///
     /// This is not generally useful, but can be helpful if you do not have the
@@ -668,14 +3754,181 @@ impl Acquired {
     /// You'll typically want to follow this up with a call to `release_raw` or
     /// similar to actually release the token later on.
     pub fn drop_without_releasing(mut self) {
-        self.client = None;
+        self.take_client();
+    }
+
+    /// Releases this token, returning any error instead of handling it
+    /// with the [`ReleasePolicy`] of the client like dropping it does.
+    pub fn release(mut self) -> Result<(), ReleaseError> {
+        match self.take_client() {
+            Some(client) => client
+                .release_or_cover(Some(&self.data))
+                .map_err(ReleaseError::new),
+            None => Ok(()),
+        }
+    }
+
+    /// Releases this token, runs `f` and acquires a token back before
+    /// returning, as a yield point for jobs entering long I/O waits which
+    /// want to lend their slot out meanwhile.
+    ///
+    /// See `AsyncAcquireClient::yield_token` for the async version.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error releasing the token without running `f`, or the
+    /// error acquiring a token back, in which case this no longer holds a
+    /// token and releases nothing when dropped.
+    pub fn yield_token<T>(&mut self, f: impl FnOnce() -> T) -> io::Result<T> {
+        let client = self.lend()?;
+        let ret = f();
+        if let Some(client) = client {
+            self.reclaim(client.acquire())?;
+        }
+        Ok(ret)
+    }
+
+    /// Release this token until [`Acquired::reclaim`] is called, returning
+    /// the client to acquire it back from.
+    fn lend(&mut self) -> io::Result<Option<Client>> {
+        match self.take_client() {
+            Some(client) => match client.release_or_cover(Some(&self.data)) {
+                Ok(()) => Ok(Some(Client(client))),
+                Err(err) => {
+                    self.idle = IdleTokens::register(&client, &self.data);
+                    self.client = Some(client);
+                    Err(err)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Hold the token `acquired` in place of the one lent by
+    /// [`Acquired::lend`].
+    fn reclaim(&mut self, acquired: io::Result<Acquired>) -> io::Result<()> {
+        let mut acquired = acquired?;
+        self.client = acquired.client.take();
+        self.idle = acquired.idle.take();
+        self.data = acquired.data.clone();
+        Ok(())
     }
 }
 
 impl Drop for Acquired {
+    fn drop(&mut self) {
+        if let Some(client) = self.take_client() {
+            if let Err(err) = client.release_or_cover(Some(&self.data)) {
+                client.release_failed(err);
+            }
+        }
+    }
+}
+
+/// Guard returned by [`Client::release_implicit`], which acquires the
+/// implicit token back once dropped, blocking until one is available.
+///
+/// Errors happening on drop are ignored, use [`ImplicitToken::reacquire`]
+/// to handle them.
+#[derive(Debug)]
+pub struct ImplicitToken {
+    client: Option<Arc<ClientInner>>,
+}
+
+impl ImplicitToken {
+    /// Acquires the implicit token back, blocking until one is available.
+    pub fn reacquire(mut self) -> io::Result<()> {
+        match self.client.take() {
+            Some(client) => client.inner.acquire().map(drop),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ImplicitToken {
     fn drop(&mut self) {
         if let Some(client) = self.client.take() {
-            drop(client.inner.release(Some(&self.data)));
+            drop(client.inner.acquire());
+        }
+    }
+}
+
+/// Style of the value returned by [`Client::makeflags_value`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MakeflagsStyle {
+    /// `-jN --jobserver-fds=AUTH --jobserver-auth=AUTH`, as set by
+    /// [`Client::configure_and_run`], which is compatible with make < 4.4.
+    Compatible,
+    /// `-jN --jobserver-auth=fifo:PATH`, as set by
+    /// [`Client::configure_and_run_with_fifo`], falling back to
+    /// [`MakeflagsStyle::Compatible`] just like it if there is no fifo.
+    Fifo,
+}
+
+/// Shell to generate a snippet for with [`Client::to_shell_exports`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Shell {
+    /// POSIX `sh` and compatible shells, e.g. `export MAKEFLAGS='...'`.
+    Posix,
+    /// PowerShell, e.g. `$env:MAKEFLAGS = '...'`.
+    PowerShell,
+}
+
+impl Shell {
+    fn write_export(self, snippet: &mut String, name: &str, value: &str) {
+        match self {
+            Shell::Posix => {
+                // Nothing is special within single quotes, except for the
+                // single quote itself which has to be written outside.
+                let value = value.replace('\'', r"'\''");
+                snippet.push_str(&format!("export {}='{}'\n", name, value));
+            }
+            Shell::PowerShell => {
+                // Single quotes, including the typographic ones PowerShell
+                // also accepts, are escaped by doubling them.
+                let mut quoted = String::with_capacity(value.len());
+                for c in value.chars() {
+                    if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+                        quoted.push(c);
+                    }
+                    quoted.push(c);
+                }
+                snippet.push_str(&format!("$env:{} = '{}'\n", name, quoted));
+            }
+        }
+    }
+}
+
+/// Returned by [`Client::acquire_or_proceed`].
+#[derive(Debug)]
+pub enum AcquireOrProceed {
+    /// A token is acquired before the deadline.
+    Acquired(Acquired),
+    /// No token is acquired before the deadline.
+    Borrowed(Borrowed),
+}
+
+/// A slot borrowed from a jobserver without a token, returned by
+/// [`Client::acquire_or_proceed`].
+///
+/// While it is alive, the next token released by the [`Client`] is kept to
+/// back it, and that token is released when it is dropped.
+#[derive(Debug)]
+pub struct Borrowed {
+    client: Arc<ClientInner>,
+}
+
+impl Drop for Borrowed {
+    fn drop(&mut self) {
+        let mut borrowed = self.client.borrowed();
+        if borrowed.uncovered > 0 {
+            borrowed.uncovered -= 1;
+        } else {
+            borrowed.covered -= 1;
+            drop(borrowed);
+            drop(self.client.release_token(None));
         }
     }
 }
@@ -683,10 +3936,10 @@ impl Drop for Acquired {
 /// Possible errors for [`Client::into_try_acquire_client`]
 #[derive(Debug)]
 pub enum IntoTryAcquireClientError {
-    /// The jobserver uses annoymous pipe, [`TryAcquireClient::try_acquire`]
-    /// requires setting `O_NONBLOCK`, since annoymous pipe is passed by fd,
-    /// it will affect all processes using the annoymous pipe,
-    /// which will break make < `4.4`.
+    /// The jobserver uses annoymous pipe and the helper thread cannot be
+    /// spawned, so [`TryAcquireClient::try_acquire`] requires setting
+    /// `O_NONBLOCK`. Since annoymous pipe is passed by fd, it will affect
+    /// all processes using the annoymous pipe, which will break make < `4.4`.
     ///
     /// If you know that no make < `4.4` is spawned when [`TryAcquireClient`],
     /// then you can simply unwrap this error and continue.
@@ -733,8 +3986,189 @@ impl StdError for IntoTryAcquireClientError {
     }
 }
 
+/// Possible errors for [`Client::from_env_with_config`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum FromEnvError {
+    /// No jobserver is specified in the environment.
+    NotFound,
+
+    /// The jobserver specified in the environment cannot be parsed.
+    Malformed(String),
+
+    /// The jobserver is disabled on purpose by make, which passes negative
+    /// fds (`--jobserver-auth=-2,-2`) to commands it does not consider
+    /// recursive makes, e.g. ones not marked with `+`.
+    ///
+    /// Unlike the other errors, this is not a misconfiguration, so tools
+    /// usually fall back to running serially without warning about it.
+    Disabled,
+
+    /// The jobserver specified in the environment uses stdin, stdout or
+    /// stderr, which is rejected unless [`FromEnvConfig::allow_stdio_fds`]
+    /// is set.
+    #[cfg(any(unix, target_os = "wasi"))]
+    StdioFd(i32),
+
+    /// The jobserver specified in the environment does not refer to a
+    /// valid jobserver, e.g. the fds are not inherited or are not pipes.
+    Invalid(String),
+
+    /// An I/O error happened while opening the jobserver.
+    IoError(io::Error),
+}
+
+impl fmt::Display for FromEnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => f.write_str("no jobserver found in the environment"),
+            Self::Malformed(auth) => write!(f, "cannot parse jobserver auth `{}`", auth),
+            Self::Disabled => f.write_str("jobserver is disabled by make"),
+            #[cfg(any(unix, target_os = "wasi"))]
+            Self::StdioFd(fd) => write!(
+                f,
+                "jobserver uses fd {} which is one of stdin, stdout or stderr",
+                fd
+            ),
+            Self::Invalid(auth) => write!(f, "`{}` is not a valid jobserver", auth),
+            Self::IoError(io_error) => write!(f, "io error: {}", io_error),
+        }
+    }
+}
+
+impl From<io::Error> for FromEnvError {
+    fn from(io_error: io::Error) -> Self {
+        Self::IoError(io_error)
+    }
+}
+
+impl StdError for FromEnvError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::IoError(io_error) => Some(io_error),
+            _ => None,
+        }
+    }
+}
+
+/// Possible errors for [`Client::configure_and_run`] and its variants,
+/// wrapped in an [`io::Error`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ConfigureError {
+    /// There is no cross process jobserver support on this platform, so
+    /// the client cannot be passed to a child process.
+    Unsupported,
+    /// The semaphore of the client has no name on Windows, e.g. one created
+    /// by `Client::new_unnamed`, so it can only be passed to a child with
+    /// `Client::duplicate_into_process`.
+    Unnamed,
+}
+
+impl fmt::Display for ConfigureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported => {
+                f.write_str("cross process jobserver is not supported on this platform")
+            }
+            Self::Unnamed => f.write_str(
+                "the jobserver semaphore has no name and can only be passed to a child by duplicating its handle",
+            ),
+        }
+    }
+}
+
+impl StdError for ConfigureError {}
+
+impl From<ConfigureError> for io::Error {
+    fn from(err: ConfigureError) -> Self {
+        let kind = match err {
+            ConfigureError::Unsupported | ConfigureError::Unnamed => io::ErrorKind::Unsupported,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Possible errors for [`Client::try_acquire`], wrapped in an
+/// [`io::Error`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum TryAcquireError {
+    /// The jobserver needs to be set up for non-blocking acquire first,
+    /// with [`Client::into_try_acquire_client`].
+    ConversionRequired,
+}
+
+impl fmt::Display for TryAcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConversionRequired => f.write_str(
+                "non-blocking acquire requires converting the client into a `TryAcquireClient`",
+            ),
+        }
+    }
+}
+
+impl StdError for TryAcquireError {}
+
+impl From<TryAcquireError> for io::Error {
+    fn from(err: TryAcquireError) -> Self {
+        let kind = match err {
+            TryAcquireError::ConversionRequired => io::ErrorKind::Unsupported,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Possible errors for [`Client::acquire_with_cancel`], wrapped in an
+/// [`io::Error`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum AcquireError {
+    /// The [`CancellationFlag`] passed to it is cancelled.
+    Cancelled,
+}
+
+impl fmt::Display for AcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cancelled => f.write_str("acquire is cancelled"),
+        }
+    }
+}
+
+impl StdError for AcquireError {}
+
+impl From<AcquireError> for io::Error {
+    fn from(err: AcquireError) -> Self {
+        let kind = match err {
+            AcquireError::Cancelled => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// How non-blocking acquire is implemented, returned by
+/// [`TryAcquireClient::mode`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum TryAcquireMode {
+    /// The jobserver supports non-blocking acquire natively, e.g. the
+    /// semaphore on windows.
+    Native,
+    /// A new file description of the jobserver with `O_NONBLOCK` set is
+    /// used, other processes are not affected.
+    IndependentDescription,
+    /// A helper thread reads from the jobserver on demand, other processes
+    /// are not affected.
+    HelperThread,
+    /// `O_NONBLOCK` is set on the jobserver shared with other processes,
+    /// which breaks make < 4.4.
+    SharedNonblocking,
+}
+
 /// Extension of [`Client`] that supports non-blocking acquire.
-#[derive(Debug, derive_destructure2::destructure)]
+#[derive(Debug)]
 pub struct TryAcquireClient(Client);
 
 impl ops::Deref for TryAcquireClient {
@@ -746,24 +4180,64 @@ impl ops::Deref for TryAcquireClient {
 }
 
 impl TryAcquireClient {
+    /// Another instance of this `TryAcquireClient`, which keeps non-blocking
+    /// acquire set up just like `self`.
+    #[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+    pub(crate) fn share(&self) -> Self {
+        #[cfg(unix)]
+        {
+            *self.0 .0.acitve_try_acquire_client_count() += 1;
+        }
+        Self(self.0.clone())
+    }
+
     /// Similar to [`Client::acquire`], but returns `Ok(None)`
     /// instead of bocking, if there is no token available.
     pub fn try_acquire(&self) -> io::Result<Option<Acquired>> {
-        match self.0 .0.inner.try_acquire() {
-            Ok(Some(data)) => Ok(Some(Acquired::new(&self.0, data))),
-            Ok(None) => Ok(None),
-            Err(err) => Err(err),
+        self.0.try_acquire_inner()
+    }
+
+    /// Acquires every token available right now, up to `max` if set,
+    /// without blocking, e.g. to claim capacity for a burst of queued work.
+    ///
+    /// Returns an empty `Vec` if there is no token available.
+    ///
+    /// # Errors
+    ///
+    /// If an I/O error happens before any token is acquired, it is
+    /// returned. Otherwise it is ignored and the tokens acquired so far are
+    /// returned.
+    pub fn try_acquire_all(&self, max: Option<usize>) -> io::Result<Vec<Acquired>> {
+        let max = max.unwrap_or(usize::MAX);
+        let mut tokens = Vec::new();
+        while tokens.len() < max {
+            match self.try_acquire() {
+                Ok(Some(token)) => tokens.push(token),
+                Ok(None) => break,
+                Err(err) if tokens.is_empty() => return Err(err),
+                Err(_) => break,
+            }
         }
+        Ok(tokens)
     }
 
     /// Similar to [`Client::acquire_raw`], but returns `Ok(None)`
     /// instead of blocking, if there is no token available.
     pub fn try_acquire_raw(&self) -> io::Result<Option<()>> {
-        match self.0 .0.inner.try_acquire() {
-            Ok(Some(_)) => Ok(Some(())),
-            Ok(None) => Ok(None),
-            Err(err) => Err(err),
-        }
+        let obtained = self
+            .0
+             .0
+            .acquire_with(Instant::now(), None, imp::Client::try_acquire)?;
+        Ok(obtained.map(|obtained| self.0 .0.acquired_raw(obtained.into_data())))
+    }
+
+    /// Return how non-blocking acquire is implemented for this client.
+    pub fn mode(&self) -> TryAcquireMode {
+        #[cfg(unix)]
+        return self.0 .0.inner.try_acquire_mode();
+
+        #[cfg(not(unix))]
+        return TryAcquireMode::Native;
     }
 
     #[cfg(unix)]
@@ -772,7 +4246,7 @@ impl TryAcquireClient {
         *active_try_acquire_client_count -= 1;
 
         if *active_try_acquire_client_count == 0 {
-            self.0 .0.inner.set_blocking()?;
+            self.0 .0.inner.stop_try_acquire()?;
         }
 
         Ok(())
@@ -783,7 +4257,14 @@ impl TryAcquireClient {
         #[cfg(unix)]
         self.cleanup()?;
 
-        Ok(self.destructure().0)
+        Ok(self.into_client())
+    }
+
+    /// Move the [`Client`] out without running `Drop`.
+    fn into_client(self) -> Client {
+        let this = mem::ManuallyDrop::new(self);
+        // Safety: `this` is never used or dropped afterwards.
+        unsafe { ptr::read(&this.0) }
     }
 }
 
@@ -794,9 +4275,58 @@ impl Drop for TryAcquireClient {
     }
 }
 
+/// Returns the read end of the jobserver.
+#[cfg(unix)]
+impl std::os::unix::prelude::AsRawFd for Client {
+    fn as_raw_fd(&self) -> std::os::unix::prelude::RawFd {
+        self.0.inner.get_read_fd()
+    }
+}
+
+/// Returns the read end of the jobserver.
+#[cfg(unix)]
+impl std::os::unix::prelude::AsFd for Client {
+    fn as_fd(&self) -> std::os::unix::prelude::BorrowedFd<'_> {
+        self.0.inner.get_read_borrowed_fd()
+    }
+}
+
+/// Returns the handle to the semaphore used by the jobserver.
+#[cfg(windows)]
+impl std::os::windows::io::AsRawHandle for Client {
+    fn as_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.0.inner.get_raw_handle()
+    }
+}
+
+/// Returns the handle to the semaphore used by the jobserver.
+#[cfg(windows)]
+impl std::os::windows::io::AsHandle for Client {
+    fn as_handle(&self) -> std::os::windows::io::BorrowedHandle<'_> {
+        self.0.inner.get_borrowed_handle()
+    }
+}
+
+/// Returns the fd which becomes readable once
+/// [`TryAcquireClient::try_acquire`] might succeed.
+///
+/// This is the read end of the jobserver, unless a helper thread is used
+/// to avoid setting `O_NONBLOCK` on it, see
+/// [`Client::into_try_acquire_client`].
 #[cfg(unix)]
 impl std::os::unix::prelude::AsRawFd for TryAcquireClient {
     fn as_raw_fd(&self) -> std::os::unix::prelude::RawFd {
-        self.0 .0.inner.get_read_fd()
+        self.0 .0.inner.get_try_acquire_fd()
+    }
+}
+
+#[cfg(unix)]
+impl std::os::unix::prelude::AsFd for TryAcquireClient {
+    fn as_fd(&self) -> std::os::unix::prelude::BorrowedFd<'_> {
+        use std::os::unix::prelude::{AsRawFd, BorrowedFd};
+
+        // SAFETY: The fd is kept open as long as any `TryAcquireClient`
+        // of this jobserver is alive.
+        unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) }
     }
 }
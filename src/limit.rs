@@ -0,0 +1,79 @@
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    io,
+    num::NonZeroUsize,
+    thread,
+};
+
+use crate::parse_jobs;
+
+/// Environment variables holding a job limit, in order of precedence.
+const LIMIT_VARS: [&str; 3] = ["CARGO_BUILD_JOBS", "JOBS", "NPROC"];
+
+/// Read the job limit to pass to [`Client::new`](crate::Client::new) from
+/// the environment, following the conventions of common build tools.
+///
+/// The first of `CARGO_BUILD_JOBS`, `JOBS` and `NPROC` which is set is
+/// used, where `default` or `0` means the number of CPUs and, as in
+/// cargo, a negative number `-N` means the number of CPUs minus `N`, but
+/// at least 1. Otherwise the last `-jN` (or `--jobs=N`) in `MAKEFLAGS` is
+/// used.
+///
+/// Returns `Ok(None)` if no limit is set.
+///
+/// # Errors
+///
+/// Returns an error of kind [`io::ErrorKind::InvalidData`] if the value of
+/// `CARGO_BUILD_JOBS`, `JOBS` or `NPROC` is not valid.
+pub fn limit_from_env() -> io::Result<Option<NonZeroUsize>> {
+    limit_from_vars(|name| env::var_os(name))
+}
+
+/// Same as [`limit_from_env`], except that the variables are looked up
+/// with `var` instead of in the environment of the current process, e.g.
+/// in the environment configured for a child process.
+pub fn limit_from_vars<F>(mut var: F) -> io::Result<Option<NonZeroUsize>>
+where
+    F: FnMut(&str) -> Option<OsString>,
+{
+    for name in LIMIT_VARS {
+        if let Some(value) = var(name) {
+            return parse_limit(&value).map(Some).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid job limit {}={:?}", name, value),
+                )
+            });
+        }
+    }
+
+    let makeflags = var("CARGO_MAKEFLAGS")
+        .or_else(|| var("MAKEFLAGS"))
+        .or_else(|| var("MFLAGS"));
+
+    Ok(makeflags.and_then(|makeflags| {
+        let makeflags = makeflags.to_str()?.as_bytes();
+        parse_jobs(makeflags.split(u8::is_ascii_whitespace)).and_then(NonZeroUsize::new)
+    }))
+}
+
+fn parse_limit(value: &OsStr) -> Option<NonZeroUsize> {
+    let value = value.to_str()?.trim();
+    if value == "default" {
+        return Some(ncpu());
+    }
+
+    match value.parse::<isize>().ok()? {
+        0 => Some(ncpu()),
+        limit if limit > 0 => NonZeroUsize::new(limit as usize),
+        limit => {
+            let limit = ncpu().get().saturating_sub(limit.unsigned_abs());
+            Some(NonZeroUsize::new(limit).unwrap_or(NonZeroUsize::new(1).unwrap()))
+        }
+    }
+}
+
+fn ncpu() -> NonZeroUsize {
+    thread::available_parallelism().unwrap_or(NonZeroUsize::new(1).unwrap())
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{imp, ClientInner};
+
+/// Tokens released by a [`Client`](crate::Client) with
+/// [`Client::set_linger`](crate::Client::set_linger), kept for reuse
+/// before being written back to the jobserver.
+#[derive(Debug, Default)]
+pub(crate) struct Linger {
+    state: Mutex<LingerState>,
+    cvar: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct LingerState {
+    duration: Duration,
+    /// Kept tokens along with when they have to be released.
+    tokens: VecDeque<(imp::Acquired, Instant)>,
+    /// Whether a thread releasing expired tokens is running.
+    ///
+    /// It holds a strong reference to the client and exits once there
+    /// is no token left, so that the client outlives the kept tokens.
+    thread_running: bool,
+}
+
+impl Linger {
+    fn state(&self) -> MutexGuard<'_, LingerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn set_duration(&self, duration: Duration) {
+        self.state().duration = duration;
+        // Let the thread release tokens that expire earlier now.
+        self.cvar.notify_one();
+    }
+
+    /// Take the most recently kept token.
+    pub(crate) fn take(&self) -> Option<imp::Acquired> {
+        self.state().tokens.pop_back().map(|(token, _)| token)
+    }
+
+    /// Return the number of kept tokens.
+    pub(crate) fn len(&self) -> usize {
+        self.state().tokens.len()
+    }
+
+    /// Forget all kept tokens without releasing them.
+    pub(crate) fn clear(&self) {
+        self.state().tokens.clear();
+        self.cvar.notify_one();
+    }
+
+    /// Return false if `token` should be released right away.
+    pub(crate) fn keep(client: &Arc<ClientInner>, token: &imp::Acquired) -> bool {
+        let linger = &client.linger;
+        let mut state = linger.state();
+
+        if state.duration.is_zero() {
+            return false;
+        }
+
+        if !state.thread_running {
+            let client = client.clone();
+            let spawned = thread::Builder::new()
+                .name("jobslot-linger".into())
+                .spawn(move || release_expired(&client));
+
+            if spawned.is_err() {
+                return false;
+            }
+            state.thread_running = true;
+        }
+
+        let expiry = Instant::now() + state.duration;
+        state.tokens.push_back((token.clone(), expiry));
+        linger.cvar.notify_one();
+
+        true
+    }
+}
+
+fn release_expired(client: &ClientInner) {
+    let linger = &client.linger;
+    let mut state = linger.state();
+
+    loop {
+        let now = Instant::now();
+        let duration = state.duration;
+
+        // Tokens might be kept with a longer duration than the current one.
+        let expiry = match state.tokens.front() {
+            Some((_, expiry)) => (*expiry).min(now + duration),
+            None => break,
+        };
+
+        if expiry > now {
+            state = linger
+                .cvar
+                .wait_timeout(state, expiry - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        } else if let Some((token, _)) = state.tokens.pop_front() {
+            drop(state);
+            drop(client.release_token(Some(&token)));
+            state = linger.state();
+        }
+    }
+
+    state.thread_running = false;
+}
@@ -0,0 +1,98 @@
+use std::{fmt::Write, time::Duration};
+
+use crate::sync::{Mutex, MutexGuard};
+
+/// Upper bounds of the buckets of the acquire latency histogram, in
+/// seconds.
+const BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 10.0];
+
+/// Metrics of a client and its clones, exported by
+/// [`Client::gather`](crate::Client::gather).
+#[derive(Debug, Default)]
+pub(crate) struct Metrics(Mutex<State>);
+
+#[derive(Debug, Default)]
+struct State {
+    held: usize,
+    /// Non-cumulative counts of acquires per bucket, with the last one for
+    /// those slower than all of [`BUCKETS`].
+    buckets: [u64; BUCKETS.len() + 1],
+    latency_sum: Duration,
+    failures: u64,
+}
+
+impl Metrics {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.0.lock()
+    }
+
+    pub(crate) fn acquired(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|le| seconds <= *le)
+            .unwrap_or(BUCKETS.len());
+
+        let mut state = self.lock();
+        state.held += 1;
+        state.buckets[bucket] += 1;
+        state.latency_sum += latency;
+    }
+
+    pub(crate) fn released(&self) {
+        let mut state = self.lock();
+        state.held = state.held.saturating_sub(1);
+    }
+
+    pub(crate) fn failed(&self) {
+        self.lock().failures += 1;
+    }
+
+    /// Render the metrics in the Prometheus text exposition format, with
+    /// `available` tokens if known.
+    pub(crate) fn gather(&self, available: Option<usize>) -> String {
+        let state = self.lock();
+        let mut out = String::new();
+
+        if let Some(available) = available {
+            gauge(
+                &mut out,
+                "jobslot_tokens_available",
+                "Tokens available in the jobserver.",
+                available,
+            );
+        }
+        gauge(
+            &mut out,
+            "jobslot_tokens_held",
+            "Tokens acquired from the jobserver by this process and not yet released.",
+            state.held,
+        );
+
+        let name = "jobslot_acquire_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Time spent acquiring tokens.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut count = 0;
+        for (le, n) in BUCKETS.iter().zip(&state.buckets) {
+            count += n;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, count);
+        }
+        count += state.buckets[BUCKETS.len()];
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, state.latency_sum.as_secs_f64());
+        let _ = writeln!(out, "{}_count {}", name, count);
+
+        let name = "jobslot_acquire_failures_total";
+        let _ = writeln!(out, "# HELP {} Acquires which returned an error.", name);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(out, "{} {}", name, state.failures);
+
+        out
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
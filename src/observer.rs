@@ -0,0 +1,58 @@
+use std::{io, sync::Arc, time::Duration};
+
+use crate::{AvailabilityWatch, ClientInner, Stats};
+
+/// A read-only handle to a jobserver, returned by
+/// [`Client::observe`](crate::Client::observe).
+///
+/// It can report the tokens available and watch the token flow of the
+/// client it is created from, but it has no way to acquire or release
+/// tokens, so that dashboards and linting tools holding it cannot leak or
+/// hold on to them.
+///
+/// Counting the available tokens is passive, unlike
+/// [`Client::available`](crate::Client::available): on windows the count
+/// of the semaphore is queried instead of taking a token for a moment, and
+/// the tokens of [`Client::new_lazy`](crate::Client::new_lazy) not issued
+/// yet are counted without writing any of them.
+#[derive(Clone, Debug)]
+pub struct Observer(Arc<ClientInner>);
+
+impl Observer {
+    pub(crate) fn new(client: Arc<ClientInner>) -> Self {
+        Self(client)
+    }
+
+    /// Same as [`Client::available`](crate::Client::available), without
+    /// its side effects.
+    ///
+    /// # Errors
+    ///
+    /// On windows, returns an error of kind [`io::ErrorKind::Unsupported`]
+    /// if the semaphore is inherited as a handle without the access right
+    /// to query it, and has no name to open it again with that right.
+    pub fn available(&self) -> io::Result<usize> {
+        self.0.peek_available()
+    }
+
+    /// Same as [`Client::jobs`](crate::Client::jobs).
+    pub fn jobs(&self) -> Option<usize> {
+        self.0.jobs
+    }
+
+    /// Same as [`Client::id`](crate::Client::id), so that the observer can
+    /// be matched with the client it is created from.
+    pub fn id(&self) -> usize {
+        self.0.id()
+    }
+
+    /// Same as [`Client::stats`](crate::Client::stats).
+    pub fn stats(&self) -> Stats {
+        self.0.stats.get(self.0.jobs)
+    }
+
+    /// Same as [`Client::watch_available`](crate::Client::watch_available).
+    pub fn watch_available(&self, interval: Duration) -> io::Result<AvailabilityWatch> {
+        AvailabilityWatch::new(&self.0, interval)
+    }
+}
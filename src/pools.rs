@@ -0,0 +1,101 @@
+use std::{collections::BTreeMap, io, sync::Arc};
+
+use crate::{sync::Mutex, Acquired, Client};
+
+/// Several named jobservers managed together, e.g. `"cpu"` for cores and
+/// `"mem-GB"` for gigabytes of memory, since builds are often memory-bound
+/// rather than CPU-bound.
+///
+/// Each pool is a regular [`Client`], so a pool shared with make (usually
+/// the CPU one) can be passed down to child processes with
+/// [`Client::configure_make_and_run`] after getting it with
+/// [`Pools::get`].
+#[derive(Clone, Debug, Default)]
+pub struct Pools {
+    // Ordered by name, which is also the order of acquisition.
+    pools: BTreeMap<Box<str>, Client>,
+    /// Held while acquiring more than one token, shared between clones.
+    acquiring: Arc<Mutex<()>>,
+}
+
+impl Pools {
+    /// Create an empty set of pools.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a pool named `name`, replacing the existing one if any.
+    pub fn insert(&mut self, name: &str, client: Client) -> &mut Self {
+        self.pools.insert(name.into(), client);
+        self
+    }
+
+    /// Get the pool named `name`.
+    pub fn get(&self, name: &str) -> Option<&Client> {
+        self.pools.get(name)
+    }
+
+    /// Acquire `n` tokens from each `(pool, n)` in `resources`, blocking
+    /// until all of them are acquired.
+    ///
+    /// Acquisition is all-or-nothing: if any of the acquires fails, the
+    /// tokens acquired so far are released before returning the error.
+    ///
+    /// Requests of more than one token in total are served one at a time
+    /// across this [`Pools`] and its clones, so that two of them cannot
+    /// each hold part of what they need while waiting for the other, e.g.
+    /// two requests of 3 tokens from a pool of 4 getting 2 each. This does
+    /// not cover other processes, or other [`Pools`] sharing the jobservers,
+    /// which might still hold tokens while waiting for more.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] without
+    /// acquiring anything if any of the pools does not exist.
+    pub fn acquire(&self, resources: &[(&str, usize)]) -> io::Result<PoolTokens> {
+        let mut requests = Vec::with_capacity(resources.len());
+        for (name, n) in resources {
+            let client = self.pools.get_key_value(*name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("pool `{}` does not exist", name),
+                )
+            })?;
+            requests.push((client, *n));
+        }
+        requests.sort_by_key(|((name, _), _)| *name);
+
+        let total = requests.iter().map(|(_, n)| n).sum();
+        let _acquiring = if total > 1 {
+            Some(self.acquiring.lock())
+        } else {
+            None
+        };
+
+        let mut tokens = Vec::with_capacity(total);
+        for ((_, client), n) in requests {
+            for _ in 0..n {
+                // On error, `tokens` are dropped and thus released.
+                tokens.push(client.acquire()?);
+            }
+        }
+
+        Ok(PoolTokens { tokens })
+    }
+}
+
+/// Tokens acquired by [`Pools::acquire`], released when dropped.
+#[derive(Debug)]
+pub struct PoolTokens {
+    tokens: Vec<Acquired>,
+}
+
+impl PoolTokens {
+    /// Return the total number of tokens held across all pools.
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Return true if no token is held.
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    io::{self, Read, Write},
+    sync::Arc,
+    thread,
+};
+
+use crate::ClientInner;
+
+/// Write one byte per requested token to the preemption channel.
+pub(crate) fn request(client: &ClientInner, tokens: usize) -> io::Result<()> {
+    // Do not block once the channel is full of unanswered requests.
+    let file = client.inner.open_preemption_channel(true)?;
+
+    let buf = [b'!'; 64];
+    let mut left = tokens;
+    while left > 0 {
+        let n = left.min(buf.len());
+        match (&file).write(&buf[..n]) {
+            Ok(written) => left -= written,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Spawn a thread answering requests on the preemption channel.
+pub(crate) fn listen<F>(client: &Arc<ClientInner>, mut callback: F) -> io::Result<()>
+where
+    F: FnMut(usize) + Send + 'static,
+{
+    let file = client.inner.open_preemption_channel(false)?;
+    let client = Arc::downgrade(client);
+
+    thread::Builder::new()
+        .name("jobslot-preemption".into())
+        .spawn(move || {
+            let mut buf = [0; 64];
+            loop {
+                let mut left = match (&file).read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+
+                let client = match client.upgrade() {
+                    Some(client) => client,
+                    None => break,
+                };
+
+                // Tokens kept by `Client::set_linger` are idle, so release
+                // them first.
+                while left > 0 {
+                    match client.linger.take() {
+                        Some(token) => {
+                            drop(client.release_token(Some(&token)));
+                            left -= 1;
+                        }
+                        None => break,
+                    }
+                }
+                drop(client);
+
+                if left > 0 {
+                    callback(left);
+                }
+            }
+        })?;
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::{
+    fs, io,
+    time::{Duration, Instant},
+};
+
+/// A governor delaying acquires while the system is under memory or I/O
+/// pressure, set with
+/// [`Client::set_pressure_governor`](crate::Client::set_pressure_governor).
+///
+/// It watches the `some avg10` stall percentages of `/proc/pressure/memory`
+/// and `/proc/pressure/io` ([PSI]), i.e. the share of the last ten seconds
+/// during which at least one task was stalled on the resource. While any
+/// of them exceeds its threshold, acquiring a token from the jobserver is
+/// delayed, up to [`PressureGovernor::max_delay`], so that e.g. link-heavy
+/// phases of a build do not run the machine out of memory. Tokens are
+/// still acquired from the jobserver as usual afterwards, so other
+/// processes using it are not affected.
+///
+/// If PSI is not available, e.g. on kernels older than 4.20 or built
+/// without it, acquires are never delayed.
+///
+/// [PSI]: https://docs.kernel.org/accounting/psi.html
+#[derive(Clone, Debug, PartialEq)]
+pub struct PressureGovernor {
+    /// Threshold of the memory stall percentage, or `None` to ignore it.
+    ///
+    /// Defaults to `Some(10.0)`.
+    pub memory: Option<f64>,
+
+    /// Threshold of the I/O stall percentage, or `None` to ignore it.
+    ///
+    /// Defaults to `Some(40.0)`.
+    pub io: Option<f64>,
+
+    /// How often the stall percentages are checked while delaying an
+    /// acquire.
+    ///
+    /// Defaults to 250ms.
+    pub poll_interval: Duration,
+
+    /// Maximum delay of an acquire, after which it proceeds regardless of
+    /// the pressure, so that builds keep making progress.
+    ///
+    /// Defaults to 30s.
+    pub max_delay: Duration,
+}
+
+impl Default for PressureGovernor {
+    fn default() -> Self {
+        Self {
+            memory: Some(10.0),
+            io: Some(40.0),
+            poll_interval: Duration::from_millis(250),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl PressureGovernor {
+    /// Return true if any of the stall percentages exceeds its threshold.
+    pub fn is_under_pressure(&self) -> bool {
+        [("memory", self.memory), ("io", self.io)]
+            .iter()
+            .any(|(resource, threshold)| match (threshold, stall(resource)) {
+                (Some(threshold), Some(stall)) => stall > *threshold,
+                _ => false,
+            })
+    }
+
+    /// Wait with `sleep` while under pressure, for up to `max_delay` or
+    /// until `deadline`, returning the first error of `sleep`, e.g. once
+    /// the acquire is cancelled.
+    pub(crate) fn throttle(
+        &self,
+        deadline: Option<Instant>,
+        mut sleep: impl FnMut(Duration) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let max_deadline = Instant::now() + self.max_delay;
+        let deadline = deadline.map_or(max_deadline, |deadline| deadline.min(max_deadline));
+
+        while self.is_under_pressure() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            sleep(self.poll_interval.min(deadline - now))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the `some avg10` stall percentage of `resource`, or `None` if it
+/// cannot be read.
+fn stall(resource: &str) -> Option<f64> {
+    let content = fs::read_to_string(format!("/proc/pressure/{}", resource)).ok()?;
+    let some = content.lines().find(|line| line.starts_with("some "))?;
+    some.split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
@@ -0,0 +1,338 @@
+//! Parsing and formatting of the values of `--jobserver-auth=` and
+//! `--jobserver-fds=` in `MAKEFLAGS`, following the same rules as
+//! [`Client::from_env`](crate::Client::from_env), for tools wrapping or
+//! analyzing make invocations without connecting to the jobserver.
+//!
+//! # Examples
+//!
+//! ```
+//! use jobslot::protocol::JobserverAuth;
+//!
+//! let auth = JobserverAuth::from_makeflags("-j4 --jobserver-auth=fifo:/tmp/fifo")
+//!     .unwrap()
+//!     .unwrap();
+//! assert_eq!(auth, JobserverAuth::Fifo("/tmp/fifo".into()));
+//! assert_eq!(auth.to_string(), "fifo:/tmp/fifo");
+//! ```
+
+use std::{error::Error as StdError, fmt, path::PathBuf, str::FromStr};
+
+/// A jobserver as named by the value of `--jobserver-auth=`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum JobserverAuth {
+    /// `fifo:PATH`, a named pipe used by make >= 4.4 on unix.
+    Fifo(PathBuf),
+
+    /// `R,W`, the read and write ends of an inherited pipe on unix.
+    ///
+    /// Negative fds, e.g. `-2,-2`, mean that the jobserver is disabled on
+    /// purpose by make, see [`JobserverAuth::is_disabled`].
+    Pipe {
+        /// The fd of the read end.
+        read: i32,
+        /// The fd of the write end.
+        write: i32,
+    },
+
+    /// `NAME`, a named semaphore on windows, also parsed from `sem:NAME`.
+    /// It is formatted without the prefix, as make does.
+    Semaphore(String),
+
+    /// `handle:N`, the value of a semaphore handle duplicated into the
+    /// process by `Client::duplicate_into_process` on windows. This is
+    /// specific to this crate.
+    Handle(u64),
+}
+
+impl JobserverAuth {
+    /// Parse the value of `--jobserver-auth=` or `--jobserver-fds=`.
+    pub fn parse(auth: &str) -> Result<Self, ParseAuthError> {
+        let err = || ParseAuthError(auth.into());
+
+        if let Some(path) = auth.strip_prefix("fifo:") {
+            return Some(path)
+                .filter(|path| !path.is_empty())
+                .map(|path| Self::Fifo(path.into()))
+                .ok_or_else(err);
+        }
+
+        if let Some(name) = auth.strip_prefix("sem:") {
+            return Some(name)
+                .filter(|name| !name.is_empty())
+                .map(|name| Self::Semaphore(name.into()))
+                .ok_or_else(err);
+        }
+
+        if let Some(handle) = auth.strip_prefix("handle:") {
+            return handle.parse().map(Self::Handle).map_err(|_| err());
+        }
+
+        match auth.split_once(',') {
+            Some((read, write)) => Ok(Self::Pipe {
+                read: read.parse().map_err(|_| err())?,
+                write: write.parse().map_err(|_| err())?,
+            }),
+            None if auth.is_empty() => Err(err()),
+            None => Ok(Self::Semaphore(auth.into())),
+        }
+    }
+
+    /// Parse the jobserver in `makeflags`, i.e. the last `--jobserver-auth=`
+    /// or, if there is none, the last `--jobserver-fds=`.
+    ///
+    /// Returns `None` if `makeflags` has neither.
+    pub fn from_makeflags(makeflags: &str) -> Option<Result<Self, ParseAuthError>> {
+        let auth = select_auth(makeflags.as_bytes().split(u8::is_ascii_whitespace))?;
+        // Both the prefix and the separators are ASCII.
+        Some(Self::parse(std::str::from_utf8(auth).unwrap()))
+    }
+
+    /// Return true if this is make's way of saying that the jobserver is
+    /// disabled on purpose, i.e. negative fds.
+    pub fn is_disabled(&self) -> bool {
+        match self {
+            Self::Pipe { read, write } => *read < 0 || *write < 0,
+            _ => false,
+        }
+    }
+
+    /// Translate the path of a fifo exported by a Cygwin or MSYS2 make, e.g.
+    /// `/tmp/GMfifo1`, to a path a native windows process can open, e.g.
+    /// `C:\msys64\tmp\GMfifo1`. Other jobservers are returned as is.
+    pub fn to_native(&self, translation: &PathTranslation) -> Self {
+        match self {
+            Self::Fifo(path) => match path.to_str() {
+                Some(path) => Self::Fifo(translation.to_native(path).into()),
+                None => self.clone(),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// The reverse of [`JobserverAuth::to_native`], to pass the fifo of a
+    /// native windows process to a Cygwin or MSYS2 make.
+    pub fn to_posix(&self, translation: &PathTranslation) -> Self {
+        match self {
+            Self::Fifo(path) => match path.to_str() {
+                Some(path) => Self::Fifo(translation.to_posix(path).into()),
+                None => self.clone(),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// Format the arguments to put in `MAKEFLAGS` to pass this jobserver,
+    /// without the `-jN`.
+    ///
+    /// Pipes are passed as both `--jobserver-fds=` and `--jobserver-auth=`,
+    /// so that make < 4.2 understands them too, like
+    /// [`MakeflagsStyle::Compatible`](crate::MakeflagsStyle::Compatible).
+    pub fn to_makeflags(&self) -> String {
+        match self {
+            Self::Pipe { .. } => format!("--jobserver-fds={0} --jobserver-auth={0}", self),
+            _ => format!("--jobserver-auth={}", self),
+        }
+    }
+}
+
+/// Parse the number of jobs in `makeflags`, i.e. the last `-jN`,
+/// `--jobs=N` or `--jobs N`, which [`Client::jobs`](crate::Client::jobs)
+/// returns for a client created by
+/// [`Client::from_env`](crate::Client::from_env).
+///
+/// Returns `None` if there is none, or only a bare `-j` or `--jobs`.
+///
+/// # Examples
+///
+/// ```
+/// use jobslot::protocol::jobs_from_makeflags;
+///
+/// assert_eq!(jobs_from_makeflags("-j2 --jobs=5"), Some(5));
+/// assert_eq!(jobs_from_makeflags("-j --jobserver-auth=3,4"), None);
+/// ```
+pub fn jobs_from_makeflags(makeflags: &str) -> Option<usize> {
+    crate::parse_jobs(makeflags.as_bytes().split(u8::is_ascii_whitespace))
+}
+
+impl fmt::Display for JobserverAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fifo(path) => write!(f, "fifo:{}", path.display()),
+            Self::Pipe { read, write } => write!(f, "{},{}", read, write),
+            Self::Semaphore(name) => f.write_str(name),
+            Self::Handle(handle) => write!(f, "handle:{}", handle),
+        }
+    }
+}
+
+impl FromStr for JobserverAuth {
+    type Err = ParseAuthError;
+
+    fn from_str(auth: &str) -> Result<Self, Self::Err> {
+        Self::parse(auth)
+    }
+}
+
+/// Translation between the paths of Cygwin or MSYS2 and native windows
+/// paths, like `cygpath` does, for [`JobserverAuth::to_native`] and
+/// [`JobserverAuth::to_posix`].
+///
+/// Drives are mounted at `/cygdrive/c` on Cygwin and at `/c` on MSYS2, and
+/// other absolute paths are relative to the root of the installation.
+/// Only these default mounts are supported, not the ones of `/etc/fstab`.
+///
+/// # Examples
+///
+/// ```
+/// use jobslot::protocol::PathTranslation;
+///
+/// let msys = PathTranslation::msys(r"C:\msys64");
+/// assert_eq!(msys.to_native("/tmp/GMfifo1"), r"C:\msys64\tmp\GMfifo1");
+/// assert_eq!(msys.to_native("/d/build/fifo"), r"D:\build\fifo");
+/// assert_eq!(msys.to_posix(r"D:\build\fifo"), "/d/build/fifo");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathTranslation {
+    root: String,
+    drives: &'static str,
+}
+
+impl PathTranslation {
+    /// Translation for MSYS2 installed at `root`, e.g. `C:\msys64`.
+    pub fn msys(root: impl Into<String>) -> Self {
+        Self::new(root.into(), "/")
+    }
+
+    /// Translation for Cygwin installed at `root`, e.g. `C:\cygwin64`.
+    pub fn cygwin(root: impl Into<String>) -> Self {
+        Self::new(root.into(), "/cygdrive/")
+    }
+
+    fn new(root: String, drives: &'static str) -> Self {
+        let root = root.replace('/', "\\").trim_end_matches('\\').to_owned();
+        Self { root, drives }
+    }
+
+    /// Translate the posix path `path` to a native windows path. Relative
+    /// paths only have their separators replaced.
+    pub fn to_native(&self, path: &str) -> String {
+        if !path.starts_with('/') {
+            return path.replace('/', "\\");
+        }
+
+        if let Some(rest) = path.strip_prefix(self.drives) {
+            let mut chars = rest.chars();
+            if let (Some(drive), None | Some('/')) = (chars.next(), chars.next()) {
+                if drive.is_ascii_alphabetic() {
+                    return format!(
+                        "{}:\\{}",
+                        drive.to_ascii_uppercase(),
+                        rest[1..].trim_start_matches('/').replace('/', "\\")
+                    );
+                }
+            }
+        }
+
+        format!("{}{}", self.root, path.replace('/', "\\"))
+    }
+
+    /// Translate the native windows path `path` to a posix path. Paths
+    /// which are not absolute windows paths are returned as is.
+    pub fn to_posix(&self, path: &str) -> String {
+        let normalized = path.replace('\\', "/");
+        let root = self.root.replace('\\', "/");
+
+        if let Some(rest) = strip_prefix_ignore_case(&normalized, &root) {
+            if rest.is_empty() || rest.starts_with('/') {
+                return if rest.is_empty() {
+                    "/".into()
+                } else {
+                    rest.into()
+                };
+            }
+        }
+
+        let bytes = normalized.as_bytes();
+        if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+            let rest = normalized[2..].trim_start_matches('/');
+            return format!(
+                "{}{}/{}",
+                self.drives,
+                char::from(bytes[0].to_ascii_lowercase()),
+                rest
+            )
+            .trim_end_matches('/')
+            .to_owned();
+        }
+
+        path.into()
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = s.get(..prefix.len())?;
+    if head.eq_ignore_ascii_case(prefix) {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+/// Error returned by [`JobserverAuth::parse`] for a malformed value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseAuthError(String);
+
+impl fmt::Display for ParseAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot parse jobserver auth `{}`", self.0)
+    }
+}
+
+impl StdError for ParseAuthError {}
+
+/// Return the value of the last `--jobserver-auth=` in `makeflags`, or of
+/// the last `--jobserver-fds=` if there is none.
+pub(crate) fn select_auth<'a>(
+    makeflags: impl Iterator<Item = &'a [u8]> + Clone,
+) -> Option<&'a [u8]> {
+    // `--jobserver-auth=` is the only documented makeflags.
+    // `--jobserver-fds=` is actually an internal only makeflags, so we should
+    // always prefer `--jobserver-auth=`.
+    //
+    // Also, according to doc of makeflags, if there are multiple `--jobserver-auth=`
+    // the last one is used
+    makeflags
+        .clone()
+        .filter_map(|s| s.strip_prefix(b"--jobserver-auth="))
+        .last()
+        .or_else(|| {
+            makeflags
+                .filter_map(|s| s.strip_prefix(b"--jobserver-fds="))
+                .last()
+        })
+}
+
+/// Return true if `auth` names negative fds, e.g. `-2,-2`, which GNU make
+/// exports when the jobserver is disabled on purpose.
+///
+/// Only a pair of plain integers counts, so that e.g. the name of a
+/// semaphore containing a comma is still opened as is.
+pub(crate) fn is_disabled_auth(auth: &[u8]) -> bool {
+    let parse_fd = |fd: &[u8]| {
+        let digits = fd.strip_prefix(b"-").unwrap_or(fd);
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        std::str::from_utf8(fd).ok()?.parse::<i64>().ok()
+    };
+
+    let mut fds = auth.split(|b| *b == b',');
+    match (fds.next(), fds.next(), fds.next()) {
+        (Some(read), Some(write), None) => match (parse_fd(read), parse_fd(write)) {
+            (Some(read), Some(write)) => read < 0 || write < 0,
+            _ => false,
+        },
+        _ => false,
+    }
+}
@@ -0,0 +1,93 @@
+use std::{error::Error as StdError, fmt, io, sync::Arc};
+
+/// What to do with an error releasing a token from a drop, e.g. of
+/// [`Acquired`](crate::Acquired), set with
+/// [`Client::set_release_policy`](crate::Client::set_release_policy).
+///
+/// Releasing typically fails because the jobserver is gone, e.g. the
+/// parent make died and nothing reads the pipe anymore, see
+/// [`ReleaseError::is_jobserver_gone`]. On unix, such a release fails with
+/// `EPIPE` instead of raising `SIGPIPE`, which would kill the process if
+/// it is not ignored.
+///
+/// Errors of explicit releases, e.g. [`Acquired::release`](crate::Acquired::release)
+/// or [`Client::release_raw`](crate::Client::release_raw), are always
+/// returned to the caller instead.
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub enum ReleasePolicy {
+    /// Ignore the error, the default.
+    #[default]
+    Ignore,
+
+    /// Call the hook with the error.
+    Hook(Arc<dyn Fn(&ReleaseError) + Send + Sync>),
+
+    /// Keep the error and return it from the next acquire of the client
+    /// from the jobserver, e.g. [`Client::acquire`](crate::Client::acquire),
+    /// including try-acquires and async acquires. Only the first error is
+    /// kept until it is returned.
+    Propagate,
+}
+
+impl fmt::Debug for ReleasePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ignore => f.write_str("Ignore"),
+            Self::Hook(_) => f.write_str("Hook(..)"),
+            Self::Propagate => f.write_str("Propagate"),
+        }
+    }
+}
+
+impl ReleasePolicy {
+    /// Create a [`ReleasePolicy::Hook`] calling `hook`.
+    pub fn hook(hook: impl Fn(&ReleaseError) + Send + Sync + 'static) -> Self {
+        Self::Hook(Arc::new(hook))
+    }
+}
+
+/// Error releasing a token to the jobserver.
+#[derive(Debug)]
+pub struct ReleaseError(io::Error);
+
+impl ReleaseError {
+    pub(crate) fn new(err: io::Error) -> Self {
+        Self(err)
+    }
+
+    /// Return true if the release failed because nothing reads the
+    /// jobserver anymore, e.g. its owner exited, in which case the token
+    /// is lost and later releases fail too.
+    pub fn is_jobserver_gone(&self) -> bool {
+        self.0.kind() == io::ErrorKind::BrokenPipe
+    }
+
+    /// Return the underlying I/O error.
+    pub fn io_error(&self) -> &io::Error {
+        &self.0
+    }
+}
+
+impl fmt::Display for ReleaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_jobserver_gone() {
+            f.write_str("failed to release token: the jobserver is gone")
+        } else {
+            f.write_str("failed to release token")
+        }
+    }
+}
+
+impl StdError for ReleaseError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl From<ReleaseError> for io::Error {
+    fn from(err: ReleaseError) -> Self {
+        let kind = err.0.kind();
+        io::Error::new(kind, err)
+    }
+}
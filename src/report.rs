@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+/// How a token is obtained, returned by
+/// [`Client::acquire_with_report`](crate::Client::acquire_with_report), e.g.
+/// to debug performance differences between jobservers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AcquireReport {
+    /// What the jobserver is backed by.
+    pub backend: AcquireBackend,
+    /// Which path the token is obtained from.
+    pub path: AcquirePath,
+    /// Time spent acquiring the token.
+    pub wait: Duration,
+    /// Number of times the waiting thread is woken up, e.g. by `poll` on
+    /// unix, including the times another process takes the token first.
+    ///
+    /// On platforms other than unix, waiting is a single call, so this is
+    /// 1 if the token is not acquired on the fast path and 0 otherwise.
+    pub wakeups: u32,
+}
+
+/// What a jobserver is backed by, see [`AcquireReport::backend`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AcquireBackend {
+    /// A named fifo on unix, e.g. created by
+    /// [`Client::new_with_fifo`](crate::Client::new_with_fifo).
+    Fifo,
+    /// An anonymous pipe on unix, passed by fds.
+    Pipe,
+    /// A semaphore on windows.
+    Semaphore,
+    /// A semaphore in the current process, or shared with the host on
+    /// WASI.
+    InProcess,
+}
+
+/// Which path a token is obtained from, see [`AcquireReport::path`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AcquirePath {
+    /// A token kept by [`Client::set_linger`](crate::Client::set_linger) is
+    /// reused, without going through the jobserver.
+    Linger,
+    /// A token is available right away.
+    Fast,
+    /// No token is available right away, so the thread waits for one.
+    Fallback,
+}
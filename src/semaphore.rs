@@ -0,0 +1,317 @@
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Condvar, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+};
+
+#[cfg(not(any(all(feature = "tokio", unix), not(any(unix, windows)))))]
+use std::thread;
+
+#[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+use crate::AsyncAcquireClient;
+use crate::{sync::Mutex, Acquired, Client, TryAcquireClient};
+
+/// A semaphore backed either by an in-process counter or by the jobserver
+/// of a [`Client`], chosen at construction, so that code limiting its
+/// parallelism works the same whether or not it runs under a jobserver.
+///
+/// Cloning it returns another handle to the same semaphore.
+#[derive(Clone, Debug)]
+pub struct Semaphore(Backend);
+
+#[derive(Clone, Debug)]
+enum Backend {
+    Local(Arc<Local>),
+    Jobserver(Arc<Jobserver>),
+}
+
+struct Local {
+    state: std::sync::Mutex<LocalState>,
+    cvar: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct LocalState {
+    permits: usize,
+    /// Tasks waiting in [`Semaphore::acquire_async`], all woken up on
+    /// release.
+    wakers: Vec<Waker>,
+}
+
+#[derive(Debug)]
+struct Jobserver {
+    client: Client,
+    /// Kept alive once needed, so that [`Client::try_acquire`] works on
+    /// unix.
+    try_acquire_client: Mutex<Option<TryAcquireClient>>,
+    #[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+    async_client: Mutex<Option<AsyncAcquireClient>>,
+}
+
+/// A permit of a [`Semaphore`], given back on drop.
+#[derive(Debug)]
+pub struct SemaphorePermit(Permit);
+
+#[derive(Debug)]
+enum Permit {
+    Local(Arc<Local>),
+    /// Released by `Acquired` itself on drop.
+    Jobserver {
+        _token: Acquired,
+    },
+}
+
+impl Local {
+    fn state(&self) -> MutexGuard<'_, LocalState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn try_take(self: &Arc<Self>, state: &mut LocalState) -> Option<SemaphorePermit> {
+        if state.permits == 0 {
+            return None;
+        }
+        state.permits -= 1;
+        Some(SemaphorePermit(Permit::Local(self.clone())))
+    }
+
+    fn release(&self) {
+        let wakers = {
+            let mut state = self.state();
+            state.permits += 1;
+            std::mem::take(&mut state.wakers)
+        };
+        self.cvar.notify_one();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Semaphore {
+    /// Create a semaphore local to this process with `permits` permits.
+    pub fn new(permits: usize) -> Self {
+        Self(Backend::Local(Arc::new(Local {
+            state: std::sync::Mutex::new(LocalState {
+                permits,
+                wakers: Vec::new(),
+            }),
+            cvar: Condvar::new(),
+        })))
+    }
+
+    /// Create a semaphore whose permits are tokens of the jobserver of
+    /// `client`.
+    ///
+    /// Like for the client, the implicit token is not included.
+    pub fn from_client(client: Client) -> Self {
+        Self(Backend::Jobserver(Arc::new(Jobserver {
+            client,
+            try_acquire_client: Mutex::new(None),
+            #[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+            async_client: Mutex::new(None),
+        })))
+    }
+
+    /// Returns the client backing this semaphore, if any.
+    pub fn client(&self) -> Option<&Client> {
+        match &self.0 {
+            Backend::Local(_) => None,
+            Backend::Jobserver(jobserver) => Some(&jobserver.client),
+        }
+    }
+
+    /// Acquire a permit, blocking until one is available.
+    pub fn acquire(&self) -> io::Result<SemaphorePermit> {
+        match &self.0 {
+            Backend::Local(local) => {
+                let mut state = local.state();
+                loop {
+                    if let Some(permit) = local.try_take(&mut state) {
+                        return Ok(permit);
+                    }
+                    state = local
+                        .cvar
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+            Backend::Jobserver(jobserver) => jobserver
+                .client
+                .acquire()
+                .map(|token| SemaphorePermit(Permit::Jobserver { _token: token })),
+        }
+    }
+
+    /// Acquire a permit if one is available right away.
+    ///
+    /// On unix, a jobserver-backed semaphore converts a clone of its client
+    /// with [`Client::into_try_acquire_client`] on first use, failing with
+    /// [`io::ErrorKind::Unsupported`] if that would break make < `4.4`.
+    pub fn try_acquire(&self) -> io::Result<Option<SemaphorePermit>> {
+        match &self.0 {
+            Backend::Local(local) => Ok(local.try_take(&mut local.state())),
+            Backend::Jobserver(jobserver) => {
+                let token = match jobserver.client.try_acquire() {
+                    Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                        jobserver.try_acquire_client()?;
+                        jobserver.client.try_acquire()
+                    }
+                    res => res,
+                }?;
+                Ok(token.map(|token| SemaphorePermit(Permit::Jobserver { _token: token })))
+            }
+        }
+    }
+
+    /// Acquire a permit asynchronously.
+    ///
+    /// A jobserver-backed semaphore uses an [`AsyncAcquireClient`] where
+    /// available, which requires a tokio runtime on unix, and otherwise
+    /// waits for the token on a thread spawned for each call.
+    pub async fn acquire_async(&self) -> io::Result<SemaphorePermit> {
+        match &self.0 {
+            Backend::Local(local) => LocalAcquire(local).await,
+            Backend::Jobserver(jobserver) => jobserver
+                .acquire_async()
+                .await
+                .map(|token| SemaphorePermit(Permit::Jobserver { _token: token })),
+        }
+    }
+}
+
+impl From<Client> for Semaphore {
+    fn from(client: Client) -> Self {
+        Self::from_client(client)
+    }
+}
+
+impl Jobserver {
+    fn try_acquire_client(&self) -> io::Result<()> {
+        let mut try_acquire_client = self.try_acquire_client.lock();
+        if try_acquire_client.is_none() {
+            *try_acquire_client = Some(
+                self.client
+                    .clone()
+                    .into_try_acquire_client()
+                    .map_err(into_io_error)?,
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+    async fn acquire_async(&self) -> io::Result<Acquired> {
+        let async_client = {
+            let mut async_client = self.async_client.lock();
+            match &*async_client {
+                Some(async_client) => async_client.clone(),
+                None => {
+                    let try_acquire_client = self
+                        .client
+                        .clone()
+                        .into_try_acquire_client()
+                        .map_err(into_io_error)?;
+                    async_client
+                        .insert(AsyncAcquireClient::new(try_acquire_client)?)
+                        .clone()
+                }
+            }
+        };
+        async_client.acquire_owned().await
+    }
+
+    #[cfg(not(any(all(feature = "tokio", unix), not(any(unix, windows)))))]
+    async fn acquire_async(&self) -> io::Result<Acquired> {
+        let shared = Arc::new(Mutex::new(ThreadAcquire::default()));
+        thread::Builder::new()
+            .name("jobslot-semaphore".into())
+            .spawn({
+                let client = self.client.clone();
+                let shared = shared.clone();
+                // Should the future be dropped first, the token is released
+                // along with `shared`.
+                move || {
+                    let res = client.acquire();
+                    let waker = {
+                        let mut shared = shared.lock();
+                        shared.res = Some(res);
+                        shared.waker.take()
+                    };
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            })?;
+
+        ThreadAcquireFuture(shared).await
+    }
+}
+
+fn into_io_error(err: crate::IntoTryAcquireClientError) -> io::Error {
+    #[cfg(unix)]
+    if let crate::IntoTryAcquireClientError::IoError(err) = err {
+        return err;
+    }
+    io::Error::new(io::ErrorKind::Unsupported, err.to_string())
+}
+
+struct LocalAcquire<'a>(&'a Arc<Local>);
+
+impl Future for LocalAcquire<'_> {
+    type Output = io::Result<SemaphorePermit>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.state();
+        if let Some(permit) = self.0.try_take(&mut state) {
+            return Poll::Ready(Ok(permit));
+        }
+        if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(not(any(all(feature = "tokio", unix), not(any(unix, windows)))))]
+#[derive(Default)]
+struct ThreadAcquire {
+    res: Option<io::Result<Acquired>>,
+    waker: Option<Waker>,
+}
+
+#[cfg(not(any(all(feature = "tokio", unix), not(any(unix, windows)))))]
+struct ThreadAcquireFuture(Arc<Mutex<ThreadAcquire>>);
+
+#[cfg(not(any(all(feature = "tokio", unix), not(any(unix, windows)))))]
+impl Future for ThreadAcquireFuture {
+    type Output = io::Result<Acquired>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.0.lock();
+        match shared.res.take() {
+            Some(res) => Poll::Ready(res),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for SemaphorePermit {
+    fn drop(&mut self) {
+        match &self.0 {
+            Permit::Local(local) => local.release(),
+            Permit::Jobserver { .. } => (),
+        }
+    }
+}
+
+impl fmt::Debug for Local {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Local")
+            .field("permits", &self.state().permits)
+            .finish()
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    convert::TryInto,
+    ffi::CStr,
+    fs::File,
+    io,
+    os::unix::prelude::*,
+    path::Path,
+    ptr, slice,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use crate::imp::cvt;
+
+/// Identifies a region created by [`Region::create`].
+const MAGIC: u64 = u64::from_ne_bytes(*b"jobslot1");
+
+/// A slot of the region, for one process.
+#[repr(C)]
+struct Slot {
+    /// 0 if the slot is free.
+    pid: AtomicU32,
+    held: AtomicU32,
+}
+
+#[repr(C)]
+struct Header {
+    magic: AtomicU64,
+}
+
+/// A memfd mapped into memory, holding a [`Header`] followed by slots
+/// recording how many tokens each process holds.
+///
+/// Its size is sealed, so that it cannot be shrunk by another process
+/// while it is mapped, which would crash readers with `SIGBUS`.
+#[derive(Debug)]
+pub(crate) struct Region {
+    file: File,
+    ptr: *mut u8,
+    len: usize,
+}
+
+// Safety: the mapped memory is only accessed through atomics.
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+impl Region {
+    pub(crate) fn create(slots: usize) -> io::Result<Self> {
+        let len = slots
+            .checked_mul(std::mem::size_of::<Slot>())
+            .and_then(|len| len.checked_add(std::mem::size_of::<Header>()))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+        let name = CStr::from_bytes_with_nul(b"jobslot-ledger\0").unwrap();
+        let fd = cvt(unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        })?;
+        let file = unsafe { File::from_raw_fd(fd) };
+
+        file.set_len(len as u64)?;
+        cvt(unsafe {
+            libc::fcntl(
+                fd,
+                libc::F_ADD_SEALS,
+                libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL,
+            )
+        })?;
+
+        let region = Self::map(file)?;
+        region.header().magic.store(MAGIC, Ordering::Release);
+        Ok(region)
+    }
+
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+
+        // Files not supporting seals fail with `EINVAL`.
+        match cvt(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GET_SEALS) }) {
+            Ok(seals) if seals & libc::F_SEAL_SHRINK != 0 => (),
+            Ok(_) => return Err(invalid(path)),
+            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => return Err(invalid(path)),
+            Err(err) => return Err(err),
+        }
+
+        let region = Self::map(file)?;
+        if region.header().magic.load(Ordering::Acquire) != MAGIC {
+            return Err(invalid(path));
+        }
+        Ok(region)
+    }
+
+    fn map(file: File) -> io::Result<Self> {
+        let len: usize = file
+            .metadata()?
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+        if len < std::mem::size_of::<Header>() {
+            return Err(io::Error::from(io::ErrorKind::InvalidData));
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            file,
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    pub(crate) fn fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    fn header(&self) -> &Header {
+        // Safety: the mapping is page-aligned and at least as large as the
+        // header.
+        unsafe { &*self.ptr.cast::<Header>() }
+    }
+
+    fn slots(&self) -> &[Slot] {
+        let header = std::mem::size_of::<Header>();
+        let len = (self.len - header) / std::mem::size_of::<Slot>();
+        // Safety: the slots are within the mapping and aligned, since the
+        // header is 8 bytes.
+        unsafe { slice::from_raw_parts(self.ptr.add(header).cast::<Slot>(), len) }
+    }
+
+    fn find(&self, pid: u32) -> Option<&Slot> {
+        self.slots()
+            .iter()
+            .find(|slot| slot.pid.load(Ordering::Acquire) == pid)
+    }
+
+    pub(crate) fn held(&self, pid: u32) -> usize {
+        self.find(pid)
+            .map_or(0, |slot| slot.held.load(Ordering::Acquire) as usize)
+    }
+
+    pub(crate) fn entries(&self) -> Vec<(u32, usize)> {
+        self.slots()
+            .iter()
+            .filter_map(|slot| {
+                let pid = slot.pid.load(Ordering::Acquire);
+                let held = slot.held.load(Ordering::Acquire) as usize;
+                Some((pid, held)).filter(|_| pid != 0 && held != 0)
+            })
+            .collect()
+    }
+
+    pub(crate) fn remove(&self, pid: u32) {
+        if let Some(slot) = self.find(pid) {
+            slot.held.store(0, Ordering::Release);
+            slot.pid.store(0, Ordering::Release);
+        }
+    }
+
+    pub(crate) fn record(&self, pid: u32, held: usize) -> io::Result<()> {
+        if held == 0 {
+            self.remove(pid);
+            return Ok(());
+        }
+
+        let held = held.try_into().unwrap_or(u32::MAX);
+        let slot = match self.find(pid) {
+            Some(slot) => slot,
+            None => self
+                .slots()
+                .iter()
+                .find(|slot| {
+                    slot.pid
+                        .compare_exchange(0, pid, Ordering::AcqRel, Ordering::Relaxed)
+                        .is_ok()
+                })
+                .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "ledger is full"))?,
+        };
+        slot.held.store(held, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
+
+fn invalid(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} is not a shared ledger", path.display()),
+    )
+}
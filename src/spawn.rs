@@ -0,0 +1,98 @@
+use std::{ffi::OsStr, ffi::OsString, io, os::unix::io::RawFd};
+
+use crate::EnvConsumer;
+
+/// The configuration needed to pass a [`Client`](crate::Client) to a child
+/// spawned without [`std::process`], e.g. with `fork` + `exec` or
+/// `posix_spawn`.
+///
+/// Fill it with [`Client::configure_into`](crate::Client::configure_into)
+/// or [`Client::configure_make_into`](crate::Client::configure_make_into),
+/// then apply [`SpawnConfig::envs`] to the environment of the child and
+/// make the fds inheritable with [`SpawnConfig::clear_cloexec`] (after
+/// `fork`) or [`SpawnConfig::add_file_actions`] (for `posix_spawn`).
+#[derive(Clone, Debug, Default)]
+pub struct SpawnConfig {
+    envs: Vec<(OsString, OsString)>,
+    fds: Vec<RawFd>,
+}
+
+impl SpawnConfig {
+    /// Create an empty configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the environment variables to set in the child.
+    pub fn envs(&self) -> &[(OsString, OsString)] {
+        &self.envs
+    }
+
+    /// Return the fds whose `FD_CLOEXEC` flag must be cleared in the child.
+    pub fn fds(&self) -> &[RawFd] {
+        &self.fds
+    }
+
+    /// Clear `FD_CLOEXEC` of [`SpawnConfig::fds`].
+    ///
+    /// This only calls `fcntl`, so it is async-signal-safe and can be
+    /// called in the child between `fork` and `exec`.
+    pub fn clear_cloexec(&self) -> io::Result<()> {
+        for fd in &self.fds {
+            if unsafe { libc::fcntl(*fd, libc::F_SETFD, 0) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a `dup2(fd, fd)` action for each of [`SpawnConfig::fds`] to
+    /// `actions`, which clears `FD_CLOEXEC` in the spawned child.
+    ///
+    /// Note that some older libcs do not clear the flag for such actions,
+    /// in which case the fds must be made inheritable with
+    /// [`Client::set_inheritable`](crate::Client::set_inheritable) instead.
+    ///
+    /// # Safety
+    ///
+    /// `actions` must have been initialized by
+    /// `posix_spawn_file_actions_init`.
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+    ))]
+    pub unsafe fn add_file_actions(
+        &self,
+        actions: *mut libc::posix_spawn_file_actions_t,
+    ) -> io::Result<()> {
+        for fd in &self.fds {
+            match libc::posix_spawn_file_actions_adddup2(actions, *fd, *fd) {
+                0 => (),
+                err => return Err(io::Error::from_raw_os_error(err)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl EnvConsumer for SpawnConfig {
+    fn set(&mut self, key: &OsStr, value: &OsStr) {
+        self.envs.set(key, value)
+    }
+
+    fn unset(&mut self, key: &OsStr) {
+        self.envs.unset(key)
+    }
+
+    fn needs_fd(&mut self, fd: RawFd) {
+        if !self.fds.contains(&fd) {
+            self.fds.push(fd);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+use crate::sync::{Mutex, MutexGuard};
+
+/// Utilization statistics of a client and its clones, returned by
+/// [`Client::stats`](crate::Client::stats), e.g. for printing a summary of
+/// the parallelism at the end of a build.
+///
+/// Only tokens going through the jobserver are counted, not the implicit
+/// token or the ones reused by
+/// [`Client::set_linger`](crate::Client::set_linger).
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// Time since the client is created or the statistics are reset.
+    pub elapsed: Duration,
+    /// Total number of tokens acquired.
+    pub acquired: u64,
+    /// Maximum number of tokens held at the same time.
+    pub peak_held: usize,
+    /// Number of tokens held right now.
+    pub held: usize,
+    /// Cumulative time spent waiting for tokens.
+    pub wait_time: Duration,
+    /// Cumulative time tokens are held, e.g. two tokens held for one
+    /// second count as two seconds.
+    pub held_time: Duration,
+    /// Average number of tokens held divided by
+    /// [`Client::jobs`](crate::Client::jobs), or `None` if the limit is not
+    /// known or no time has elapsed.
+    pub utilization: Option<f64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct StatsRecorder(Mutex<State>);
+
+#[derive(Debug)]
+struct State {
+    since: Instant,
+    acquired: u64,
+    peak_held: usize,
+    held: usize,
+    wait_time: Duration,
+    held_time: Duration,
+    /// When `held` last changed, up to which `held_time` is accumulated.
+    updated: Instant,
+}
+
+impl State {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            since: now,
+            acquired: 0,
+            peak_held: 0,
+            held: 0,
+            wait_time: Duration::ZERO,
+            held_time: Duration::ZERO,
+            updated: now,
+        }
+    }
+
+    /// Accumulate `held_time` up to `now`.
+    fn update(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.held_time += elapsed * self.held as u32;
+        self.updated = now;
+    }
+}
+
+impl Default for StatsRecorder {
+    fn default() -> Self {
+        Self(Mutex::new(State::new()))
+    }
+}
+
+impl StatsRecorder {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.0.lock()
+    }
+
+    pub(crate) fn acquired(&self, wait: Duration) {
+        let mut state = self.lock();
+        state.update(Instant::now());
+        state.acquired += 1;
+        state.held += 1;
+        state.peak_held = state.peak_held.max(state.held);
+        state.wait_time += wait;
+    }
+
+    pub(crate) fn released(&self) {
+        let mut state = self.lock();
+        state.update(Instant::now());
+        state.held = state.held.saturating_sub(1);
+    }
+
+    /// Reset the statistics, keeping the number of tokens held.
+    pub(crate) fn reset(&self) {
+        let mut state = self.lock();
+        let held = state.held;
+        *state = State::new();
+        state.held = held;
+        state.peak_held = held;
+    }
+
+    pub(crate) fn get(&self, jobs: Option<usize>) -> Stats {
+        let mut state = self.lock();
+        let now = Instant::now();
+        state.update(now);
+
+        let elapsed = now.saturating_duration_since(state.since);
+        let utilization = match jobs {
+            Some(jobs) if !elapsed.is_zero() => {
+                Some(state.held_time.as_secs_f64() / elapsed.as_secs_f64() / jobs as f64)
+            }
+            _ => None,
+        };
+
+        Stats {
+            elapsed,
+            acquired: state.acquired,
+            peak_held: state.peak_held,
+            held: state.held,
+            wait_time: state.wait_time,
+            held_time: state.held_time,
+            utilization,
+        }
+    }
+}
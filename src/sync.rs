@@ -0,0 +1,38 @@
+//! Synchronization primitives built on top of the jobserver.
+//!
+//! [`Semaphore`] limits parallelism the same way whether it is backed by a
+//! counter in this process or by a jobserver [`Client`](crate::Client).
+
+// Locks taken on the acquire and release paths, backed by `parking_lot` if
+// the `parking_lot` feature is enabled.
+//
+// Both flavours ignore poisoning, since none of the state they protect can
+// be left inconsistent by a panic.
+
+pub use crate::semaphore::{Semaphore, SemaphorePermit};
+
+#[cfg(feature = "parking_lot")]
+pub(crate) use parking_lot::{Mutex, MutexGuard};
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) use std_mutex::{Mutex, MutexGuard};
+
+#[cfg(not(feature = "parking_lot"))]
+mod std_mutex {
+    use std::sync::PoisonError;
+
+    pub(crate) type MutexGuard<'a, T> = std::sync::MutexGuard<'a, T>;
+
+    #[derive(Debug, Default)]
+    pub(crate) struct Mutex<T>(std::sync::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub(crate) const fn new(value: T) -> Self {
+            Self(std::sync::Mutex::new(value))
+        }
+
+        pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Write},
+    process,
+    sync::Arc,
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use crate::sync::{Mutex, MutexGuard};
+
+/// A recorder of the token activity of clients, set with
+/// [`Client::set_trace_recorder`](crate::Client::set_trace_recorder), which
+/// can be exported as Chrome trace-event JSON.
+///
+/// Load the output of [`TraceRecorder::write_json`] into
+/// `chrome://tracing` or [Perfetto](https://ui.perfetto.dev) to see how
+/// long each thread waited for tokens and how many tokens were held over
+/// time, e.g. to find where the parallelism of a build collapsed.
+///
+/// Only tokens going through the jobserver are recorded, not the ones
+/// reused by [`Client::set_linger`](crate::Client::set_linger). Clones
+/// share the same events, so one recorder can be set on several clients.
+#[derive(Clone, Debug)]
+pub struct TraceRecorder(Arc<Mutex<Trace>>);
+
+#[derive(Debug)]
+struct Trace {
+    start: Instant,
+    events: Vec<Event>,
+    /// Numeric ids assigned in order of appearance, since [`ThreadId`]
+    /// cannot be converted to one on stable.
+    threads: HashMap<ThreadId, (u64, Option<String>)>,
+    held: usize,
+}
+
+#[derive(Debug)]
+struct Event {
+    kind: EventKind,
+    /// When the token is acquired or released.
+    at: Instant,
+    tid: u64,
+    /// Number of tokens held after the event.
+    held: usize,
+}
+
+#[derive(Debug)]
+enum EventKind {
+    Acquire { wait: Duration },
+    Release,
+}
+
+impl Default for TraceRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TraceRecorder {
+    /// Create a recorder without events, with timestamps relative to now.
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Trace {
+            start: Instant::now(),
+            events: Vec::new(),
+            threads: HashMap::new(),
+            held: 0,
+        })))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Trace> {
+        self.0.lock()
+    }
+
+    /// Return the number of acquires and releases recorded.
+    pub fn len(&self) -> usize {
+        self.lock().events.len()
+    }
+
+    /// Return true if nothing has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all recorded events.
+    pub fn clear(&self) {
+        self.lock().events.clear();
+    }
+
+    /// Record a token acquired after waiting since `started`.
+    pub(crate) fn record_acquire(&self, started: Instant) {
+        let at = Instant::now();
+        self.lock().push(
+            EventKind::Acquire {
+                wait: at.saturating_duration_since(started),
+            },
+            at,
+            |held| held + 1,
+        );
+    }
+
+    pub(crate) fn record_release(&self) {
+        self.lock()
+            .push(EventKind::Release, Instant::now(), |held| {
+                held.saturating_sub(1)
+            });
+    }
+
+    /// Write the recorded events as Chrome trace-event JSON to `writer`.
+    ///
+    /// Each acquire is a complete event spanning its wait for a token on
+    /// the thread acquiring it, each release is an instant event, and the
+    /// number of tokens held is a counter.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let json = self.lock().to_json();
+        writer.write_all(json.as_bytes())
+    }
+}
+
+impl Trace {
+    fn push(&mut self, kind: EventKind, at: Instant, held: impl FnOnce(usize) -> usize) {
+        let thread = thread::current();
+        let next_tid = self.threads.len() as u64 + 1;
+        let tid = self
+            .threads
+            .entry(thread.id())
+            .or_insert_with(|| (next_tid, thread.name().map(Into::into)))
+            .0;
+
+        self.held = held(self.held);
+        self.events.push(Event {
+            kind,
+            at,
+            tid,
+            held: self.held,
+        });
+    }
+
+    /// Microseconds since the creation of the recorder.
+    fn timestamp(&self, at: Instant) -> u128 {
+        at.saturating_duration_since(self.start).as_micros()
+    }
+
+    fn to_json(&self) -> String {
+        let pid = process::id();
+        let mut events = Vec::new();
+
+        let mut threads: Vec<_> = self.threads.values().collect();
+        threads.sort_unstable_by_key(|(tid, _)| *tid);
+        for (tid, name) in threads {
+            let name = match name {
+                Some(name) => escape(name),
+                None => format!("thread {}", tid),
+            };
+            events.push(format!(
+                r#"{{"name":"thread_name","ph":"M","pid":{},"tid":{},"args":{{"name":"{}"}}}}"#,
+                pid, tid, name
+            ));
+        }
+
+        for event in &self.events {
+            let ts = self.timestamp(event.at);
+            events.push(match event.kind {
+                EventKind::Acquire { wait } => {
+                    let wait = wait.as_micros();
+                    format!(
+                        r#"{{"name":"acquire","cat":"jobslot","ph":"X","ts":{},"dur":{},"pid":{},"tid":{}}}"#,
+                        ts.saturating_sub(wait),
+                        wait,
+                        pid,
+                        event.tid
+                    )
+                }
+                EventKind::Release => format!(
+                    r#"{{"name":"release","cat":"jobslot","ph":"i","s":"t","ts":{},"pid":{},"tid":{}}}"#,
+                    ts, pid, event.tid
+                ),
+            });
+            events.push(format!(
+                r#"{{"name":"tokens held","cat":"jobslot","ph":"C","ts":{},"pid":{},"args":{{"held":{}}}}}"#,
+                ts, pid, event.held
+            ));
+        }
+
+        format!(
+            r#"{{"traceEvents":[{}],"displayTimeUnit":"ms"}}"#,
+            events.join(",")
+        )
+    }
+}
+
+/// Escape `s` to be put in a JSON string.
+pub(crate) fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c < ' ' => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
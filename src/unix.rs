@@ -1,19 +1,25 @@
 use std::{
     borrow::Cow,
     convert::TryInto,
-    ffi::OsStr,
-    fmt::Write as _,
+    ffi::{CString, OsStr},
     fs::{self, File},
     io::{self, Read, Write},
     mem::{ManuallyDrop, MaybeUninit},
     os::unix::{ffi::OsStrExt, prelude::*},
     path::{Path, PathBuf},
+    process, ptr, slice,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use getrandom::getrandom;
 use libc::c_int;
 
-use crate::Command;
+use crate::{sync, Backoff, Command, FromEnvConfig, FromEnvError, RawParts, TryAcquireMode};
 
 #[derive(Debug)]
 pub struct Client {
@@ -24,14 +30,179 @@ pub struct Client {
     /// Path to the named fifo if any
     path: Option<Box<Path>>,
     /// If the Client owns the fifo, then we should remove it on drop.
-    owns_fifo: bool,
+    owns_fifo: AtomicBool,
+    /// If set, `CLOEXEC` has been cleared on `read` and `write`
+    /// permanently and `pre_run` has nothing to do.
+    inheritable: AtomicBool,
+    /// How `try_acquire` is implemented, set by `start_try_acquire`.
+    try_acquire: sync::Mutex<Option<TryAcquire>>,
+    /// Applied after losing the race for a token, set by `set_backoff`.
+    backoff: sync::Mutex<Backoff>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Acquired {
     byte: u8,
 }
 
+impl Acquired {
+    pub fn byte(&self) -> u8 {
+        self.byte
+    }
+}
+
+/// Self-pipe which becomes readable once cancelled.
+#[derive(Debug)]
+pub struct CancellationFlag {
+    cancelled: AtomicBool,
+    read: File,
+    write: File,
+}
+
+impl CancellationFlag {
+    pub fn new() -> io::Result<Self> {
+        let [read, write] = create_pipe()?;
+        // Safety: the fds are just created and owned by nobody else.
+        let (read, write) = unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) };
+        set_nonblocking(write.as_raw_fd())?;
+
+        Ok(Self {
+            cancelled: AtomicBool::new(false),
+            read,
+            write,
+        })
+    }
+
+    pub fn cancel(&self) -> io::Result<()> {
+        if !self.cancelled.swap(true, Ordering::AcqRel) {
+            (&self.write).write_all(&[0])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// Process group led by the first process spawned in it.
+#[derive(Debug, Default)]
+pub struct ProcessGroup {
+    /// 0 until the first process is spawned, and -1 once it is reaped.
+    /// Locked across spawning so that concurrent spawns do not each create
+    /// a new group.
+    pgid: Mutex<libc::pid_t>,
+}
+
+impl ProcessGroup {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self::default())
+    }
+
+    fn pgid(&self) -> MutexGuard<'_, libc::pid_t> {
+        self.pgid.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Return the id of the group, or 0 if there is none (anymore).
+    ///
+    /// Once the leader is reaped, its pid is free to be reused, and so is
+    /// the id of the group once its other processes exit. There is no
+    /// telling from then on whether a group with that id is this one.
+    fn live_pgid(&self) -> io::Result<MutexGuard<'_, libc::pid_t>> {
+        let mut pgid = self.pgid();
+        if *pgid > 0 && !is_unreaped_child(*pgid)? {
+            *pgid = -1;
+        }
+        Ok(pgid)
+    }
+
+    pub fn spawn(&self, cmd: &mut process::Command) -> io::Result<process::Child> {
+        let mut pgid = self.live_pgid()?;
+        if *pgid < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the leader of the process group has been reaped",
+            ));
+        }
+
+        // Make sure this is executed only once, so that the command may
+        // be reused with another group.
+        let mut group = Some(*pgid);
+        unsafe {
+            CommandExt::pre_exec(cmd, move || match group.take() {
+                Some(pgid) => cvt(libc::setpgid(0, pgid)).map(drop),
+                None => Ok(()),
+            })
+        };
+        let child = cmd.spawn()?;
+
+        let pid = child.id() as libc::pid_t;
+        if *pgid == 0 {
+            *pgid = pid;
+        }
+        // Also set it here so that it is in place once this returns, the
+        // child might not have called `setpgid` yet. This fails if the
+        // child has already exec'd, in which case it is done.
+        unsafe { libc::setpgid(pid, *pgid) };
+
+        Ok(child)
+    }
+
+    pub fn signal(&self, signal: c_int) -> io::Result<()> {
+        let pgid = *self.live_pgid()?;
+        if pgid <= 0 {
+            return Ok(());
+        }
+
+        match cvt(unsafe { libc::killpg(pgid, signal) }) {
+            // All processes in the group have exited.
+            Err(err) if err.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+            res => res.map(drop),
+        }
+    }
+
+    pub fn kill(&self) -> io::Result<()> {
+        self.signal(libc::SIGKILL)
+    }
+}
+
+/// Return false if `pid` is not a child of this process waiting to be
+/// reaped or still running, without reaping it.
+#[cfg(not(target_os = "redox"))]
+fn is_unreaped_child(pid: libc::pid_t) -> io::Result<bool> {
+    let mut info = MaybeUninit::<libc::siginfo_t>::uninit();
+    let res = cvt(unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            info.as_mut_ptr(),
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    });
+    match res {
+        Ok(_) => Ok(true),
+        Err(err) if err.raw_os_error() == Some(libc::ECHILD) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Redox has no `waitid`, so assume the leader is never reaped.
+#[cfg(target_os = "redox")]
+fn is_unreaped_child(_pid: libc::pid_t) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[derive(Debug)]
+enum TryAcquire {
+    /// A new file description of `read` with `O_NONBLOCK` set.
+    Reopened(File),
+    /// Helper thread reading from `read`.
+    Reader(TokenReader),
+    /// `O_NONBLOCK` is set on `read` and `write`.
+    Shared,
+}
+
 impl Client {
     pub fn new(limit: usize) -> io::Result<Self> {
         // Create nonblocking and cloexec pipes
@@ -44,56 +215,101 @@ impl Client {
         Ok(client)
     }
 
+    /// Same as `new`, except that the pipe is created in packet mode with
+    /// `O_DIRECT`, so that every token is a discrete packet.
+    #[cfg(target_os = "linux")]
+    pub fn new_packet(limit: usize) -> io::Result<Self> {
+        let mut pipes = [0; 2];
+        cvt(unsafe { libc::pipe2(pipes.as_mut_ptr(), libc::O_CLOEXEC | libc::O_DIRECT) })?;
+
+        let client = unsafe { Self::from_fds(pipes[0], pipes[1]) };
+
+        client.reserve_packets(limit)?;
+        client.init(limit)?;
+
+        Ok(client)
+    }
+
+    /// Grow the pipe so that it holds `limit` packets, since every packet
+    /// takes a whole page of the pipe buffer and a write blocks once it is
+    /// full, e.g. after 16 packets by default.
+    #[cfg(target_os = "linux")]
+    fn reserve_packets(&self, limit: usize) -> io::Result<()> {
+        let too_many = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("a pipe in packet mode cannot hold {} tokens", limit),
+            )
+        };
+
+        let fd = self.write.as_raw_fd();
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let capacity = |size: i32| size as usize / page;
+
+        if capacity(cvt(unsafe { libc::fcntl(fd, libc::F_GETPIPE_SZ) })?) >= limit {
+            return Ok(());
+        }
+
+        let size = limit
+            .checked_mul(page)
+            .filter(|&size| size <= i32::MAX as usize)
+            .ok_or_else(too_many)?;
+        match cvt(unsafe { libc::fcntl(fd, libc::F_SETPIPE_SZ, size as i32) }) {
+            Ok(size) if capacity(size) >= limit => Ok(()),
+            // Above `/proc/sys/fs/pipe-max-size` for unprivileged users.
+            Ok(_) => Err(too_many()),
+            Err(err) if err.raw_os_error() == Some(libc::EPERM) => Err(too_many()),
+            Err(err) => Err(err),
+        }
+    }
+
     pub fn new_fifo(limit: usize) -> io::Result<Self> {
-        // Try a bunch of random file name in /tmp until we get a unique one,
-        // but don't try for too long.
-        let prefix = "/tmp/__rust_jobslot_fifo_";
-
-        let mut name = String::with_capacity(
-            prefix.len() +
-            // 32B for the max size of u128
-            32 +
-            // 1B for the null byte
-            1,
-        );
-        name.push_str(prefix);
+        Self::new_fifo_in(&fifo_dir()?, limit)
+    }
+
+    pub fn new_fifo_in(dir: &Path, limit: usize) -> io::Result<Self> {
+        // Try a bunch of random file name in `dir` until we get a unique
+        // one, but don't try for too long.
+        //
+        // The pid and boot id in the name keep names of concurrent and
+        // earlier boots apart, and identify orphaned fifos left by crashed
+        // processes, which are removed on collision.
+        let prefix = format!("{}{}_{}_", FIFO_PREFIX, process::id(), boot_id());
 
         for _ in 0..100 {
             let mut bytes = [0; 16];
             getrandom(&mut bytes)?;
 
-            write!(&mut name, "{:x}\0", u128::from_ne_bytes(bytes)).unwrap();
+            let name = dir.join(format!("{}{:x}", prefix, u128::from_ne_bytes(bytes)));
+            let cname = CString::new(name.as_os_str().as_bytes())?;
 
-            let res = cvt(unsafe {
-                libc::mkfifo(name.as_ptr() as *const _, libc::S_IRUSR | libc::S_IWUSR)
-            });
+            let mut res = mkfifo(&cname);
+            if matches!(&res, Err(err) if err.kind() == io::ErrorKind::AlreadyExists)
+                && remove_if_orphaned(&name, ORPHANED_FIFO_AGE)?
+            {
+                res = mkfifo(&cname);
+            }
 
             match res {
-                Ok(_) => {
-                    name.pop(); // chop off the trailing null
-                    let name = PathBuf::from(name);
-
+                Ok(()) => {
                     let file = open_file_rw(&name)?;
 
                     let client = Self {
                         read: file.try_clone()?,
                         write: file,
                         path: Some(name.into_boxed_path()),
-                        owns_fifo: true,
+                        owns_fifo: AtomicBool::new(true),
+                        inheritable: AtomicBool::new(false),
+                        try_acquire: sync::Mutex::default(),
+                        backoff: sync::Mutex::default(),
                     };
 
                     client.init(limit)?;
 
                     return Ok(client);
                 }
-                Err(err) => {
-                    if err.kind() == io::ErrorKind::AlreadyExists {
-                        name.truncate(prefix.len());
-                        continue;
-                    } else {
-                        return Err(err);
-                    }
-                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
             }
         }
 
@@ -103,7 +319,127 @@ impl Client {
         ))
     }
 
-    fn init(&self, mut limit: usize) -> io::Result<()> {
+    /// Create a jobserver with a fifo in the directory `dir`, with
+    /// `mkfifoat` and `openat` so that no absolute path is resolved.
+    ///
+    /// If the path of `dir` is unknown, e.g. in capability mode, the fifo
+    /// is unlinked right away, since it could neither be passed by path to
+    /// children nor removed later on.
+    pub fn new_fifo_at(dir: BorrowedFd<'_>, limit: usize) -> io::Result<Self> {
+        let dir_path = fd_path(dir.as_raw_fd());
+        let prefix = format!("{}{}_{}_", FIFO_PREFIX, process::id(), boot_id());
+
+        for _ in 0..100 {
+            let mut bytes = [0; 16];
+            getrandom(&mut bytes)?;
+
+            let name = format!("{}{:x}", prefix, u128::from_ne_bytes(bytes));
+            let cname = CString::new(name.as_str())?;
+
+            match mkfifoat(dir, &cname) {
+                Ok(()) => (),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+
+            let unlink = || unsafe { libc::unlinkat(dir.as_raw_fd(), cname.as_ptr(), 0) };
+            let fd = match cvt(unsafe {
+                libc::openat(
+                    dir.as_raw_fd(),
+                    cname.as_ptr(),
+                    libc::O_RDWR | libc::O_CLOEXEC,
+                )
+            }) {
+                Ok(fd) => fd,
+                Err(err) => {
+                    unlink();
+                    return Err(err);
+                }
+            };
+            let file = unsafe { File::from_raw_fd(fd) };
+
+            let path = match &dir_path {
+                Some(dir_path) => Some(dir_path.join(&name).into_boxed_path()),
+                None => {
+                    unlink();
+                    None
+                }
+            };
+
+            let client = Self {
+                read: file.try_clone()?,
+                write: file,
+                owns_fifo: AtomicBool::new(path.is_some()),
+                path,
+                inheritable: AtomicBool::new(false),
+                try_acquire: sync::Mutex::default(),
+                backoff: sync::Mutex::default(),
+            };
+
+            client.init(limit)?;
+
+            return Ok(client);
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "failed to find a unique name for a fifo",
+        ))
+    }
+
+    /// Create a jobserver with a fifo at the well-known path of `name`,
+    /// see `named_fifo_path`.
+    pub fn new_named(name: &str, limit: usize) -> io::Result<Self> {
+        let path = named_fifo_path(name);
+        mkfifo(&CString::new(path.as_os_str().as_bytes())?)?;
+
+        let file = open_file_rw(&path)?;
+        check_named_fifo(&file)?;
+
+        let client = Self {
+            read: file.try_clone()?,
+            write: file,
+            path: Some(path.into_boxed_path()),
+            owns_fifo: AtomicBool::new(true),
+            inheritable: AtomicBool::new(false),
+            try_acquire: sync::Mutex::default(),
+            backoff: sync::Mutex::default(),
+        };
+
+        client.init(limit)?;
+
+        Ok(client)
+    }
+
+    /// Open the jobserver created by `new_named`.
+    pub fn connect_named(name: &str) -> io::Result<Self> {
+        let path = named_fifo_path(name);
+
+        let client =
+            Self::from_fifo(&path, &FromEnvConfig::default()).map_err(|err| match err {
+                FromEnvError::IoError(err) => err,
+                err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+            })?;
+        check_named_fifo(&client.read)?;
+
+        Ok(client)
+    }
+
+    /// Create a jobserver with one token per byte of `tokens`.
+    pub fn new_with_tokens(tokens: &[u8]) -> io::Result<Self> {
+        let pipes = create_pipe()?;
+
+        let client = unsafe { Self::from_fds(pipes[0], pipes[1]) };
+
+        // Use nonblocking write here so that if the pipe
+        // would block, then return err instead of blocking
+        // the entire process forever.
+        client.write_tokens(tokens)?;
+
+        Ok(client)
+    }
+
+    pub fn init(&self, mut limit: usize) -> io::Result<()> {
         // I don't think the character written here matters, but I could be
         // wrong!
         const BUFFER: [u8; 128] = [b'|'; 128];
@@ -114,43 +450,157 @@ impl Client {
             // Use nonblocking write here so that if the pipe
             // would block, then return err instead of blocking
             // the entire process forever.
-            (&self.write).write_all(&BUFFER[..n])?;
+            self.write_tokens(&BUFFER[..n])?;
             limit -= n;
         }
 
         Ok(())
     }
 
-    pub unsafe fn open(var: &[u8]) -> Option<Self> {
+    /// Return true if the pipe is in packet mode, see `new_packet`.
+    pub fn is_packet_mode(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        return matches!(
+            cvt(unsafe { libc::fcntl(self.write.as_raw_fd(), libc::F_GETFL) }),
+            Ok(flags) if flags & libc::O_DIRECT != 0
+        );
+
+        #[cfg(not(target_os = "linux"))]
+        return false;
+    }
+
+    /// Write `tokens` to the jobserver.
+    ///
+    /// In packet mode, a write of several bytes is a single packet, of
+    /// which a reader of one byte would discard the rest, so every token
+    /// is written on its own.
+    fn write_tokens(&self, tokens: &[u8]) -> io::Result<()> {
+        if self.is_packet_mode() {
+            tokens
+                .iter()
+                .try_for_each(|token| (&self.write).write_all(slice::from_ref(token)))
+        } else {
+            (&self.write).write_all(tokens)
+        }
+    }
+
+    pub unsafe fn open(var: &[u8], config: &FromEnvConfig) -> Result<Self, FromEnvError> {
         if let Some(fifo) = var.strip_prefix(b"fifo:") {
-            Self::from_fifo(Path::new(OsStr::from_bytes(fifo)))
+            Self::from_fifo(Path::new(OsStr::from_bytes(fifo)), config)
+        } else {
+            Self::from_pipe(
+                OsStr::from_bytes(var).to_str().ok_or_else(|| {
+                    FromEnvError::Malformed(String::from_utf8_lossy(var).into_owned())
+                })?,
+                config,
+            )
+        }
+    }
+
+    /// Open the jobserver `auth` of process `pid`, duplicating its fds
+    /// with `pidfd_getfd`.
+    #[cfg(target_os = "linux")]
+    pub fn from_process(pid: u32, auth: &[u8]) -> Result<Self, FromEnvError> {
+        if let Some(fifo) = auth.strip_prefix(b"fifo:") {
+            // Resolve the path in the mount namespace of the process.
+            let mut path = PathBuf::from(format!("/proc/{}/root", pid));
+            path.push(
+                Path::new(OsStr::from_bytes(fifo))
+                    .strip_prefix("/")
+                    .map_err(|_| {
+                        FromEnvError::Malformed(String::from_utf8_lossy(auth).into_owned())
+                    })?,
+            );
+            return Self::from_fifo(&path, &FromEnvConfig::default());
+        }
+
+        let s = OsStr::from_bytes(auth)
+            .to_str()
+            .ok_or_else(|| FromEnvError::Malformed(String::from_utf8_lossy(auth).into_owned()))?;
+        let malformed = || FromEnvError::Malformed(s.into());
+
+        let (read, write) = s.split_once(',').ok_or_else(malformed)?;
+        let read: RawFd = read.parse().map_err(|_| malformed())?;
+        let write: RawFd = write.parse().map_err(|_| malformed())?;
+
+        let pid: libc::pid_t = pid
+            .try_into()
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        // Safety: pidfd_open takes a pid and flags, and returns a new fd
+        // owned by us.
+        let pidfd = unsafe {
+            File::from_raw_fd(cvt(libc::syscall(libc::SYS_pidfd_open, pid, 0) as RawFd)?)
+        };
+        // Safety: pidfd_getfd takes a pidfd, a fd of that process and
+        // flags, and returns a new fd with `CLOEXEC` set owned by us.
+        let getfd = |fd: RawFd| -> io::Result<File> {
+            let fd = cvt(unsafe {
+                libc::syscall(libc::SYS_pidfd_getfd, pidfd.as_raw_fd(), fd, 0) as RawFd
+            })?;
+            Ok(unsafe { File::from_raw_fd(fd) })
+        };
+        let read = getfd(read)?;
+        let write = getfd(write)?;
+
+        if is_pipe(&read)? && is_pipe(&write)? {
+            Ok(Self {
+                read,
+                write,
+                path: None,
+                owns_fifo: AtomicBool::new(false),
+                inheritable: AtomicBool::new(false),
+                try_acquire: sync::Mutex::default(),
+                backoff: sync::Mutex::default(),
+            })
         } else {
-            Self::from_pipe(OsStr::from_bytes(var).to_str()?)
+            Err(FromEnvError::Invalid(s.into()))
         }
     }
 
     /// `--jobserver-auth=fifo:PATH`
-    fn from_fifo(path: &Path) -> Option<Self> {
-        let file = open_file_rw(path).ok()?;
+    fn from_fifo(path: &Path, config: &FromEnvConfig) -> Result<Self, FromEnvError> {
+        let file = open_file_rw(path)?;
 
         if is_pipe(&file)? {
-            Some(Self {
-                read: file.try_clone().ok()?,
+            let client = Self {
+                read: file.try_clone()?,
                 write: file,
                 path: Some(path.into()),
-                owns_fifo: false,
-            })
+                owns_fifo: AtomicBool::new(false),
+                inheritable: AtomicBool::new(false),
+                try_acquire: sync::Mutex::default(),
+                backoff: sync::Mutex::default(),
+            };
+
+            // Fds opened by std always have `CLOEXEC` set.
+            if !config.set_cloexec {
+                client.set_inheritable(true)?;
+            }
+
+            Ok(client)
         } else {
-            None
+            Err(FromEnvError::Invalid(format!("fifo:{}", path.display())))
         }
     }
 
     /// `--jobserver-auth=fd-for-R,fd-for-W`
-    unsafe fn from_pipe(s: &str) -> Option<Self> {
-        let (read, write) = s.split_once(',')?;
+    unsafe fn from_pipe(s: &str, config: &FromEnvConfig) -> Result<Self, FromEnvError> {
+        let malformed = || FromEnvError::Malformed(s.into());
+
+        let (read, write) = s.split_once(',').ok_or_else(malformed)?;
+
+        let read: c_int = read.parse().map_err(|_| malformed())?;
+        let write: c_int = write.parse().map_err(|_| malformed())?;
 
-        let read = read.parse().ok()?;
-        let write = write.parse().ok()?;
+        if !config.allow_stdio_fds {
+            if let Some(fd) = [read, write]
+                .iter()
+                .copied()
+                .find(|fd| (0..=2).contains(fd))
+            {
+                return Err(FromEnvError::StdioFd(fd));
+            }
+        }
 
         let read = ManuallyDrop::new(File::from_raw_fd(read));
         let write = ManuallyDrop::new(File::from_raw_fd(write));
@@ -169,8 +619,8 @@ impl Client {
             get_access_mode(&write),
         ) {
             (
-                Some(true),
-                Some(true),
+                Ok(true),
+                Ok(true),
                 Some(libc::O_RDONLY) | Some(libc::O_RDWR),
                 Some(libc::O_WRONLY) | Some(libc::O_RDWR),
             ) => {
@@ -183,23 +633,58 @@ impl Client {
                 //
                 // I tested this on macOS 14 and Linux 6.5.13
                 #[cfg(target_os = "linux")]
-                if let Some(jobserver) =
-                    Self::from_fifo(Path::new(&format!("/dev/fd/{}", read.as_raw_fd())))
-                {
-                    return Some(jobserver);
+                if config.prefer_fifo_reopen {
+                    if let Ok(jobserver) =
+                        Self::from_fifo(Path::new(&format!("/dev/fd/{}", read.as_raw_fd())), config)
+                    {
+                        return Ok(jobserver);
+                    }
+                }
+
+                // On macOS, the fds might refer to a named fifo, e.g. if it
+                // is opened by a parent process from `fifo:PATH` and then
+                // passed down as fds, so try to recover its path to reopen it.
+                #[cfg(target_vendor = "apple")]
+                if config.prefer_fifo_reopen {
+                    if let Some(jobserver) = get_path(read.as_raw_fd())
+                        .and_then(|path| Self::from_fifo(&path, config).ok())
+                        .filter(|jobserver| is_same_file(&jobserver.read, &read).unwrap_or(false))
+                    {
+                        return Ok(jobserver);
+                    }
                 }
 
-                let read = read.try_clone().ok()?;
-                let write = write.try_clone().ok()?;
+                let (read, write) = if config.dup_fds {
+                    (
+                        dup_fd(read.as_raw_fd(), config.set_cloexec)?,
+                        dup_fd(write.as_raw_fd(), config.set_cloexec)?,
+                    )
+                } else {
+                    let read = ManuallyDrop::into_inner(read);
+                    // Avoid closing the same fd twice.
+                    let write = if read.as_raw_fd() == write.as_raw_fd() {
+                        dup_fd(write.as_raw_fd(), config.set_cloexec)?
+                    } else {
+                        ManuallyDrop::into_inner(write)
+                    };
+
+                    set_cloexec(read.as_raw_fd(), config.set_cloexec)?;
+                    set_cloexec(write.as_raw_fd(), config.set_cloexec)?;
+
+                    (read, write)
+                };
 
-                Some(Self {
+                Ok(Self {
                     read,
                     write,
                     path: None,
-                    owns_fifo: false,
+                    owns_fifo: AtomicBool::new(false),
+                    inheritable: AtomicBool::new(!config.set_cloexec),
+                    try_acquire: sync::Mutex::default(),
+                    backoff: sync::Mutex::default(),
                 })
             }
-            _ => None,
+            _ => Err(FromEnvError::Invalid(s.into())),
         }
     }
 
@@ -208,48 +693,223 @@ impl Client {
             read: File::from_raw_fd(read),
             write: File::from_raw_fd(write),
             path: None,
-            owns_fifo: false,
+            owns_fifo: AtomicBool::new(false),
+            inheritable: AtomicBool::new(false),
+            try_acquire: sync::Mutex::default(),
+            backoff: sync::Mutex::default(),
         }
     }
 
+    pub fn set_backoff(&self, backoff: Backoff) {
+        *self.backoff.lock() = backoff;
+    }
+
+    /// Wait before retry number `attempt` after losing the race for a
+    /// token, but not past `deadline`, returning early once `cancel` is
+    /// cancelled. With `interruptible`, a signal interrupting the wait is
+    /// returned as `EINTR` instead of resuming it.
+    fn backoff(
+        &self,
+        attempt: &mut u32,
+        deadline: Option<Instant>,
+        cancel: Option<&CancellationFlag>,
+        interruptible: bool,
+    ) -> io::Result<()> {
+        let backoff = *self.backoff.lock();
+        let mut delay = backoff.delay(*attempt);
+        *attempt = attempt.saturating_add(1);
+
+        if let Some(deadline) = deadline {
+            delay = delay.min(deadline.saturating_duration_since(Instant::now()));
+        }
+        sleep(delay, cancel, interruptible)
+    }
+
     pub fn acquire(&self) -> io::Result<Acquired> {
+        let mut attempt = 0;
+        let mut woken = false;
         loop {
             // Ignore EAGAIN and keep trying if that happens
             if let Some(token) = self.acquire_allow_interrupts()? {
                 return Ok(token);
             }
+            // Lost the race after being woken up.
+            if woken {
+                self.backoff(&mut attempt, None, None, false)?;
+            }
+            woken = true;
 
             poll_for_readiness1(self.read.as_raw_fd())?;
         }
     }
 
-    /// Waiting for a token in a non-blocking manner, returning `None`
-    /// if we're interrupted with EINTR or EAGAIN.
-    fn acquire_allow_interrupts(&self) -> io::Result<Option<Acquired>> {
-        let mut buf = [0];
+    /// Same as `acquire`, counting the times it is woken up by `poll` in
+    /// `wakeups`.
+    ///
+    /// Unlike `acquire`, it polls before reading even if `read` is
+    /// blocking, so that waiting is noticed, at the cost of a syscall.
+    pub fn acquire_counting_wakeups(&self, wakeups: &mut u32) -> io::Result<Acquired> {
+        let mut attempt = 0;
         loop {
-            match (&self.read).read(&mut buf) {
-                Ok(1) => break Ok(Some(Acquired { byte: buf[0] })),
-                Ok(_) => break Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            if !poll_for_readiness_timeout(self.read.as_raw_fd(), Duration::ZERO)? {
+                poll_for_readiness1(self.read.as_raw_fd())?;
+                *wakeups = wakeups.saturating_add(1);
+            }
+
+            // Ignore EAGAIN and keep trying if that happens
+            if let Some(token) = self.acquire_allow_interrupts()? {
+                return Ok(token);
+            }
+            // Lost the race after being woken up.
+            self.backoff(&mut attempt, None, None, false)?;
+        }
+    }
+
+    /// Return `None` if no token is acquired before `deadline`.
+    pub fn acquire_until(&self, deadline: Instant) -> io::Result<Option<Acquired>> {
+        // See `acquire_with_cancel`.
+        let nonblocking = self.reopen_read();
+        let read = nonblocking.as_ref().unwrap_or(&self.read);
+
+        let mut attempt = 0;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if poll_for_readiness_timeout(read.as_raw_fd(), timeout)? {
+                if let Some(token) = read_token(read, &self.write)? {
+                    break Ok(Some(token));
+                }
+                self.backoff(&mut attempt, Some(deadline), None, false)?;
+            } else if timeout.is_zero() {
+                break Ok(None);
+            }
+        }
+    }
+
+    pub fn acquire_interruptible(&self) -> io::Result<Acquired> {
+        // See `acquire_with_cancel`.
+        let nonblocking = self.reopen_read();
+        let read = nonblocking.as_ref().unwrap_or(&self.read);
+
+        let mut fds = [libc::pollfd {
+            fd: read.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+
+        let mut attempt = 0;
+        loop {
+            // Unlike `poll`, return EINTR to the caller. `poll` is never
+            // restarted after being interrupted, even with `SA_RESTART`.
+            cvt(unsafe { libc::poll(fds.as_mut_ptr(), 1, -1) })?;
+
+            if is_ready(fds[0].revents)? {
+                if let Some(token) = read_token(read, &self.write)? {
+                    break Ok(token);
+                }
+                self.backoff(&mut attempt, None, None, true)?;
+            }
+        }
+    }
 
-                // Interrupted by signal, try again
-                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(None),
+    pub fn acquire_with_cancel(&self, flag: &CancellationFlag) -> io::Result<Acquired> {
+        // Read from a new file description with `O_NONBLOCK` set if
+        // possible, otherwise the read could block if the token is taken
+        // by someone else after `poll` returns.
+        let nonblocking = self.reopen_read();
+        let read = nonblocking.as_ref().unwrap_or(&self.read);
 
-                Err(e) => break Err(e),
+        let mut attempt = 0;
+        loop {
+            if flag.is_cancelled() {
+                break Err(crate::cancelled());
+            }
+
+            let mut fds = [
+                libc::pollfd {
+                    fd: read.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: flag.read.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            poll(&mut fds, -1)?;
+
+            if is_ready(fds[0].revents)? && !flag.is_cancelled() {
+                if let Some(token) = read_token(read, &self.write)? {
+                    break Ok(token);
+                }
+                self.backoff(&mut attempt, None, Some(flag), false)?;
             }
         }
     }
 
-    /// `set_nonblocking` must be called prior to this call
+    /// Waiting for a token in a non-blocking manner, returning `None`
+    /// if we're interrupted with EINTR or EAGAIN.
+    fn acquire_allow_interrupts(&self) -> io::Result<Option<Acquired>> {
+        read_token(&self.read, &self.write)
+    }
+
+    /// `start_try_acquire` must be called prior to this call, otherwise
+    /// this fails instead of blocking.
     pub fn try_acquire(&self) -> io::Result<Option<Acquired>> {
-        self.acquire_allow_interrupts()
+        match &*self.try_acquire_state() {
+            Some(TryAcquire::Reopened(file)) => read_token(file, &self.write),
+            Some(TryAcquire::Reader(reader)) => reader.try_acquire(&self.read),
+            Some(TryAcquire::Shared) => self.acquire_allow_interrupts(),
+            None => Err(crate::TryAcquireError::ConversionRequired.into()),
+        }
     }
 
     pub fn get_read_fd(&self) -> RawFd {
         self.read.as_raw_fd()
     }
 
+    pub fn get_write_fd(&self) -> RawFd {
+        self.write.as_raw_fd()
+    }
+
+    /// Return the fd which becomes readable once `try_acquire` can
+    /// succeed.
+    pub fn get_try_acquire_fd(&self) -> RawFd {
+        match &*self.try_acquire_state() {
+            Some(TryAcquire::Reopened(file)) => file.as_raw_fd(),
+            Some(TryAcquire::Reader(reader)) => reader.notify_fd(),
+            Some(TryAcquire::Shared) | None => self.read.as_raw_fd(),
+        }
+    }
+
+    fn try_acquire_state(&self) -> sync::MutexGuard<'_, Option<TryAcquire>> {
+        self.try_acquire.lock()
+    }
+
+    pub fn get_read_borrowed_fd(&self) -> BorrowedFd<'_> {
+        self.read.as_fd()
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        (&self.write).write_all(&[crate::SHUTDOWN_TOKEN])
+    }
+
+    /// Drain all tokens available, including the one written by
+    /// `shutdown`, and write `tokens` instead.
+    pub fn reset(&self, tokens: &[u8]) -> io::Result<()> {
+        // See `acquire_with_cancel`.
+        let nonblocking = self.reopen_read();
+        let read = nonblocking.as_ref().unwrap_or(&self.read);
+
+        while poll_for_readiness_timeout(read.as_raw_fd(), Duration::ZERO)? {
+            if read_byte(read)?.is_none() {
+                break;
+            }
+        }
+
+        self.write_tokens(tokens)
+    }
+
     pub fn release(&self, data: Option<&Acquired>) -> io::Result<()> {
         // For write to block, this would mean that pipe is full.
         // If all every release are pair with an acquire, then this cannot
@@ -261,7 +921,7 @@ impl Client {
         //
         // If that turns out to not be the case we'll get an error anyway!
         let byte = data.map(|d| d.byte).unwrap_or(b'+');
-        match (&self.write).write(&[byte])? {
+        match write_without_sigpipe(&self.write, &[byte])? {
             1 => Ok(()),
             _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
         }
@@ -279,16 +939,97 @@ impl Client {
         self.path.as_deref()
     }
 
+    pub fn persist_fifo(&self) -> bool {
+        self.owns_fifo.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn take_fifo_ownership(&self) -> bool {
+        if self.path.is_some() {
+            self.owns_fifo.store(true, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn owns_fifo(&self) -> bool {
+        self.owns_fifo.load(Ordering::Relaxed)
+    }
+
+    /// Return true if both `self` and `other` use the same named fifo.
+    pub fn is_same_fifo(&self, other: &Self) -> io::Result<bool> {
+        if self.path.is_none() || other.path.is_none() {
+            return Ok(false);
+        }
+
+        is_same_file(&self.read, &other.read)
+    }
+
+    /// Check that the fifo still exists and refers to the pipe we have
+    /// opened, since it might have been removed, e.g. by a tmp cleaner.
+    ///
+    /// Note that removing the fifo does not affect fds already opened,
+    /// it only prevents new processes from connecting to it.
+    pub fn verify_fifo(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let removed = || {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("jobserver fifo `{}` has been removed", path.display()),
+            )
+        };
+
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err)
+                if err.kind() == io::ErrorKind::NotFound
+                    || err.raw_os_error() == Some(libc::ESTALE) =>
+            {
+                return Err(removed())
+            }
+            Err(err) => return Err(err),
+        };
+        let opened = self.read.metadata()?;
+
+        if metadata.dev() == opened.dev() && metadata.ino() == opened.ino() {
+            Ok(())
+        } else {
+            // Someone else has created a new file at the same path.
+            Err(removed())
+        }
+    }
+
     pub fn pre_run<Cmd>(&self, cmd: &mut Cmd)
     where
         Cmd: Command,
     {
+        self.pre_run_keeping(cmd, ())
+    }
+
+    /// Same as `pre_run`, except that `owner` is kept alive along with
+    /// `cmd`, so that the fds cannot be closed, and their numbers reused,
+    /// before it is spawned.
+    pub fn pre_run_keeping<Cmd, T>(&self, cmd: &mut Cmd, owner: T)
+    where
+        Cmd: Command,
+        T: Send + Sync + 'static,
+    {
+        if self.inheritable.load(Ordering::Relaxed) {
+            return;
+        }
+
         let read = self.read.as_raw_fd();
         let write = self.write.as_raw_fd();
 
         let mut fds = Some([read, write]);
 
         let f = move || {
+            let _owner = &owner;
+
             // Make sure this function is executed only once,
             // so that the command may be reused with another
             // Client.
@@ -302,47 +1043,591 @@ impl Client {
         unsafe { cmd.pre_exec(f) };
     }
 
+    pub fn set_inheritable(&self, inheritable: bool) -> io::Result<()> {
+        set_cloexec(self.read.as_raw_fd(), !inheritable)?;
+        set_cloexec(self.write.as_raw_fd(), !inheritable)?;
+        self.inheritable.store(inheritable, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    pub fn into_raw_parts(self) -> RawParts {
+        let auth = self.string_arg().into_owned();
+        let (read, write, path, _owns_fifo, _inheritable, _try_acquire, _backoff) =
+            self.destructure();
+
+        RawParts {
+            read: read.into(),
+            write: write.into(),
+            fifo: path.map(PathBuf::from),
+            auth,
+        }
+    }
+
+    /// Remove the fifo along with its preemption channel if owned.
+    fn remove_fifo(&self) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            // Do not remove the file if it is no longer our fifo.
+            if self.owns_fifo() && self.verify_fifo().is_ok() {
+                fs::remove_file(path)?;
+                if let Some(path) = self.preemption_channel_path() {
+                    match fs::remove_file(path) {
+                        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create a client with fds of its own, on a new file description if
+    /// possible.
+    pub fn duplicate(&self) -> io::Result<Self> {
+        let (read, write) = match self.reopen() {
+            Some(file) => (file.try_clone()?, file),
+            None => (self.read.try_clone()?, self.write.try_clone()?),
+        };
+
+        Ok(Self {
+            read,
+            write,
+            path: self.path.clone(),
+            owns_fifo: AtomicBool::new(false),
+            inheritable: AtomicBool::new(false),
+            try_acquire: sync::Mutex::default(),
+            backoff: sync::Mutex::default(),
+        })
+    }
+
+    pub fn close(self) -> io::Result<()> {
+        let res = self.remove_fifo();
+        let (read, write, _path, _owns_fifo, _inheritable, try_acquire, _backoff) =
+            self.destructure();
+        drop(try_acquire);
+
+        let close = |file: File| cvt(unsafe { libc::close(file.into_raw_fd()) }).map(drop);
+        let (read, write) = (close(read), close(write));
+
+        res.and(read).and(write)
+    }
+
+    /// Wait up to `timeout` for the jobserver to become readable, i.e. for
+    /// a token to be available, returning false on timeout.
+    pub fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        poll_for_readiness_timeout(self.read.as_raw_fd(), timeout)
+    }
+
+    /// Identify the pipe or fifo by its device and inode, which are the
+    /// same for every client connected to it in this process.
+    pub fn jobserver_key(&self) -> Option<String> {
+        let metadata = self.read.metadata().ok()?;
+        Some(format!("{}:{}", metadata.dev(), metadata.ino()))
+    }
+
     pub fn available(&self) -> io::Result<usize> {
         let mut len = MaybeUninit::<c_int>::uninit();
-        cvt(unsafe { libc::ioctl(self.read.as_raw_fd(), libc::FIONREAD, len.as_mut_ptr()) })?;
+        let res =
+            cvt(unsafe { libc::ioctl(self.read.as_raw_fd(), libc::FIONREAD, len.as_mut_ptr()) });
+
+        // AIX does not support `FIONREAD` on every kind of pipe, but reports
+        // the number of bytes buffered in pipes and fifos as their size.
+        #[cfg(target_os = "aix")]
+        if let Err(err) = &res {
+            if matches!(err.raw_os_error(), Some(libc::ENOTTY) | Some(libc::EINVAL)) {
+                return Ok(self.read.metadata()?.len().try_into().unwrap());
+            }
+        }
+
+        res.map_err(|err| match err.raw_os_error() {
+            // Some platforms, e.g. Redox, might not support `FIONREAD`
+            // on pipes.
+            Some(libc::ENOTTY) | Some(libc::ENOSYS) => unsupported_fionread(),
+            // Haiku rejects unsupported ioctls on fifos with `EINVAL`.
+            #[cfg(target_os = "haiku")]
+            Some(libc::EINVAL) => unsupported_fionread(),
+            _ => err,
+        })?;
         Ok(unsafe { len.assume_init() }.try_into().unwrap())
     }
 
     pub fn is_try_acquire_safe(&self) -> bool {
-        self.path.is_some()
+        !matches!(self.try_acquire_mode(), TryAcquireMode::SharedNonblocking)
     }
 
-    pub fn set_nonblocking(&self) -> io::Result<()> {
-        set_nonblocking(self.read.as_raw_fd())?;
-        set_nonblocking(self.write.as_raw_fd())
+    /// Return false if `start_try_acquire` has fallen back to setting
+    /// `O_NONBLOCK` on the shared pipe.
+    ///
+    /// Before that, it only falls back if neither a new file description
+    /// can be opened nor a helper thread spawned, and the latter works for
+    /// every backend unless the process runs out of resources.
+    pub fn supports_try_acquire(&self) -> bool {
+        !matches!(&*self.try_acquire_state(), Some(TryAcquire::Shared))
     }
 
-    pub fn set_blocking(&self) -> io::Result<()> {
-        set_blocking(self.read.as_raw_fd())?;
-        set_blocking(self.write.as_raw_fd())
+    pub fn try_acquire_mode(&self) -> TryAcquireMode {
+        match &*self.try_acquire_state() {
+            Some(TryAcquire::Reopened(_)) => TryAcquireMode::IndependentDescription,
+            Some(TryAcquire::Reader(_)) => TryAcquireMode::HelperThread,
+            Some(TryAcquire::Shared) | None => TryAcquireMode::SharedNonblocking,
+        }
+    }
+
+    pub fn start_try_acquire(&self) -> io::Result<()> {
+        let try_acquire = match self.independent_try_acquire() {
+            Some(try_acquire) => try_acquire,
+            None => {
+                set_nonblocking(self.read.as_raw_fd())?;
+                set_nonblocking(self.write.as_raw_fd())?;
+                TryAcquire::Shared
+            }
+        };
+
+        *self.try_acquire_state() = Some(try_acquire);
+
+        Ok(())
+    }
+
+    /// The pipe might be shared with other processes which do not expect
+    /// `O_NONBLOCK`, e.g. make < 4.4, so try to obtain a new file
+    /// description of it, or read from it in a helper thread.
+    fn independent_try_acquire(&self) -> Option<TryAcquire> {
+        if let Some(file) = self.reopen_read() {
+            Some(TryAcquire::Reopened(file))
+        } else {
+            TokenReader::new(&self.read, &self.write)
+                .ok()
+                .map(TryAcquire::Reader)
+        }
+    }
+
+    pub fn stop_try_acquire(&self) -> io::Result<()> {
+        if let Some(TryAcquire::Shared) = self.try_acquire_state().take() {
+            set_blocking(self.read.as_raw_fd())?;
+            set_blocking(self.write.as_raw_fd())?;
+        }
+
+        Ok(())
+    }
+
+    /// Open the side channel used to request preemption, a fifo next to
+    /// the jobserver fifo, creating it if it does not exist yet.
+    ///
+    /// The returned file has a file description of its own, so setting
+    /// `O_NONBLOCK` on it does not affect anyone else.
+    pub fn open_preemption_channel(&self, nonblocking: bool) -> io::Result<File> {
+        let path = self.preemption_channel_path().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "preemption requires a jobserver using a named fifo",
+            )
+        })?;
+
+        let cpath = CString::new(path.as_os_str().as_bytes())?;
+        match cvt(unsafe { libc::mkfifo(cpath.as_ptr(), libc::S_IRUSR | libc::S_IWUSR) }) {
+            Err(err) if err.raw_os_error() != Some(libc::EEXIST) => return Err(err),
+            _ => (),
+        }
+
+        let file = open_file_rw(&path)?;
+        if !is_pipe(&file)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a fifo", path.display()),
+            ));
+        }
+        if nonblocking {
+            set_nonblocking(file.as_raw_fd())?;
+        }
+
+        Ok(file)
+    }
+
+    fn preemption_channel_path(&self) -> Option<PathBuf> {
+        let mut path = self.path.as_deref()?.as_os_str().to_owned();
+        path.push(".preempt");
+        Some(path.into())
+    }
+
+    /// Open a new file description of `read` with `O_NONBLOCK` set.
+    fn reopen_read(&self) -> Option<File> {
+        let file = self.reopen()?;
+        set_nonblocking(file.as_raw_fd()).ok()?;
+
+        Some(file)
+    }
+
+    /// Open a new file description of the jobserver, for both reading and
+    /// writing.
+    fn reopen(&self) -> Option<File> {
+        let path: Cow<'_, Path> = match &self.path {
+            Some(path) => Cow::Borrowed(path),
+
+            // On linux, opening `/proc/self/fd/$fd` returns a fd with a new
+            // file description even for anonymous pipes.
+            #[cfg(target_os = "linux")]
+            None => Cow::Owned(format!("/proc/self/fd/{}", self.read.as_raw_fd()).into()),
+
+            #[cfg(target_vendor = "apple")]
+            None => Cow::Owned(get_path(self.read.as_raw_fd())?),
+
+            // On illumos and Solaris, `/proc/self/path/$fd` links to the
+            // path of fifos, while anonymous pipes have none.
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            None => Cow::Owned(
+                fs::read_link(format!("/proc/self/path/{}", self.read.as_raw_fd()))
+                    .ok()
+                    .filter(|path| path.is_absolute())?,
+            ),
+
+            #[cfg(not(any(
+                target_os = "linux",
+                target_vendor = "apple",
+                target_os = "illumos",
+                target_os = "solaris"
+            )))]
+            // Elsewhere, e.g. on Haiku, which has neither `/proc` nor
+            // `/dev/fd`, inherited fds cannot be reopened.
+            None => return None,
+        };
+
+        let file = open_file_rw(&path).ok()?;
+        if !is_same_file(&file, &self.read).ok()? {
+            return None;
+        }
+
+        Some(file)
     }
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
-        if let Some(path) = &self.path {
-            if self.owns_fifo {
-                fs::remove_file(path).ok();
+        self.remove_fifo().ok();
+    }
+}
+
+impl Client {
+    /// Move the fields out without running `Drop`.
+    #[allow(clippy::type_complexity)]
+    fn destructure(
+        self,
+    ) -> (
+        File,
+        File,
+        Option<Box<Path>>,
+        AtomicBool,
+        AtomicBool,
+        sync::Mutex<Option<TryAcquire>>,
+        sync::Mutex<Backoff>,
+    ) {
+        let this = ManuallyDrop::new(self);
+        // Safety: `this` is never used or dropped afterwards, so every
+        // field is moved out exactly once.
+        unsafe {
+            (
+                ptr::read(&this.read),
+                ptr::read(&this.write),
+                ptr::read(&this.path),
+                ptr::read(&this.owns_fifo),
+                ptr::read(&this.inheritable),
+                ptr::read(&this.try_acquire),
+                ptr::read(&this.backoff),
+            )
+        }
+    }
+}
+
+/// Implements `try_acquire` on a blocking pipe by reading from it in a
+/// helper thread whenever a token is requested.
+///
+/// `try_acquire` requests a token and, if the pipe is readable, waits up to
+/// `HELPER_REPLY_TIMEOUT` for the helper thread to read it, returning
+/// `None` otherwise. At most one token is held by the helper thread at any
+/// time, which is handed out by the next `try_acquire`, or released if
+/// none takes it within `UNCLAIMED_TOKEN_TIMEOUT` or on drop.
+#[derive(Debug)]
+struct TokenReader {
+    shared: Arc<TokenReaderShared>,
+}
+
+#[derive(Debug)]
+struct TokenReaderShared {
+    state: Mutex<TokenReaderState>,
+    /// Notified when a token is requested, taken or on shutdown.
+    requested: Condvar,
+    /// Notified when the helper thread has read a token or failed to.
+    replied: Condvar,
+    /// Becomes readable once a token is held, nonblocking.
+    notify_read: File,
+    notify_write: File,
+}
+
+/// How long the helper thread holds a token no `try_acquire` takes before
+/// releasing it, so that it is not kept from other processes.
+const UNCLAIMED_TOKEN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long `try_acquire` waits for the helper thread to read a token from
+/// a readable pipe, in case another process takes it first.
+const HELPER_REPLY_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct TokenReaderState {
+    requested: bool,
+    shutdown: bool,
+    token: Option<u8>,
+    error: Option<io::Error>,
+}
+
+impl TokenReader {
+    fn new(read: &File, write: &File) -> io::Result<Self> {
+        let pipes = create_pipe()?;
+        let (notify_read, notify_write) =
+            unsafe { (File::from_raw_fd(pipes[0]), File::from_raw_fd(pipes[1])) };
+        set_nonblocking(notify_read.as_raw_fd())?;
+
+        let shared = Arc::new(TokenReaderShared {
+            state: Mutex::default(),
+            requested: Condvar::new(),
+            replied: Condvar::new(),
+            notify_read,
+            notify_write,
+        });
+
+        let (read, write) = (read.try_clone()?, write.try_clone()?);
+        let shared2 = shared.clone();
+        thread::Builder::new()
+            .name("jobslot-token-reader".into())
+            .spawn(move || shared2.run(read, write))?;
+
+        Ok(Self { shared })
+    }
+
+    fn notify_fd(&self) -> RawFd {
+        self.shared.notify_read.as_raw_fd()
+    }
+
+    /// `read` is the pipe the helper thread reads from.
+    fn try_acquire(&self, read: &File) -> io::Result<Option<Acquired>> {
+        let mut state = self.shared.state();
+
+        if state.error.is_none() && state.token.is_none() {
+            if !state.requested {
+                state.requested = true;
+                self.shared.requested.notify_one();
+            }
+
+            // Don't return `None` while tokens are available, the helper
+            // thread reads one right away.
+            if !poll_for_readiness_timeout(read.as_raw_fd(), Duration::ZERO)? {
+                return Ok(None);
+            }
+            state = self
+                .shared
+                .replied
+                .wait_timeout_while(state, HELPER_REPLY_TIMEOUT, |state| {
+                    state.error.is_none() && state.token.is_none()
+                })
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+
+        let res = match (state.error.take(), state.token.take()) {
+            (Some(error), _) => Err(error),
+            (None, Some(byte)) => Ok(Some(Acquired { byte })),
+            (None, None) => return Ok(None),
+        };
+
+        self.shared.consume_notification();
+        // Let the helper thread know that the token is taken.
+        self.shared.requested.notify_one();
+
+        res
+    }
+}
+
+impl Drop for TokenReader {
+    fn drop(&mut self) {
+        // The helper thread might be blocked reading from the pipe, so
+        // don't wait for it. It releases the token it gets afterwards.
+        self.shared.state().shutdown = true;
+        self.shared.requested.notify_one();
+    }
+}
+
+impl TokenReaderShared {
+    fn state(&self) -> MutexGuard<'_, TokenReaderState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn notify(&self) {
+        (&self.notify_write).write_all(&[0]).ok();
+        self.replied.notify_all();
+    }
+
+    fn consume_notification(&self) {
+        (&self.notify_read).read_exact(&mut [0]).ok();
+    }
+
+    fn run(&self, read: File, write: File) {
+        loop {
+            let mut state = self.state();
+            while !state.requested && !state.shutdown {
+                state = self
+                    .requested
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            if state.shutdown {
+                break;
+            }
+            drop(state);
+
+            // Poll with a timeout so that shutdown is noticed while no
+            // token is available.
+            match poll_for_readiness_timeout(read.as_raw_fd(), Duration::from_millis(100)) {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(err) => {
+                    let mut state = self.state();
+                    state.requested = false;
+                    state.error = Some(err);
+                    self.notify();
+                    continue;
+                }
+            }
+
+            // The token might have been taken by another process after
+            // poll returns, in which case this blocks until the next one
+            // is available.
+            let mut buf = [0];
+            let res = loop {
+                match (&read).read(&mut buf) {
+                    Ok(1) => break Ok(buf[0]),
+                    Ok(_) => break Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => break Err(e),
+                }
+            };
+
+            let mut state = self.state();
+            state.requested = false;
+            match res {
+                Ok(byte) if state.shutdown => {
+                    (&write).write_all(&[byte]).ok();
+                    break;
+                }
+                Ok(crate::SHUTDOWN_TOKEN) => {
+                    (&write).write_all(&[crate::SHUTDOWN_TOKEN]).ok();
+                    state.error = Some(crate::shut_down());
+                }
+                Ok(byte) => state.token = Some(byte),
+                Err(err) => state.error = Some(err),
+            }
+            self.notify();
+
+            // Don't keep a token nobody asks for anymore from other
+            // processes, the next `try_acquire` requests a new one.
+            let (mut state, _) = self
+                .requested
+                .wait_timeout_while(state, UNCLAIMED_TOKEN_TIMEOUT, |state| {
+                    state.token.is_some() && !state.shutdown
+                })
+                .unwrap_or_else(PoisonError::into_inner);
+            if let Some(byte) = state.token.take() {
+                self.consume_notification();
+                (&write).write_all(&[byte]).ok();
             }
         }
+
+        // Release the token nobody has taken.
+        if let Some(byte) = self.state().token.take() {
+            (&write).write_all(&[byte]).ok();
+        }
     }
 }
 
 // start of syscalls
 
+/// Sleep for `delay`, returning early once `cancel` is cancelled. With
+/// `interruptible`, a signal interrupting the sleep is returned as `EINTR`
+/// instead of resuming it.
+pub fn sleep(
+    delay: Duration,
+    cancel: Option<&CancellationFlag>,
+    interruptible: bool,
+) -> io::Result<()> {
+    if delay.is_zero() {
+        return Ok(());
+    }
+
+    // `poll` ignores negative fds, so without `cancel` this only sleeps.
+    let mut fds = [libc::pollfd {
+        fd: cancel.map_or(-1, |flag| flag.read.as_raw_fd()),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    let until = Instant::now() + delay;
+    loop {
+        let timeout = until.saturating_duration_since(Instant::now());
+        // Round up, so that delays below a millisecond are still waited.
+        let timeout = (timeout.as_nanos() + 999_999) / 1_000_000;
+        let timeout = timeout.try_into().unwrap_or(i32::MAX);
+        match cvt(unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout) }) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted && !interruptible => {}
+            res => break res.map(drop),
+        }
+    }
+}
+
+/// Read a token from `file`, returning an error if the jobserver is shut
+/// down, in which case the token is written back to `write` for the other
+/// waiters.
+fn read_token(file: &File, write: &File) -> io::Result<Option<Acquired>> {
+    match read_byte(file)? {
+        Some(crate::SHUTDOWN_TOKEN) => {
+            (&*write).write_all(&[crate::SHUTDOWN_TOKEN]).ok();
+            Err(crate::shut_down())
+        }
+        Some(byte) => Ok(Some(Acquired { byte })),
+        None => Ok(None),
+    }
+}
+
+fn read_byte(file: &File) -> io::Result<Option<u8>> {
+    let mut buf = [0];
+    loop {
+        match (&*file).read(&mut buf) {
+            Ok(1) => break Ok(Some(buf[0])),
+            Ok(_) => break Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+
+            // Interrupted by signal, try again
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(None),
+
+            Err(e) => break Err(e),
+        }
+    }
+}
+
 /// Return fds that are nonblocking and cloexec
 fn create_pipe() -> io::Result<[RawFd; 2]> {
     let mut pipes = [0; 2];
 
-    // Attempt atomically-create-with-cloexec if we can on Linux,
-    // detected by using the `syscall` function in `libc` to try to work
-    // with as many kernels/glibc implementations as possible.
-    #[cfg(target_os = "linux")]
+    // Attempt atomically-create-with-cloexec if we can on platforms that
+    // provide `pipe2`, falling back to `pipe` if the kernel doesn't
+    // support it to work with as many kernels/libc implementations as
+    // possible.
+    #[cfg(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "illumos",
+        target_os = "linux",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "redox",
+    ))]
     {
         use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
 
@@ -358,6 +1643,10 @@ fn create_pipe() -> io::Result<[RawFd; 2]> {
         }
     }
 
+    // On other platforms, e.g. macOS, QNX or AIX, there is a small window between
+    // `pipe` and `fcntl` where a concurrently spawned child could inherit
+    // the fds. Duplicating them with `F_DUPFD_CLOEXEC` would not close it,
+    // since the original fds would still be inheritable until closed.
     cvt(unsafe { libc::pipe(pipes.as_mut_ptr()) })?;
 
     set_cloexec(pipes[0], true)?;
@@ -366,6 +1655,256 @@ fn create_pipe() -> io::Result<[RawFd; 2]> {
     Ok(pipes)
 }
 
+/// Duplicate `fd`, optionally with `CLOEXEC` set on the new fd.
+fn dup_fd(fd: c_int, cloexec: bool) -> io::Result<File> {
+    let cmd = if cloexec {
+        libc::F_DUPFD_CLOEXEC
+    } else {
+        libc::F_DUPFD
+    };
+    // Safety: F_DUPFD/F_DUPFD_CLOEXEC takes one c_int, the lowest fd to use
+    // for the new fd, and returns a new fd owned by us.
+    let fd = cvt(unsafe { libc::fcntl(fd, cmd, 3) })?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Fifos created by `Client::new_fifo` are named
+/// `{FIFO_PREFIX}{pid}_{boot id}_{random}`.
+const FIFO_PREFIX: &str = "__rust_jobslot_fifo_";
+
+/// Orphaned fifos are only removed on collision once they are older than
+/// this, since a fifo persisted with `persist_fifo` may still be used by
+/// other processes after its creator exits.
+const ORPHANED_FIFO_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Named jobservers live in `$XDG_RUNTIME_DIR` if set, which only the
+/// current user can write to, or else in `/tmp` with the uid in their
+/// name. Since anyone can create the fifo first in `/tmp`, it is checked
+/// with `check_named_fifo` once opened.
+pub fn named_fifo_path(name: &str) -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute() && dir.is_dir())
+        .or_else(|| fifo_dir().ok())
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join(format!(
+            "__rust_jobslot_named_{}_{}",
+            unsafe { libc::getuid() },
+            name
+        ))
+}
+
+/// Return an error of kind `PermissionDenied` unless `file` is a fifo
+/// owned by the current user and only accessible by them, so that another
+/// user cannot create a named jobserver first to steal or starve tokens.
+fn check_named_fifo(file: &File) -> io::Result<()> {
+    let metadata = file.metadata()?;
+
+    if metadata.file_type().is_fifo()
+        && metadata.uid() == unsafe { libc::getuid() }
+        && metadata.mode() & 0o777 == 0o600
+    {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "named jobserver is not a fifo owned by and only accessible to the current user",
+        ))
+    }
+}
+
+/// Directory fifos are created in by default: `/tmp`, except on Android,
+/// where there is no such directory writable by apps, `$TMPDIR` or else
+/// the cache directory of the app.
+fn fifo_dir() -> io::Result<PathBuf> {
+    #[cfg(not(target_os = "android"))]
+    return Ok(PathBuf::from("/tmp"));
+
+    #[cfg(target_os = "android")]
+    {
+        let dirs = std::env::var_os("TMPDIR")
+            .map(PathBuf::from)
+            .into_iter()
+            .chain(android_cache_dir());
+        for dir in dirs {
+            if is_writable_dir(&dir) {
+                return Ok(dir);
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no writable directory found for the jobserver fifo, set TMPDIR to \
+             one or pass one to Client::new_with_fifo_in",
+        ))
+    }
+}
+
+/// Return the cache directory of the app the current process belongs to.
+#[cfg(target_os = "android")]
+fn android_cache_dir() -> Option<PathBuf> {
+    let cmdline = fs::read("/proc/self/cmdline").ok()?;
+    // Processes of an app are named after its package, with `:NAME`
+    // appended for its secondary processes.
+    let name = cmdline.split(|b| *b == 0).next()?;
+    let package = name.split(|b| *b == b':').next()?;
+    let package = std::str::from_utf8(package)
+        .ok()
+        .filter(|package| !package.is_empty() && !package.contains('/'))?;
+
+    Some(Path::new("/data/data").join(package).join("cache"))
+}
+
+#[cfg(target_os = "android")]
+fn is_writable_dir(dir: &Path) -> bool {
+    match CString::new(dir.as_os_str().as_bytes()) {
+        Ok(cdir) => {
+            dir.is_dir() && unsafe { libc::access(cdir.as_ptr(), libc::W_OK | libc::X_OK) } == 0
+        }
+        Err(_) => false,
+    }
+}
+
+fn mkfifo(path: &CString) -> io::Result<()> {
+    cvt(unsafe { libc::mkfifo(path.as_ptr(), libc::S_IRUSR | libc::S_IWUSR) }).map(drop)
+}
+
+fn mkfifoat(dir: BorrowedFd<'_>, name: &CString) -> io::Result<()> {
+    #[cfg(not(any(target_os = "android", target_os = "solaris", target_os = "illumos")))]
+    return cvt(unsafe {
+        libc::mkfifoat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            libc::S_IRUSR | libc::S_IWUSR,
+        )
+    })
+    .map(drop);
+
+    #[cfg(any(target_os = "android", target_os = "solaris", target_os = "illumos"))]
+    {
+        let _ = (dir, name);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "mkfifoat is not supported on this platform",
+        ))
+    }
+}
+
+/// Return the absolute path of the directory `fd`, if it can be found.
+fn fd_path(fd: RawFd) -> Option<PathBuf> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    return fs::read_link(format!("/proc/self/fd/{}", fd))
+        .ok()
+        .filter(|path| path.is_absolute() && path.is_dir());
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    {
+        let mut buf = [0u8; libc::PATH_MAX as usize];
+        cvt(unsafe { libc::fcntl(fd, libc::F_GETPATH, buf.as_mut_ptr()) }).ok()?;
+        let len = buf.iter().position(|b| *b == 0)?;
+        return Some(PathBuf::from(OsStr::from_bytes(&buf[..len])));
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    )))]
+    {
+        let _ = fd;
+        None
+    }
+}
+
+/// Return an identifier of the current boot, or `0` if unknown.
+fn boot_id() -> String {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Ok(id) = fs::read_to_string("/proc/sys/kernel/random/boot_id") {
+        let id: String = id.chars().filter(char::is_ascii_hexdigit).take(8).collect();
+        if !id.is_empty() {
+            return id;
+        }
+    }
+
+    "0".into()
+}
+
+/// Remove the fifo at `path` if it was created by `Client::new_fifo` of a
+/// process which is no longer running, is owned by the current user and
+/// is older than `age`.
+///
+/// Return true if it is removed.
+pub fn remove_if_orphaned(path: &Path, age: Duration) -> io::Result<bool> {
+    let rest = match path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix(FIFO_PREFIX))
+    {
+        Some(rest) => rest,
+        None => return Ok(false),
+    };
+    let mut parts = rest.split('_');
+    let (pid, boot) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(pid), Some(boot), Some(random), None) if u128::from_str_radix(random, 16).is_ok() => {
+            match pid.parse::<libc::pid_t>() {
+                Ok(pid) => (pid, boot),
+                Err(_) => return Ok(false),
+            }
+        }
+        _ => return Ok(false),
+    };
+
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    if !metadata.file_type().is_fifo()
+        || metadata.uid() != unsafe { libc::getuid() }
+        || metadata
+            .modified()?
+            .elapsed()
+            .map_or(true, |elapsed| elapsed < age)
+    {
+        return Ok(false);
+    }
+
+    // Pids are only meaningful within the boot the fifo is created in.
+    let alive = boot == boot_id()
+        && (unsafe { libc::kill(pid, 0) } == 0
+            || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH));
+    if alive {
+        return Ok(false);
+    }
+
+    for path in [path.to_owned(), {
+        let mut preempt = path.as_os_str().to_owned();
+        preempt.push(".preempt");
+        PathBuf::from(preempt)
+    }] {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Remove orphaned fifos in `fifo_dir` older than `age`, see
+/// `remove_if_orphaned`.
+pub fn remove_orphaned_fifos(age: Duration) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(fifo_dir()?)? {
+        if remove_if_orphaned(&entry?.path(), age)? {
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
 fn set_cloexec(fd: c_int, set: bool) -> io::Result<()> {
     // F_GETFD/F_SETFD can only ret/set FD_CLOEXEC
     let flag = if set { libc::FD_CLOEXEC } else { 0 };
@@ -388,7 +1927,7 @@ fn set_blocking(fd: c_int) -> io::Result<()> {
     set_fd_flags(fd, 0)
 }
 
-fn cvt(t: c_int) -> io::Result<c_int> {
+pub(crate) fn cvt(t: c_int) -> io::Result<c_int> {
     if t == -1 {
         Err(io::Error::last_os_error())
     } else {
@@ -405,8 +1944,31 @@ fn cvt_retry_on_interrupt(f: impl Fn() -> c_int) -> io::Result<c_int> {
     }
 }
 
-fn is_pipe(file: &File) -> Option<bool> {
-    Some(file.metadata().ok()?.file_type().is_fifo())
+fn is_pipe(file: &File) -> io::Result<bool> {
+    Ok(file.metadata()?.file_type().is_fifo())
+}
+
+fn is_same_file(file: &File, other: &File) -> io::Result<bool> {
+    let (file, other) = (file.metadata()?, other.metadata()?);
+    Ok(file.dev() == other.dev() && file.ino() == other.ino())
+}
+
+/// Return path of the file referred by `fd`, if any.
+#[cfg(target_vendor = "apple")]
+fn get_path(fd: RawFd) -> Option<PathBuf> {
+    let mut buf = vec![0_u8; libc::MAXPATHLEN as usize];
+    // Safety: F_GETPATH takes a buffer of at least MAXPATHLEN bytes and
+    // writes a nul-terminated path into it.
+    cvt(unsafe { libc::fcntl(fd, libc::F_GETPATH, buf.as_mut_ptr()) }).ok()?;
+
+    let len = buf.iter().position(|b| *b == 0)?;
+    buf.truncate(len);
+
+    if buf.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(std::ffi::OsString::from_vec(buf)))
+    }
 }
 
 fn get_access_mode(file: &File) -> Option<c_int> {
@@ -435,15 +1997,44 @@ fn poll_for_readiness1(fd: RawFd) -> io::Result<()> {
     }
 }
 
+/// Return `false` if the fd is not ready before `timeout`.
+fn poll_for_readiness_timeout(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    let mut fds = [libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+
+    let timeout = timeout.as_millis().try_into().unwrap_or(c_int::MAX);
+    let ret = poll(&mut fds, timeout)?;
+    Ok(ret != 0 && is_ready(fds[0].revents)?)
+}
+
 fn poll(fds: &mut [libc::pollfd], timeout: c_int) -> io::Result<c_int> {
     let nfds: libc::nfds_t = fds.len().try_into().unwrap();
     let fds = fds.as_mut_ptr();
     cvt_retry_on_interrupt(move || unsafe { libc::poll(fds, nfds, timeout) })
 }
 
+fn unsupported_fionread() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "querying available tokens is not supported on this platform",
+    )
+}
+
 fn is_ready(revents: libc::c_short) -> io::Result<bool> {
     use libc::{POLLERR, POLLHUP, POLLIN, POLLNVAL};
 
+    // On QNX, `POLLIN` is `POLLRDNORM | POLLRDBAND` and a readable pipe
+    // only reports `POLLRDNORM`.
+    #[cfg(target_os = "nto")]
+    let revents = if revents & POLLIN != 0 {
+        (revents & !POLLIN) | POLLIN
+    } else {
+        revents
+    };
+
     match revents {
         POLLERR | POLLHUP | POLLIN => Ok(true),
         // This should be very rare
@@ -458,3 +2049,48 @@ fn is_ready(revents: libc::c_short) -> io::Result<bool> {
 fn open_file_rw(file: &Path) -> io::Result<File> {
     fs::OpenOptions::new().read(true).write(true).open(file)
 }
+
+/// Write `buf` to `file` with `SIGPIPE` blocked, so that writing to a pipe
+/// without readers left, e.g. since the parent make died, fails with
+/// `EPIPE` instead of killing the process if `SIGPIPE` is not ignored.
+///
+/// A `SIGPIPE` raised by the write is consumed before unblocking it,
+/// unless one was already pending.
+fn write_without_sigpipe(file: &File, buf: &[u8]) -> io::Result<usize> {
+    unsafe {
+        let mut sigpipe = MaybeUninit::uninit();
+        libc::sigemptyset(sigpipe.as_mut_ptr());
+        libc::sigaddset(sigpipe.as_mut_ptr(), libc::SIGPIPE);
+        let sigpipe = sigpipe.assume_init();
+
+        let mut old = MaybeUninit::uninit();
+        match libc::pthread_sigmask(libc::SIG_BLOCK, &sigpipe, old.as_mut_ptr()) {
+            0 => (),
+            err => return Err(io::Error::from_raw_os_error(err)),
+        }
+        let old = old.assume_init();
+        let was_pending = sigpipe_pending();
+
+        let res = (&*file).write(buf);
+
+        if matches!(&res, Err(err) if err.raw_os_error() == Some(libc::EPIPE))
+            && !was_pending
+            && sigpipe_pending()
+        {
+            let mut sig = 0;
+            libc::sigwait(&sigpipe, &mut sig);
+        }
+
+        libc::pthread_sigmask(libc::SIG_SETMASK, &old, ptr::null_mut());
+        res
+    }
+}
+
+fn sigpipe_pending() -> bool {
+    unsafe {
+        let mut pending = MaybeUninit::uninit();
+        libc::sigemptyset(pending.as_mut_ptr());
+        libc::sigpending(pending.as_mut_ptr());
+        libc::sigismember(pending.as_ptr(), libc::SIGPIPE) == 1
+    }
+}
@@ -1,63 +1,456 @@
 use std::{
     borrow::Cow,
+    collections::VecDeque,
     io,
-    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex, MutexGuard, PoisonError,
+    },
     task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
+#[cfg(target_os = "wasi")]
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    mem::ManuallyDrop,
+    os::wasi::io::{AsRawFd, FromRawFd, RawFd},
+    sync::Arc,
+    thread,
+};
+
+#[cfg(all(
+    feature = "web-workers",
+    target_family = "wasm",
+    target_os = "unknown",
+    not(target_feature = "atomics")
+))]
+use crate::web_workers::SharedCounter;
+use crate::{FromEnvConfig, FromEnvError};
+
 #[derive(Debug)]
 pub struct Client {
     count: Mutex<usize>,
+    /// Set by `shutdown` while holding the lock of `count`.
+    shut_down: AtomicBool,
     cvar: Condvar,
-    wakers: Mutex<Vec<Waker>>,
+    wakers: Mutex<Wakers>,
+    /// Jobserver shared with the host runtime, via descriptors passed to
+    /// this module.
+    #[cfg(target_os = "wasi")]
+    remote: Option<Remote>,
+    /// Jobserver shared with other web workers through a
+    /// `SharedArrayBuffer`.
+    #[cfg(all(
+        feature = "web-workers",
+        target_family = "wasm",
+        target_os = "unknown",
+        not(target_feature = "atomics")
+    ))]
+    shared: Option<SharedCounter>,
+}
+
+#[cfg(target_os = "wasi")]
+#[derive(Debug)]
+struct Remote {
+    shared: Arc<RemoteShared>,
+    auth: Box<str>,
 }
 
+/// Part of [`Remote`] shared with the thread polling the jobserver for
+/// `poll_acquire`.
+#[cfg(target_os = "wasi")]
 #[derive(Debug)]
-pub struct Acquired(());
+struct RemoteShared {
+    read: ManuallyDrop<File>,
+    write: ManuallyDrop<File>,
+    /// Whether `read` and `write` are opened by this module and closed on
+    /// drop, descriptors given by the host are only borrowed.
+    owned: bool,
+    poller: Mutex<Poller>,
+    /// Notified when a waker is registered or removed, or on drop.
+    poller_cvar: Condvar,
+}
+
+/// Wakers of pending `poll_acquire`s, woken up by the poller thread once
+/// the jobserver becomes readable.
+#[cfg(target_os = "wasi")]
+#[derive(Debug, Default)]
+struct Poller {
+    state: PollerState,
+    wakers: Wakers,
+}
+
+#[cfg(target_os = "wasi")]
+#[derive(Debug, Default, PartialEq)]
+enum PollerState {
+    #[default]
+    NotStarted,
+    Running,
+    /// Threads cannot be spawned, e.g. on `wasm32-wasip1`.
+    Unavailable,
+    /// The [`Remote`] is dropped.
+    Stopped,
+}
+
+/// Byte of the token, if it is read from a jobserver shared with the host.
+#[derive(Clone, Debug)]
+pub struct Acquired(Option<u8>);
+
+impl Acquired {
+    pub fn byte(&self) -> Option<u8> {
+        self.0
+    }
+}
+
+#[derive(Debug)]
+pub struct CancellationFlag(AtomicBool);
+
+impl CancellationFlag {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self(AtomicBool::new(false)))
+    }
+
+    pub fn cancel(&self) -> io::Result<()> {
+        self.0.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Wakers of pending `poll_acquire`s, keyed so that they can be updated
+/// instead of being pushed again on every poll and removed once the
+/// pending acquire is cancelled.
+#[derive(Debug, Default)]
+struct Wakers {
+    next_key: usize,
+    waiting: VecDeque<(usize, Waker)>,
+}
+
+impl Wakers {
+    /// Register `waker` under `key`, allocating a new key if it is `None`.
+    ///
+    /// Without a key, `waker` is deduplicated against the ones already
+    /// registered instead.
+    fn register(&mut self, key: &mut Option<usize>, waker: &Waker) {
+        let entry = match *key {
+            Some(key) => self.waiting.iter_mut().find(|(k, _)| *k == key),
+            None => self.waiting.iter_mut().find(|(_, w)| w.will_wake(waker)),
+        };
+
+        match (entry, *key) {
+            (Some((_, w)), _) => {
+                if !w.will_wake(waker) {
+                    *w = waker.clone();
+                }
+            }
+            // Already woken up, but still no token available.
+            (None, Some(key)) => self.waiting.push_back((key, waker.clone())),
+            (None, None) => {
+                let new_key = self.next_key;
+                self.next_key = self.next_key.wrapping_add(1);
+                self.waiting.push_back((new_key, waker.clone()));
+                *key = Some(new_key);
+            }
+        }
+    }
+
+    /// Return false if there is no waker registered under `key`, i.e. it
+    /// has already been woken up.
+    fn remove(&mut self, key: usize) -> bool {
+        if let Some(index) = self.waiting.iter().position(|(k, _)| *k == key) {
+            self.waiting.remove(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn wake_one(&mut self) {
+        if let Some((_, waker)) = self.waiting.pop_front() {
+            waker.wake();
+        }
+    }
+}
 
 impl Client {
     pub fn new(limit: usize) -> io::Result<Client> {
         Ok(Client {
             count: Mutex::new(limit),
+            shut_down: AtomicBool::new(false),
             cvar: Condvar::new(),
             wakers: Mutex::default(),
+            #[cfg(target_os = "wasi")]
+            remote: None,
+            #[cfg(all(
+                feature = "web-workers",
+                target_family = "wasm",
+                target_os = "unknown",
+                not(target_feature = "atomics")
+            ))]
+            shared: None,
         })
     }
 
-    pub unsafe fn open(_s: &[u8]) -> Option<Client> {
-        None
+    #[cfg(all(
+        feature = "web-workers",
+        target_family = "wasm",
+        target_os = "unknown",
+        not(target_feature = "atomics")
+    ))]
+    pub fn new_shared(shared: SharedCounter) -> Client {
+        Client {
+            count: Mutex::new(0),
+            shut_down: AtomicBool::new(false),
+            cvar: Condvar::new(),
+            wakers: Mutex::default(),
+            shared: Some(shared),
+        }
+    }
+
+    #[cfg(all(
+        feature = "web-workers",
+        target_family = "wasm",
+        target_os = "unknown",
+        not(target_feature = "atomics")
+    ))]
+    pub fn shared(&self) -> Option<&SharedCounter> {
+        self.shared.as_ref()
+    }
+
+    #[cfg(not(target_os = "wasi"))]
+    pub unsafe fn open(_s: &[u8], _config: &FromEnvConfig) -> Result<Client, FromEnvError> {
+        Err(FromEnvError::NotFound)
+    }
+
+    /// On WASI, the host runtime can share its jobserver either by
+    /// passing `fifo:PATH` with `PATH` inside a preopened directory, or by
+    /// passing `R,W` with `R` and `W` being descriptors it has given to this
+    /// module.
+    #[cfg(target_os = "wasi")]
+    pub unsafe fn open(var: &[u8], config: &FromEnvConfig) -> Result<Client, FromEnvError> {
+        let auth = std::str::from_utf8(var)
+            .map_err(|_| FromEnvError::Malformed(String::from_utf8_lossy(var).into_owned()))?;
+
+        let remote = if let Some(path) = auth.strip_prefix("fifo:") {
+            // `try_clone` is not supported on WASI, so open it twice.
+            let read = fs::OpenOptions::new().read(true).write(true).open(path)?;
+            let write = fs::OpenOptions::new().write(true).open(path)?;
+            Remote::new(read, write, true, auth)?
+        } else {
+            let malformed = || FromEnvError::Malformed(auth.into());
+
+            let (read, write) = auth.split_once(',').ok_or_else(malformed)?;
+            let read: RawFd = read.parse().map_err(|_| malformed())?;
+            let write: RawFd = write.parse().map_err(|_| malformed())?;
+
+            if !config.allow_stdio_fds {
+                if let Some(fd) = [read, write].iter().copied().find(|fd| (0..3).contains(fd)) {
+                    return Err(FromEnvError::StdioFd(fd));
+                }
+            }
+
+            // `try_clone` is not supported on WASI, so borrow them.
+            Remote::new(
+                File::from_raw_fd(read),
+                File::from_raw_fd(write),
+                false,
+                auth,
+            )?
+        };
+
+        Ok(Client {
+            count: Mutex::new(0),
+            shut_down: AtomicBool::new(false),
+            cvar: Condvar::new(),
+            wakers: Mutex::default(),
+            remote: Some(remote),
+        })
     }
 
     fn count(&self) -> MutexGuard<'_, usize> {
         self.count.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
+    /// Return an error if the jobserver is shut down, which must be
+    /// checked while holding the lock of `count`.
+    fn check_shut_down(&self) -> io::Result<()> {
+        if self.shut_down.load(Ordering::Acquire) {
+            Err(crate::shut_down())
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn acquire(&self) -> io::Result<Acquired> {
+        #[cfg(target_os = "wasi")]
+        if let Some(remote) = &self.remote {
+            return remote.acquire();
+        }
+        #[cfg(all(
+            feature = "web-workers",
+            target_family = "wasm",
+            target_os = "unknown",
+            not(target_feature = "atomics")
+        ))]
+        if let Some(shared) = &self.shared {
+            return shared.acquire().map(|()| Acquired(None));
+        }
+
         let mut lock = self.count();
-        while *lock == 0 {
+        loop {
+            self.check_shut_down()?;
+            if *lock > 0 {
+                break;
+            }
             lock = self.cvar.wait(lock).unwrap_or_else(PoisonError::into_inner);
         }
         *lock -= 1;
-        Ok(Acquired(()))
+        Ok(Acquired(None))
+    }
+
+    /// Return `None` if no token is acquired before `deadline`.
+    pub fn acquire_until(&self, deadline: Instant) -> io::Result<Option<Acquired>> {
+        #[cfg(target_os = "wasi")]
+        if let Some(remote) = &self.remote {
+            return remote.acquire_until(deadline);
+        }
+        #[cfg(all(
+            feature = "web-workers",
+            target_family = "wasm",
+            target_os = "unknown",
+            not(target_feature = "atomics")
+        ))]
+        if let Some(shared) = &self.shared {
+            return Ok(shared.acquire_until(deadline)?.then_some(Acquired(None)));
+        }
+
+        let mut lock = self.count();
+        loop {
+            self.check_shut_down()?;
+            if *lock > 0 {
+                break;
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Ok(None);
+            }
+            lock = self
+                .cvar
+                .wait_timeout(lock, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        *lock -= 1;
+        Ok(Some(Acquired(None)))
+    }
+
+    pub fn acquire_interruptible(&self) -> io::Result<Acquired> {
+        self.acquire()
+    }
+
+    pub fn acquire_with_cancel(&self, flag: &CancellationFlag) -> io::Result<Acquired> {
+        #[cfg(target_os = "wasi")]
+        if let Some(remote) = &self.remote {
+            return remote.acquire_with_cancel(flag);
+        }
+        #[cfg(all(
+            feature = "web-workers",
+            target_family = "wasm",
+            target_os = "unknown",
+            not(target_feature = "atomics")
+        ))]
+        if let Some(shared) = &self.shared {
+            return shared
+                .acquire_with_cancel(|| flag.is_cancelled())
+                .map(|()| Acquired(None));
+        }
+
+        // The flag has no way to notify `cvar`, so check it periodically.
+        let mut lock = self.count();
+        loop {
+            if flag.is_cancelled() {
+                return Err(crate::cancelled());
+            }
+            self.check_shut_down()?;
+            if *lock > 0 {
+                break;
+            }
+            lock = self
+                .cvar
+                .wait_timeout(lock, Duration::from_millis(10))
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        *lock -= 1;
+        Ok(Acquired(None))
     }
 
     pub fn try_acquire(&self) -> io::Result<Option<Acquired>> {
+        #[cfg(target_os = "wasi")]
+        if let Some(remote) = &self.remote {
+            return remote.try_acquire();
+        }
+        #[cfg(all(
+            feature = "web-workers",
+            target_family = "wasm",
+            target_os = "unknown",
+            not(target_feature = "atomics")
+        ))]
+        if let Some(shared) = &self.shared {
+            return Ok(shared.try_acquire()?.then_some(Acquired(None)));
+        }
+
         let mut lock = self.count();
+        self.check_shut_down()?;
         if *lock == 0 {
             Ok(None)
         } else {
             *lock -= 1;
-            Ok(Some(Acquired(())))
+            Ok(Some(Acquired(None)))
         }
     }
 
-    fn wakers(&self) -> MutexGuard<'_, Vec<Waker>> {
+    fn wakers(&self) -> MutexGuard<'_, Wakers> {
         self.wakers.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
-    pub fn poll_acquire(&self, cx: &mut Context<'_>) -> Poll<io::Result<Acquired>> {
+    /// `key` identifies the pending acquire across polls, it is set on
+    /// the first `Poll::Pending` and cleared once a token is acquired.
+    /// If the pending acquire is dropped, `cancel_poll_acquire` must be
+    /// called with it.
+    pub fn poll_acquire(
+        &self,
+        cx: &mut Context<'_>,
+        key: &mut Option<usize>,
+    ) -> Poll<io::Result<Acquired>> {
+        #[cfg(target_os = "wasi")]
+        if let Some(remote) = &self.remote {
+            return remote.poll_acquire(cx, key);
+        }
+        #[cfg(all(
+            feature = "web-workers",
+            target_family = "wasm",
+            target_os = "unknown",
+            not(target_feature = "atomics")
+        ))]
+        if let Some(shared) = &self.shared {
+            return shared.poll_acquire(cx).map_ok(|()| Acquired(None));
+        }
+
         let mut lock = self.count();
 
+        if let Err(err) = self.check_shut_down() {
+            if let Some(key) = key.take() {
+                self.wakers().remove(key);
+            }
+            return Poll::Ready(Err(err));
+        }
+
         if *lock == 0 {
             // Obtain wakers within critical section of count,
             // to make sure no one else can release any token
@@ -66,32 +459,154 @@ impl Client {
             //
             // Afterwards, anyone who release the token will
             // wake us up.
-            self.wakers().push(cx.waker().clone());
+            self.wakers().register(key, cx.waker());
             Poll::Pending
         } else {
             *lock -= 1;
-            Poll::Ready(Ok(Acquired(())))
+            let mut wakers = self.wakers();
+            match key.take() {
+                Some(key) => {
+                    wakers.remove(key);
+                }
+                None => wakers.waiting.retain(|(_, w)| !w.will_wake(cx.waker())),
+            }
+            Poll::Ready(Ok(Acquired(None)))
         }
     }
 
-    pub fn release(&self, _data: Option<&Acquired>) -> io::Result<()> {
+    pub fn cancel_poll_acquire(&self, key: usize) {
+        #[cfg(target_os = "wasi")]
+        if let Some(remote) = &self.remote {
+            return remote.cancel_poll_acquire(key);
+        }
+        #[cfg(all(
+            feature = "web-workers",
+            target_family = "wasm",
+            target_os = "unknown",
+            not(target_feature = "atomics")
+        ))]
+        if self.shared.is_some() {
+            return;
+        }
+
+        let lock = self.count();
+        let mut wakers = self.wakers();
+
+        // If it has been woken up by `release`, pass the wakeup on to the
+        // next one, otherwise the released token might never be acquired.
+        if !wakers.remove(key) && *lock > 0 {
+            wakers.wake_one();
+        }
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        #[cfg(target_os = "wasi")]
+        if self.remote.is_some() {
+            return Err(Remote::unsupported());
+        }
+        #[cfg(all(
+            feature = "web-workers",
+            target_family = "wasm",
+            target_os = "unknown",
+            not(target_feature = "atomics")
+        ))]
+        if let Some(shared) = &self.shared {
+            return shared.shutdown();
+        }
+
+        let lock = self.count();
+        self.shut_down.store(true, Ordering::Release);
+        drop(lock);
+
+        self.cvar.notify_all();
+        for (_, waker) in self.wakers().waiting.drain(..) {
+            waker.wake();
+        }
+
+        Ok(())
+    }
+
+    /// Drain all tokens available and release one per byte of `tokens`
+    /// instead, undoing `shutdown`.
+    pub fn reset(&self, tokens: &[u8]) -> io::Result<()> {
+        #[cfg(target_os = "wasi")]
+        if self.remote.is_some() {
+            return Err(Remote::unsupported());
+        }
+        #[cfg(all(
+            feature = "web-workers",
+            target_family = "wasm",
+            target_os = "unknown",
+            not(target_feature = "atomics")
+        ))]
+        if let Some(shared) = &self.shared {
+            return shared.reset(tokens.len());
+        }
+
+        {
+            let mut lock = self.count();
+            *lock = 0;
+            self.shut_down.store(false, Ordering::Release);
+        }
+        for _ in tokens {
+            self.release(None)?;
+        }
+        Ok(())
+    }
+
+    pub fn release(&self, data: Option<&Acquired>) -> io::Result<()> {
+        #[cfg(target_os = "wasi")]
+        if let Some(remote) = &self.remote {
+            return remote.release(data.and_then(Acquired::byte).unwrap_or(b'+'));
+        }
+        #[cfg(all(
+            feature = "web-workers",
+            target_family = "wasm",
+            target_os = "unknown",
+            not(target_feature = "atomics")
+        ))]
+        if let Some(shared) = &self.shared {
+            return shared.release();
+        }
+        #[cfg(not(target_os = "wasi"))]
+        let _ = data;
+
         let mut lock = self.count();
         *lock += 1;
         drop(lock);
 
-        // Wake up, even if the lock might not be enough for everyone,
-        // it still has to wake up all async wakers to prevent any of
-        // them from beinmg asleep forever.
+        // Only one token is released, so wake up one waiter.
         //
         // It's ok to not hold the lock of count, the worst case scenario
-        // is they will add themselves back to the queue again.
+        // is the waiter will add itself back to the queue again, and the
+        // waiter is passed on in `cancel_poll_acquire` if it gives up.
         self.cvar.notify_one();
-        self.wakers().drain(..).for_each(Waker::wake);
+        self.wakers().wake_one();
 
         Ok(())
     }
 
+    pub fn close(self) -> io::Result<()> {
+        // Files of a jobserver shared with the host report no error on
+        // close on WASI.
+        Ok(())
+    }
+
+    /// Return false if `string_arg` panics.
+    pub fn has_string_arg(&self) -> bool {
+        #[cfg(target_os = "wasi")]
+        return self.remote.is_some();
+
+        #[cfg(not(target_os = "wasi"))]
+        return false;
+    }
+
     pub fn string_arg(&self) -> Cow<'_, str> {
+        #[cfg(target_os = "wasi")]
+        if let Some(remote) = &self.remote {
+            return Cow::Borrowed(&remote.auth);
+        }
+
         panic!(
             "On this platform there is no cross process jobserver support,
              so Client::configure_and_run is not supported."
@@ -105,7 +620,343 @@ impl Client {
         );
     }
 
+    /// Identify a jobserver shared with the host by its auth, jobservers
+    /// within this module have no key.
+    pub fn jobserver_key(&self) -> Option<String> {
+        #[cfg(target_os = "wasi")]
+        if let Some(remote) = &self.remote {
+            return Some(remote.auth.to_string());
+        }
+
+        None
+    }
+
     pub fn available(&self) -> io::Result<usize> {
+        #[cfg(target_os = "wasi")]
+        if self.remote.is_some() {
+            return Err(Remote::unsupported());
+        }
+        #[cfg(all(
+            feature = "web-workers",
+            target_family = "wasm",
+            target_os = "unknown",
+            not(target_feature = "atomics")
+        ))]
+        if let Some(shared) = &self.shared {
+            return shared.available();
+        }
+
         Ok(*self.count())
     }
 }
+
+#[cfg(target_os = "wasi")]
+impl Remote {
+    /// How long the poller thread waits for readiness before checking
+    /// whether it is stopped, and how long `poll_acquire` waits for a token
+    /// if no poller thread can be spawned.
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    fn new(read: File, write: File, owned: bool, auth: &str) -> io::Result<Self> {
+        // A fifo opened by this module has a file description of its own,
+        // so only descriptors given by the host need `O_NONBLOCK` to be
+        // set around each read, see `RemoteShared::read_token`.
+        if owned {
+            set_nonblocking(read.as_raw_fd(), true)?;
+        }
+
+        Ok(Self {
+            shared: Arc::new(RemoteShared {
+                read: ManuallyDrop::new(read),
+                write: ManuallyDrop::new(write),
+                owned,
+                poller: Mutex::default(),
+                poller_cvar: Condvar::new(),
+            }),
+            auth: auth.into(),
+        })
+    }
+
+    fn try_acquire(&self) -> io::Result<Option<Acquired>> {
+        self.shared.read_token()
+    }
+
+    fn wait(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        self.shared.wait(timeout)
+    }
+
+    fn acquire(&self) -> io::Result<Acquired> {
+        loop {
+            if let Some(acquired) = self.try_acquire()? {
+                break Ok(acquired);
+            }
+            self.wait(None)?;
+        }
+    }
+
+    fn acquire_until(&self, deadline: Instant) -> io::Result<Option<Acquired>> {
+        loop {
+            if let Some(acquired) = self.try_acquire()? {
+                break Ok(Some(acquired));
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                break Ok(None);
+            }
+            self.wait(Some(timeout))?;
+        }
+    }
+
+    fn acquire_with_cancel(&self, flag: &CancellationFlag) -> io::Result<Acquired> {
+        // The flag has no descriptor to poll, so check it periodically.
+        loop {
+            if flag.is_cancelled() {
+                break Err(crate::cancelled());
+            }
+            if let Some(acquired) = self.try_acquire()? {
+                break Ok(acquired);
+            }
+            self.wait(Some(Duration::from_millis(10)))?;
+        }
+    }
+
+    /// Register the waker of `cx` with the poller thread, which wakes it up
+    /// once the jobserver is readable, see `Client::poll_acquire` for
+    /// `key`.
+    fn poll_acquire(
+        &self,
+        cx: &mut Context<'_>,
+        key: &mut Option<usize>,
+    ) -> Poll<io::Result<Acquired>> {
+        let shared = &self.shared;
+
+        let res = shared.read_token();
+        let mut poller = shared.poller();
+        match res {
+            Ok(None) => (),
+            res => {
+                if let Some(key) = key.take() {
+                    poller.wakers.remove(key);
+                }
+                shared.poller_cvar.notify_all();
+                return Poll::Ready(res.map(Option::unwrap));
+            }
+        }
+
+        if poller.state == PollerState::NotStarted {
+            let shared2 = shared.clone();
+            poller.state = match thread::Builder::new()
+                .name("jobslot-wasi-poller".into())
+                .spawn(move || shared2.run_poller())
+            {
+                Ok(_) => PollerState::Running,
+                Err(_) => PollerState::Unavailable,
+            };
+        }
+
+        if poller.state == PollerState::Running {
+            poller.wakers.register(key, cx.waker());
+            shared.poller_cvar.notify_all();
+            return Poll::Pending;
+        }
+        drop(poller);
+
+        // Without a thread to wait for the descriptor, wait for it for a
+        // short while, to not spin the executor, and then let the task poll
+        // it again once it yields.
+        match shared.wait(Some(Self::POLL_INTERVAL)) {
+            Ok(_) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+
+    fn cancel_poll_acquire(&self, key: usize) {
+        let mut poller = self.shared.poller();
+
+        // If it has been woken up by the poller thread, pass the wakeup on
+        // to the next one, otherwise the token might never be acquired.
+        if !poller.wakers.remove(key) {
+            poller.wakers.wake_one();
+        }
+        self.shared.poller_cvar.notify_all();
+    }
+
+    fn release(&self, byte: u8) -> io::Result<()> {
+        match (&*self.shared.write).write(&[byte])? {
+            1 => Ok(()),
+            _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+        }
+    }
+
+    /// The number of tokens available, resetting and shutting down are not
+    /// supported for a jobserver shared with the host.
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "not supported on a jobserver shared with the host",
+        )
+    }
+}
+
+#[cfg(target_os = "wasi")]
+impl Drop for Remote {
+    fn drop(&mut self) {
+        self.shared.poller().state = PollerState::Stopped;
+        self.shared.poller_cvar.notify_all();
+    }
+}
+
+#[cfg(target_os = "wasi")]
+impl RemoteShared {
+    fn poller(&self) -> MutexGuard<'_, Poller> {
+        self.poller.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Read a token without blocking, returning `None` if there is none.
+    ///
+    /// Descriptors given by the host are shared with the rest of this
+    /// module, which might expect them to block, so `O_NONBLOCK` is only
+    /// set while reading. WASI runtimes keep the flags of the descriptors
+    /// of a module to themselves, so the host is not affected either way.
+    fn read_token(&self) -> io::Result<Option<Acquired>> {
+        let fd = self.read.as_raw_fd();
+        if !self.owned {
+            set_nonblocking(fd, true)?;
+        }
+        let res = self.read_byte();
+        if !self.owned {
+            if let Err(err) = set_nonblocking(fd, false) {
+                if let Ok(Some(byte)) = res {
+                    (&*self.write).write_all(&[byte]).ok();
+                }
+                return Err(err);
+            }
+        }
+
+        match res? {
+            // Put it back for the other waiters, see `Client::shutdown`.
+            Some(crate::SHUTDOWN_TOKEN) => {
+                (&*self.write).write_all(&[crate::SHUTDOWN_TOKEN]).ok();
+                Err(crate::shut_down())
+            }
+            Some(byte) => Ok(Some(Acquired(Some(byte)))),
+            None => Ok(None),
+        }
+    }
+
+    fn read_byte(&self) -> io::Result<Option<u8>> {
+        let mut buf = [0];
+        loop {
+            match (&*self.read).read(&mut buf) {
+                Ok(1) => break Ok(Some(buf[0])),
+                Ok(_) => break Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(None),
+                Err(e) => break Err(e),
+            }
+        }
+    }
+
+    /// Wait for the jobserver to be readable for up to `timeout`, or
+    /// without a timeout if it is `None`, returning false if it times out.
+    ///
+    /// `poll` is implemented with the pollables of the stream on WASI
+    /// preview 2. Another waiter might still take the token first.
+    fn wait(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut fd = libc::pollfd {
+            fd: self.read.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Rounded up so that it does not spin before the deadline.
+        let timeout = timeout.map_or(-1, |timeout| {
+            ((timeout.as_nanos() + 999_999) / 1_000_000).min(i32::MAX as u128) as i32
+        });
+
+        loop {
+            match unsafe { libc::poll(&mut fd, 1, timeout) } {
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        break Err(err);
+                    }
+                }
+                0 => break Ok(false),
+                _ => break Ok(true),
+            }
+        }
+    }
+
+    /// Wake up one pending `poll_acquire` whenever the jobserver becomes
+    /// readable, until the [`Remote`] is dropped.
+    fn run_poller(&self) {
+        let mut poller = self.poller();
+        loop {
+            while poller.wakers.waiting.is_empty() && poller.state == PollerState::Running {
+                poller = self
+                    .poller_cvar
+                    .wait(poller)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            if poller.state != PollerState::Running {
+                break;
+            }
+            drop(poller);
+
+            // Time out, so that dropping the `Remote` is noticed.
+            let res = self.wait(Some(Remote::POLL_INTERVAL));
+
+            poller = self.poller();
+            match res {
+                Ok(false) => continue,
+                Ok(true) => poller.wakers.wake_one(),
+                // Let the pending acquires run into the error themselves.
+                Err(_) => {
+                    for (_, waker) in poller.wakers.waiting.drain(..) {
+                        waker.wake();
+                    }
+                }
+            }
+
+            // Give the task woken up a chance to take the token before
+            // polling again, so that one token does not wake up everyone.
+            poller = self
+                .poller_cvar
+                .wait_timeout(poller, Remote::POLL_INTERVAL)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+#[cfg(target_os = "wasi")]
+impl Drop for RemoteShared {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {
+                ManuallyDrop::drop(&mut self.read);
+                ManuallyDrop::drop(&mut self.write);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "wasi")]
+fn set_nonblocking(fd: RawFd, nonblocking: bool) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = if nonblocking {
+        flags | libc::O_NONBLOCK
+    } else {
+        flags & !libc::O_NONBLOCK
+    };
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
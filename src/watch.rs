@@ -0,0 +1,228 @@
+use std::{
+    io,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+};
+
+use crate::ClientInner;
+
+/// A handle yielding updated estimates of the tokens available in a
+/// jobserver, returned by
+/// [`Client::watch_available`](crate::Client::watch_available).
+///
+/// A background thread samples the tokens available periodically, the same
+/// way as [`Observer::available`](crate::Observer::available), without any
+/// side effect, and wakes up the handles once the estimate changes, so
+/// that TUIs and schedulers can react to capacity changes without polling
+/// it in a loop themselves. It exits once all handles are dropped.
+///
+/// On unix, while no token is available, it waits for the jobserver to
+/// become readable instead of sampling it, so that the release of a token
+/// is observed right away.
+///
+/// Each clone keeps track of the estimates it has seen on its own.
+#[derive(Debug)]
+pub struct AvailabilityWatch {
+    shared: Arc<Shared>,
+    seen: u64,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<WatchState>,
+    cvar: Condvar,
+}
+
+#[derive(Debug)]
+struct WatchState {
+    /// The latest estimate, or the error which stopped the sampling.
+    available: Result<usize, (io::ErrorKind, String)>,
+    /// Incremented on every change of `available`.
+    version: u64,
+    wakers: Vec<Waker>,
+    /// Number of alive handles, the sampling thread exits once it is 0.
+    watchers: usize,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, WatchState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl WatchState {
+    fn get(&self) -> io::Result<usize> {
+        self.available
+            .as_ref()
+            .map(|available| *available)
+            .map_err(|(kind, msg)| io::Error::new(*kind, msg.as_str()))
+    }
+}
+
+impl AvailabilityWatch {
+    pub(crate) fn new(client: &Arc<ClientInner>, interval: Duration) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(WatchState {
+                available: Ok(client.peek_available()?),
+                version: 0,
+                wakers: Vec::new(),
+                watchers: 1,
+            }),
+            cvar: Condvar::new(),
+        });
+
+        {
+            let client = client.clone();
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("jobslot-watch".into())
+                .spawn(move || sample(&client, &shared, interval))?;
+        }
+
+        Ok(Self { shared, seen: 0 })
+    }
+
+    /// Return the latest estimate, without marking it as seen.
+    pub fn current(&self) -> io::Result<usize> {
+        self.shared.state().get()
+    }
+
+    /// Block until the estimate changes from the last one returned by this
+    /// handle, and return it.
+    ///
+    /// The first call returns the current estimate right away.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of [`Client::available`](crate::Client::available)
+    /// which stopped the sampling.
+    pub fn changed(&mut self) -> io::Result<usize> {
+        let mut state = self.shared.state();
+        loop {
+            if let Some(res) = take(&mut self.seen, &state) {
+                return res;
+            }
+            state = self
+                .shared
+                .cvar
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Async version of [`AvailabilityWatch::changed`], to be wrapped in a
+    /// future, e.g. with `poll_fn`.
+    pub fn poll_changed(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.state();
+        match take(&mut self.seen, &state) {
+            Some(res) => Poll::Ready(res),
+            None => {
+                if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    state.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Return the estimate if it has not been `seen`, or the error.
+fn take(seen: &mut u64, state: &WatchState) -> Option<io::Result<usize>> {
+    if state.available.is_err() || state.version >= *seen {
+        *seen = state.version + 1;
+        Some(state.get())
+    } else {
+        None
+    }
+}
+
+impl Clone for AvailabilityWatch {
+    fn clone(&self) -> Self {
+        self.shared.state().watchers += 1;
+        Self {
+            shared: self.shared.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl Drop for AvailabilityWatch {
+    fn drop(&mut self) {
+        let mut state = self.shared.state();
+        state.watchers -= 1;
+        if state.watchers == 0 {
+            // Let the sampling thread exit right away.
+            self.shared.cvar.notify_all();
+        }
+    }
+}
+
+fn sample(client: &ClientInner, shared: &Shared, interval: Duration) {
+    let mut state = shared.state();
+    while state.watchers > 0 {
+        state = wait(client, shared, state, interval);
+        if state.watchers == 0 {
+            break;
+        }
+
+        // Do not hold the lock while sampling, which might block briefly
+        // on windows.
+        drop(state);
+        let available = client
+            .peek_available()
+            .map_err(|err| (err.kind(), err.to_string()));
+        state = shared.state();
+
+        if available != state.available {
+            let stop = available.is_err();
+            state.available = available;
+            state.version += 1;
+            shared.cvar.notify_all();
+            for waker in state.wakers.drain(..) {
+                waker.wake();
+            }
+            if stop {
+                break;
+            }
+        }
+    }
+}
+
+/// Wait until the next sample is due.
+///
+/// On unix, no token can be taken while none is available, so if the
+/// latest estimate is 0, wait for the jobserver to become readable instead,
+/// checking for dropped handles every `interval`.
+fn wait<'a>(
+    client: &ClientInner,
+    shared: &'a Shared,
+    state: MutexGuard<'a, WatchState>,
+    interval: Duration,
+) -> MutexGuard<'a, WatchState> {
+    #[cfg(unix)]
+    if state.available == Ok(0) {
+        drop(state);
+        loop {
+            match client.inner.wait_readable(interval) {
+                Ok(false) => {
+                    let state = shared.state();
+                    if state.watchers == 0 {
+                        return state;
+                    }
+                }
+                // Errors are left to the next sample.
+                Ok(true) | Err(_) => return shared.state(),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = client;
+
+    shared
+        .cvar
+        .wait_timeout(state, interval)
+        .unwrap_or_else(PoisonError::into_inner)
+        .0
+}
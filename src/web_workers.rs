@@ -0,0 +1,240 @@
+use std::{
+    convert::TryFrom,
+    io,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use js_sys::{Atomics, Function, Int32Array, Reflect, SharedArrayBuffer};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+
+/// Index of the number of tokens available in the buffer.
+const TOKENS: u32 = 0;
+/// Index of the flag set by `shutdown` in the buffer.
+const SHUT_DOWN: u32 = 1;
+/// Size of the buffer in bytes, two `i32`s.
+const BUFFER_LEN: u32 = 8;
+
+/// Jobserver whose tokens are counted in a `SharedArrayBuffer`, which can
+/// be posted to web workers running their own instance of the module.
+///
+/// Every access goes through `Atomics`, so that all instances sharing the
+/// buffer agree on the count, and waiters on it are woken up by
+/// `Atomics.notify` once a token is released.
+#[derive(Debug)]
+pub(crate) struct SharedCounter {
+    array: Int32Array,
+}
+
+// Without the `atomics` target feature, the module only ever runs on the
+// thread of the worker instantiating it, so the JS values held here are
+// never touched from another thread.
+unsafe impl Send for SharedCounter {}
+unsafe impl Sync for SharedCounter {}
+
+impl SharedCounter {
+    /// How often blocking acquires check their cancellation flag, which
+    /// has no way to notify the buffer.
+    const CANCEL_INTERVAL: Duration = Duration::from_millis(10);
+
+    pub(crate) fn new(limit: usize) -> io::Result<Self> {
+        let limit = i32::try_from(limit).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "limit does not fit in a SharedArrayBuffer",
+            )
+        })?;
+
+        // `SharedArrayBuffer` is only defined in cross-origin isolated
+        // contexts, constructing it otherwise would throw.
+        if !Reflect::has(&js_sys::global(), &JsValue::from_str("SharedArrayBuffer"))
+            .unwrap_or(false)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "SharedArrayBuffer is not available, the page must be cross-origin isolated",
+            ));
+        }
+
+        let this = Self {
+            array: Int32Array::new(&SharedArrayBuffer::new(BUFFER_LEN)),
+        };
+        this.store(TOKENS, limit)?;
+        Ok(this)
+    }
+
+    pub(crate) fn from_buffer(buffer: &SharedArrayBuffer) -> io::Result<Self> {
+        if buffer.byte_length() != BUFFER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SharedArrayBuffer is not one returned by Client::shared_array_buffer",
+            ));
+        }
+
+        Ok(Self {
+            array: Int32Array::new(buffer),
+        })
+    }
+
+    pub(crate) fn buffer(&self) -> SharedArrayBuffer {
+        self.array.buffer().unchecked_into()
+    }
+
+    fn load(&self, index: u32) -> io::Result<i32> {
+        Atomics::load(&self.array, index).map_err(js_error)
+    }
+
+    fn store(&self, index: u32, value: i32) -> io::Result<()> {
+        Atomics::store(&self.array, index, value)
+            .map(drop)
+            .map_err(js_error)
+    }
+
+    /// Wake up every instance waiting for a token, which is cheaper than
+    /// tracking how many of them could get one.
+    fn notify(&self) -> io::Result<()> {
+        Atomics::notify(&self.array, TOKENS)
+            .map(drop)
+            .map_err(js_error)
+    }
+
+    pub(crate) fn try_acquire(&self) -> io::Result<bool> {
+        loop {
+            if self.load(SHUT_DOWN)? != 0 {
+                return Err(crate::shut_down());
+            }
+
+            let available = self.load(TOKENS)?;
+            if available <= 0 {
+                return Ok(false);
+            }
+
+            let previous = Atomics::compare_exchange(&self.array, TOKENS, available, available - 1)
+                .map_err(js_error)?;
+            if previous == available {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Wait until the count is changed from 0, or until `timeout` expires.
+    ///
+    /// Browsers do not allow `Atomics.wait` on the main thread, where it
+    /// throws and an error is returned instead.
+    fn wait(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match timeout {
+            Some(timeout) => {
+                Atomics::wait_with_timeout(&self.array, TOKENS, 0, timeout.as_secs_f64() * 1000.0)
+            }
+            None => Atomics::wait(&self.array, TOKENS, 0),
+        }
+        .map(drop)
+        .map_err(js_error)
+    }
+
+    pub(crate) fn acquire(&self) -> io::Result<()> {
+        while !self.try_acquire()? {
+            self.wait(None)?;
+        }
+        Ok(())
+    }
+
+    /// Return false if no token is acquired before `deadline`.
+    pub(crate) fn acquire_until(&self, deadline: Instant) -> io::Result<bool> {
+        loop {
+            if self.try_acquire()? {
+                return Ok(true);
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Ok(false);
+            }
+            self.wait(Some(timeout))?;
+        }
+    }
+
+    pub(crate) fn acquire_with_cancel(&self, is_cancelled: impl Fn() -> bool) -> io::Result<()> {
+        loop {
+            if is_cancelled() {
+                return Err(crate::cancelled());
+            }
+            if self.try_acquire()? {
+                return Ok(());
+            }
+            self.wait(Some(Self::CANCEL_INTERVAL))?;
+        }
+    }
+
+    /// Wait with `Atomics.waitAsync`, which works on the main thread too,
+    /// and wake up the task once the count is changed from 0.
+    ///
+    /// Every release notifies all waiters, so a task giving up never
+    /// needs to pass its wakeup on.
+    pub(crate) fn poll_acquire(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match self.try_acquire() {
+                Ok(true) => return Poll::Ready(Ok(())),
+                Ok(false) => (),
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+
+            let result = match Atomics::wait_async(&self.array, TOKENS, 0) {
+                Ok(result) => result,
+                Err(err) => return Poll::Ready(Err(js_error(err))),
+            };
+
+            // `async` is false if the count is no longer 0, i.e. a token
+            // has been released since `try_acquire`.
+            let is_async = Reflect::get(&result, &JsValue::from_str("async"))
+                .map(|value| value.is_truthy())
+                .unwrap_or(false);
+            if !is_async {
+                continue;
+            }
+
+            let waker = cx.waker().clone();
+            let on_notified = Closure::once_into_js(move || waker.wake());
+
+            let registered =
+                Reflect::get(&result, &JsValue::from_str("value")).and_then(|promise| {
+                    let then: Function =
+                        Reflect::get(&promise, &JsValue::from_str("then"))?.unchecked_into();
+                    then.call1(&promise, &on_notified)
+                });
+            return match registered {
+                Ok(_) => Poll::Pending,
+                Err(err) => Poll::Ready(Err(js_error(err))),
+            };
+        }
+    }
+
+    pub(crate) fn release(&self) -> io::Result<()> {
+        Atomics::add(&self.array, TOKENS, 1).map_err(js_error)?;
+        self.notify()
+    }
+
+    pub(crate) fn shutdown(&self) -> io::Result<()> {
+        self.store(SHUT_DOWN, 1)?;
+        self.notify()
+    }
+
+    pub(crate) fn reset(&self, tokens: usize) -> io::Result<()> {
+        let tokens = i32::try_from(tokens)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many tokens"))?;
+        self.store(TOKENS, tokens)?;
+        self.store(SHUT_DOWN, 0)?;
+        self.notify()
+    }
+
+    pub(crate) fn available(&self) -> io::Result<usize> {
+        Ok(usize::try_from(self.load(TOKENS)?).unwrap_or(0))
+    }
+}
+
+fn js_error(err: JsValue) -> io::Error {
+    let message = err
+        .dyn_ref::<js_sys::Error>()
+        .map(|err| String::from(err.message()))
+        .unwrap_or_else(|| format!("{:?}", err));
+    io::Error::new(io::ErrorKind::Other, message)
+}
@@ -1,24 +1,40 @@
 use std::{
-    borrow::Cow, convert::TryInto, ffi::CString, fmt::Write, io, mem::MaybeUninit,
-    num::NonZeroIsize, ptr,
+    borrow::Cow,
+    convert::TryInto,
+    io, iter,
+    mem::{self, MaybeUninit},
+    num::NonZeroIsize,
+    os::windows::{
+        io::{AsRawHandle, BorrowedHandle, FromRawHandle, IntoRawHandle, OwnedHandle},
+        process::CommandExt,
+    },
+    process, ptr,
+    time::Instant,
 };
 
 use getrandom::getrandom;
 use windows_sys::Win32::{
     Foundation::{
-        CloseHandle, ERROR_ALREADY_EXISTS, FALSE, HANDLE as RawHandle, WAIT_ABANDONED, WAIT_FAILED,
-        WAIT_OBJECT_0, WAIT_TIMEOUT,
+        CloseHandle, DuplicateHandle, RtlNtStatusToDosError, DUPLICATE_SAME_ACCESS,
+        ERROR_ACCESS_DENIED, ERROR_ALREADY_EXISTS, FALSE, HANDLE as RawHandle,
+        INVALID_HANDLE_VALUE, NTSTATUS, TRUE, WAIT_ABANDONED, WAIT_FAILED, WAIT_OBJECT_0,
+        WAIT_TIMEOUT,
     },
     System::{
+        Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+        },
+        JobObjects::{AssignProcessToJobObject, CreateJobObjectA, TerminateJobObject},
         Threading::{
-            CreateSemaphoreA, ReleaseSemaphore, WaitForSingleObject, INFINITE,
-            SEMAPHORE_MODIFY_STATE, THREAD_SYNCHRONIZE as SYNCHRONIZE,
+            CreateEventW, CreateSemaphoreW, GetCurrentProcess, OpenEventW, OpenSemaphoreW,
+            OpenThread, ReleaseSemaphore, ResetEvent, ResumeThread, SetEvent,
+            WaitForMultipleObjects, WaitForSingleObject, CREATE_SUSPENDED, INFINITE,
+            SEMAPHORE_MODIFY_STATE, THREAD_SUSPEND_RESUME, THREAD_SYNCHRONIZE as SYNCHRONIZE,
         },
-        WindowsProgramming::OpenSemaphoreA,
     },
 };
 
-use crate::Command;
+use crate::{sync, AbandonedPolicy, Command, FromEnvConfig, FromEnvError, RawParts};
 
 type LONG = i32;
 
@@ -26,72 +42,114 @@ type LONG = i32;
 pub struct Client {
     sem: Handle,
     name: Box<str>,
+    /// Manual-reset event signaled by `shutdown`, named after the
+    /// semaphore, or `None` if the semaphore is not created by this crate.
+    shutdown: Option<Handle>,
+    /// The semaphore is opened without `SEMAPHORE_MODIFY_STATE`, so tokens
+    /// can be acquired but not released.
+    read_only: bool,
+    abandoned: sync::Mutex<AbandonedPolicy>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Acquired;
 
-impl Client {
-    pub fn new(limit: usize) -> io::Result<Client> {
-        let limit: LONG = limit
-            .try_into()
-            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+/// Manual-reset event which is signaled once cancelled.
+#[derive(Debug)]
+pub struct CancellationFlag {
+    event: Handle,
+}
+
+impl CancellationFlag {
+    pub fn new() -> io::Result<Self> {
+        let event =
+            unsafe { Handle::new_or_err(CreateEventW(ptr::null(), TRUE, FALSE, ptr::null()))? };
+        Ok(Self { event })
+    }
+
+    pub fn cancel(&self) -> io::Result<()> {
+        if unsafe { SetEvent(self.event.as_raw_handle()) } != 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        unsafe { WaitForSingleObject(self.event.as_raw_handle(), 0) == WAIT_OBJECT_0 }
+    }
+}
+
+/// Job object processes are assigned to before they start running.
+#[derive(Debug)]
+pub struct ProcessGroup {
+    job: Handle,
+}
+
+impl ProcessGroup {
+    pub fn new() -> io::Result<Self> {
+        let job = unsafe { Handle::new_or_err(CreateJobObjectA(ptr::null(), ptr::null()))? };
+        Ok(Self { job })
+    }
+
+    pub fn spawn(&self, cmd: &mut process::Command) -> io::Result<process::Child> {
+        // Spawn it suspended, so that it cannot spawn processes outside of
+        // the job before it is assigned to it.
+        cmd.creation_flags(CREATE_SUSPENDED);
+        let res = cmd.spawn();
+        cmd.creation_flags(0);
+        let mut child = res?;
+
+        let r = unsafe {
+            AssignProcessToJobObject(self.job.as_raw_handle(), child.as_raw_handle() as RawHandle)
+        };
+        let res = if r == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            resume_process(child.id())
+        };
+        if let Err(err) = res {
+            // It cannot be killed along with the group, or never runs.
+            child.kill().ok();
+            child.wait().ok();
+            return Err(err);
+        }
 
-        // Note that `limit == 0` is a valid argument above but Windows
-        // won't let us create a semaphore with 0 slots available to it. Get
-        // `limit == 0` working by creating a semaphore instead with one
-        // slot and then immediately acquire it (without ever releaseing it
-        // back).
-        let create_limit: LONG = if limit == 0 { 1 } else { limit };
+        Ok(child)
+    }
+
+    pub fn kill(&self) -> io::Result<()> {
+        if unsafe { TerminateJobObject(self.job.as_raw_handle(), 1) } != 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
 
+impl Client {
+    pub fn new(limit: usize) -> io::Result<Client> {
         // Try a bunch of random semaphore names until we get a unique one,
         // but don't try for too long.
-        let prefix = "__rust_jobslot_semaphore_";
-
-        let mut name = String::with_capacity(
-            prefix.len() +
-            // 32B for the max size of u128
-            32 +
-            // 1B for the null byte
-            1,
-        );
-        name.push_str(prefix);
+        //
+        // Named objects go away with their last handle, so unlike fifos on
+        // unix there are no leftovers of crashed processes, but the pid
+        // still keeps names of concurrent processes apart.
+        let prefix = format!("__rust_jobslot_semaphore_{}_", process::id());
 
         for _ in 0..100 {
             let mut bytes = [0; 16];
             getrandom(&mut bytes)?;
 
-            write!(&mut name, "{}\0", u128::from_ne_bytes(bytes)).unwrap();
+            let name = format!("{}{}", prefix, u128::from_ne_bytes(bytes));
 
-            let res = unsafe {
-                Handle::new_or_err(CreateSemaphoreA(
-                    ptr::null_mut(),
-                    create_limit,
-                    create_limit,
-                    name.as_ptr(),
-                ))
-            };
-
-            match res {
-                Ok(sem) => {
-                    name.pop(); // chop off the trailing nul
-                    let client = Client {
-                        sem,
-                        name: name.into_boxed_str(),
-                    };
-                    if create_limit != limit {
-                        client.acquire()?;
-                    }
-                    return Ok(client);
-                }
-                Err(err) => {
-                    if err.raw_os_error() == Some(ERROR_ALREADY_EXISTS.try_into().unwrap()) {
-                        name.truncate(prefix.len());
-                        continue;
-                    } else {
-                        return Err(err);
-                    }
+            match Self::create(name, limit) {
+                Err(err)
+                    if err.raw_os_error() == Some(ERROR_ALREADY_EXISTS.try_into().unwrap()) =>
+                {
+                    continue
                 }
+                res => return res,
             }
         }
 
@@ -101,17 +159,144 @@ impl Client {
         ))
     }
 
-    pub unsafe fn open(var: &[u8]) -> Option<Client> {
-        let name = String::from_utf8_lossy(var);
+    /// Create a jobserver with the well-known semaphore name of `name`.
+    pub fn new_named(name: &str, limit: usize) -> io::Result<Client> {
+        Self::create(named_semaphore_name(name), limit)
+    }
+
+    /// Open the jobserver created by `new_named`.
+    pub fn connect_named(name: &str) -> io::Result<Client> {
+        unsafe {
+            Self::open(
+                named_semaphore_name(name).as_bytes(),
+                &FromEnvConfig::default(),
+            )
+        }
+        .map_err(|err| match err {
+            FromEnvError::IoError(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+        })
+    }
+
+    /// Create a jobserver with an unnamed semaphore, which can only be
+    /// passed to children with `duplicate_into_process`.
+    pub fn new_unnamed(limit: usize) -> io::Result<Client> {
+        let sem = unsafe { Handle::new_or_err(create_semaphore(limit, ptr::null())?)? };
+        let shutdown =
+            unsafe { Handle::new_or_err(CreateEventW(ptr::null(), TRUE, FALSE, ptr::null()))? };
 
-        let sem = OpenSemaphoreA(
+        Ok(Client {
+            name: handle_auth(sem.as_raw_handle()).into_boxed_str(),
+            sem,
+            shutdown: Some(shutdown),
+            read_only: false,
+            abandoned: sync::Mutex::default(),
+        })
+    }
+
+    /// Create a semaphore named `name`, failing with `ERROR_ALREADY_EXISTS`
+    /// if it exists.
+    fn create(name: String, limit: usize) -> io::Result<Client> {
+        let sem = unsafe { Handle::new_or_err(create_semaphore(limit, to_wide(&name).as_ptr())?)? };
+        // `CreateSemaphoreW` opens the semaphore if it already exists.
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(ERROR_ALREADY_EXISTS.try_into().unwrap()) {
+            return Err(err);
+        }
+
+        let shutdown = unsafe {
+            Handle::new_or_err(CreateEventW(
+                ptr::null(),
+                TRUE,
+                FALSE,
+                shutdown_event_name(&name).as_ptr(),
+            ))?
+        };
+
+        Ok(Client {
+            sem,
+            name: name.into_boxed_str(),
+            shutdown: Some(shutdown),
+            read_only: false,
+            abandoned: sync::Mutex::default(),
+        })
+    }
+
+    /// Open the semaphore named by `handle:N`, a handle duplicated into
+    /// this process by `duplicate_into_process` of the parent.
+    unsafe fn open_handle(value: &str, config: &FromEnvConfig) -> Result<Client, FromEnvError> {
+        let handle = match value.parse::<isize>().ok().and_then(NonZeroIsize::new) {
+            Some(handle) if handle.get() > 0 => Handle(handle),
+            _ => return Err(FromEnvError::Malformed(format!("handle:{}", value))),
+        };
+
+        // Duplicating it also checks that the handle is valid.
+        let sem = if config.dup_fds {
+            handle.duplicate_borrowed()?
+        } else {
+            handle
+        };
+
+        Ok(Client {
+            name: handle_auth(sem.as_raw_handle()).into_boxed_str(),
+            sem,
+            shutdown: None,
+            read_only: false,
+            abandoned: sync::Mutex::default(),
+        })
+    }
+
+    pub unsafe fn open(var: &[u8], config: &FromEnvConfig) -> Result<Client, FromEnvError> {
+        // Names are passed as UTF-16 so that non-ASCII ones are not mangled
+        // by the ANSI code page, while ASCII ones (e.g. the ones created by
+        // mingw32-make) name the same semaphore either way.
+        let name = match std::str::from_utf8(var) {
+            Ok(name) if !name.contains('\0') => name,
+            _ => {
+                return Err(FromEnvError::Malformed(
+                    String::from_utf8_lossy(var).into_owned(),
+                ))
+            }
+        };
+
+        if let Some(value) = name.strip_prefix("handle:") {
+            return Self::open_handle(value, config);
+        }
+
+        let wide_name = to_wide(name);
+
+        let mut read_only = false;
+        let mut sem = OpenSemaphoreW(
             SYNCHRONIZE | SEMAPHORE_MODIFY_STATE,
             FALSE,
-            CString::new(var).ok()?.as_bytes().as_ptr(),
+            wide_name.as_ptr(),
         );
-        Handle::new(sem).map(|sem| Client {
+        // The creator may have restricted the access with its DACL, in
+        // which case retry with the rights needed to acquire tokens only,
+        // if the caller accepts that they cannot be released.
+        if sem == 0
+            && config.allow_acquire_only
+            && io::Error::last_os_error().raw_os_error()
+                == Some(ERROR_ACCESS_DENIED.try_into().unwrap())
+        {
+            sem = OpenSemaphoreW(SYNCHRONIZE, FALSE, wide_name.as_ptr());
+            read_only = true;
+        }
+        // Check it before `OpenEventW` overwrites the last error.
+        let sem = Handle::new_or_err(sem)?;
+        // Jobservers not created by this crate have no such event.
+        let shutdown = Handle::new(OpenEventW(
+            SYNCHRONIZE,
+            FALSE,
+            shutdown_event_name(name).as_ptr(),
+        ));
+
+        Ok(Client {
             sem,
             name: name.into(),
+            shutdown,
+            read_only,
+            abandoned: sync::Mutex::default(),
         })
     }
 
@@ -123,18 +308,35 @@ impl Client {
 
     /// * `timeout` - can be `INFINITE` or 0 or any other number.
     fn acquire_inner(&self, timeout: u32) -> io::Result<Option<Acquired>> {
+        if let Some(shutdown) = &self.shutdown {
+            // `WaitForMultipleObjects` prefers the first handle signaled,
+            // so that the event is noticed even if tokens are available.
+            let handles = [shutdown.as_raw_handle(), self.sem.as_raw_handle()];
+            let r = unsafe { WaitForMultipleObjects(2, handles.as_ptr(), FALSE, timeout) };
+
+            return match r {
+                WAIT_OBJECT_0 => Err(crate::shut_down()),
+                r if r == WAIT_OBJECT_0 + 1 => Ok(Some(Acquired)),
+                r if r == WAIT_ABANDONED + 1 => self.abandoned().map(Some),
+                WAIT_TIMEOUT => Ok(None),
+                WAIT_FAILED => Err(io::Error::last_os_error()),
+                ret => Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "Unexpected return value `{:#01x}` from WaitForMultipleObjects",
+                        ret
+                    ),
+                )),
+            };
+        }
+
         let r = unsafe { WaitForSingleObject(self.sem.as_raw_handle(), timeout) };
 
         match r {
             WAIT_OBJECT_0 => Ok(Some(Acquired)),
             WAIT_TIMEOUT => Ok(None),
             WAIT_FAILED => Err(io::Error::last_os_error()),
-            // We believe this should be impossible for a semaphore, but still
-            // check the error code just in case it happens.
-            WAIT_ABANDONED => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Wait on jobserver semaphore returned WAIT_ABANDONED",
-            )),
+            WAIT_ABANDONED => self.abandoned().map(Some),
             ret => Err(io::Error::new(
                 io::ErrorKind::Other,
                 format!(
@@ -149,11 +351,139 @@ impl Client {
         self.acquire_inner(0)
     }
 
+    /// Return `None` if no token is acquired before `deadline`.
+    pub fn acquire_until(&self, deadline: Instant) -> io::Result<Option<Acquired>> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            // `INFINITE` is `u32::MAX`, so wait for at most `u32::MAX - 1`
+            // milliseconds at a time.
+            let millis = timeout.as_millis().try_into().unwrap_or(INFINITE - 1);
+
+            match self.acquire_inner(millis)? {
+                Some(token) => break Ok(Some(token)),
+                None if Instant::now() >= deadline => break Ok(None),
+                None => continue,
+            }
+        }
+    }
+
+    pub fn acquire_interruptible(&self) -> io::Result<Acquired> {
+        self.acquire()
+    }
+
+    pub fn acquire_with_cancel(&self, flag: &CancellationFlag) -> io::Result<Acquired> {
+        // `WaitForMultipleObjects` prefers the semaphore if both are
+        // signaled, so check for cancellation first.
+        if flag.is_cancelled() {
+            return Err(crate::cancelled());
+        }
+        if self.is_shut_down() {
+            return Err(crate::shut_down());
+        }
+
+        let mut handles = [self.sem.as_raw_handle(), flag.event.as_raw_handle(), 0];
+        let mut count = 2;
+        if let Some(shutdown) = &self.shutdown {
+            handles[2] = shutdown.as_raw_handle();
+            count = 3;
+        }
+        let r = unsafe { WaitForMultipleObjects(count, handles.as_ptr(), FALSE, INFINITE) };
+
+        match r {
+            // The semaphore is preferred, but `shutdown` is signaled
+            // before it is acquired.
+            WAIT_OBJECT_0 if self.is_shut_down() => {
+                self.release_inner(None)?;
+                Err(crate::shut_down())
+            }
+            WAIT_OBJECT_0 => Ok(Acquired),
+            WAIT_ABANDONED => self.abandoned(),
+            r if r == WAIT_OBJECT_0 + 1 => Err(crate::cancelled()),
+            r if r == WAIT_OBJECT_0 + 2 => Err(crate::shut_down()),
+            WAIT_FAILED => Err(io::Error::last_os_error()),
+            ret => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "Unexpected return value `{:#01x}` from WaitForMultipleObjects",
+                    ret
+                ),
+            )),
+        }
+    }
+
+    /// Handle `WAIT_ABANDONED` returned by a wait on the semaphore.
+    fn abandoned(&self) -> io::Result<Acquired> {
+        let policy = self.abandoned.lock().clone();
+        match policy {
+            AbandonedPolicy::Acquire => Ok(Acquired),
+            // We believe this should be impossible for a semaphore, but
+            // still check the error code just in case it happens.
+            AbandonedPolicy::Error => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Wait on jobserver semaphore returned WAIT_ABANDONED",
+            )),
+            AbandonedPolicy::Recover(recover) => recover().map(|()| Acquired),
+        }
+    }
+
+    pub fn set_abandoned_policy(&self, policy: AbandonedPolicy) {
+        *self.abandoned.lock() = policy;
+    }
+
+    fn is_shut_down(&self) -> bool {
+        self.shutdown.as_ref().map_or(false, |shutdown| unsafe {
+            WaitForSingleObject(shutdown.as_raw_handle(), 0) == WAIT_OBJECT_0
+        })
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        let shutdown = self.shutdown.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "jobserver is not created by this crate",
+            )
+        })?;
+
+        if unsafe { SetEvent(shutdown.as_raw_handle()) } != 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Drain all tokens available and release one per byte of `tokens`
+    /// instead, undoing `shutdown`.
+    pub fn reset(&self, tokens: &[u8]) -> io::Result<()> {
+        if let Some(shutdown) = &self.shutdown {
+            if unsafe { ResetEvent(shutdown.as_raw_handle()) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        while self.acquire_inner(0)?.is_some() {}
+        for _ in tokens {
+            self.release_inner(None)?;
+        }
+        Ok(())
+    }
+
     pub fn release(&self, _data: Option<&Acquired>) -> io::Result<()> {
         self.release_inner(None)
     }
 
+    pub fn can_release(&self) -> bool {
+        !self.read_only
+    }
+
     fn release_inner(&self, prev_count: Option<&mut MaybeUninit<LONG>>) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "jobserver semaphore is opened without SEMAPHORE_MODIFY_STATE access, \
+                 tokens cannot be released",
+            ));
+        }
+
         // SAFETY: ReleaseSemaphore will write to prev_count is it is Some
         // and release semaphore self.sem by 1.
         let r = unsafe {
@@ -172,10 +502,38 @@ impl Client {
         }
     }
 
+    /// Duplicate the semaphore into `process` with `DuplicateHandle`,
+    /// returning the auth naming the handle in it.
+    pub fn duplicate_into_process(&self, process: BorrowedHandle<'_>) -> io::Result<String> {
+        let mut handle: RawHandle = 0;
+        let res = unsafe {
+            DuplicateHandle(
+                GetCurrentProcess(),
+                self.sem.as_raw_handle(),
+                process.as_raw_handle() as _,
+                &mut handle,
+                0,
+                FALSE,
+                DUPLICATE_SAME_ACCESS,
+            )
+        };
+        if res == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(handle_auth(handle))
+    }
+
     pub fn string_arg(&self) -> Cow<'_, str> {
         Cow::Borrowed(&self.name)
     }
 
+    /// Returns false if `string_arg` names a handle, which is only valid
+    /// in this process.
+    pub fn has_string_arg(&self) -> bool {
+        !self.name.starts_with("handle:")
+    }
+
     pub fn pre_run<Cmd>(&self, _cmd: &mut Cmd)
     where
         Cmd: Command,
@@ -184,7 +542,65 @@ impl Client {
         // child above
     }
 
+    pub fn get_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.sem.as_raw_handle() as _
+    }
+
+    pub fn get_borrowed_handle(&self) -> BorrowedHandle<'_> {
+        // SAFETY: self.sem is a valid handle that is kept open until
+        // `self` is dropped.
+        unsafe { BorrowedHandle::borrow_raw(self.get_raw_handle()) }
+    }
+
+    pub fn set_inheritable(&self, _inheritable: bool) -> io::Result<()> {
+        // nothing to do here, the semaphore is opened by name in the child
+        Ok(())
+    }
+
+    pub fn into_raw_parts(self) -> RawParts {
+        RawParts {
+            // SAFETY: self.sem is a valid handle owned by us
+            handle: unsafe { OwnedHandle::from_raw_handle(self.sem.into_raw() as _) },
+            auth: self.name.into(),
+        }
+    }
+
+    pub fn duplicate(&self) -> io::Result<Client> {
+        Ok(Client {
+            sem: self.sem.duplicate()?,
+            name: self.name.clone(),
+            shutdown: self.shutdown.as_ref().map(Handle::duplicate).transpose()?,
+            read_only: self.read_only,
+            abandoned: sync::Mutex::new(self.abandoned.lock().clone()),
+        })
+    }
+
+    pub fn close(self) -> io::Result<()> {
+        let sem = self.sem.close();
+        let shutdown = self.shutdown.map_or(Ok(()), Handle::close);
+        sem.and(shutdown)
+    }
+
+    /// Identify the semaphore by its name, or return `None` if it has none
+    /// and is only known by a handle of this process.
+    pub fn jobserver_key(&self) -> Option<String> {
+        if self.name.starts_with("handle:") {
+            None
+        } else {
+            Some(self.name.to_string())
+        }
+    }
+
     pub fn available(&self) -> io::Result<usize> {
+        if self.read_only {
+            // The token acquired below could not be released.
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "jobserver semaphore is opened without SEMAPHORE_MODIFY_STATE access, \
+                 available tokens cannot be counted",
+            ));
+        }
+
         // Can't read value of a semaphore on Windows, so
         // try to acquire without sleeping, since we can find out the
         // old value on release.
@@ -198,6 +614,144 @@ impl Client {
             Ok(0)
         }
     }
+
+    /// Same as `available`, but reads the count of the semaphore with
+    /// `NtQuerySemaphore` instead of taking a token for a moment, which
+    /// other processes might observe.
+    pub fn peek_available(&self) -> io::Result<usize> {
+        match query_semaphore_count(self.sem.as_raw_handle()) {
+            // Semaphores are opened without `SEMAPHORE_QUERY_STATE`, which
+            // some jobservers not created by this crate might not grant,
+            // so open it again just for querying.
+            Err(err)
+                if err.kind() == io::ErrorKind::PermissionDenied
+                    && !self.name.starts_with("handle:") =>
+            {
+                let sem = unsafe {
+                    Handle::new_or_err(OpenSemaphoreW(
+                        SEMAPHORE_QUERY_STATE,
+                        FALSE,
+                        to_wide(&self.name).as_ptr(),
+                    ))?
+                };
+                query_semaphore_count(sem.as_raw_handle())
+            }
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "inherited jobserver semaphore cannot be queried without taking a token",
+            )),
+            res => res,
+        }
+    }
+}
+
+/// Access right needed by `NtQuerySemaphore`.
+const SEMAPHORE_QUERY_STATE: u32 = 0x0001;
+
+/// `SemaphoreBasicInformation` of `SEMAPHORE_INFORMATION_CLASS`.
+const SEMAPHORE_BASIC_INFORMATION: u32 = 0;
+
+#[repr(C)]
+struct SemaphoreBasicInformation {
+    current_count: LONG,
+    maximum_count: LONG,
+}
+
+// Not exposed by `windows-sys`, but exported by ntdll since Windows NT.
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtQuerySemaphore(
+        handle: RawHandle,
+        class: u32,
+        info: *mut SemaphoreBasicInformation,
+        len: u32,
+        ret_len: *mut u32,
+    ) -> NTSTATUS;
+}
+
+fn query_semaphore_count(sem: RawHandle) -> io::Result<usize> {
+    let mut info = MaybeUninit::<SemaphoreBasicInformation>::uninit();
+    let status = unsafe {
+        NtQuerySemaphore(
+            sem,
+            SEMAPHORE_BASIC_INFORMATION,
+            info.as_mut_ptr(),
+            mem::size_of::<SemaphoreBasicInformation>()
+                .try_into()
+                .unwrap(),
+            ptr::null_mut(),
+        )
+    };
+    if status < 0 {
+        let code = unsafe { RtlNtStatusToDosError(status) };
+        return Err(io::Error::from_raw_os_error(code.try_into().unwrap()));
+    }
+    // SAFETY: NtQuerySemaphore has initialized it
+    let info = unsafe { info.assume_init() };
+    Ok(info.current_count.try_into().unwrap())
+}
+
+/// Create a semaphore with `limit` tokens, named `name` unless it is null.
+unsafe fn create_semaphore(limit: usize, name: *const u16) -> io::Result<RawHandle> {
+    let limit: LONG = limit
+        .try_into()
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+    // Note that `limit == 0` is a valid argument above but Windows
+    // won't let us create a semaphore with a maximum count of 0. Get
+    // `limit == 0` working by creating a semaphore with no slots
+    // available but a maximum count of 1, so that it starts out empty
+    // without ever being acquired, just like the pipe on unix.
+    let max_count: LONG = limit.max(1);
+
+    Ok(CreateSemaphoreW(ptr::null_mut(), limit, max_count, name))
+}
+
+/// The auth naming the semaphore `handle` in the process owning it.
+/// Resume the threads of the process `pid` spawned with `CREATE_SUSPENDED`,
+/// i.e. its main thread.
+fn resume_process(pid: u32) -> io::Result<()> {
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    let snapshot = unsafe { Handle::new_or_err(snapshot)? };
+
+    let mut entry: THREADENTRY32 = unsafe { mem::zeroed() };
+    entry.dwSize = mem::size_of::<THREADENTRY32>().try_into().unwrap();
+    let mut found = unsafe { Thread32First(snapshot.as_raw_handle(), &mut entry) } != 0;
+    while found {
+        if entry.th32OwnerProcessID == pid {
+            let thread = unsafe {
+                Handle::new_or_err(OpenThread(THREAD_SUSPEND_RESUME, FALSE, entry.th32ThreadID))?
+            };
+            if unsafe { ResumeThread(thread.as_raw_handle()) } == u32::MAX {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        found = unsafe { Thread32Next(snapshot.as_raw_handle(), &mut entry) } != 0;
+    }
+
+    Ok(())
+}
+
+fn handle_auth(handle: RawHandle) -> String {
+    format!("handle:{}", handle)
+}
+
+/// Name of the semaphore of the named jobserver `name`.
+fn named_semaphore_name(name: &str) -> String {
+    format!("__rust_jobslot_named_{}", name)
+}
+
+/// Name of the event signaled when the semaphore `name` is shut down.
+fn shutdown_event_name(name: &str) -> Vec<u16> {
+    to_wide(&format!("{}_shutdown", name))
+}
+
+/// Encode `s` as a nul-terminated UTF-16 string for the wide APIs.
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(iter::once(0)).collect()
 }
 
 #[derive(Debug)]
@@ -216,6 +770,37 @@ impl Handle {
     fn as_raw_handle(&self) -> RawHandle {
         self.0.get()
     }
+
+    fn into_raw(self) -> RawHandle {
+        let handle = self.as_raw_handle();
+        mem::forget(self);
+        handle
+    }
+
+    fn duplicate(&self) -> io::Result<Self> {
+        // SAFETY: self is a valid handle that is kept open until it is
+        // dropped.
+        let handle = unsafe { BorrowedHandle::borrow_raw(self.as_raw_handle() as _) }
+            .try_clone_to_owned()?;
+        // SAFETY: The handle is valid and owned by us.
+        Ok(unsafe { Self::new(handle.into_raw_handle() as _) }.unwrap())
+    }
+
+    /// Duplicate the handle, leaving `self` open without closing it, e.g.
+    /// since it is owned by someone else.
+    fn duplicate_borrowed(self) -> io::Result<Self> {
+        let res = self.duplicate();
+        mem::forget(self);
+        res
+    }
+
+    fn close(self) -> io::Result<()> {
+        if unsafe { CloseHandle(self.into_raw()) } != 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
 }
 
 unsafe impl Sync for Handle {}
@@ -0,0 +1,92 @@
+use std::{collections::HashMap, io};
+
+#[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+use crate::AsyncAcquireClient;
+use crate::{sync::Mutex, Acquired, Client};
+
+/// Tokens held on behalf of the in-flight work requests of a Bazel
+/// persistent worker, keyed by their `request_id`.
+///
+/// Acquire a token with [`WorkerTokens::on_request`] when a `WorkRequest`
+/// is received, and release it with [`WorkerTokens::on_response`] once
+/// its `WorkResponse` is sent, including responses to cancelled requests,
+/// so that the parallelism of the worker is bounded by the jobserver
+/// without tracking the tokens by hand.
+///
+/// Singleplex workers, whose requests all have the id `0`, must respond
+/// to a request before receiving the next one, like Bazel does.
+#[derive(Debug, Default)]
+pub struct WorkerTokens {
+    in_flight: Mutex<HashMap<i32, Acquired>>,
+}
+
+impl WorkerTokens {
+    /// Create an empty set of tokens.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Block until a token is acquired from `client` for the request
+    /// `request_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::AlreadyExists`] without
+    /// acquiring anything if a token is already held for `request_id`, or
+    /// any error returned by [`Client::acquire`].
+    pub fn on_request(&self, client: &Client, request_id: i32) -> io::Result<()> {
+        self.check(request_id)?;
+        self.insert(request_id, client.acquire()?)
+    }
+
+    /// Async version of [`WorkerTokens::on_request`].
+    #[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+    pub async fn on_request_async(
+        &self,
+        client: &AsyncAcquireClient,
+        request_id: i32,
+    ) -> io::Result<()> {
+        self.check(request_id)?;
+        self.insert(request_id, client.acquire().await?)
+    }
+
+    /// Release the token held for the request `request_id`, returning
+    /// false if there is none.
+    pub fn on_response(&self, request_id: i32) -> bool {
+        // Release it outside of the lock.
+        let acquired = self.in_flight.lock().remove(&request_id);
+        acquired.is_some()
+    }
+
+    /// Return the number of requests holding a token.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().len()
+    }
+
+    fn check(&self, request_id: i32) -> io::Result<()> {
+        if self.in_flight.lock().contains_key(&request_id) {
+            Err(already_exists(request_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn insert(&self, request_id: i32, acquired: Acquired) -> io::Result<()> {
+        let mut in_flight = self.in_flight.lock();
+        if in_flight.contains_key(&request_id) {
+            // Raced with another request with the same id.
+            drop(in_flight);
+            drop(acquired);
+            return Err(already_exists(request_id));
+        }
+        in_flight.insert(request_id, acquired);
+        Ok(())
+    }
+}
+
+fn already_exists(request_id: i32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("work request {} is already in flight", request_id),
+    )
+}
@@ -0,0 +1,206 @@
+use std::{
+    io::{self, Read, Write},
+    process::{self, ChildStdin, ChildStdout, Stdio},
+    sync::Arc,
+    thread,
+};
+
+use crate::{Acquired, CancellationFlag, Client};
+
+/// Script run by the relay, with the path to the fifo as `$1`.
+///
+/// It creates the fifo, reports it with a newline, then moves each `|`
+/// read from stdin into the fifo until stdin is closed. The fifo is then
+/// removed and as many tokens are read back from it and written to stdout,
+/// once the processes holding them release them.
+///
+/// `dd bs=1` reads exactly one byte from a pipe at a time, unlike `head`,
+/// which might buffer more.
+const RELAY_SCRIPT: &str = r#"
+mkfifo -m 600 "$1" || exit
+exec 3<>"$1"
+echo
+n=0
+while [ "$(dd bs=1 count=1 2>/dev/null)" = "|" ]; do
+    printf '|' >&3
+    n=$((n + 1))
+done
+rm -f "$1"
+dd bs=1 count="$n" <&3 2>/dev/null
+"#;
+
+/// Lends tokens of a [`Client`], e.g. the semaphore of a windows-side
+/// orchestrator, to a jobserver fifo inside WSL, so that builds on both
+/// sides share one parallelism budget.
+///
+/// A relay process is run inside WSL, which creates the fifo and moves
+/// tokens between it and this process over its stdin and stdout. A thread
+/// acquires up to the given number of tokens from the client in the
+/// background and deposits each into the fifo, so the WSL side never gets
+/// more tokens than the client can spare. Linux builds join it by
+/// `MAKEFLAGS` set to [`WslBridge::makeflags_value`], e.g. with
+/// `WSLENV=MAKEFLAGS/u`.
+///
+/// Tokens are lent eagerly, not on demand: the fifo cannot tell whether
+/// any Linux process is waiting on it, so the thread takes every token it
+/// can get, up to the given number, as soon as the client has one to
+/// spare, and keeps them in the fifo even while no Linux build runs. Those
+/// tokens are unavailable to the windows side until the bridge is closed,
+/// so create it right before the Linux builds start, close it once they
+/// are done, and lend only as many tokens as they need.
+///
+/// The tokens are returned to the client by [`WslBridge::close`], which
+/// waits for the Linux processes holding them to release them. Dropping
+/// the bridge does the same, ignoring errors.
+#[derive(Debug)]
+pub struct WslBridge {
+    path: Box<str>,
+    tokens: usize,
+    relay: process::Child,
+    stdout: ChildStdout,
+    cancel: Arc<CancellationFlag>,
+    pump: Option<thread::JoinHandle<(ChildStdin, Vec<Acquired>)>>,
+}
+
+impl WslBridge {
+    /// Create a fifo at `path` inside the default WSL distribution, or
+    /// `distro` if set, and lend up to `tokens` tokens of `client` to it,
+    /// taking each from `client` as soon as it is available.
+    ///
+    /// `path` must not exist yet. It is removed by [`WslBridge::close`].
+    #[cfg(windows)]
+    pub fn new(
+        client: &Client,
+        distro: Option<&str>,
+        path: &str,
+        tokens: usize,
+    ) -> io::Result<Self> {
+        let mut launcher = process::Command::new("wsl.exe");
+        if let Some(distro) = distro {
+            launcher.args(["-d", distro]);
+        }
+        launcher.arg("-e");
+
+        Self::with_launcher(client, launcher, path, tokens)
+    }
+
+    /// Same as `WslBridge::new`, except that the relay is run with
+    /// `launcher`, to which `sh -c SCRIPT sh PATH` is appended as is, e.g.
+    /// `wsl.exe --user builder -e` or `env` to run it locally.
+    pub fn with_launcher(
+        client: &Client,
+        mut launcher: process::Command,
+        path: &str,
+        tokens: usize,
+    ) -> io::Result<Self> {
+        let mut relay = launcher
+            .args(["sh", "-c", RELAY_SCRIPT, "sh", path])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = relay.stdin.take().unwrap();
+        let mut stdout = relay.stdout.take().unwrap();
+
+        let mut ready = [0];
+        if stdout.read(&mut ready)? == 0 {
+            drop(stdin);
+            let status = relay.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("failed to create fifo `{}` for WSL: {}", path, status),
+            ));
+        }
+
+        let cancel = Arc::new(CancellationFlag::new()?);
+        let pump = thread::Builder::new()
+            .name("jobslot-wsl-bridge".into())
+            .spawn({
+                let client = client.clone();
+                let cancel = cancel.clone();
+                move || {
+                    let mut lent = Vec::with_capacity(tokens);
+                    while lent.len() < tokens {
+                        let token = match client.acquire_with_cancel(&cancel) {
+                            Ok(token) => token,
+                            Err(_) => break,
+                        };
+                        if stdin.write_all(b"|").is_err() {
+                            break;
+                        }
+                        lent.push(token);
+                    }
+                    (stdin, lent)
+                }
+            })?;
+
+        Ok(Self {
+            path: path.into(),
+            tokens,
+            relay,
+            stdout,
+            cancel,
+            pump: Some(pump),
+        })
+    }
+
+    /// Path to the fifo inside WSL.
+    pub fn fifo_path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the value of `MAKEFLAGS` for Linux builds to join the fifo,
+    /// `-jN --jobserver-auth=fifo:PATH`, where `N` is the number of tokens
+    /// to lend plus the implicit one.
+    pub fn makeflags_value(&self) -> String {
+        format!("-j{} --jobserver-auth=fifo:{}", self.tokens + 1, self.path)
+    }
+
+    /// Stop lending tokens and return the lent ones to the client, waiting
+    /// for the Linux processes holding them to release them, then remove
+    /// the fifo.
+    ///
+    /// If the relay exits before returning all of them, e.g. since WSL is
+    /// shut down, the remaining ones are released anyway.
+    pub fn close(mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        let pump = match self.pump.take() {
+            Some(pump) => pump,
+            None => return Ok(()),
+        };
+
+        self.cancel.cancel()?;
+        let (stdin, mut lent) = pump
+            .join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "WSL bridge thread panicked"))?;
+        // Makes the relay reclaim the tokens.
+        drop(stdin);
+
+        let mut token = [0];
+        while !lent.is_empty() {
+            match self.stdout.read(&mut token) {
+                Ok(0) => break,
+                Ok(_) => drop(lent.pop()),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    drop(lent);
+                    let _ = self.relay.kill();
+                    let _ = self.relay.wait();
+                    return Err(err);
+                }
+            }
+        }
+        // Released on drop.
+        drop(lent);
+
+        self.relay.wait().map(drop)
+    }
+}
+
+impl Drop for WslBridge {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
@@ -6,7 +6,7 @@ use std::sync::mpsc;
 use std::sync::Arc;
 use std::thread;
 
-use jobslot::Client;
+use jobslot::{Client, FromEnvConfig, FromEnvError};
 use tokio::process::Command;
 
 struct Test {
@@ -41,6 +41,15 @@ const TESTS: &[Test] = &[
             assert!(unsafe { Client::from_env().is_some() });
         },
     },
+    Test {
+        name: "jobs",
+        make_args: &["-j3"],
+        rule: &|me| format!("+{}", me),
+        f: &|| {
+            let c = unsafe { Client::from_env().unwrap() };
+            assert_eq!(c.jobs(), Some(3));
+        },
+    },
     Test {
         name: "acquire",
         make_args: &["-j2"],
@@ -94,6 +103,58 @@ const TESTS: &[Test] = &[
             c.release_raw().unwrap();
         },
     },
+    #[cfg(unix)]
+    Test {
+        name: "from_env_with_config",
+        make_args: &["-j2"],
+        rule: &|me| format!("+{}", me),
+        f: &|| {
+            use std::os::unix::io::AsRawFd;
+
+            let mut config = FromEnvConfig::default();
+            config.dup_fds = false;
+            config.prefer_fifo_reopen = false;
+            let c = unsafe { Client::from_env_with_config(&config).unwrap() };
+
+            let makeflags = env::var("MAKEFLAGS").unwrap();
+            let auth = makeflags
+                .split_whitespace()
+                .filter_map(|s| s.strip_prefix("--jobserver-auth="))
+                .next_back()
+                .unwrap();
+            if !auth.starts_with("fifo:") {
+                let (read, _) = auth.split_once(',').unwrap();
+                assert_eq!(c.as_raw_fd(), read.parse::<i32>().unwrap());
+            }
+
+            drop(c.acquire().unwrap());
+        },
+    },
+    #[cfg(unix)]
+    Test {
+        name: "stdio fds are rejected",
+        make_args: &[],
+        rule: &|me| format!("CARGO_MAKEFLAGS=--jobserver-auth=0,1 {}", me),
+        f: &|| {
+            assert!(unsafe { Client::from_env().is_none() });
+
+            let err =
+                unsafe { Client::from_env_with_config(&FromEnvConfig::default()) }.unwrap_err();
+            assert!(matches!(err, FromEnvError::StdioFd(0)), "{:?}", err);
+        },
+    },
+    Test {
+        name: "disabled jobserver",
+        make_args: &[],
+        rule: &|me| format!("CARGO_MAKEFLAGS=--jobserver-auth=-2,-2 {}", me),
+        f: &|| {
+            assert!(unsafe { Client::from_env().is_none() });
+
+            let err =
+                unsafe { Client::from_env_with_config(&FromEnvConfig::default()) }.unwrap_err();
+            assert!(matches!(err, FromEnvError::Disabled), "{:?}", err);
+        },
+    },
 ];
 
 #[tokio::main()]
@@ -9,7 +9,9 @@ use std::thread;
 
 #[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
 use jobslot::AsyncAcquireClient;
-use jobslot::{Client, IntoTryAcquireClientError, TryAcquireClient};
+#[cfg(unix)]
+use jobslot::TryAcquireMode;
+use jobslot::{CancellationFlag, Client, IntoTryAcquireClientError, TryAcquireClient};
 
 fn get_try_acquire_client(client: Client) -> TryAcquireClient {
     match client.into_try_acquire_client() {
@@ -218,3 +220,2812 @@ fn zero_client() {
 
     assert!(!handle.is_finished());
 }
+
+#[test]
+fn client_identity() {
+    let a = Client::new(1).unwrap();
+    let b = a.clone();
+    let c = Client::new(1).unwrap();
+
+    assert!(a.ptr_eq(&b));
+    assert_eq!(a.id(), b.id());
+    assert!(!a.ptr_eq(&c));
+    assert_ne!(a.id(), c.id());
+
+    let b = get_try_acquire_client(b);
+    assert!(a.ptr_eq(&b));
+}
+
+/// Covers the event ports used on illumos and Solaris as well, see the
+/// `illumos` job of the CI.
+#[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+#[tokio::test]
+async fn async_acquire_cancel() {
+    use std::time::Duration;
+
+    let client = AsyncAcquireClient::new(get_try_acquire_client(Client::new(1).unwrap())).unwrap();
+    let token = client.acquire().await.unwrap();
+
+    // Cancelled while waiting, without taking a token.
+    tokio::time::timeout(Duration::from_millis(50), client.acquire())
+        .await
+        .unwrap_err();
+
+    let waiter = tokio::spawn({
+        let client = client.clone();
+        async move { drop(client.acquire().await.unwrap()) }
+    });
+    drop(token);
+    waiter.await.unwrap();
+
+    // The cancelled acquire did not keep the token either.
+    drop(client.try_acquire().unwrap().unwrap());
+}
+
+#[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+#[tokio::test]
+async fn async_client_shared() {
+    let client = AsyncAcquireClient::new(get_try_acquire_client(Client::new(1).unwrap())).unwrap();
+    let token = client.acquire().await.unwrap();
+
+    let tasks: Vec<_> = (0..4)
+        .map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move { drop(client.acquire().await.unwrap()) })
+        })
+        .collect();
+
+    drop(token);
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let other = client.clone();
+    let other = other.try_into_inner().unwrap_err();
+    #[allow(deprecated)]
+    let try_acquire_client = other.into_inner();
+    drop(try_acquire_client.try_acquire().unwrap().unwrap());
+    client.try_into_inner().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn client_as_fd() {
+    use std::os::unix::io::{AsFd, AsRawFd};
+
+    let client = Client::new(1).unwrap();
+    assert_eq!(client.as_fd().as_raw_fd(), client.as_raw_fd());
+
+    // A new file description is opened for non-blocking acquire
+    let client = Client::new_with_fifo(1).unwrap();
+    let try_acquire_client = client.clone().into_try_acquire_client().unwrap();
+    assert_eq!(
+        try_acquire_client.mode(),
+        TryAcquireMode::IndependentDescription
+    );
+    assert_ne!(try_acquire_client.as_raw_fd(), client.as_raw_fd());
+}
+
+#[cfg(unix)]
+#[test]
+fn try_acquire_anonymous_pipe() {
+    use std::os::unix::io::AsRawFd;
+
+    let client = Client::new(1).unwrap();
+    let flags = unsafe { libc::fcntl(client.as_raw_fd(), libc::F_GETFL) };
+    assert!(client.supports_try_acquire());
+
+    // No `O_NONBLOCK` is set on the shared pipe
+    let try_acquire_client = client.clone().into_try_acquire_client().unwrap();
+    assert_eq!(
+        unsafe { libc::fcntl(client.as_raw_fd(), libc::F_GETFL) },
+        flags
+    );
+    assert!(client.supports_try_acquire());
+    assert_ne!(try_acquire_client.as_raw_fd(), client.as_raw_fd());
+    #[cfg(target_os = "linux")]
+    assert_eq!(
+        try_acquire_client.mode(),
+        TryAcquireMode::IndependentDescription
+    );
+
+    let token = try_acquire_client.try_acquire().unwrap().unwrap();
+    assert!(try_acquire_client.try_acquire().unwrap().is_none());
+    drop(token);
+    let token = try_acquire_client.try_acquire().unwrap().unwrap();
+
+    // Token requested by the helper thread, if any, is released on drop
+    assert!(try_acquire_client.try_acquire().unwrap().is_none());
+    drop(token);
+    drop(try_acquire_client);
+    drop(client.acquire().unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn client_into_raw_parts() {
+    use std::os::unix::io::AsRawFd;
+
+    let client = Client::new(1).unwrap();
+    client.set_inheritable(true).unwrap();
+
+    let read = client.as_raw_fd();
+    let clone = client.clone().into_raw_parts().unwrap_err();
+    drop(clone);
+
+    let parts = client.into_raw_parts().unwrap();
+    assert_eq!(parts.read.as_raw_fd(), read);
+    assert_eq!(
+        parts.auth,
+        format!("{},{}", parts.read.as_raw_fd(), parts.write.as_raw_fd())
+    );
+    assert!(parts.fifo.is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn fifo_removed() {
+    fn auth(client: &Client) -> String {
+        let output = client
+            .configure_and_run_with_fifo(
+                Command::new("sh").args(["-c", "echo $CARGO_MAKEFLAGS"]),
+                |cmd| cmd.output(),
+            )
+            .unwrap();
+        assert!(output.status.success());
+
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    let client = Client::new_with_fifo(1).unwrap();
+    client.verify_fifo().unwrap();
+
+    let makeflags = auth(&client);
+    let path = makeflags.trim().split_once("fifo:").unwrap().1;
+
+    std::fs::remove_file(path).unwrap();
+    let err = client.verify_fifo().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
+    assert!(auth(&client).contains("--jobserver-fds="));
+}
+
+#[cfg(unix)]
+#[test]
+fn persist_fifo() {
+    let client = Client::new_with_fifo(1).unwrap();
+    let path = client.fifo_path().unwrap().to_owned();
+
+    client.persist_fifo();
+    drop(client);
+
+    assert!(path.exists());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[test]
+fn new_with_fifo_at() {
+    let td = tempfile::tempdir().unwrap();
+    let dir = File::open(td.path()).unwrap();
+
+    let client = Client::new_with_fifo_at(&dir, 2).unwrap();
+    let path = client.fifo_path().unwrap().to_owned();
+    assert_eq!(
+        path.parent().unwrap().canonicalize().unwrap(),
+        td.path().canonicalize().unwrap()
+    );
+    assert!(client.owns_fifo());
+    assert_eq!(client.available().unwrap(), 2);
+
+    drop(client);
+    assert!(!path.exists());
+}
+
+#[test]
+fn jobs_from_long_option() {
+    use jobslot::protocol::jobs_from_makeflags;
+
+    let auth = "--jobserver-auth=fifo:/tmp/fifo";
+    assert_eq!(jobs_from_makeflags(&format!("--jobs=5 {}", auth)), Some(5));
+    assert_eq!(jobs_from_makeflags(&format!("--jobs 6 {}", auth)), Some(6));
+    assert_eq!(
+        jobs_from_makeflags(&format!("-j2 --jobs {}", auth)),
+        Some(2)
+    );
+}
+
+#[test]
+fn from_auth_disabled() {
+    use jobslot::{FromEnvConfig, FromEnvError};
+
+    let err = unsafe { Client::from_auth("-2,-2", &FromEnvConfig::default()) }.unwrap_err();
+    assert!(matches!(err, FromEnvError::Disabled), "{:?}", err);
+
+    // Only a pair of plain integers is make's way of disabling it.
+    for auth in ["sem,-2", "-2,-2,-2", "+2,-2"] {
+        let err = unsafe { Client::from_auth(auth, &FromEnvConfig::default()) }.unwrap_err();
+        assert!(
+            !matches!(err, FromEnvError::Disabled),
+            "{}: {:?}",
+            auth,
+            err
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn transfer_fifo_ownership() {
+    let creator = Client::new_with_fifo(1).unwrap();
+    let path = creator.fifo_path().unwrap().to_owned();
+
+    let auth = format!("fifo:{}", path.to_str().unwrap());
+    let worker = unsafe { Client::from_auth(&auth, &Default::default()) }.unwrap();
+
+    assert!(creator.owns_fifo());
+    assert!(!worker.owns_fifo());
+    assert_eq!(
+        worker.transfer_fifo_ownership(&creator).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+    assert!(creator
+        .transfer_fifo_ownership(&Client::new_with_fifo(1).unwrap())
+        .is_err());
+
+    creator.transfer_fifo_ownership(&worker).unwrap();
+    assert!(!creator.owns_fifo());
+    assert!(worker.owns_fifo());
+
+    drop(creator);
+    assert!(path.exists());
+    drop(worker);
+    assert!(!path.exists());
+}
+
+#[cfg(all(unix, feature = "jobserver-compat"))]
+#[test]
+fn compat_configure_outlives_client() {
+    use jobslot::compat;
+
+    let client = compat::Client::new(1).unwrap();
+    let auth = client.string_arg();
+    let (read, _) = auth.split_once(',').unwrap();
+
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", &format!("test -p /dev/fd/{}", read)]);
+    client.configure(&mut cmd);
+
+    // The fds stay open for `cmd`, instead of being closed and reused.
+    drop(client);
+    let _reused = File::open("/dev/null").unwrap();
+    assert!(cmd.status().unwrap().success());
+}
+
+#[cfg(feature = "jobserver-compat")]
+#[test]
+fn compat_helper_thread() {
+    use jobslot::compat;
+
+    let client = compat::Client::new(1).unwrap();
+    let acquired = client.acquire().unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let helper = client
+        .clone()
+        .into_helper_thread(move |token| tx.send(token).unwrap())
+        .unwrap();
+
+    helper.request_token();
+    assert!(rx.try_recv().is_err());
+    drop(acquired);
+    drop(rx.recv().unwrap().unwrap());
+
+    #[cfg(unix)]
+    {
+        let output = {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", "echo $CARGO_MAKEFLAGS"]);
+            client.configure(&mut cmd);
+            cmd.output().unwrap()
+        };
+        assert!(String::from_utf8(output.stdout)
+            .unwrap()
+            .contains(&client.string_arg()));
+    }
+}
+
+#[cfg(any(unix, windows))]
+#[test]
+fn client_from_auth() {
+    let client = Client::new(1).unwrap();
+    let other =
+        unsafe { Client::from_auth(&client.string_arg().unwrap(), &Default::default()) }.unwrap();
+    assert!(!client.ptr_eq(&other));
+    // Connected separately, but to the same jobserver.
+    assert_eq!(client.id(), other.id());
+
+    let token = client.acquire().unwrap();
+    assert_eq!(other.available().unwrap(), 0);
+    drop(token);
+    drop(client);
+    drop(other.acquire().unwrap());
+}
+
+#[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+#[tokio::test]
+async fn async_client_fifo() {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    let client = AsyncAcquireClient::new(get_try_acquire_client(Client::new(1).unwrap())).unwrap();
+    let token = client.acquire().await.unwrap();
+
+    // Dropping a pending acquire does not block the ones behind it
+    let res = tokio::time::timeout(Duration::from_millis(10), client.acquire()).await;
+    assert!(res.is_err());
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let mut tasks = Vec::new();
+    for i in 0..4 {
+        let client = client.clone();
+        let order = order.clone();
+        tasks.push(tokio::spawn(async move {
+            let token = client.acquire().await.unwrap();
+            order.lock().unwrap().push(i);
+            drop(token);
+        }));
+
+        // Let the task start waiting before spawning the next one
+        tokio::task::yield_now().await;
+    }
+
+    drop(token);
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    assert_eq!(*order.lock().unwrap(), [0, 1, 2, 3]);
+}
+
+#[test]
+fn zero_client_release_raw() {
+    let client = Client::new(0).unwrap();
+    assert_eq!(client.available().unwrap(), 0);
+
+    // Releasing a token never acquired makes it available, on all platforms
+    client.release_raw().unwrap();
+    assert_eq!(client.available().unwrap(), 1);
+    drop(client.acquire().unwrap());
+    client.acquire_raw().unwrap();
+    assert_eq!(client.available().unwrap(), 0);
+}
+
+#[test]
+fn acquire_with_cancel() {
+    let client = Client::new(1).unwrap();
+    let flag = Arc::new(CancellationFlag::new().unwrap());
+    drop(client.acquire_with_cancel(&flag).unwrap());
+
+    let token = client.acquire().unwrap();
+    let handle = thread::spawn({
+        let client = client.clone();
+        let flag = flag.clone();
+        move || client.acquire_with_cancel(&flag).map(drop)
+    });
+
+    flag.cancel().unwrap();
+    let err = handle.join().unwrap().unwrap_err();
+    assert_eq!(
+        err.get_ref().unwrap().downcast_ref(),
+        Some(&jobslot::AcquireError::Cancelled)
+    );
+    assert!(flag.is_cancelled());
+
+    // A cancelled flag never acquires a token
+    drop(token);
+    client.acquire_with_cancel(&flag).unwrap_err();
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn acquire_interruptible() {
+    extern "C" fn handler(_: libc::c_int) {}
+
+    // Install a handler without `SA_RESTART`, like most ctrl-c handlers
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as *const () as libc::sighandler_t;
+        assert_eq!(
+            libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()),
+            0
+        );
+    }
+
+    let client = Client::new(0).unwrap();
+    let (tx, rx) = mpsc::channel();
+
+    let handle = thread::spawn({
+        let client = client.clone();
+        move || {
+            tx.send(unsafe { libc::pthread_self() }).unwrap();
+            client.acquire_interruptible().map(drop)
+        }
+    });
+
+    // The signal might arrive before the thread starts waiting, so keep
+    // sending it until the thread returns.
+    let thread = rx.recv().unwrap();
+    while !handle.is_finished() {
+        unsafe { libc::pthread_kill(thread, libc::SIGUSR1) };
+        thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    let err = handle.join().unwrap().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+}
+
+#[test]
+fn acquire_or_proceed() {
+    use jobslot::AcquireOrProceed;
+    use std::time::{Duration, Instant};
+
+    let client = Client::new(1).unwrap();
+    let token = match client.acquire_or_proceed(Instant::now()).unwrap() {
+        AcquireOrProceed::Acquired(token) => token,
+        res => panic!("unexpected {:?}", res),
+    };
+
+    let deadline = Instant::now() + Duration::from_millis(10);
+    let borrowed = match client.acquire_or_proceed(deadline).unwrap() {
+        AcquireOrProceed::Borrowed(borrowed) => borrowed,
+        res => panic!("unexpected {:?}", res),
+    };
+    assert!(Instant::now() >= deadline);
+
+    // The released token is kept for the borrowed slot
+    drop(token);
+    assert_eq!(client.available().unwrap(), 0);
+    drop(borrowed);
+    assert_eq!(client.available().unwrap(), 1);
+
+    // A borrowed slot dropped before any release is simply forgotten
+    let token = client.acquire().unwrap();
+    let borrowed = client.acquire_or_proceed(Instant::now()).unwrap();
+    assert!(matches!(borrowed, AcquireOrProceed::Borrowed(_)));
+    drop(borrowed);
+    drop(token);
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[test]
+fn linger() {
+    use std::time::{Duration, Instant};
+
+    let client = Client::new(1).unwrap();
+    client.set_linger(Duration::from_secs(60));
+
+    // The released token is kept and reused
+    drop(client.acquire().unwrap());
+    assert_eq!(client.available().unwrap(), 0);
+    drop(client.acquire().unwrap());
+    assert_eq!(client.available().unwrap(), 0);
+
+    // Kept tokens are written back once the linger is disabled
+    client.set_linger(Duration::ZERO);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while client.available().unwrap() == 0 {
+        assert!(Instant::now() < deadline);
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[test]
+fn acquire_lease() {
+    use std::time::{Duration, Instant};
+
+    let client = Client::new(1).unwrap();
+
+    // Released normally before the TTL runs out
+    let lease = client.acquire_lease(Duration::from_secs(60)).unwrap();
+    assert!(lease.renew());
+    assert_eq!(client.available().unwrap(), 0);
+    drop(lease);
+    assert_eq!(client.available().unwrap(), 1);
+
+    // Re-issued once expired, and not released again on drop
+    let lease = client.acquire_lease(Duration::from_millis(10)).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !lease.is_expired() {
+        assert!(Instant::now() < deadline);
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert!(!lease.renew());
+    assert_eq!(client.available().unwrap(), 1);
+    drop(lease);
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn preemption() {
+    use std::time::Duration;
+
+    // Anonymous pipes have no side channel
+    let client = Client::new(1).unwrap();
+    let err = client.request_preemption(1).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+
+    let client = Client::new_with_fifo(2).unwrap();
+    let (tx, rx) = mpsc::channel();
+    client
+        .on_preemption(move |tokens| tx.send(tokens).unwrap())
+        .unwrap();
+
+    // A kept token is released without bothering the callback
+    client.set_linger(Duration::from_secs(60));
+    drop(client.acquire().unwrap());
+    assert_eq!(client.available().unwrap(), 1);
+    client.request_preemption(1).unwrap();
+
+    let mut requested = 0;
+    while client.available().unwrap() != 2 {
+        requested += rx.recv_timeout(Duration::from_millis(10)).unwrap_or(0);
+    }
+    assert_eq!(requested, 0);
+
+    client.request_preemption(3).unwrap();
+    while requested < 3 {
+        requested += rx.recv().unwrap();
+    }
+}
+
+#[test]
+fn pools() {
+    use jobslot::Pools;
+
+    let mut pools = Pools::new();
+    pools
+        .insert("cpu", Client::new(2).unwrap())
+        .insert("mem-GB", Client::new(4).unwrap());
+    let cpu = pools.get("cpu").unwrap().clone();
+    let mem = pools.get("mem-GB").unwrap().clone();
+
+    let err = pools.acquire(&[("cpu", 1), ("gpu", 1)]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(cpu.available().unwrap(), 2);
+
+    let tokens = pools.acquire(&[("mem-GB", 3), ("cpu", 1)]).unwrap();
+    assert_eq!(tokens.len(), 4);
+    assert_eq!(cpu.available().unwrap(), 1);
+    assert_eq!(mem.available().unwrap(), 1);
+
+    let (tx, rx) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let tokens = pools.acquire(&[("cpu", 1), ("mem-GB", 2)]).unwrap();
+        tx.send(()).unwrap();
+        drop(tokens);
+    });
+
+    assert!(rx
+        .recv_timeout(std::time::Duration::from_millis(10))
+        .is_err());
+    drop(tokens);
+    rx.recv().unwrap();
+    handle.join().unwrap();
+
+    assert_eq!(cpu.available().unwrap(), 2);
+    assert_eq!(mem.available().unwrap(), 4);
+}
+
+#[test]
+fn pools_concurrent_partial_requests() {
+    use jobslot::Pools;
+    use std::time::Duration;
+
+    let mut pools = Pools::new();
+    pools.insert("cpu", Client::new(4).unwrap());
+    let cpu = pools.get("cpu").unwrap().clone();
+
+    // Hand the tokens out one by one while two requests of 3 are waiting,
+    // neither of which may keep part of them while waiting for the rest.
+    let held: Vec<_> = (0..4).map(|_| cpu.acquire().unwrap()).collect();
+    let (tx, rx) = mpsc::channel();
+    for _ in 0..2 {
+        let (pools, tx) = (pools.clone(), tx.clone());
+        thread::spawn(move || {
+            drop(pools.acquire(&[("cpu", 3)]).unwrap());
+            tx.send(()).unwrap();
+        });
+    }
+    thread::sleep(Duration::from_millis(50));
+    for token in held {
+        drop(token);
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    for _ in 0..2 {
+        rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+    assert_eq!(cpu.available().unwrap(), 4);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn cpusets() {
+    use jobslot::CpuSet;
+
+    let client = Client::new_with_cpusets(vec![
+        [0].iter().copied().collect::<CpuSet>(),
+        [0, 1].iter().copied().collect(),
+    ])
+    .unwrap();
+
+    let a = client.acquire().unwrap();
+    let b = client.acquire().unwrap();
+    let mut sets = [a.cpuset().unwrap().clone(), b.cpuset().unwrap().clone()];
+    sets.sort_by_key(|set| set.iter().count());
+    assert!(sets[0].contains(0) && !sets[0].contains(1));
+    assert!(sets[1].contains(0) && sets[1].contains(1));
+
+    // The set is kept by the token across release.
+    let expected = a.cpuset().cloned();
+    drop(a);
+    let c = client.acquire().unwrap();
+    assert_eq!(c.cpuset().cloned(), expected);
+
+    // CPU 0 is always available.
+    thread::spawn(|| {
+        [0].iter()
+            .copied()
+            .collect::<CpuSet>()
+            .set_affinity(0)
+            .unwrap()
+    })
+    .join()
+    .unwrap();
+
+    let too_many = (0..255).map(|_| CpuSet::new());
+    let err = Client::new_with_cpusets(too_many).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn token_bytes() {
+    let client = Client::new_with_tokens(b"ab").unwrap();
+    assert_eq!(client.available().unwrap(), 2);
+
+    let a = client.acquire().unwrap();
+    let b = client.acquire().unwrap();
+
+    #[cfg(unix)]
+    {
+        let mut bytes = [a.byte().unwrap(), b.byte().unwrap()];
+        bytes.sort_unstable();
+        assert_eq!(&bytes, b"ab");
+
+        // The byte is written back on release.
+        let byte = a.byte();
+        drop(a);
+        assert_eq!(client.acquire().unwrap().byte(), byte);
+    }
+    #[cfg(not(unix))]
+    {
+        assert_eq!(a.byte(), None);
+        assert_eq!(b.byte(), None);
+    }
+}
+
+#[test]
+fn strict() {
+    let client = Client::new_with_tokens(b"a").unwrap();
+    client.set_strict(true);
+
+    let err = client.release_raw().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(client.available().unwrap(), 1);
+
+    client.acquire_raw().unwrap();
+    assert_eq!(client.available().unwrap(), 0);
+    client.release_raw().unwrap();
+    assert_eq!(client.available().unwrap(), 1);
+
+    // Double release is rejected.
+    let err = client.release_raw().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(client.available().unwrap(), 1);
+
+    // The acquired byte is written back.
+    #[cfg(unix)]
+    assert_eq!(client.acquire().unwrap().byte(), Some(b'a'));
+
+    client.set_strict(false);
+    client.release_raw().unwrap();
+    assert_eq!(client.available().unwrap(), 2);
+}
+
+#[test]
+fn ledger() {
+    use jobslot::Ledger;
+
+    let td = tempfile::tempdir().unwrap();
+    let ledger = Ledger::open(td.path().join("ledger")).unwrap();
+    let pid = std::process::id();
+
+    let client = Client::new(3).unwrap();
+    client.set_ledger(Some(ledger.clone())).unwrap();
+
+    let a = client.acquire().unwrap();
+    client.acquire_raw().unwrap();
+    assert_eq!(ledger.held(pid).unwrap(), 2);
+    assert_eq!(ledger.entries().unwrap(), vec![(pid, 2)]);
+
+    drop(a);
+    assert_eq!(ledger.held(pid).unwrap(), 1);
+
+    // Another image of this process picks up the recorded tokens.
+    let client = Client::new(3).unwrap();
+    client.set_ledger(Some(ledger.clone())).unwrap();
+    client.release_raw().unwrap();
+    assert_eq!(ledger.held(pid).unwrap(), 0);
+    assert_eq!(ledger.entries().unwrap(), vec![]);
+}
+
+#[test]
+fn reset() {
+    let client = Client::new(2).unwrap();
+
+    // Leak one token and double-release another.
+    client.acquire().unwrap().drop_without_releasing();
+    client.release_raw().unwrap();
+    client.release_raw().unwrap();
+    assert_eq!(client.available().unwrap(), 3);
+
+    client.reset().unwrap();
+    assert_eq!(client.available().unwrap(), 2);
+
+    #[cfg(unix)]
+    {
+        let client = Client::new_with_tokens(b"a").unwrap();
+        client.release_raw().unwrap();
+        client.reset().unwrap();
+        assert_eq!(client.acquire().unwrap().byte(), Some(b'a'));
+    }
+
+    // Only the client creating the jobserver can reset it.
+    #[cfg(any(unix, windows))]
+    {
+        let client = Client::new(1).unwrap();
+        let other =
+            unsafe { Client::from_auth(&client.string_arg().unwrap(), &Default::default()) }
+                .unwrap();
+        let err = other.reset().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+}
+
+#[test]
+fn wait_for_all_returned() {
+    use std::time::Duration;
+
+    let client = Client::new(2).unwrap();
+    assert_eq!(client.wait_for_all_returned(Duration::ZERO).unwrap(), 0);
+
+    let token = client.acquire().unwrap();
+    let handle = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        drop(token);
+    });
+    assert_eq!(
+        client
+            .wait_for_all_returned(Duration::from_secs(10))
+            .unwrap(),
+        0
+    );
+    handle.join().unwrap();
+
+    // A leaked token is reported once the timeout expires.
+    client.acquire().unwrap().drop_without_releasing();
+    assert_eq!(
+        client
+            .wait_for_all_returned(Duration::from_millis(10))
+            .unwrap(),
+        1
+    );
+
+    #[cfg(any(unix, windows))]
+    {
+        let other =
+            unsafe { Client::from_auth(&client.string_arg().unwrap(), &Default::default()) }
+                .unwrap();
+        let err = other.wait_for_all_returned(Duration::ZERO).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+}
+
+#[test]
+fn shutdown() {
+    let client = Client::new(1).unwrap();
+    let token = client.acquire().unwrap();
+
+    let handles: Vec<_> = (0..3)
+        .map(|_| {
+            let client = client.clone();
+            thread::spawn(move || client.acquire().map(drop))
+        })
+        .collect();
+
+    client.shutdown().unwrap();
+    for handle in handles {
+        let err = handle.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+    }
+
+    let client = get_try_acquire_client(client);
+    let err = client.try_acquire().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+
+    // Waiters in other processes using this crate are woken up as well.
+    #[cfg(any(unix, windows))]
+    {
+        let other =
+            unsafe { Client::from_auth(&client.string_arg().unwrap(), &Default::default()) }
+                .unwrap();
+        let err = other.acquire().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        assert_eq!(
+            other.shutdown().unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+    }
+
+    drop(token);
+    client.reset().unwrap();
+    drop(client.acquire().unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn shutdown_after_configure() {
+    let client = Client::new(1).unwrap();
+    client
+        .configure_make_and_run(Command::new("true"), |cmd| cmd.status())
+        .unwrap();
+
+    // Make would take the sentinel as a token.
+    let err = client.shutdown().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    drop(client.acquire().unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn shutdown_after_string_arg() {
+    let client = Client::new(1).unwrap();
+    let _auth = client.string_arg().unwrap();
+
+    let err = client.shutdown().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn close() {
+    Client::new(1).unwrap().close().unwrap();
+
+    let client = Client::new_with_fifo(1).unwrap();
+    let clone = client.clone();
+    let err = clone.close().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Other);
+
+    #[cfg(unix)]
+    {
+        let path = client.fifo_path().unwrap().to_owned();
+        assert!(path.exists());
+        client.close().unwrap();
+        assert!(!path.exists());
+    }
+    #[cfg(not(unix))]
+    client.close().unwrap();
+}
+
+#[test]
+fn can_release() {
+    let client = Client::new(1).unwrap();
+    assert!(client.can_release());
+
+    let token = client.acquire().unwrap();
+    drop(token);
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[cfg(any(unix, windows))]
+#[test]
+fn makeflags_value() {
+    use jobslot::MakeflagsStyle;
+
+    let configured = |client: &Client, fifo: bool| {
+        let mut cmd = Command::new("make");
+        let get = |cmd: &mut &mut Command| {
+            Ok(cmd
+                .get_envs()
+                .find(|(key, _)| *key == "CARGO_MAKEFLAGS")
+                .and_then(|(_, value)| value)
+                .unwrap()
+                .to_owned())
+        };
+        if fifo {
+            client.configure_and_run_with_fifo(&mut cmd, get).unwrap()
+        } else {
+            client.configure_and_run(&mut cmd, get).unwrap()
+        }
+    };
+
+    let client = Client::new_with_fifo(1).unwrap();
+    let compatible = client.makeflags_value(MakeflagsStyle::Compatible).unwrap();
+    assert_eq!(
+        compatible,
+        *format!(
+            "-j1 --jobserver-fds={0} --jobserver-auth={0}",
+            client.string_arg().unwrap()
+        )
+    );
+    assert_eq!(compatible, configured(&client, false));
+
+    let fifo = client.makeflags_value(MakeflagsStyle::Fifo).unwrap();
+    assert_eq!(fifo, configured(&client, true));
+    #[cfg(unix)]
+    {
+        let mut expected = std::ffi::OsString::from("-j1 --jobserver-auth=fifo:");
+        expected.push(client.fifo_path().unwrap());
+        assert_eq!(fifo, expected);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn to_shell_exports() {
+    use jobslot::{MakeflagsStyle, Shell};
+    use std::os::unix::ffi::OsStrExt;
+
+    let td = tempfile::tempdir().unwrap();
+    let path = td.path().join("it's a \"fifo\"");
+    let cpath = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(cpath.as_ptr(), 0o600) }, 0);
+
+    let auth = format!("fifo:{}", path.to_str().unwrap());
+    let client = unsafe { Client::from_auth(&auth, &Default::default()) }.unwrap();
+    let value = client.makeflags_value(MakeflagsStyle::Fifo).unwrap();
+
+    let snippet = client
+        .to_shell_exports(Shell::Posix, MakeflagsStyle::Fifo)
+        .unwrap();
+    assert_eq!(snippet.lines().count(), 3);
+
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!(
+            "{}printf '%s\\n%s\\n%s' \"$CARGO_MAKEFLAGS\" \"$MAKEFLAGS\" \"$MFLAGS\"",
+            snippet
+        ))
+        .env_clear()
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 3);
+    for line in stdout.lines() {
+        assert_eq!(value, *line);
+    }
+
+    let snippet = client
+        .to_shell_exports(Shell::PowerShell, MakeflagsStyle::Fifo)
+        .unwrap();
+    assert!(snippet.starts_with(&format!(
+        "$env:CARGO_MAKEFLAGS = '-j --jobserver-auth=fifo:{}'\n",
+        path.to_str().unwrap().replace('\'', "''")
+    )));
+}
+
+#[cfg(any(unix, windows))]
+#[test]
+fn configure_into() {
+    use jobslot::{EnvConsumer, MakeflagsStyle};
+    use std::collections::HashMap;
+    use std::ffi::{OsStr, OsString};
+
+    let client = Client::new(1).unwrap();
+    let value = client.makeflags_value(MakeflagsStyle::Compatible).unwrap();
+
+    let mut map = HashMap::<OsString, OsString>::new();
+    client.configure_into(&mut map).unwrap();
+    assert_eq!(map.len(), 1);
+    assert_eq!(map[OsStr::new("CARGO_MAKEFLAGS")], value);
+
+    let mut vec = vec![(OsString::from("MAKEFLAGS"), OsString::from("-j1"))];
+    client.configure_make_into(&mut vec).unwrap();
+    assert_eq!(vec.len(), 3);
+    assert_eq!(vec[0], (OsString::from("MAKEFLAGS"), value.clone()));
+    assert!(vec.iter().all(|(_, v)| *v == value));
+    vec.unset(OsStr::new("MFLAGS"));
+    assert_eq!(vec.len(), 2);
+
+    #[derive(Default)]
+    struct Consumer {
+        envs: Vec<(OsString, OsString)>,
+        #[cfg(unix)]
+        fds: Vec<std::os::unix::io::RawFd>,
+    }
+
+    impl EnvConsumer for Consumer {
+        fn set(&mut self, key: &OsStr, value: &OsStr) {
+            self.envs.set(key, value)
+        }
+
+        fn unset(&mut self, key: &OsStr) {
+            self.envs.unset(key)
+        }
+
+        #[cfg(unix)]
+        fn needs_fd(&mut self, fd: std::os::unix::io::RawFd) {
+            self.fds.push(fd)
+        }
+    }
+
+    let mut consumer = Consumer::default();
+    client.configure_into(&mut consumer).unwrap();
+    assert_eq!(consumer.envs.len(), 1);
+    #[cfg(unix)]
+    assert_eq!(
+        consumer
+            .fds
+            .iter()
+            .map(|fd| fd.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+        client.string_arg().unwrap()
+    );
+}
+
+#[cfg(any(unix, windows))]
+#[test]
+fn named() {
+    use std::io::ErrorKind;
+
+    let name = format!("jobslot-test-{}", std::process::id());
+    assert_eq!(
+        Client::connect_named(&name).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+
+    let client = Client::new_named(&name, 2).unwrap();
+    assert_eq!(
+        Client::new_named(&name, 2).unwrap_err().kind(),
+        ErrorKind::AlreadyExists
+    );
+
+    let other = Client::connect_named(&name).unwrap();
+    let token = other.acquire().unwrap();
+    assert_eq!(client.available().unwrap(), 1);
+    drop(token);
+    assert_eq!(client.available().unwrap(), 2);
+
+    // A fifo accessible to other users might have been created by them.
+    #[cfg(unix)]
+    {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let path = client.fifo_path().unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o666)).unwrap();
+        assert_eq!(
+            Client::connect_named(&name).unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap();
+        Client::connect_named(&name).unwrap();
+    }
+
+    for name in ["", ".hidden", "a/b", "a b"] {
+        assert_eq!(
+            Client::new_named(name, 1).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            Client::connect_named(name).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    drop(other);
+    drop(client);
+    assert_eq!(
+        Client::connect_named(&name).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn release_implicit() {
+    let client = Client::new(0).unwrap();
+
+    let implicit = client.release_implicit().unwrap();
+    assert_eq!(client.available().unwrap(), 1);
+
+    // Another process might take the token in the meantime.
+    let token = client.acquire().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let t = thread::spawn(move || {
+        drop(implicit);
+        tx.send(()).unwrap();
+    });
+    assert!(rx
+        .recv_timeout(std::time::Duration::from_millis(50))
+        .is_err());
+    drop(token);
+    rx.recv().unwrap();
+    t.join().unwrap();
+    assert_eq!(client.available().unwrap(), 0);
+
+    client.release_implicit().unwrap().reacquire().unwrap();
+    assert_eq!(client.available().unwrap(), 0);
+}
+
+#[test]
+fn yield_token() {
+    let client = Client::new(1).unwrap();
+    let mut token = client.acquire().unwrap();
+
+    let other = client.clone();
+    let ret = token
+        .yield_token(|| {
+            drop(other.acquire().unwrap());
+            1
+        })
+        .unwrap();
+    assert_eq!(ret, 1);
+    assert_eq!(client.available().unwrap(), 0);
+
+    drop(token);
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+#[tokio::test]
+async fn async_yield_token() {
+    let client = AsyncAcquireClient::new(get_try_acquire_client(Client::new(1).unwrap())).unwrap();
+    let mut token = client.acquire().await.unwrap();
+
+    let ret = client
+        .yield_token(&mut token, async {
+            drop(client.acquire().await.unwrap());
+            1
+        })
+        .await
+        .unwrap();
+    assert_eq!(ret, 1);
+    assert_eq!(client.available().unwrap(), 0);
+
+    drop(token);
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[cfg(any(unix, windows))]
+#[test]
+fn configure_and_run_custom_error() {
+    #[derive(Debug)]
+    enum SpawnError {
+        Io(std::io::Error),
+        Rejected(&'static str),
+    }
+
+    impl From<std::io::Error> for SpawnError {
+        fn from(err: std::io::Error) -> Self {
+            Self::Io(err)
+        }
+    }
+
+    let client = Client::new(1).unwrap();
+    let mut cmd = Command::new("jobslot-does-not-exist");
+
+    let res: Result<(), SpawnError> =
+        client.configure_and_run_with_err(&mut cmd, |_| Err(SpawnError::Rejected("no")));
+    assert!(matches!(res, Err(SpawnError::Rejected("no"))));
+
+    let res =
+        client.configure_make_and_run_with_err(&mut cmd, |cmd| Ok::<_, SpawnError>(cmd.spawn()?));
+    match res {
+        Err(SpawnError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[cfg(all(feature = "tokio", unix))]
+#[tokio::test]
+async fn configure_and_run_async() {
+    let client = Client::new(1).unwrap();
+    let expected = client
+        .makeflags_value(jobslot::MakeflagsStyle::Compatible)
+        .unwrap();
+
+    let mut cmd = tokio::process::Command::new("sh");
+    cmd.arg("-c").arg("printf %s \"$MAKEFLAGS\"");
+
+    let output = client
+        .configure_make_and_run_async(&mut cmd, |cmd| {
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                cmd.output().await
+            })
+        })
+        .await
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(expected, *String::from_utf8(output.stdout).unwrap());
+    assert!(cmd.as_std().get_envs().all(|(_, value)| value.is_none()));
+
+    // The environment variables are removed if the future is dropped.
+    let fut = client.configure_and_run_async(&mut cmd, |_| {
+        Box::pin(async { std::future::pending::<std::io::Result<()>>().await })
+    });
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(10), fut)
+            .await
+            .is_err()
+    );
+    assert!(cmd.as_std().get_envs().all(|(_, value)| value.is_none()));
+}
+
+#[test]
+fn trace_recorder() {
+    let recorder = jobslot::TraceRecorder::new();
+    let client = Client::new(1).unwrap();
+    client.set_trace_recorder(Some(recorder.clone()));
+
+    let token = client.acquire().unwrap();
+    let waiter = {
+        let client = client.clone();
+        thread::Builder::new()
+            .name("waiter \"1\"".into())
+            .spawn(move || drop(client.acquire().unwrap()))
+            .unwrap()
+    };
+    thread::sleep(std::time::Duration::from_millis(50));
+    drop(token);
+    waiter.join().unwrap();
+
+    // Two acquires and two releases.
+    assert_eq!(recorder.len(), 4);
+
+    let mut json = Vec::new();
+    recorder.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.starts_with(r#"{"traceEvents":["#));
+    assert_eq!(json.matches(r#""name":"acquire""#).count(), 2);
+    assert_eq!(json.matches(r#""name":"release""#).count(), 2);
+    assert!(json.contains(r#""name":"waiter \"1\"""#));
+    assert!(json.contains(r#""args":{"held":1}"#));
+
+    client.set_trace_recorder(None);
+    drop(client.acquire().unwrap());
+    assert_eq!(recorder.len(), 4);
+
+    recorder.clear();
+    assert!(recorder.is_empty());
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn gather() {
+    let client = Client::new(2).unwrap();
+    let token = client.acquire().unwrap();
+
+    let metrics = client.gather();
+    assert!(metrics.contains("# TYPE jobslot_tokens_available gauge\njobslot_tokens_available 1\n"));
+    assert!(metrics.contains("\njobslot_tokens_held 1\n"));
+    assert!(metrics.contains("\njobslot_acquire_duration_seconds_bucket{le=\"+Inf\"} 1\n"));
+    assert!(metrics.contains("\njobslot_acquire_duration_seconds_count 1\n"));
+    assert!(metrics.contains("\njobslot_acquire_failures_total 0\n"));
+
+    drop(token);
+    let flag = CancellationFlag::new().unwrap();
+    flag.cancel().unwrap();
+    client.acquire_with_cancel(&flag).unwrap_err();
+
+    let metrics = client.gather();
+    assert!(metrics.contains("\njobslot_tokens_held 0\n"));
+    assert!(metrics.contains("\njobslot_acquire_failures_total 1\n"));
+}
+
+#[test]
+fn backoff() {
+    use jobslot::Backoff;
+    use std::time::Duration;
+
+    assert_eq!(Backoff::default().delay(3), Duration::ZERO);
+    assert_eq!(
+        Backoff::Fixed(Duration::from_millis(5)).delay(3),
+        Duration::from_millis(5)
+    );
+
+    let exponential = |jitter| Backoff::Exponential {
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(10),
+        jitter,
+    };
+    assert_eq!(exponential(false).delay(0), Duration::from_millis(1));
+    assert_eq!(exponential(false).delay(2), Duration::from_millis(4));
+    assert_eq!(exponential(false).delay(100), Duration::from_millis(10));
+    assert!(exponential(true).delay(100) <= Duration::from_millis(10));
+
+    let client = Client::new(1).unwrap();
+    client.set_backoff(exponential(true));
+
+    let token = client.acquire().unwrap();
+    let waiters: Vec<_> = (0..4)
+        .map(|_| {
+            let client = client.clone();
+            thread::spawn(move || drop(client.acquire().unwrap()))
+        })
+        .collect();
+    drop(token);
+    for waiter in waiters {
+        waiter.join().unwrap();
+    }
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[test]
+fn stats() {
+    let client = Client::new(2).unwrap();
+
+    let a = client.acquire().unwrap();
+    let b = client.acquire().unwrap();
+    thread::sleep(std::time::Duration::from_millis(50));
+    drop(a);
+
+    let stats = client.stats();
+    assert_eq!(stats.acquired, 2);
+    assert_eq!(stats.peak_held, 2);
+    assert_eq!(stats.held, 1);
+    assert!(stats.held_time >= std::time::Duration::from_millis(100));
+    assert!(stats.held_time <= stats.elapsed * 2);
+    let utilization = stats.utilization.unwrap();
+    assert!(utilization > 0.0 && utilization <= 1.0, "{}", utilization);
+
+    client.reset_stats();
+    let stats = client.stats();
+    assert_eq!(stats.acquired, 0);
+    assert_eq!(stats.peak_held, 1);
+    assert_eq!(stats.held, 1);
+    drop(b);
+    assert_eq!(client.stats().held, 0);
+
+    assert_eq!(Client::new(0).unwrap().stats().utilization, None);
+}
+
+#[test]
+fn watch_available() {
+    let client = Client::new(2).unwrap();
+    let mut watch = client
+        .watch_available(std::time::Duration::from_millis(10))
+        .unwrap();
+    assert_eq!(watch.changed().unwrap(), 2);
+
+    let token = client.acquire().unwrap();
+    assert_eq!(watch.changed().unwrap(), 1);
+    assert_eq!(watch.current().unwrap(), 1);
+
+    let mut clone = watch.clone();
+    drop(token);
+    assert_eq!(watch.changed().unwrap(), 2);
+    assert_eq!(clone.changed().unwrap(), 2);
+}
+
+#[cfg(all(feature = "tokio", unix))]
+#[tokio::test]
+async fn watch_available_async() {
+    let client = Client::new(1).unwrap();
+    let mut watch = client
+        .watch_available(std::time::Duration::from_millis(10))
+        .unwrap();
+    assert_eq!(
+        std::future::poll_fn(|cx| watch.poll_changed(cx))
+            .await
+            .unwrap(),
+        1
+    );
+
+    let token = client.acquire().unwrap();
+    assert_eq!(
+        std::future::poll_fn(|cx| watch.poll_changed(cx))
+            .await
+            .unwrap(),
+        0
+    );
+    drop(token);
+}
+
+#[cfg(unix)]
+#[test]
+fn watch_available_wakes_up_on_release() {
+    let client = Client::new(1).unwrap();
+    let a = client.acquire().unwrap();
+
+    // Far longer than the test takes, the release is observed through the
+    // readiness of the jobserver instead.
+    let mut watch = client
+        .watch_available(std::time::Duration::from_secs(30))
+        .unwrap();
+    assert_eq!(watch.changed().unwrap(), 0);
+
+    let started = std::time::Instant::now();
+    drop(a);
+    assert_eq!(watch.changed().unwrap(), 1);
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+}
+
+#[test]
+fn event_sink() {
+    let td = tempfile::tempdir().unwrap();
+    let path = td.path().join("events.jsonl");
+
+    let client = Client::new(1).unwrap();
+    client.set_event_sink(Some(jobslot::EventSink::append_to(&path).unwrap()));
+
+    drop(client.acquire().unwrap());
+    #[cfg(any(unix, windows))]
+    client
+        .configure_make_and_run(Command::new("true"), |_| Ok(()))
+        .unwrap();
+
+    client.set_event_sink(None);
+    drop(client.acquire().unwrap());
+
+    let events = std::fs::read_to_string(&path).unwrap();
+    let events: Vec<_> = events.lines().collect();
+
+    assert!(events[0].starts_with(r#"{"event":"acquire","ts_us":"#));
+    assert!(events[0].contains(&format!(r#""pid":{},"#, std::process::id())));
+    assert!(events[0].contains(r#""wait_us":"#));
+    assert!(events[1].starts_with(r#"{"event":"release","#));
+
+    #[cfg(any(unix, windows))]
+    {
+        assert_eq!(events.len(), 3);
+        assert!(events[2].starts_with(r#"{"event":"configure","#));
+        assert!(
+            events[2].ends_with(r#""envs":["CARGO_MAKEFLAGS","MAKEFLAGS","MFLAGS"],"fifo":false}"#)
+        );
+    }
+    #[cfg(not(any(unix, windows)))]
+    assert_eq!(events.len(), 2);
+}
+
+#[cfg(any(unix, windows))]
+#[test]
+fn increment_makelevel() {
+    let makelevel = |cmd: &Command| {
+        cmd.get_envs()
+            .find(|(key, _)| *key == "MAKELEVEL")
+            .and_then(|(_, value)| value.map(|value| value.to_str().unwrap().to_owned()))
+    };
+    let expected = env::var("MAKELEVEL")
+        .ok()
+        .and_then(|level| level.parse::<u32>().ok())
+        .unwrap_or(0)
+        + 1;
+
+    let client = Client::new(1).unwrap();
+    client
+        .configure_make_and_run(Command::new("true"), |cmd| {
+            assert_eq!(makelevel(cmd), None);
+            Ok(())
+        })
+        .unwrap();
+
+    client.set_increment_makelevel(true);
+    client
+        .configure_make_and_run(Command::new("true"), |cmd| {
+            assert_eq!(makelevel(cmd), Some(expected.to_string()));
+            Ok(())
+        })
+        .unwrap();
+    client
+        .configure_and_run(Command::new("true"), |cmd| {
+            assert_eq!(makelevel(cmd), None);
+            Ok(())
+        })
+        .unwrap();
+
+    // A value set by the caller is left alone.
+    let mut cmd = Command::new("true");
+    cmd.env("MAKELEVEL", "7");
+    client
+        .configure_make_and_run(&mut cmd, |cmd| {
+            assert_eq!(makelevel(cmd), Some("7".to_string()));
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(makelevel(&cmd), Some("7".to_string()));
+}
+
+#[test]
+fn probe() {
+    let client = Client::new(2).unwrap();
+    client.probe(std::time::Duration::from_secs(1)).unwrap();
+    assert_eq!(client.available().unwrap(), 2);
+
+    // All tokens in use is not an error.
+    let client = Client::new(0).unwrap();
+    client.probe(std::time::Duration::from_millis(10)).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn probe_removed_fifo() {
+    let client = Client::new_with_fifo(1).unwrap();
+    std::fs::remove_file(client.fifo_path().unwrap()).unwrap();
+
+    let err = client.probe(std::time::Duration::from_secs(1)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(err.to_string().contains("is not working"));
+}
+
+#[test]
+fn configure_error() {
+    let err = std::io::Error::from(jobslot::ConfigureError::Unsupported);
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    assert_eq!(
+        err.get_ref().unwrap().downcast_ref(),
+        Some(&jobslot::ConfigureError::Unsupported)
+    );
+
+    let client = Client::new(1).unwrap();
+    let res = client.configure_and_run(Command::new("true"), |_| Ok(()));
+
+    #[cfg(any(unix, windows))]
+    res.unwrap();
+    #[cfg(not(any(unix, windows)))]
+    assert_eq!(res.unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn limit_from_vars() {
+    use std::ffi::OsString;
+
+    let ncpu = std::thread::available_parallelism().unwrap().get();
+    let limit = |vars: &[(&str, &str)]| {
+        jobslot::limit_from_vars(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| OsString::from(value))
+        })
+        .map(|limit| limit.map(|limit| limit.get()))
+    };
+
+    assert_eq!(limit(&[]).unwrap(), None);
+    assert_eq!(limit(&[("JOBS", "3")]).unwrap(), Some(3));
+    assert_eq!(limit(&[("JOBS", "default")]).unwrap(), Some(ncpu));
+    assert_eq!(limit(&[("JOBS", "0")]).unwrap(), Some(ncpu));
+    assert_eq!(limit(&[("JOBS", "-1")]).unwrap(), Some((ncpu - 1).max(1)));
+    assert_eq!(limit(&[("JOBS", "-100000")]).unwrap(), Some(1));
+    assert_eq!(
+        limit(&[("JOBS", "three")]).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    assert_eq!(
+        limit(&[("JOBS", "3"), ("CARGO_BUILD_JOBS", "5")]).unwrap(),
+        Some(5)
+    );
+    assert_eq!(limit(&[("MAKEFLAGS", "-j4 --jobs=6")]).unwrap(), Some(6));
+}
+
+#[test]
+fn recommended_jobs() {
+    let client = Client::new(4).unwrap();
+    assert_eq!(client.recommended_jobs(), 4);
+
+    let tokens: Vec<_> = (0..4).map(|_| client.acquire().unwrap()).collect();
+    // Smoothed by the previous snapshot.
+    assert_eq!(client.recommended_jobs(), 3);
+    assert_eq!(client.recommended_jobs(), 2);
+    drop(tokens);
+
+    let client = Client::new(0).unwrap();
+    assert_eq!(client.recommended_jobs(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn configure_and_run_with_jobs_arg() {
+    let run = |client: &Client| {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("printf %s \"$1\"").arg("sh");
+        let output = client
+            .configure_and_run_with_jobs_arg(&mut cmd, |cmd| cmd.output())
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let client = Client::new(3).unwrap();
+    assert_eq!(run(&client), "-j3");
+
+    let client = unsafe {
+        Client::from_auth(
+            &client.string_arg().unwrap(),
+            &jobslot::FromEnvConfig::default(),
+        )
+    }
+    .unwrap();
+    assert_eq!(client.jobs(), None);
+    let _token = client.acquire().unwrap();
+    assert_eq!(run(&client), "-j3");
+}
+
+#[cfg(unix)]
+#[test]
+fn configure_and_spawn() {
+    let client = Client::new(1).unwrap();
+    let expected = client
+        .makeflags_value(jobslot::MakeflagsStyle::Compatible)
+        .unwrap();
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg("printf %s \"$CARGO_MAKEFLAGS\"");
+
+    let output = client.configure_and_output(&mut cmd).unwrap();
+    assert!(output.status.success());
+    assert_eq!(expected, *String::from_utf8(output.stdout).unwrap());
+
+    let status = client
+        .configure_and_spawn(cmd.stdout(std::process::Stdio::null()))
+        .unwrap()
+        .wait()
+        .unwrap();
+    assert!(status.success());
+
+    // The environment is only set up for the spawned processes.
+    assert!(cmd.get_envs().all(|(_, value)| value.is_none()));
+}
+
+#[cfg(all(feature = "tokio", unix))]
+#[tokio::test]
+async fn configure_and_spawn_tokio() {
+    let client = Client::new(1).unwrap();
+    let expected = client
+        .makeflags_value(jobslot::MakeflagsStyle::Compatible)
+        .unwrap();
+
+    let mut cmd = tokio::process::Command::new("sh");
+    cmd.arg("-c").arg("printf %s \"$CARGO_MAKEFLAGS\"");
+
+    let output = client
+        .configure_and_output(&mut cmd)
+        .unwrap()
+        .await
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(expected, *String::from_utf8(output.stdout).unwrap());
+
+    let status = client
+        .configure_and_spawn(&mut cmd)
+        .unwrap()
+        .wait()
+        .await
+        .unwrap();
+    assert!(status.success());
+}
+
+#[cfg(any(unix, windows))]
+#[test]
+fn duplicate() {
+    let client = Client::new(2).unwrap();
+    let duplicate = client.duplicate().unwrap();
+    #[cfg(unix)]
+    assert_ne!(
+        client.string_arg().unwrap(),
+        duplicate.string_arg().unwrap()
+    );
+
+    let token = duplicate.acquire().unwrap();
+    assert_eq!(client.available().unwrap(), 1);
+    drop(token);
+    assert_eq!(client.available().unwrap(), 2);
+
+    duplicate.close().unwrap();
+    drop(client.acquire().unwrap());
+    assert_eq!(client.available().unwrap(), 2);
+
+    #[cfg(unix)]
+    {
+        let client = Client::new_with_fifo(1).unwrap();
+        let duplicate = client.duplicate().unwrap();
+        assert_eq!(duplicate.fifo_path(), client.fifo_path());
+        drop(duplicate);
+        // The duplicate does not own the fifo.
+        assert!(client.fifo_path().unwrap().exists());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn daemon() {
+    use jobslot::Daemon;
+    use std::io::ErrorKind;
+    use std::time::{Duration, Instant};
+
+    let wait_for_exit = |pid: u32| {
+        let start = Instant::now();
+        while unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    };
+
+    let name = format!("jobslot-daemon-test-{}", std::process::id());
+    assert_eq!(
+        Daemon::attach(&name).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+
+    let daemon = Daemon::spawn(&name, 2).unwrap();
+    assert!(daemon.is_alive());
+
+    // The tokens survive all clients being closed.
+    let client = daemon.client().unwrap();
+    drop(client.acquire().unwrap());
+    drop(client);
+
+    let attached = Daemon::attach(&name).unwrap();
+    assert_eq!(attached.pid(), daemon.pid());
+    assert_eq!(attached.client().unwrap().available().unwrap(), 2);
+    assert_eq!(
+        Daemon::spawn(&name, 2).unwrap_err().kind(),
+        ErrorKind::AlreadyExists
+    );
+
+    // A daemon which has exited is detected and cleaned up.
+    unsafe { libc::kill(daemon.pid() as libc::pid_t, libc::SIGKILL) };
+    wait_for_exit(daemon.pid());
+    assert!(!daemon.is_alive());
+    assert_eq!(
+        Daemon::attach(&name).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert_eq!(
+        Client::connect_named(&name).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+
+    let daemon = Daemon::spawn(&name, 1).unwrap();
+    let pid = daemon.pid();
+    daemon.teardown().unwrap();
+    wait_for_exit(pid);
+    assert_eq!(
+        Daemon::attach(&name).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn remove_orphaned_fifos() {
+    use std::time::Duration;
+
+    let client = Client::new_with_fifo(1).unwrap();
+    let path = client.fifo_path().unwrap().to_owned();
+    assert!(path
+        .to_str()
+        .unwrap()
+        .contains(&format!("_{}_", std::process::id())));
+
+    let mut child = Command::new("true").spawn().unwrap();
+    let pid = child.id();
+    child.wait().unwrap();
+
+    // A fifo of a process of another boot which has exited.
+    let orphan = format!("/tmp/__rust_jobslot_fifo_{}_0_1234abcd", pid);
+    let corphan = std::ffi::CString::new(orphan.as_str()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(corphan.as_ptr(), 0o600) }, 0);
+
+    assert_eq!(
+        Client::remove_orphaned_fifos(Duration::from_secs(3600)).unwrap(),
+        0
+    );
+    assert!(std::path::Path::new(&orphan).exists());
+
+    assert!(Client::remove_orphaned_fifos(Duration::ZERO).unwrap() >= 1);
+    assert!(!std::path::Path::new(&orphan).exists());
+    assert!(path.exists());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn spawn_config() {
+    use jobslot::SpawnConfig;
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let client = Client::new(0).unwrap();
+    let mut config = SpawnConfig::new();
+    client.configure_into(&mut config).unwrap();
+    assert_eq!(config.envs().len(), 1);
+    assert_eq!(config.fds().len(), 2);
+
+    let mut envp = config
+        .envs()
+        .iter()
+        .map(|(key, value)| {
+            let mut env = key.as_bytes().to_vec();
+            env.push(b'=');
+            env.extend_from_slice(value.as_bytes());
+            CString::new(env).unwrap()
+        })
+        .collect::<Vec<_>>();
+    envp.push(CString::new(format!("FD={}", config.fds()[1])).unwrap());
+    let argv = [
+        CString::new("sh").unwrap(),
+        CString::new("-c").unwrap(),
+        CString::new("[ -n \"$CARGO_MAKEFLAGS\" ] && printf + >&\"$FD\"").unwrap(),
+    ];
+
+    let mut argv_ptrs = argv.iter().map(|s| s.as_ptr()).collect::<Vec<_>>();
+    argv_ptrs.push(std::ptr::null());
+    let mut envp_ptrs = envp.iter().map(|s| s.as_ptr()).collect::<Vec<_>>();
+    envp_ptrs.push(std::ptr::null());
+
+    unsafe {
+        let mut actions = std::mem::zeroed();
+        assert_eq!(libc::posix_spawn_file_actions_init(&mut actions), 0);
+        config.add_file_actions(&mut actions).unwrap();
+
+        let mut pid = 0;
+        assert_eq!(
+            libc::posix_spawnp(
+                &mut pid,
+                argv[0].as_ptr(),
+                &actions,
+                std::ptr::null(),
+                argv_ptrs.as_ptr() as *const *mut _,
+                envp_ptrs.as_ptr() as *const *mut _,
+            ),
+            0
+        );
+        libc::posix_spawn_file_actions_destroy(&mut actions);
+
+        let mut status = 0;
+        assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+        assert!(libc::WIFEXITED(status));
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+    }
+
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn process_group() {
+    use jobslot::ProcessGroup;
+
+    let client = Client::new(1).unwrap();
+    let group = ProcessGroup::new().unwrap();
+    let spawn = || {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "sleep 100 & exec sleep 100"]);
+        client
+            .configure_and_run(&mut cmd, |cmd| group.spawn(cmd))
+            .unwrap()
+    };
+
+    let mut a = spawn();
+    let mut b = spawn();
+    let pgid = |pid: u32| unsafe { libc::getpgid(pid as libc::pid_t) };
+    assert_eq!(pgid(a.id()), a.id() as libc::pid_t);
+    assert_eq!(pgid(b.id()), a.id() as libc::pid_t);
+
+    group.kill().unwrap();
+    assert!(!a.wait().unwrap().success());
+    assert!(!b.wait().unwrap().success());
+
+    // The pid of the reaped leader might be reused by now.
+    group.kill().unwrap();
+    let err = group.spawn(&mut Command::new("true")).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn new_lazy() {
+    // Far more than fits in a pipe.
+    let client = Client::new_lazy(1_000_000).unwrap();
+    assert_eq!(client.jobs(), Some(1_000_000));
+    assert_eq!(client.available().unwrap(), 1_000_000);
+
+    let tokens: Vec<_> = (0..1000).map(|_| client.acquire().unwrap()).collect();
+    assert_eq!(client.available().unwrap(), 999_000);
+    drop(tokens);
+    assert_eq!(
+        client
+            .wait_for_all_returned(std::time::Duration::from_secs(1))
+            .unwrap(),
+        0
+    );
+
+    client.reset().unwrap();
+    assert_eq!(client.available().unwrap(), 1_000_000);
+
+    let client = Client::new_lazy(4).unwrap();
+    assert_eq!(client.available().unwrap(), 4);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn new_packet() {
+    use std::{fs::File, io::Read};
+
+    let client = Client::new_packet(3).unwrap();
+    assert!(client.is_packet_mode());
+    assert!(!Client::new(1).unwrap().is_packet_mode());
+    assert_eq!(client.available().unwrap(), 3);
+
+    let a = client.acquire().unwrap();
+    assert_eq!(client.available().unwrap(), 2);
+
+    // A read of several bytes takes one token only.
+    let mut read = File::from(client.duplicate().unwrap().into_raw_parts().unwrap().read);
+    let mut buf = [0; 8];
+    assert_eq!(read.read(&mut buf).unwrap(), 1);
+    assert_eq!(client.available().unwrap(), 1);
+    client.release_raw().unwrap();
+    drop(a);
+    assert_eq!(client.available().unwrap(), 3);
+
+    client.reset().unwrap();
+    assert_eq!(client.available().unwrap(), 3);
+    let _tokens: Vec<_> = (0..3).map(|_| client.acquire().unwrap()).collect();
+    assert_eq!(client.available().unwrap(), 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn new_packet_above_default_pipe_capacity() {
+    // A pipe holds 16 packets by default, so creating it would block.
+    let client = Client::new_packet(40).unwrap();
+    assert_eq!(client.available().unwrap(), 40);
+
+    let tokens: Vec<_> = (0..40).map(|_| client.acquire().unwrap()).collect();
+    assert_eq!(client.available().unwrap(), 0);
+    drop(tokens);
+    assert_eq!(client.available().unwrap(), 40);
+
+    let err = Client::new_packet(usize::MAX).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn pressure_governor() {
+    use jobslot::PressureGovernor;
+    use std::time::{Duration, Instant};
+
+    let client = Client::new(1).unwrap();
+    let calm = PressureGovernor {
+        memory: None,
+        io: None,
+        ..Default::default()
+    };
+    assert!(!calm.is_under_pressure());
+
+    client.set_pressure_governor(Some(calm));
+    let start = Instant::now();
+    drop(client.acquire().unwrap());
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    if !std::path::Path::new("/proc/pressure/memory").exists() {
+        return;
+    }
+
+    // Any stall percentage exceeds a negative threshold.
+    let stalled = PressureGovernor {
+        memory: Some(-1.0),
+        io: None,
+        poll_interval: Duration::from_millis(10),
+        max_delay: Duration::from_millis(100),
+    };
+    assert!(stalled.is_under_pressure());
+
+    client.set_pressure_governor(Some(stalled));
+    let start = Instant::now();
+    drop(client.acquire().unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    // Bounded by the deadline too.
+    let start = Instant::now();
+    let res = client
+        .acquire_or_proceed(start + Duration::from_millis(20))
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_millis(100));
+    drop(res);
+
+    client.set_pressure_governor(None);
+    let start = Instant::now();
+    drop(client.acquire().unwrap());
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn from_process() {
+    use jobslot::FromEnvError;
+
+    let client = Client::new(2).unwrap();
+    let mut child = client
+        .configure_make_and_run(
+            {
+                let mut cmd = Command::new("sleep");
+                cmd.arg("5");
+                cmd
+            },
+            |cmd| cmd.spawn(),
+        )
+        .unwrap();
+
+    let res = Client::from_process(child.id());
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let adopted = match res {
+        Ok(adopted) => adopted,
+        // `pidfd_getfd` is not permitted or supported, e.g. in a sandbox.
+        Err(FromEnvError::IoError(err))
+            if matches!(
+                err.raw_os_error(),
+                Some(libc::EPERM) | Some(libc::ENOSYS) | Some(libc::EACCES)
+            ) =>
+        {
+            return
+        }
+        Err(err) => panic!("{}", err),
+    };
+    assert_eq!(adopted.jobs(), Some(2));
+
+    let a = adopted.acquire().unwrap();
+    assert_eq!(client.available().unwrap(), 1);
+    drop(a);
+    assert_eq!(client.available().unwrap(), 2);
+
+    assert!(matches!(
+        Client::from_process(std::process::id()),
+        Err(FromEnvError::NotFound)
+    ));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn shared_ledger() {
+    use jobslot::Ledger;
+
+    let ledger = Ledger::new_shared(4).unwrap();
+    let pid = std::process::id();
+
+    let client = Client::new(3).unwrap();
+    client.set_ledger(Some(ledger.clone())).unwrap();
+
+    let a = client.acquire().unwrap();
+    let b = client.acquire().unwrap();
+
+    // Seen by another mapping of the region.
+    let other = Ledger::open_shared(ledger.dir()).unwrap();
+    assert_eq!(other.held(pid).unwrap(), 2);
+    assert_eq!(other.entries().unwrap(), vec![(pid, 2)]);
+    assert_eq!(other.stale_entries().unwrap(), vec![]);
+
+    drop(a);
+    assert_eq!(other.held(pid).unwrap(), 1);
+    drop(b);
+    assert_eq!(other.entries().unwrap(), vec![]);
+
+    let td = tempfile::tempdir().unwrap();
+    let path = td.path().join("ledger");
+    std::fs::write(&path, "").unwrap();
+    assert_eq!(
+        Ledger::open_shared(&path).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn stale_ledger_entries() {
+    use jobslot::Ledger;
+
+    let td = tempfile::tempdir().unwrap();
+    let ledger = Ledger::open(td.path()).unwrap();
+
+    let mut child = Command::new("true").spawn().unwrap();
+    let dead = child.id();
+    child.wait().unwrap();
+    std::fs::write(td.path().join(dead.to_string()), "2").unwrap();
+
+    let client = Client::new(1).unwrap();
+    client.set_ledger(Some(ledger.clone())).unwrap();
+    let _a = client.acquire().unwrap();
+
+    let pid = std::process::id();
+    let mut entries = vec![(pid, 1), (dead, 2)];
+    entries.sort_unstable();
+    assert_eq!(ledger.entries().unwrap(), entries);
+    assert_eq!(ledger.stale_entries().unwrap(), vec![(dead, 2)]);
+}
+
+#[test]
+fn observe() {
+    let client = Client::new(2).unwrap();
+    let observer = client.observe();
+    assert_eq!(observer.id(), client.id());
+    assert_eq!(observer.jobs(), Some(2));
+    assert_eq!(observer.available().unwrap(), 2);
+
+    let mut watch = observer
+        .watch_available(std::time::Duration::from_millis(10))
+        .unwrap();
+    assert_eq!(watch.changed().unwrap(), 2);
+
+    let a = client.acquire().unwrap();
+    assert_eq!(observer.available().unwrap(), 1);
+    assert_eq!(watch.changed().unwrap(), 1);
+    assert_eq!(observer.stats().held, 1);
+
+    // Outlives the client.
+    drop(a);
+    drop(client);
+    assert_eq!(observer.available().unwrap(), 2);
+
+    // Tokens not issued yet are counted without being written.
+    let client = Client::new_lazy(1000).unwrap();
+    assert_eq!(client.observe().available().unwrap(), 1000);
+}
+
+#[test]
+fn worker_tokens() {
+    use jobslot::WorkerTokens;
+
+    let client = Client::new(2).unwrap();
+    let tokens = Arc::new(WorkerTokens::new());
+
+    tokens.on_request(&client, 1).unwrap();
+    tokens.on_request(&client, 2).unwrap();
+    assert_eq!(tokens.in_flight(), 2);
+    assert_eq!(client.available().unwrap(), 0);
+    assert_eq!(
+        tokens.on_request(&client, 1).unwrap_err().kind(),
+        std::io::ErrorKind::AlreadyExists
+    );
+
+    // Blocks until a response is sent.
+    let handle = {
+        let client = client.clone();
+        let tokens = tokens.clone();
+        thread::spawn(move || tokens.on_request(&client, 3).unwrap())
+    };
+    assert!(tokens.on_response(1));
+    handle.join().unwrap();
+
+    assert!(!tokens.on_response(1));
+    assert!(tokens.on_response(2));
+    assert!(tokens.on_response(3));
+    assert_eq!(tokens.in_flight(), 0);
+    assert_eq!(client.available().unwrap(), 2);
+}
+
+#[cfg(all(feature = "tokio", unix))]
+#[tokio::test]
+async fn worker_tokens_async() {
+    use jobslot::WorkerTokens;
+
+    let client = Client::new(1).unwrap();
+    let async_client =
+        AsyncAcquireClient::new(client.clone().into_try_acquire_client().unwrap()).unwrap();
+    let tokens = Arc::new(WorkerTokens::new());
+
+    tokens.on_request_async(&async_client, 0).await.unwrap();
+    assert_eq!(client.available().unwrap(), 0);
+
+    let task = {
+        let tokens = tokens.clone();
+        tokio::spawn(async move { tokens.on_request_async(&async_client, 1).await })
+    };
+    assert!(tokens.on_response(0));
+    task.await.unwrap().unwrap();
+    assert!(tokens.on_response(1));
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[test]
+fn build_script_jobs() {
+    use jobslot::build_script::Jobs;
+    use std::sync::atomic::AtomicUsize;
+
+    let client = Client::new(1).unwrap();
+    let jobs = Jobs::new(Some(client.clone()));
+
+    let a = jobs.acquire().unwrap();
+    assert!(a.is_implicit());
+    let b = jobs.acquire().unwrap();
+    assert!(!b.is_implicit());
+    assert_eq!(client.available().unwrap(), 0);
+
+    // Woken up by the implicit token while blocked on the jobserver.
+    let handle = {
+        let jobs = jobs.clone();
+        thread::spawn(move || jobs.acquire().unwrap().is_implicit())
+    };
+    thread::sleep(std::time::Duration::from_millis(50));
+    drop(a);
+    assert!(handle.join().unwrap());
+    drop(b);
+    assert_eq!(client.available().unwrap(), 1);
+
+    let running = AtomicUsize::new(0);
+    let peak = AtomicUsize::new(0);
+    let results = jobs
+        .run(0..8, |i| {
+            let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(n, Ordering::SeqCst);
+            thread::sleep(std::time::Duration::from_millis(10));
+            running.fetch_sub(1, Ordering::SeqCst);
+            i * 2
+        })
+        .unwrap();
+    assert_eq!(results, (0..8).map(|i| i * 2).collect::<Vec<_>>());
+    assert!(peak.load(Ordering::SeqCst) <= 2);
+
+    // Only the implicit token.
+    let jobs = Jobs::new(None);
+    assert_eq!(jobs.run(0..3, |i| i).unwrap(), vec![0, 1, 2]);
+}
+
+#[test]
+fn client_try_acquire() {
+    let client = Client::new(1).unwrap();
+
+    #[cfg(unix)]
+    {
+        use jobslot::TryAcquireError;
+
+        let err = client.try_acquire().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        assert_eq!(
+            err.get_ref()
+                .and_then(|err| err.downcast_ref::<TryAcquireError>()),
+            Some(&TryAcquireError::ConversionRequired)
+        );
+    }
+
+    let try_client = get_try_acquire_client(client.clone());
+
+    let a = client.try_acquire().unwrap().unwrap();
+    assert!(client.try_acquire().unwrap().is_none());
+    drop(a);
+    drop(try_client);
+
+    #[cfg(unix)]
+    assert!(client.try_acquire().is_err());
+}
+
+#[test]
+fn acquire_with_report() {
+    use jobslot::{AcquireBackend, AcquirePath};
+    use std::time::Duration;
+
+    let client = Client::new(1).unwrap();
+
+    let (a, report) = client.acquire_with_report().unwrap();
+    assert_eq!(report.path, AcquirePath::Fast);
+    assert_eq!(report.wakeups, 0);
+    #[cfg(unix)]
+    assert_eq!(report.backend, AcquireBackend::Pipe);
+    #[cfg(windows)]
+    assert_eq!(report.backend, AcquireBackend::Semaphore);
+    #[cfg(not(any(unix, windows)))]
+    assert_eq!(report.backend, AcquireBackend::InProcess);
+
+    let handle = {
+        let client = client.clone();
+        thread::spawn(move || client.acquire_with_report().unwrap().1)
+    };
+    thread::sleep(Duration::from_millis(50));
+    drop(a);
+    let report = handle.join().unwrap();
+    assert_eq!(report.path, AcquirePath::Fallback);
+    assert!(report.wakeups >= 1);
+    assert!(report.wait >= Duration::from_millis(50));
+
+    client.set_linger(Duration::from_secs(1));
+    drop(client.acquire().unwrap());
+    let (_a, report) = client.acquire_with_report().unwrap();
+    assert_eq!(report.path, AcquirePath::Linger);
+
+    #[cfg(unix)]
+    {
+        let client = Client::new_with_fifo(1).unwrap();
+        let report = client.acquire_with_report().unwrap().1;
+        assert_eq!(report.backend, AcquireBackend::Fifo);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn release_policy() {
+    use jobslot::ReleasePolicy;
+    use std::{os::unix::io::AsRawFd, sync::Mutex};
+
+    let client = Client::new(3).unwrap();
+    let tokens: Vec<_> = (0..3).map(|_| client.acquire().unwrap()).collect();
+    let mut tokens = tokens.into_iter();
+
+    // Close the only read end of the pipe, as if the parent make died.
+    let null = File::open("/dev/null").unwrap();
+    assert_ne!(
+        unsafe { libc::dup2(null.as_raw_fd(), client.as_raw_fd()) },
+        -1
+    );
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let errors2 = errors.clone();
+    client.set_release_policy(ReleasePolicy::hook(move |err| {
+        errors2.lock().unwrap().push(err.is_jobserver_gone());
+    }));
+    drop(tokens.next().unwrap());
+    assert_eq!(*errors.lock().unwrap(), [true]);
+
+    client.set_release_policy(ReleasePolicy::Propagate);
+    drop(tokens.next().unwrap());
+    assert_eq!(
+        client.acquire().unwrap_err().kind(),
+        std::io::ErrorKind::BrokenPipe
+    );
+
+    let err = tokens.next().unwrap().release().unwrap_err();
+    assert!(err.is_jobserver_gone());
+    assert_eq!(errors.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn abandoned_policy() {
+    use jobslot::AbandonedPolicy;
+
+    let client = Client::new(1).unwrap();
+    client.set_abandoned_policy(AbandonedPolicy::recover(|| Ok(())));
+    assert_eq!(
+        format!("{:?}", AbandonedPolicy::recover(|| Ok(()))),
+        "Recover(..)"
+    );
+    assert_eq!(format!("{:?}", AbandonedPolicy::default()), "Error");
+
+    drop(client.acquire().unwrap());
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[test]
+fn protocol_auth() {
+    use jobslot::protocol::JobserverAuth;
+
+    let pipe = JobserverAuth::Pipe { read: 3, write: 4 };
+    assert_eq!(JobserverAuth::parse("3,4").unwrap(), pipe);
+    assert_eq!(pipe.to_string(), "3,4");
+    assert_eq!(
+        pipe.to_makeflags(),
+        "--jobserver-fds=3,4 --jobserver-auth=3,4"
+    );
+    assert!(!pipe.is_disabled());
+    assert!(JobserverAuth::parse("-2,-2").unwrap().is_disabled());
+
+    let sem = JobserverAuth::Semaphore("gmake_semaphore_1".into());
+    assert_eq!(JobserverAuth::parse("sem:gmake_semaphore_1").unwrap(), sem);
+    assert_eq!(JobserverAuth::parse("gmake_semaphore_1").unwrap(), sem);
+    assert_eq!(sem.to_makeflags(), "--jobserver-auth=gmake_semaphore_1");
+
+    let handle = JobserverAuth::Handle(412);
+    assert_eq!(JobserverAuth::parse("handle:412").unwrap(), handle);
+    assert_eq!(handle.to_makeflags(), "--jobserver-auth=handle:412");
+
+    for malformed in ["", "fifo:", "sem:", "3,x", "handle:x"] {
+        assert!(JobserverAuth::parse(malformed).is_err(), "{}", malformed);
+    }
+
+    assert_eq!(JobserverAuth::from_makeflags("-j4"), None);
+    assert_eq!(
+        JobserverAuth::from_makeflags("-j4 --jobserver-fds=3,4 --jobserver-auth=fifo:/a b")
+            .unwrap()
+            .unwrap(),
+        JobserverAuth::Fifo("/a".into())
+    );
+    assert_eq!(
+        JobserverAuth::from_makeflags("--jobserver-fds=5,6").unwrap(),
+        Ok(JobserverAuth::Pipe { read: 5, write: 6 })
+    );
+
+    // Round-trips through a client.
+    #[cfg(any(unix, windows))]
+    let client = Client::new(1).unwrap();
+    #[cfg(any(unix, windows))]
+    assert_eq!(
+        client
+            .string_arg()
+            .unwrap()
+            .parse::<JobserverAuth>()
+            .unwrap()
+            .to_string(),
+        client.string_arg().unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn new_with_fifo_in() {
+    let td = tempfile::tempdir().unwrap();
+
+    let client = Client::new_with_fifo_in(td.path(), 1).unwrap();
+    let path = client.fifo_path().unwrap().to_owned();
+    assert_eq!(path.parent().unwrap(), td.path());
+    assert_eq!(client.available().unwrap(), 1);
+
+    drop(client);
+    assert!(!path.exists());
+}
+
+#[cfg(windows)]
+#[test]
+fn duplicate_into_process() {
+    use std::process::Stdio;
+
+    let client = Client::new_unnamed(1).unwrap();
+    assert!(client.string_arg().unwrap().starts_with("handle:"));
+
+    // The handle is only valid in this process.
+    let err = client
+        .configure_and_run(Command::new("cmd"), |cmd| cmd.spawn())
+        .unwrap_err();
+    assert_eq!(
+        err.get_ref().unwrap().downcast_ref(),
+        Some(&jobslot::ConfigureError::Unnamed)
+    );
+
+    // Same semaphore through a handle of its own.
+    let other = unsafe {
+        Client::from_auth(
+            &client.string_arg().unwrap(),
+            &jobslot::FromEnvConfig::default(),
+        )
+    }
+    .unwrap();
+    let token = other.acquire().unwrap();
+    assert_eq!(client.available().unwrap(), 0);
+    drop(token);
+    assert_eq!(client.available().unwrap(), 1);
+
+    let mut child = Command::new("cmd")
+        .args(["/c", "pause"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let auth = client.duplicate_into_process(&child).unwrap();
+    assert!(auth.parse::<jobslot::protocol::JobserverAuth>().is_ok());
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn configure_make_and_run_for() {
+    use jobslot::MakeFlavor;
+
+    #[cfg(not(windows))]
+    assert_eq!(MakeFlavor::detect("make"), MakeFlavor::Native);
+
+    let client = Client::new(2).unwrap();
+    let mut cmd = Command::new("make");
+    let makeflags = |cmd: &mut Command| {
+        cmd.get_envs()
+            .find(|(name, _)| *name == "MAKEFLAGS")
+            .and_then(|(_, value)| value?.to_str().map(String::from))
+            .unwrap()
+    };
+
+    let value = client
+        .configure_make_and_run_for(MakeFlavor::Msys, &mut cmd, |cmd| Ok(makeflags(cmd)))
+        .unwrap();
+    assert_eq!(value, "-j2");
+
+    #[cfg(any(unix, windows))]
+    {
+        let value = client
+            .configure_make_and_run_for(MakeFlavor::Native, &mut cmd, |cmd| Ok(makeflags(cmd)))
+            .unwrap();
+        assert!(value.contains("--jobserver-auth="), "{}", value);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn wsl_bridge() {
+    let td = tempfile::tempdir().unwrap();
+    let path = td.path().join("fifo");
+
+    let client = Client::new(2).unwrap();
+    // Run the relay locally instead of inside WSL.
+    let bridge =
+        jobslot::WslBridge::with_launcher(&client, Command::new("env"), path.to_str().unwrap(), 1)
+            .unwrap();
+    assert_eq!(
+        bridge.makeflags_value(),
+        format!("-j2 --jobserver-auth=fifo:{}", path.display())
+    );
+
+    let linux = unsafe {
+        Client::from_auth(
+            &format!("fifo:{}", bridge.fifo_path()),
+            &jobslot::FromEnvConfig::default(),
+        )
+    }
+    .unwrap();
+    let token = linux.acquire().unwrap();
+    assert_eq!(client.available().unwrap(), 1);
+
+    let closing = thread::spawn(move || bridge.close());
+    thread::sleep(std::time::Duration::from_millis(100));
+    assert!(!closing.is_finished());
+    assert_eq!(client.available().unwrap(), 1);
+
+    drop(token);
+    closing.join().unwrap().unwrap();
+    assert_eq!(client.available().unwrap(), 2);
+    assert!(!path.exists());
+}
+
+#[test]
+fn protocol_path_translation() {
+    use jobslot::protocol::{JobserverAuth, PathTranslation};
+
+    let cygwin = PathTranslation::cygwin("C:/cygwin64/");
+    assert_eq!(cygwin.to_native("/tmp/fifo"), r"C:\cygwin64\tmp\fifo");
+    assert_eq!(cygwin.to_native("/cygdrive/d/fifo"), r"D:\fifo");
+    assert_eq!(cygwin.to_native("build/fifo"), r"build\fifo");
+    assert_eq!(cygwin.to_posix(r"c:\CYGWIN64\tmp\fifo"), "/tmp/fifo");
+    assert_eq!(cygwin.to_posix(r"D:\fifo"), "/cygdrive/d/fifo");
+    assert_eq!(cygwin.to_posix("/tmp/fifo"), "/tmp/fifo");
+
+    let msys = PathTranslation::msys(r"C:\msys64");
+    let auth = JobserverAuth::parse("fifo:/tmp/GMfifo1").unwrap();
+    let native = auth.to_native(&msys);
+    assert_eq!(native.to_string(), r"fifo:C:\msys64\tmp\GMfifo1");
+    assert_eq!(native.to_posix(&msys), auth);
+
+    let pipe = JobserverAuth::Pipe { read: 3, write: 4 };
+    assert_eq!(pipe.to_native(&msys), pipe);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn borrow_burst() {
+    use std::time::{Duration, Instant};
+
+    let client = Client::new(1).unwrap();
+    let token = client.acquire().unwrap();
+    client.borrow_burst(2, Duration::from_millis(50)).unwrap();
+    assert_eq!(client.available().unwrap(), 2);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while client.available().unwrap() != 0 {
+        assert!(Instant::now() < deadline, "burst tokens are not retired");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // The held token is back to the steady-state limit.
+    drop(token);
+    assert_eq!(client.available().unwrap(), 1);
+
+    #[cfg(unix)]
+    {
+        let other = unsafe {
+            Client::from_auth(
+                &client.string_arg().unwrap(),
+                &jobslot::FromEnvConfig::default(),
+            )
+        }
+        .unwrap();
+        assert_eq!(
+            other
+                .borrow_burst(1, Duration::from_millis(50))
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::Unsupported
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn borrow_burst_does_not_keep_client_alive() {
+    use std::time::{Duration, Instant};
+
+    let client = Client::new_with_fifo(1).unwrap();
+    let path = client.fifo_path().unwrap().to_owned();
+    client.borrow_burst(1, Duration::from_millis(200)).unwrap();
+
+    // Keep the tokens away from the retiring thread.
+    let other = unsafe {
+        Client::from_auth(
+            &client.string_arg().unwrap(),
+            &jobslot::FromEnvConfig::default(),
+        )
+    }
+    .unwrap();
+    let tokens = [other.acquire().unwrap(), other.acquire().unwrap()];
+    // Let the burst expire, so that the thread waits for tokens.
+    thread::sleep(Duration::from_millis(300));
+    drop(client);
+
+    // The fifo is removed once the client is dropped.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while path.exists() {
+        assert!(Instant::now() < deadline, "client is kept alive");
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(tokens);
+}
+
+#[test]
+fn idle_reclaim() {
+    use jobslot::IdleReclaim;
+    use std::time::{Duration, Instant};
+
+    let client = Client::new(2).unwrap();
+    let reclaimed = Arc::new(AtomicBool::new(false));
+    client.set_idle_reclaim(Some(
+        IdleReclaim::new(Duration::from_millis(200)).on_reclaim({
+            let reclaimed = reclaimed.clone();
+            move |_| reclaimed.store(true, Ordering::SeqCst)
+        }),
+    ));
+
+    let idle = client.acquire().unwrap();
+    let busy = client.acquire().unwrap();
+    assert_eq!(client.available().unwrap(), 0);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !reclaimed.load(Ordering::SeqCst) {
+        assert!(Instant::now() < deadline, "idle token is not reclaimed");
+        assert!(busy.touch());
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(idle.is_reclaimed());
+    assert!(!idle.touch());
+    assert!(!busy.is_reclaimed());
+    assert_eq!(client.available().unwrap(), 1);
+
+    // Reclaimed tokens are not released again.
+    drop(idle);
+    assert_eq!(client.available().unwrap(), 1);
+
+    client.set_idle_reclaim(None);
+    drop(busy);
+    assert_eq!(client.available().unwrap(), 2);
+}
+
+#[tokio::test]
+async fn semaphore() {
+    use jobslot::sync::Semaphore;
+
+    let client = Client::new(1).unwrap();
+    for semaphore in [Semaphore::new(1), Semaphore::from_client(client.clone())] {
+        let permit = semaphore.acquire().unwrap();
+        assert!(semaphore.try_acquire().unwrap().is_none());
+
+        let task = {
+            let semaphore = semaphore.clone();
+            tokio::spawn(async move { semaphore.acquire_async().await })
+        };
+        drop(permit);
+        let permit = task.await.unwrap().unwrap();
+        assert!(semaphore.try_acquire().unwrap().is_none());
+        drop(permit);
+
+        let permit = semaphore.try_acquire().unwrap().unwrap();
+        drop(permit);
+    }
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[test]
+fn with_token() {
+    use std::panic;
+
+    let client = Client::new(1).unwrap();
+    let res = client.with_token(|| client.available().unwrap()).unwrap();
+    assert_eq!(res, 0);
+    assert_eq!(client.available().unwrap(), 1);
+
+    let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        client.with_token(|| panic!("work failed")).unwrap()
+    }));
+    assert!(res.is_err());
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[cfg(any(all(feature = "tokio", unix), not(any(unix, windows))))]
+#[tokio::test]
+async fn async_with_token() {
+    use std::time::Duration;
+
+    let client = Client::new(1).unwrap();
+    let async_client = AsyncAcquireClient::new(get_try_acquire_client(client.clone())).unwrap();
+
+    let res = async_client
+        .with_token(async { client.available().unwrap() })
+        .await
+        .unwrap();
+    assert_eq!(res, 0);
+    assert_eq!(client.available().unwrap(), 1);
+
+    // Cancelled while holding the token.
+    let res = tokio::time::timeout(
+        Duration::from_millis(50),
+        async_client.with_token(std::future::pending::<()>()),
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(client.available().unwrap(), 1);
+
+    // Panicked while holding the token.
+    let task = {
+        let async_client = async_client.clone();
+        tokio::spawn(async move {
+            async_client
+                .with_token(async { panic!("work failed") })
+                .await
+        })
+    };
+    assert!(task.await.unwrap_err().is_panic());
+    assert_eq!(client.available().unwrap(), 1);
+}
+
+#[test]
+fn try_acquire_all() {
+    let client = get_try_acquire_client(Client::new(3).unwrap());
+
+    let tokens = client.try_acquire_all(Some(2)).unwrap();
+    assert_eq!(tokens.len(), 2);
+
+    let rest = client.try_acquire_all(None).unwrap();
+    assert_eq!(rest.len(), 1);
+    assert!(client.try_acquire_all(None).unwrap().is_empty());
+
+    drop((tokens, rest));
+    assert_eq!(client.try_acquire_all(None).unwrap().len(), 3);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn verify_inheritance() {
+    let client = Client::new(1).unwrap();
+    client.set_verify_inheritance(true);
+
+    let mut cmd = Command::new("sleep");
+    cmd.arg("5");
+    let mut child = client.configure_and_spawn(&mut cmd).unwrap();
+    client.verify_inheritance(child.id()).unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // Not configured.
+    let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+    let err = client.verify_inheritance(child.id()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Other);
+    child.kill().unwrap();
+
+    // Nothing is left to check once it has exited.
+    child.wait().unwrap();
+    assert!(client.verify_inheritance(child.id()).is_ok());
+}